@@ -1,13 +1,30 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
-use cosmwasm_std::{to_binary, Addr, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult};
+use cosmwasm_std::{
+    to_binary, Addr, Binary, Deps, DepsMut, Env, MessageInfo, Order, Response, StdError, StdResult,
+    Storage,
+};
+use cw2::set_contract_version;
+use cw_storage_plus::Bound;
 
 use crate::error::ContractError;
-use crate::msg::{ConfigParams, ExecuteMsg, InstantiateMsg, QueryMsg};
-use crate::state::CONFIG;
-use crate::{Config, MarsContract};
+use crate::msg::{ConfigParams, ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
+use crate::state::{CONFIG, PENDING_OWNER, REGISTERED_CONTRACTS};
+use crate::{Config, MarsContract, RegisteredContract};
 
 use mars_core::helpers::option_string_to_addr;
+use mars_core::versioning::{migrate_contract, MigrationStep, Version};
+
+// version info for migration info
+const CONTRACT_NAME: &str = "crates.io:address-provider";
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Registered contract names
+const CONTRACT_NAME_MAX_LENGTH: usize = 64;
+
+// Registered contracts pagination
+const PAGINATION_DEFAULT_LIMIT: u32 = 10;
+const PAGINATION_MAX_LIMIT: u32 = 30;
 
 // INIT
 
@@ -18,6 +35,8 @@ pub fn instantiate(
     _info: MessageInfo,
     msg: InstantiateMsg,
 ) -> StdResult<Response> {
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
     // Initialize config
     let config = Config {
         owner: deps.api.addr_validate(&msg.owner)?,
@@ -33,6 +52,7 @@ pub fn instantiate(
         treasury_address: Addr::unchecked(""),
         vesting_address: Addr::unchecked(""),
         xmars_token_address: Addr::unchecked(""),
+        guardian_address: Addr::unchecked(""),
     };
 
     CONFIG.save(deps.storage, &config)?;
@@ -53,6 +73,16 @@ pub fn execute(
         ExecuteMsg::UpdateConfig {
             config: config_params,
         } => execute_update_config(deps, env, info, config_params),
+        ExecuteMsg::RegisterContract {
+            name,
+            address,
+            version,
+            metadata,
+        } => execute_register_contract(deps, info, name, address, version, metadata),
+        ExecuteMsg::RemoveContract { name } => execute_remove_contract(deps, info, name),
+        ExecuteMsg::ProposeNewOwner { owner } => execute_propose_new_owner(deps, info, owner),
+        ExecuteMsg::DropOwnershipProposal {} => execute_drop_ownership_proposal(deps, info),
+        ExecuteMsg::ClaimOwnership {} => execute_claim_ownership(deps, info),
     }
 }
 
@@ -70,7 +100,6 @@ pub fn execute_update_config(
     }
 
     let ConfigParams {
-        owner,
         council_address,
         incentives_address,
         safety_fund_address,
@@ -83,10 +112,10 @@ pub fn execute_update_config(
         treasury_address,
         vesting_address,
         xmars_token_address,
+        guardian_address,
     } = config_params;
 
     // Update config
-    config.owner = option_string_to_addr(deps.api, owner, config.owner)?;
     config.council_address =
         option_string_to_addr(deps.api, council_address, config.council_address)?;
     config.incentives_address =
@@ -116,6 +145,8 @@ pub fn execute_update_config(
         option_string_to_addr(deps.api, vesting_address, config.vesting_address)?;
     config.xmars_token_address =
         option_string_to_addr(deps.api, xmars_token_address, config.xmars_token_address)?;
+    config.guardian_address =
+        option_string_to_addr(deps.api, guardian_address, config.guardian_address)?;
 
     CONFIG.save(deps.storage, &config)?;
 
@@ -123,6 +154,115 @@ pub fn execute_update_config(
     Ok(res)
 }
 
+/// Register a contract by name, overwriting the previous entry if there is one
+pub fn execute_register_contract(
+    deps: DepsMut,
+    info: MessageInfo,
+    name: String,
+    address: String,
+    version: Option<String>,
+    metadata: Option<String>,
+) -> Result<Response, ContractError> {
+    assert_owner(deps.as_ref(), &info.sender)?;
+    validate_contract_name(&name)?;
+
+    let registered_contract = RegisteredContract {
+        name,
+        address: deps.api.addr_validate(&address)?,
+        version,
+        metadata,
+    };
+    REGISTERED_CONTRACTS.save(
+        deps.storage,
+        &registered_contract.name,
+        &registered_contract,
+    )?;
+
+    let res = Response::new()
+        .add_attribute("action", "register_contract")
+        .add_attribute("name", registered_contract.name)
+        .add_attribute("address", registered_contract.address);
+    Ok(res)
+}
+
+/// Remove a registered contract
+pub fn execute_remove_contract(
+    deps: DepsMut,
+    info: MessageInfo,
+    name: String,
+) -> Result<Response, ContractError> {
+    assert_owner(deps.as_ref(), &info.sender)?;
+
+    if !REGISTERED_CONTRACTS.has(deps.storage, &name) {
+        return Err(ContractError::ContractNotRegistered { name });
+    }
+    REGISTERED_CONTRACTS.remove(deps.storage, &name);
+
+    let res = Response::new()
+        .add_attribute("action", "remove_contract")
+        .add_attribute("name", name);
+    Ok(res)
+}
+
+/// Propose a new owner. Ownership is only transferred once the proposed owner claims it
+pub fn execute_propose_new_owner(
+    deps: DepsMut,
+    info: MessageInfo,
+    owner: String,
+) -> Result<Response, ContractError> {
+    assert_owner(deps.as_ref(), &info.sender)?;
+
+    let pending_owner = deps.api.addr_validate(&owner)?;
+    PENDING_OWNER.save(deps.storage, &pending_owner)?;
+
+    let res = Response::new()
+        .add_attribute("action", "propose_new_owner")
+        .add_attribute("pending_owner", pending_owner);
+    Ok(res)
+}
+
+/// Drop the current ownership transfer proposal
+pub fn execute_drop_ownership_proposal(
+    deps: DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    assert_owner(deps.as_ref(), &info.sender)?;
+
+    if PENDING_OWNER.may_load(deps.storage)?.is_none() {
+        return Err(ContractError::NoOwnershipProposal {});
+    }
+    PENDING_OWNER.remove(deps.storage);
+
+    let res = Response::new().add_attribute("action", "drop_ownership_proposal");
+    Ok(res)
+}
+
+/// Accept a pending ownership transfer
+pub fn execute_claim_ownership(
+    deps: DepsMut,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let pending_owner = PENDING_OWNER
+        .may_load(deps.storage)?
+        .ok_or(ContractError::NoOwnershipProposal {})?;
+
+    if info.sender != pending_owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let mut config = CONFIG.load(deps.storage)?;
+    let previous_owner = config.owner;
+    config.owner = pending_owner;
+    CONFIG.save(deps.storage, &config)?;
+    PENDING_OWNER.remove(deps.storage);
+
+    let res = Response::new()
+        .add_attribute("action", "claim_ownership")
+        .add_attribute("previous_owner", previous_owner)
+        .add_attribute("new_owner", config.owner);
+    Ok(res)
+}
+
 // QUERIES
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -131,6 +271,12 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
         QueryMsg::Config {} => to_binary(&query_config(deps)?),
         QueryMsg::Address { contract } => to_binary(&query_address(deps, contract)?),
         QueryMsg::Addresses { contracts } => to_binary(&query_addresses(deps, contracts)?),
+        QueryMsg::RegisteredContract { name } => to_binary(&query_registered_contract(deps, name)?),
+        QueryMsg::RegisteredContracts { start_after, limit } => {
+            to_binary(&query_registered_contracts(deps, start_after, limit)?)
+        }
+        QueryMsg::PendingOwner {} => to_binary(&PENDING_OWNER.may_load(deps.storage)?),
+        QueryMsg::Guardian {} => to_binary(&CONFIG.load(deps.storage)?.guardian_address),
     }
 }
 
@@ -171,13 +317,98 @@ fn get_address(config: &Config, address: MarsContract) -> Addr {
     }
 }
 
+fn query_registered_contract(deps: Deps, name: String) -> StdResult<RegisteredContract> {
+    REGISTERED_CONTRACTS
+        .may_load(deps.storage, &name)?
+        .ok_or_else(|| StdError::generic_err(format!("Contract {} is not registered", name)))
+}
+
+fn query_registered_contracts(
+    deps: Deps,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Vec<RegisteredContract>> {
+    let start = start_after.map(|name| Bound::exclusive(name.into_bytes()));
+    let limit = limit
+        .unwrap_or(PAGINATION_DEFAULT_LIMIT)
+        .min(PAGINATION_MAX_LIMIT) as usize;
+
+    REGISTERED_CONTRACTS
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (_, registered_contract) = item?;
+            Ok(registered_contract)
+        })
+        .collect()
+}
+
+// HELPERS
+
+fn assert_owner(deps: Deps, sender: &Addr) -> Result<(), ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if *sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    Ok(())
+}
+
+/// Names are kept to a restricted charset so they are unambiguous when used as keys by other
+/// contracts and tooling
+fn validate_contract_name(name: &str) -> Result<(), ContractError> {
+    let is_valid = !name.is_empty()
+        && name.len() <= CONTRACT_NAME_MAX_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+
+    if !is_valid {
+        return Err(ContractError::InvalidContractName {
+            name: name.to_string(),
+            max_length: CONTRACT_NAME_MAX_LENGTH,
+        });
+    }
+    Ok(())
+}
+
+// MIGRATION
+
+/// State migrations, run when migrating from a version older than theirs
+const MIGRATION_STEPS: &[MigrationStep] = &[MigrationStep {
+    version: Version::new(1, 1, 0),
+    migrate: migrate_v1_1_0,
+}];
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> StdResult<Response> {
+    let attributes = migrate_contract(
+        deps.storage,
+        CONTRACT_NAME,
+        CONTRACT_VERSION,
+        MIGRATION_STEPS,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attributes(attributes))
+}
+
+/// Stores the guardian address added to the config in 1.1.0 (empty until set)
+fn migrate_v1_1_0(storage: &mut dyn Storage) -> StdResult<()> {
+    let config = CONFIG.load(storage)?;
+    CONFIG.save(storage, &config)
+}
+
 // TESTS
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, MockApi, MockQuerier, MockStorage};
-    use cosmwasm_std::{from_binary, Coin, OwnedDeps};
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+    };
+    use cosmwasm_std::{attr, from_binary, Coin, OwnedDeps, StdError};
+    use cw2::get_contract_version;
 
     #[test]
     fn test_proper_initialization() {
@@ -198,6 +429,10 @@ mod tests {
 
         let config = CONFIG.load(&deps.storage).unwrap();
         assert_eq!(owner_address, config.owner);
+
+        let contract_version = get_contract_version(&deps.storage).unwrap();
+        assert_eq!(contract_version.contract, CONTRACT_NAME);
+        assert_eq!(contract_version.version, CONTRACT_VERSION);
     }
 
     #[test]
@@ -227,6 +462,7 @@ mod tests {
                     incentives_address: Some("incentives".to_string()),
                     mars_token_address: Some("mars-token".to_string()),
                     treasury_address: Some("treasury".to_string()),
+                    guardian_address: Some("guardian".to_string()),
                     ..Default::default()
                 },
             };
@@ -246,6 +482,12 @@ mod tests {
             assert_eq!(new_config.incentives_address, Addr::unchecked("incentives"));
             assert_eq!(new_config.mars_token_address, Addr::unchecked("mars-token"));
             assert_eq!(new_config.treasury_address, Addr::unchecked("treasury"));
+            assert_eq!(new_config.guardian_address, Addr::unchecked("guardian"));
+
+            let guardian: Addr =
+                from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::Guardian {}).unwrap())
+                    .unwrap();
+            assert_eq!(guardian, Addr::unchecked("guardian"));
         }
     }
 
@@ -295,6 +537,384 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_registered_contracts() {
+        let mut deps = th_setup(&[]);
+
+        let register_msg = |name: &str, address: &str| ExecuteMsg::RegisterContract {
+            name: name.to_string(),
+            address: address.to_string(),
+            version: None,
+            metadata: None,
+        };
+
+        // only owner can register contracts
+        let error_res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("somebody", &[]),
+            register_msg("lockdrop", "lockdrop"),
+        )
+        .unwrap_err();
+        assert_eq!(error_res, ContractError::Unauthorized {});
+
+        // names are validated
+        for name in &["", "Lockdrop", "lock drop", &"a".repeat(65)] {
+            let error_res = execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("owner", &[]),
+                register_msg(name, "lockdrop"),
+            )
+            .unwrap_err();
+            assert_eq!(
+                error_res,
+                ContractError::InvalidContractName {
+                    name: name.to_string(),
+                    max_length: CONTRACT_NAME_MAX_LENGTH,
+                }
+            );
+        }
+
+        // register contracts
+        for name in &["lockdrop", "auction", "vault_1", "zapper-v2"] {
+            let res = execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("owner", &[]),
+                register_msg(name, &format!("{}_address", name)),
+            )
+            .unwrap();
+            assert_eq!(
+                res.attributes,
+                vec![
+                    attr("action", "register_contract"),
+                    attr("name", *name),
+                    attr("address", format!("{}_address", name)),
+                ]
+            );
+        }
+
+        // registering again overwrites the entry
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::RegisterContract {
+                name: "lockdrop".to_string(),
+                address: "lockdrop_v2_address".to_string(),
+                version: Some("2.0.0".to_string()),
+                metadata: Some("phase 2".to_string()),
+            },
+        )
+        .unwrap();
+
+        let registered_contract: RegisteredContract = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::RegisteredContract {
+                    name: "lockdrop".to_string(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            registered_contract,
+            RegisteredContract {
+                name: "lockdrop".to_string(),
+                address: Addr::unchecked("lockdrop_v2_address"),
+                version: Some("2.0.0".to_string()),
+                metadata: Some("phase 2".to_string()),
+            }
+        );
+
+        // list is paginated and ordered by name
+        let registered_contracts: Vec<RegisteredContract> = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::RegisteredContracts {
+                    start_after: None,
+                    limit: Some(2),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let names: Vec<&str> = registered_contracts
+            .iter()
+            .map(|registered_contract| registered_contract.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["auction", "lockdrop"]);
+
+        let registered_contracts: Vec<RegisteredContract> = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::RegisteredContracts {
+                    start_after: Some("lockdrop".to_string()),
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let names: Vec<&str> = registered_contracts
+            .iter()
+            .map(|registered_contract| registered_contract.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["vault_1", "zapper-v2"]);
+
+        // only owner can remove contracts
+        let error_res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("somebody", &[]),
+            ExecuteMsg::RemoveContract {
+                name: "auction".to_string(),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(error_res, ContractError::Unauthorized {});
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::RemoveContract {
+                name: "auction".to_string(),
+            },
+        )
+        .unwrap();
+
+        let error_res = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::RegisteredContract {
+                name: "auction".to_string(),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            error_res,
+            StdError::generic_err("Contract auction is not registered")
+        );
+
+        // cannot remove a contract that is not registered
+        let error_res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::RemoveContract {
+                name: "auction".to_string(),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(
+            error_res,
+            ContractError::ContractNotRegistered {
+                name: "auction".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_ownership_transfer() {
+        let mut deps = th_setup(&[]);
+
+        let query_pending_owner = |deps: Deps| -> Option<Addr> {
+            from_binary(&query(deps, mock_env(), QueryMsg::PendingOwner {}).unwrap()).unwrap()
+        };
+
+        // only owner can propose a new owner
+        let error_res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("somebody", &[]),
+            ExecuteMsg::ProposeNewOwner {
+                owner: "somebody".to_string(),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(error_res, ContractError::Unauthorized {});
+
+        // nothing to claim or drop without a proposal
+        let error_res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("new_owner", &[]),
+            ExecuteMsg::ClaimOwnership {},
+        )
+        .unwrap_err();
+        assert_eq!(error_res, ContractError::NoOwnershipProposal {});
+
+        let error_res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::DropOwnershipProposal {},
+        )
+        .unwrap_err();
+        assert_eq!(error_res, ContractError::NoOwnershipProposal {});
+
+        // propose and drop
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::ProposeNewOwner {
+                owner: "wrong_owner".to_string(),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            query_pending_owner(deps.as_ref()),
+            Some(Addr::unchecked("wrong_owner"))
+        );
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::DropOwnershipProposal {},
+        )
+        .unwrap();
+        assert_eq!(query_pending_owner(deps.as_ref()), None);
+
+        // propose and claim
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::ProposeNewOwner {
+                owner: "new_owner".to_string(),
+            },
+        )
+        .unwrap();
+
+        // only proposed owner can claim
+        let error_res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("somebody", &[]),
+            ExecuteMsg::ClaimOwnership {},
+        )
+        .unwrap_err();
+        assert_eq!(error_res, ContractError::Unauthorized {});
+
+        // owner is unchanged until claimed
+        let config = CONFIG.load(&deps.storage).unwrap();
+        assert_eq!(config.owner, Addr::unchecked("owner"));
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("new_owner", &[]),
+            ExecuteMsg::ClaimOwnership {},
+        )
+        .unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "claim_ownership"),
+                attr("previous_owner", "owner"),
+                attr("new_owner", "new_owner"),
+            ]
+        );
+
+        let config = CONFIG.load(&deps.storage).unwrap();
+        assert_eq!(config.owner, Addr::unchecked("new_owner"));
+        assert_eq!(query_pending_owner(deps.as_ref()), None);
+
+        // previous owner lost its permissions
+        let error_res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::RemoveContract {
+                name: "lockdrop".to_string(),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(error_res, ContractError::Unauthorized {});
+    }
+
+    #[test]
+    fn test_migrate() {
+        // contracts instantiated before versioning was added are at 1.0.0, whose config has no
+        // guardian address
+        let mut deps = mock_dependencies(&[]);
+        let contract_names = [
+            "owner",
+            "council_address",
+            "incentives_address",
+            "safety_fund_address",
+            "mars_token_address",
+            "oracle_address",
+            "protocol_admin_address",
+            "protocol_rewards_collector_address",
+            "red_bank_address",
+            "staking_address",
+            "treasury_address",
+            "vesting_address",
+            "xmars_token_address",
+        ];
+        let legacy_config = contract_names
+            .iter()
+            .map(|name| format!(r#""{}":"{}""#, name, name))
+            .collect::<Vec<_>>()
+            .join(",");
+        deps.storage
+            .set(b"config", format!("{{{}}}", legacy_config).as_bytes());
+
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "migrate"),
+                attr("previous_version", "1.0.0"),
+                attr("new_version", CONTRACT_VERSION),
+                attr("migration_steps", "1.1.0"),
+            ]
+        );
+        assert_eq!(
+            get_contract_version(&deps.storage).unwrap().version,
+            CONTRACT_VERSION
+        );
+        let stored_config = String::from_utf8(deps.storage.get(b"config").unwrap()).unwrap();
+        assert!(stored_config.contains(r#""guardian_address":"""#));
+        let config = CONFIG.load(&deps.storage).unwrap();
+        assert_eq!(config.owner, Addr::unchecked("owner"));
+        assert_eq!(config.red_bank_address, Addr::unchecked("red_bank_address"));
+
+        // steps are not run again when migrating from the current version
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+        assert_eq!(res.attributes[3], attr("migration_steps", ""));
+
+        // cannot migrate from another contract
+        set_contract_version(&mut deps.storage, "crates.io:red-bank", CONTRACT_VERSION).unwrap();
+        let error_res = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert_eq!(
+            error_res,
+            StdError::generic_err(
+                "Cannot migrate from crates.io:red-bank to crates.io:address-provider"
+            )
+        );
+
+        // cannot migrate to an older version
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let error_res = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap_err();
+        assert_eq!(
+            error_res,
+            StdError::generic_err(format!(
+                "Cannot migrate from version 99.0.0 to older version {}",
+                CONTRACT_VERSION
+            ))
+        );
+    }
+
     // TEST HELPERS
     fn th_setup(contract_balances: &[Coin]) -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
         let mut deps = mock_dependencies(contract_balances);
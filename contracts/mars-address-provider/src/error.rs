@@ -8,4 +8,13 @@ pub enum ContractError {
 
     #[error("Unauthorized")]
     Unauthorized {},
+
+    #[error("Invalid contract name \"{name}\": should be 1 to {max_length} lowercase alphanumeric characters, '-' or '_'")]
+    InvalidContractName { name: String, max_length: usize },
+
+    #[error("Contract {name} is not registered")]
+    ContractNotRegistered { name: String },
+
+    #[error("No ownership transfer proposal")]
+    NoOwnershipProposal {},
 }
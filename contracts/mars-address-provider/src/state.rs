@@ -1,6 +1,9 @@
-use cw_storage_plus::Item;
+use cosmwasm_std::Addr;
+use cw_storage_plus::{Item, Map};
 
-use crate::Config;
+use crate::{Config, RegisteredContract};
 
 // Key
 pub const CONFIG: Item<Config> = Item::new("config");
+pub const PENDING_OWNER: Item<Addr> = Item::new("pending_owner");
+pub const REGISTERED_CONTRACTS: Map<&str, RegisteredContract> = Map::new("registered_contracts");
@@ -3,7 +3,7 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use mars_council::msg::{ExecuteMsg, InstantiateMsg, QueryMsg, ReceiveMsg};
+use mars_council::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, ReceiveMsg};
 use mars_council::{Config, Proposal, ProposalVotesResponse, ProposalsListResponse};
 
 fn main() {
@@ -16,6 +16,7 @@ fn main() {
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(ReceiveMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
 
     export_schema(&schema_for!(Config), &out_dir);
     export_schema(&schema_for!(Proposal), &out_dir);
@@ -4,6 +4,7 @@ use cosmwasm_std::{
     attr, from_binary, to_binary, Addr, Binary, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Order,
     QuerierWrapper, QueryRequest, Response, StdResult, Uint128, WasmMsg, WasmQuery,
 };
+use cw2::set_contract_version;
 use cw20::{Cw20ExecuteMsg, Cw20ReceiveMsg};
 use cw_storage_plus::{Bound, U64Key};
 
@@ -11,13 +12,16 @@ use mars_core::council::error::ContractError;
 use mars_core::error::MarsError;
 use mars_core::helpers::{option_string_to_addr, zero_address};
 use mars_core::math::decimal::Decimal;
+use mars_core::versioning::{migrate_contract, MigrationStep};
 
 use mars_core::address_provider;
 use mars_core::address_provider::MarsContract;
 use mars_core::vesting;
 use mars_core::xmars_token;
 
-use crate::msg::{CreateOrUpdateConfig, ExecuteMsg, InstantiateMsg, QueryMsg, ReceiveMsg};
+use crate::msg::{
+    CreateOrUpdateConfig, ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, ReceiveMsg,
+};
 use crate::state::{CONFIG, GLOBAL_STATE, PROPOSALS, PROPOSAL_VOTES};
 use crate::{
     Config, GlobalState, Proposal, ProposalMessage, ProposalStatus, ProposalVote,
@@ -32,6 +36,10 @@ const MAX_DESC_LENGTH: usize = 1024;
 const MIN_LINK_LENGTH: usize = 12;
 const MAX_LINK_LENGTH: usize = 128;
 
+// version info for migration info
+const CONTRACT_NAME: &str = "crates.io:council";
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 // INSTANTIATE
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -41,6 +49,8 @@ pub fn instantiate(
     _info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
     // Destructuring a struct’s fields into separate variables in order to force
     // compile error if we add more params
     let CreateOrUpdateConfig {
@@ -640,6 +650,25 @@ fn query_proposal_votes(
     })
 }
 
+// MIGRATION
+
+/// State migrations, run when migrating from a version older than theirs
+const MIGRATION_STEPS: &[MigrationStep] = &[];
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let attributes = migrate_contract(
+        deps.storage,
+        CONTRACT_NAME,
+        CONTRACT_VERSION,
+        MIGRATION_STEPS,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attributes(attributes))
+}
+
 // HELPERS
 
 fn xmars_get_total_supply_at(
@@ -3,7 +3,7 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use mars_incentives::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
+use mars_incentives::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
 use mars_incentives::{AssetIncentiveResponse, Config};
 
 fn main() {
@@ -15,6 +15,7 @@ fn main() {
     export_schema(&schema_for!(InstantiateMsg), &out_dir);
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
 
     export_schema(&schema_for!(Config), &out_dir);
     export_schema(&schema_for!(AssetIncentiveResponse), &out_dir);
@@ -2,23 +2,29 @@
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
     attr, to_binary, Addr, Binary, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Order,
-    OverflowError, OverflowOperation, QueryRequest, Response, StdError, StdResult, Uint128,
-    WasmMsg, WasmQuery,
+    OverflowError, OverflowOperation, QueryRequest, Response, StdError, StdResult, Storage,
+    Uint128, WasmMsg, WasmQuery,
 };
+use cw2::set_contract_version;
 
 use mars_core::error::MarsError;
 use mars_core::helpers::option_string_to_addr;
 use mars_core::math::decimal::Decimal;
+use mars_core::versioning::{migrate_contract, MigrationStep, Version};
 
 use mars_core::address_provider;
 use mars_core::address_provider::MarsContract;
 use mars_core::staking;
 
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
+use crate::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
 use crate::state::{ASSET_INCENTIVES, CONFIG, USER_ASSET_INDICES, USER_UNCLAIMED_REWARDS};
 use crate::{AssetIncentive, AssetIncentiveResponse, Config};
 
+// version info for migration info
+const CONTRACT_NAME: &str = "crates.io:incentives";
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 // INIT
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -28,9 +34,12 @@ pub fn instantiate(
     _info: MessageInfo,
     msg: InstantiateMsg,
 ) -> StdResult<Response> {
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
     let config = Config {
         owner: deps.api.addr_validate(&msg.owner)?,
         address_provider_address: deps.api.addr_validate(&msg.address_provider_address)?,
+        paused: false,
     };
 
     CONFIG.save(deps.storage, &config)?;
@@ -78,6 +87,8 @@ pub fn execute(
         ExecuteMsg::ExecuteCosmosMsg(cosmos_msg) => {
             Ok(execute_execute_cosmos_msg(deps, env, info, cosmos_msg)?)
         }
+        ExecuteMsg::Pause {} => Ok(execute_pause(deps, env, info)?),
+        ExecuteMsg::Unpause {} => Ok(execute_unpause(deps, env, info)?),
     }
 }
 
@@ -208,6 +219,11 @@ pub fn execute_claim_rewards(
     env: Env,
     info: MessageInfo,
 ) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if config.paused {
+        return Err(MarsError::Paused {}.into());
+    }
+
     let user_address = info.sender;
     let (total_unclaimed_rewards, user_asset_incentive_statuses_to_update) =
         compute_user_unclaimed_rewards(deps.as_ref(), &env, &user_address)?;
@@ -237,7 +253,6 @@ pub fn execute_claim_rewards(
     let mut response = Response::new();
     if total_unclaimed_rewards > Uint128::zero() {
         // Build message to stake mars and send resulting xmars to the user
-        let config = CONFIG.load(deps.storage)?;
         let mars_contracts = vec![MarsContract::MarsToken, MarsContract::Staking];
         let mut addresses_query = address_provider::helpers::query_addresses(
             &deps.querier,
@@ -296,6 +311,35 @@ pub fn execute_update_config(
     Ok(response)
 }
 
+pub fn execute_pause(deps: DepsMut, _env: Env, info: MessageInfo) -> Result<Response, MarsError> {
+    let mut config = CONFIG.load(deps.storage)?;
+
+    address_provider::helpers::assert_owner_or_guardian(
+        &deps.querier,
+        config.address_provider_address.clone(),
+        &config.owner,
+        &info.sender,
+    )?;
+
+    config.paused = true;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new().add_attribute("action", "pause"))
+}
+
+pub fn execute_unpause(deps: DepsMut, _env: Env, info: MessageInfo) -> Result<Response, MarsError> {
+    let mut config = CONFIG.load(deps.storage)?;
+
+    if info.sender != config.owner {
+        return Err(MarsError::Unauthorized {});
+    }
+
+    config.paused = false;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new().add_attribute("action", "unpause"))
+}
+
 pub fn execute_execute_cosmos_msg(
     deps: DepsMut,
     _env: Env,
@@ -502,6 +546,34 @@ fn query_user_unclaimed_rewards(
     Ok(unclaimed_rewards)
 }
 
+// MIGRATION
+
+/// State migrations, run when migrating from a version older than theirs
+const MIGRATION_STEPS: &[MigrationStep] = &[MigrationStep {
+    version: Version::new(1, 1, 0),
+    migrate: migrate_v1_1_0,
+}];
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> StdResult<Response> {
+    let attributes = migrate_contract(
+        deps.storage,
+        CONTRACT_NAME,
+        CONTRACT_VERSION,
+        MIGRATION_STEPS,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attributes(attributes))
+}
+
+/// Stores the `paused` flag added to the config in 1.1.0
+fn migrate_v1_1_0(storage: &mut dyn Storage) -> StdResult<()> {
+    let config = CONFIG.load(storage)?;
+    CONFIG.save(storage, &config)
+}
+
 // TESTS
 
 #[cfg(test)]
@@ -1537,6 +1609,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_pause_claims() {
+        let mut deps = th_setup(&[]);
+
+        // only owner or guardian can pause
+        let info = mock_info("somebody", &[]);
+        let error_res = execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Pause {}).unwrap_err();
+        assert_eq!(error_res, ContractError::Mars(MarsError::Unauthorized {}));
+
+        let info = mock_info("guardian", &[]);
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Pause {}).unwrap();
+        assert!(CONFIG.load(deps.as_ref().storage).unwrap().paused);
+
+        // cannot claim while paused
+        let info = mock_info("user", &[]);
+        let error_res =
+            execute(deps.as_mut(), mock_env(), info, ExecuteMsg::ClaimRewards {}).unwrap_err();
+        assert_eq!(error_res, ContractError::Mars(MarsError::Paused {}));
+
+        // only owner can unpause
+        let info = mock_info("guardian", &[]);
+        let error_res =
+            execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Unpause {}).unwrap_err();
+        assert_eq!(error_res, ContractError::Mars(MarsError::Unauthorized {}));
+
+        let info = mock_info("owner", &[]);
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::Unpause {}).unwrap();
+
+        let info = mock_info("user", &[]);
+        execute(deps.as_mut(), mock_env(), info, ExecuteMsg::ClaimRewards {}).unwrap();
+    }
+
     #[test]
     fn test_execute_cosmos_msg() {
         let mut deps = th_setup(&[]);
@@ -19,10 +19,11 @@ use cw20_base::ContractError;
 
 use mars_core::cw20_core::instantiate_token_info_and_marketing;
 use mars_core::red_bank;
+use mars_core::versioning::{migrate_contract, MigrationStep};
 
 use crate::allowances::{execute_send_from, execute_transfer_from};
 use crate::core;
-use crate::msg::{BalanceAndTotalSupplyResponse, ExecuteMsg, InstantiateMsg, QueryMsg};
+use crate::msg::{BalanceAndTotalSupplyResponse, ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
 use crate::state::CONFIG;
 use crate::Config;
 
@@ -389,6 +390,25 @@ pub fn query_underlying_asset_balance(
     Ok(BalanceResponse { balance: query })
 }
 
+// MIGRATION
+
+/// State migrations, run when migrating from a version older than theirs
+const MIGRATION_STEPS: &[MigrationStep] = &[];
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> StdResult<Response> {
+    let attributes = migrate_contract(
+        deps.storage,
+        CONTRACT_NAME,
+        CONTRACT_VERSION,
+        MIGRATION_STEPS,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attributes(attributes))
+}
+
 #[cfg(test)]
 mod tests {
     use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
@@ -3,8 +3,11 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use mars_core::oracle::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
-use mars_core::oracle::{Config, PriceSourceChecked};
+use mars_core::oracle::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
+use mars_core::oracle::{
+    AssetPriceWithMetadataResponse, CircuitBreakerResponse, Config, FallbackPriceSourceChecked,
+    FeederPriceResponse, PriceSourceChecked,
+};
 
 fn main() {
     let mut out_dir = current_dir().unwrap();
@@ -15,7 +18,12 @@ fn main() {
     export_schema(&schema_for!(InstantiateMsg), &out_dir);
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
 
     export_schema(&schema_for!(Config), &out_dir);
     export_schema(&schema_for!(PriceSourceChecked), &out_dir);
+    export_schema(&schema_for!(FallbackPriceSourceChecked), &out_dir);
+    export_schema(&schema_for!(FeederPriceResponse), &out_dir);
+    export_schema(&schema_for!(AssetPriceWithMetadataResponse), &out_dir);
+    export_schema(&schema_for!(CircuitBreakerResponse), &out_dir);
 }
@@ -1,24 +1,43 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    attr, to_binary, Attribute, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult,
-    Uint128,
+    attr, to_binary, Addr, Attribute, Binary, Deps, DepsMut, Env, MessageInfo, Order,
+    QuerierWrapper, Response, StdResult, Storage, Uint128, Uint256,
 };
+use cw2::set_contract_version;
+use cw_storage_plus::{Bound, U64Key};
+use std::convert::TryFrom;
+
 use mars_core::error::MarsError;
-use terra_cosmwasm::TerraQuerier;
 
+use mars_core::address_provider;
 use mars_core::asset::Asset;
-use mars_core::helpers::option_string_to_addr;
+use mars_core::helpers::{decimal_param_le_one, option_string_to_addr, zero_address};
 use mars_core::math::decimal::Decimal;
+use mars_core::math::uint256_sqrt;
+use mars_core::versioning::{migrate_contract, MigrationStep, Version};
 
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
-use crate::state::{ASTROPORT_TWAP_SNAPSHOTS, CONFIG, PRICE_SOURCES};
-use crate::{AstroportTwapSnapshot, Config, PriceSourceChecked, PriceSourceUnchecked};
+use crate::msg::{ExecuteMsg, FeederPrice, InstantiateMsg, MigrateMsg, QueryMsg};
+use crate::state::{
+    ASTROPORT_EMA_PRICES, ASTROPORT_TWAP_SNAPSHOTS, CIRCUIT_BREAKERS, CIRCUIT_BREAKER_STATES,
+    CONFIG, FALLBACK_PRICE_SOURCES, FEED_PRICES, LEGACY_ASTROPORT_TWAP_SNAPSHOTS, PRICE_MAX_AGES,
+    PRICE_SOURCES,
+};
+use crate::{
+    default_base_denom, AssetPriceWithMetadataResponse, AstroportTwapSnapshot, CircuitBreaker,
+    CircuitBreakerResponse, CircuitBreakerState, Config, FallbackPriceSourceUnchecked, FeedPrice,
+    FeederPriceResponse, PriceSnapshot, PriceSourceChecked, PriceSourceUnchecked,
+};
 
 use self::helpers::*;
+use astroport::factory::PairType;
 use astroport::pair::TWAP_PRECISION;
 
+// version info for migration info
+const CONTRACT_NAME: &str = "crates.io:oracle";
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 // INIT
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -28,8 +47,17 @@ pub fn instantiate(
     _info: MessageInfo,
     msg: InstantiateMsg,
 ) -> StdResult<Response> {
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
     let config = Config {
         owner: deps.api.addr_validate(&msg.owner)?,
+        address_provider_address: option_string_to_addr(
+            deps.api,
+            msg.address_provider_address,
+            zero_address(),
+        )?,
+        paused: false,
+        base_denom: msg.base_denom.unwrap_or_else(default_base_denom),
     };
     CONFIG.save(deps.storage, &config)?;
     Ok(Response::default())
@@ -45,14 +73,39 @@ pub fn execute(
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
     match msg {
-        ExecuteMsg::UpdateConfig { owner } => execute_update_config(deps, env, info, owner),
+        ExecuteMsg::UpdateConfig {
+            owner,
+            address_provider_address,
+        } => execute_update_config(deps, env, info, owner, address_provider_address),
         ExecuteMsg::SetAsset {
             asset,
             price_source,
         } => execute_set_asset(deps, env, info, asset, price_source),
+        ExecuteMsg::SetAssetFallback { asset, fallback } => {
+            execute_set_asset_fallback(deps, env, info, asset, fallback)
+        }
+        ExecuteMsg::SetAssetMaxAge { asset, max_age } => {
+            execute_set_asset_max_age(deps, env, info, asset, max_age)
+        }
+        ExecuteMsg::SetCircuitBreaker {
+            asset,
+            circuit_breaker,
+        } => execute_set_circuit_breaker(deps, env, info, asset, circuit_breaker),
+        ExecuteMsg::CheckCircuitBreakers { assets } => {
+            execute_check_circuit_breakers(deps, env, info, assets)
+        }
+        ExecuteMsg::ResetCircuitBreaker { asset } => {
+            execute_reset_circuit_breaker(deps, env, info, asset)
+        }
+        ExecuteMsg::FeedPrices { prices } => execute_feed_prices(deps, env, info, prices),
         ExecuteMsg::RecordTwapSnapshots { assets } => {
             execute_record_twap_snapshots(deps, env, info, assets)
         }
+        ExecuteMsg::RecordEmaPrices { assets } => {
+            execute_record_ema_prices(deps, env, info, assets)
+        }
+        ExecuteMsg::Pause {} => execute_pause(deps, env, info),
+        ExecuteMsg::Unpause {} => execute_unpause(deps, env, info),
     }
 }
 
@@ -61,6 +114,7 @@ pub fn execute_update_config(
     _env: Env,
     info: MessageInfo,
     owner: Option<String>,
+    address_provider_address: Option<String>,
 ) -> Result<Response, ContractError> {
     let mut config = CONFIG.load(deps.storage)?;
     if info.sender != config.owner {
@@ -68,12 +122,52 @@ pub fn execute_update_config(
     };
 
     config.owner = option_string_to_addr(deps.api, owner, config.owner)?;
+    config.address_provider_address = option_string_to_addr(
+        deps.api,
+        address_provider_address,
+        config.address_provider_address,
+    )?;
 
     CONFIG.save(deps.storage, &config)?;
 
     Ok(Response::new().add_attribute("action", "update_config"))
 }
 
+pub fn execute_pause(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    address_provider::helpers::assert_owner_or_guardian(
+        &deps.querier,
+        config.address_provider_address.clone(),
+        &config.owner,
+        &info.sender,
+    )?;
+
+    config.paused = true;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new().add_attribute("action", "pause"))
+}
+
+pub fn execute_unpause(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(MarsError::Unauthorized {}.into());
+    };
+
+    config.paused = false;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new().add_attribute("action", "unpause"))
+}
+
 pub fn execute_set_asset(
     deps: DepsMut,
     _env: Env,
@@ -88,27 +182,198 @@ pub fn execute_set_asset(
 
     let (asset_label, asset_reference, _) = asset.get_attributes();
     let price_source = price_source_unchecked.to_checked(deps.api)?;
+    let base_asset = base_asset(&config.base_denom);
+    validate_price_source(deps.as_ref(), &asset, &price_source, &base_asset)?;
+    let fallback = FALLBACK_PRICE_SOURCES.may_load(deps.storage, &asset_reference)?;
+    assert_single_average_sources(
+        &price_source,
+        fallback.as_ref().map(|fallback| &fallback.price_source),
+        &base_asset,
+    )?;
+    assert_max_age_exceeds_twap_windows(
+        PRICE_MAX_AGES.may_load(deps.storage, &asset_reference)?,
+        &price_source,
+        fallback.as_ref().map(|fallback| &fallback.price_source),
+        &base_asset,
+    )?;
     PRICE_SOURCES.save(deps.storage, &asset_reference, &price_source)?;
 
-    // for spot and TWAP sources, we must make sure: the astroport pair indicated by `pair_address`
-    // consists of UST and the asset of interest
-    match &price_source {
-        PriceSourceChecked::AstroportSpot { pair_address }
-        | PriceSourceChecked::AstroportTwap { pair_address, .. } => {
-            assert_astroport_pool_assets(&deps.querier, &asset, pair_address)?;
+    Ok(Response::new()
+        .add_attribute("action", "set_asset")
+        .add_attribute("asset", asset_label)
+        .add_attribute("price_source", price_source_unchecked.to_string()))
+}
+
+pub fn execute_set_asset_fallback(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    asset: Asset,
+    fallback_unchecked: Option<FallbackPriceSourceUnchecked>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(MarsError::Unauthorized {}.into());
+    }
+
+    let (asset_label, asset_reference, _) = asset.get_attributes();
+    let price_source = PRICE_SOURCES
+        .may_load(deps.storage, &asset_reference)?
+        .ok_or(ContractError::PriceSourceNotSet {})?;
+
+    let fallback_label = match fallback_unchecked {
+        Some(fallback_unchecked) => {
+            let fallback = fallback_unchecked.to_checked(deps.api)?;
+            decimal_param_le_one(&fallback.max_deviation, "max_deviation")?;
+            let base_asset = base_asset(&config.base_denom);
+            validate_price_source(deps.as_ref(), &asset, &fallback.price_source, &base_asset)?;
+            assert_single_average_sources(
+                &price_source,
+                Some(&fallback.price_source),
+                &base_asset,
+            )?;
+            assert_max_age_exceeds_twap_windows(
+                PRICE_MAX_AGES.may_load(deps.storage, &asset_reference)?,
+                &price_source,
+                Some(&fallback.price_source),
+                &base_asset,
+            )?;
+            FALLBACK_PRICE_SOURCES.save(deps.storage, &asset_reference, &fallback)?;
+            fallback.price_source.to_string()
         }
-        _ => (),
+        None => {
+            FALLBACK_PRICE_SOURCES.remove(deps.storage, &asset_reference);
+            "none".to_string()
+        }
+    };
+
+    Ok(Response::new()
+        .add_attribute("action", "set_asset_fallback")
+        .add_attribute("asset", asset_label)
+        .add_attribute("fallback_price_source", fallback_label))
+}
+
+pub fn execute_set_asset_max_age(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    asset: Asset,
+    max_age: Option<u64>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(MarsError::Unauthorized {}.into());
     }
 
+    let (asset_label, asset_reference, _) = asset.get_attributes();
+    let price_source = PRICE_SOURCES
+        .may_load(deps.storage, &asset_reference)?
+        .ok_or(ContractError::PriceSourceNotSet {})?;
+    let fallback = FALLBACK_PRICE_SOURCES.may_load(deps.storage, &asset_reference)?;
+    assert_max_age_exceeds_twap_windows(
+        max_age,
+        &price_source,
+        fallback.as_ref().map(|fallback| &fallback.price_source),
+        &base_asset(&config.base_denom),
+    )?;
+
+    let max_age_label = match max_age {
+        Some(0) => {
+            return Err(MarsError::InvalidParam {
+                param_name: "max_age".to_string(),
+                invalid_value: "0".to_string(),
+                predicate: "> 0".to_string(),
+            }
+            .into())
+        }
+        Some(max_age) => {
+            PRICE_MAX_AGES.save(deps.storage, &asset_reference, &max_age)?;
+            max_age.to_string()
+        }
+        None => {
+            PRICE_MAX_AGES.remove(deps.storage, &asset_reference);
+            "none".to_string()
+        }
+    };
+
     Ok(Response::new()
-        .add_attribute("action", "set_asset")
+        .add_attribute("action", "set_asset_max_age")
         .add_attribute("asset", asset_label)
-        .add_attribute("price_source", price_source_unchecked.to_string()))
+        .add_attribute("max_age", max_age_label))
 }
 
-/// Modified from
-/// https://github.com/Uniswap/uniswap-v2-periphery/blob/master/contracts/examples/ExampleOracleSimple.sol
-pub fn execute_record_twap_snapshots(
+pub fn execute_set_circuit_breaker(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    asset: Asset,
+    circuit_breaker: Option<CircuitBreaker>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(MarsError::Unauthorized {}.into());
+    }
+
+    let (asset_label, asset_reference, _) = asset.get_attributes();
+    if !PRICE_SOURCES.has(deps.storage, &asset_reference) {
+        return Err(ContractError::PriceSourceNotSet {});
+    }
+
+    let circuit_breaker_label = match circuit_breaker {
+        Some(circuit_breaker) => {
+            validate_circuit_breaker(&circuit_breaker)?;
+            CIRCUIT_BREAKERS.save(deps.storage, &asset_reference, &circuit_breaker)?;
+            "set"
+        }
+        None => {
+            CIRCUIT_BREAKERS.remove(deps.storage, &asset_reference);
+            CIRCUIT_BREAKER_STATES.remove(deps.storage, &asset_reference);
+            "none"
+        }
+    };
+
+    Ok(Response::new()
+        .add_attribute("action", "set_circuit_breaker")
+        .add_attribute("asset", asset_label)
+        .add_attribute("circuit_breaker", circuit_breaker_label))
+}
+
+fn validate_circuit_breaker(circuit_breaker: &CircuitBreaker) -> Result<(), MarsError> {
+    if let (Some(min_price), Some(max_price)) =
+        (circuit_breaker.min_price, circuit_breaker.max_price)
+    {
+        if min_price > max_price {
+            return Err(MarsError::InvalidParam {
+                param_name: "min_price".to_string(),
+                invalid_value: min_price.to_string(),
+                predicate: format!("<= {} (max_price)", max_price),
+            });
+        }
+    }
+
+    if let Some(max_change) = circuit_breaker.max_change {
+        if max_change.is_zero() {
+            return Err(MarsError::InvalidParam {
+                param_name: "max_change".to_string(),
+                invalid_value: max_change.to_string(),
+                predicate: "> 0".to_string(),
+            });
+        }
+    }
+
+    // a zero window would let a single reading trip the breaker
+    if circuit_breaker.window == 0 {
+        return Err(MarsError::InvalidParam {
+            param_name: "window".to_string(),
+            invalid_value: "0".to_string(),
+            predicate: "> 0".to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+pub fn execute_check_circuit_breakers(
     deps: DepsMut,
     env: Env,
     _info: MessageInfo,
@@ -119,821 +384,3707 @@ pub fn execute_record_twap_snapshots(
 
     for asset in assets {
         let (asset_label, asset_reference, _) = asset.get_attributes();
-        let price_source = PRICE_SOURCES.load(deps.storage, &asset_reference)?;
+        let circuit_breaker = CIRCUIT_BREAKERS
+            .may_load(deps.storage, &asset_reference)?
+            .ok_or(ContractError::CircuitBreakerNotSet {})?;
+        let mut state = CIRCUIT_BREAKER_STATES
+            .may_load(deps.storage, &asset_reference)?
+            .unwrap_or_default();
+
+        if state.tripped {
+            attrs.extend(vec![attr("asset", asset_label), attr("status", "tripped")]);
+            continue;
+        }
 
-        // Asset must be configured to use TWAP price source
-        let (pair_address, window_size, tolerance) = match price_source {
-            PriceSourceChecked::AstroportTwap {
-                pair_address,
-                window_size,
-                tolerance,
-            } => (pair_address, window_size, tolerance),
-            _ => {
-                return Err(ContractError::PriceSourceNotTwap {});
+        let price = query_asset_price_ignoring_circuit_breaker(
+            deps.as_ref(),
+            env.clone(),
+            &asset_reference,
+        )?
+        .price;
+
+        let status = match assert_circuit_breaker_not_breached(&circuit_breaker, &state, price) {
+            Ok(()) => {
+                state.breached_since = None;
+                // Only record a new price once the window has elapsed, so the price cannot be
+                // moved further than the max change by checking repeatedly
+                let window_elapsed = match &state.last_price {
+                    Some(last_price) => timestamp - last_price.timestamp >= circuit_breaker.window,
+                    None => true,
+                };
+                if window_elapsed {
+                    state.last_price = Some(PriceSnapshot { price, timestamp });
+                    "recorded"
+                } else {
+                    "ok"
+                }
             }
+            // Checks are permissionless and a single reading can be manipulated (price queries
+            // fail meanwhile anyway), so the breaker only trips if the breach still holds a
+            // window after it was first seen
+            Err(ContractError::PriceBelowMin { .. })
+            | Err(ContractError::PriceAboveMax { .. })
+            | Err(ContractError::PriceChangeExceeded { .. }) => match state.breached_since {
+                Some(breached_since) if timestamp - breached_since >= circuit_breaker.window => {
+                    state.tripped = true;
+                    state.breached_since = None;
+                    "tripped"
+                }
+                Some(_) => "breached",
+                None => {
+                    state.breached_since = Some(timestamp);
+                    "breached"
+                }
+            },
+            Err(err) => return Err(err),
         };
 
-        // Load existing snapshots. If there's none, we initialize an empty vector
-        let mut snapshots = ASTROPORT_TWAP_SNAPSHOTS
-            .load(deps.storage, &asset_reference)
-            .unwrap_or_else(|_| vec![]);
+        CIRCUIT_BREAKER_STATES.save(deps.storage, &asset_reference, &state)?;
 
-        // A potential attack is to repeatly call `RecordTwapSnapshots` so that `snapshots` becomes a
-        // very big vector, so that calculating the average price becomes extremely gas expensive.
-        // To deter this, we reject a new snapshot if the most recent snapshot is less than `tolerance`
-        // seconds ago.
-        if let Some(latest_snapshot) = snapshots.last() {
-            if timestamp - latest_snapshot.timestamp < tolerance {
-                continue;
-            }
-        }
+        attrs.extend(vec![
+            attr("asset", asset_label),
+            attr("price", price.to_string()),
+            attr("status", status),
+        ]);
+    }
 
-        // Query new price data
-        let price_cumulative = query_astroport_cumulative_price(&deps.querier, &pair_address)?;
+    Ok(Response::new()
+        .add_attribute("action", "check_circuit_breakers")
+        .add_attribute("timestamp", timestamp.to_string())
+        .add_attributes(attrs))
+}
 
-        // Purge snapshots that are too old, i.e. more than (window_size + tolerance) away from the
-        // current timestamp. These snapshots will never be used in the future for calculating
-        // average prices
-        snapshots.retain(|snapshot| timestamp - snapshot.timestamp <= window_size + tolerance);
+pub fn execute_reset_circuit_breaker(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    asset: Asset,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    address_provider::helpers::assert_owner_or_guardian(
+        &deps.querier,
+        config.address_provider_address,
+        &config.owner,
+        &info.sender,
+    )?;
 
-        snapshots.push(AstroportTwapSnapshot {
-            timestamp,
-            price_cumulative,
-        });
+    let (asset_label, asset_reference, _) = asset.get_attributes();
+    if !CIRCUIT_BREAKERS.has(deps.storage, &asset_reference) {
+        return Err(ContractError::CircuitBreakerNotSet {});
+    }
+
+    CIRCUIT_BREAKER_STATES.save(
+        deps.storage,
+        &asset_reference,
+        &CircuitBreakerState::default(),
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "reset_circuit_breaker")
+        .add_attribute("asset", asset_label))
+}
+
+pub fn execute_feed_prices(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    prices: Vec<FeederPrice>,
+) -> Result<Response, ContractError> {
+    let timestamp = env.block.time.seconds();
+    let mut attrs: Vec<Attribute> = vec![];
+
+    for FeederPrice { asset, price } in prices {
+        let (asset_label, asset_reference, _) = asset.get_attributes();
+
+        // Sender must be whitelisted in a feed used by the asset, either as primary or fallback
+        let price_source = PRICE_SOURCES
+            .may_load(deps.storage, &asset_reference)?
+            .ok_or(ContractError::PriceSourceNotSet {})?;
+        let fallback = FALLBACK_PRICE_SOURCES.may_load(deps.storage, &asset_reference)?;
+        let is_feeder = is_feeder(&price_source, &info.sender)
+            || fallback
+                .iter()
+                .any(|fallback| is_feeder(&fallback.price_source, &info.sender));
+        if !is_feeder {
+            return Err(MarsError::Unauthorized {}.into());
+        }
+
+        if price.is_zero() {
+            return Err(ContractError::ZeroFeedPrice {});
+        }
 
-        ASTROPORT_TWAP_SNAPSHOTS.save(deps.storage, &asset_reference, &snapshots)?;
+        FEED_PRICES.save(
+            deps.storage,
+            (&asset_reference, &info.sender),
+            &FeedPrice { price, timestamp },
+        )?;
 
         attrs.extend(vec![
             attr("asset", asset_label),
-            attr("price_cumulative", price_cumulative),
+            attr("price", price.to_string()),
         ]);
     }
 
     Ok(Response::new()
-        .add_attribute("action", "record_twap_snapshots")
+        .add_attribute("action", "feed_prices")
+        .add_attribute("feeder", info.sender)
         .add_attribute("timestamp", timestamp.to_string())
         .add_attributes(attrs))
 }
 
-// QUERIES
-
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::Config {} => to_binary(&query_config(deps, env)?),
-        QueryMsg::AssetPriceSource { asset } => {
-            to_binary(&query_asset_price_source(deps, env, asset)?)
+/// Checks a price source can be used for the given asset, quoted in `quote_asset` (the base denom,
+/// unless the source is chained through another asset)
+fn validate_price_source(
+    deps: Deps,
+    asset: &Asset,
+    price_source: &PriceSourceChecked,
+    quote_asset: &Asset,
+) -> Result<(), ContractError> {
+    let querier = &deps.querier;
+
+    // for spot, TWAP and EMA sources, we must make sure: the astroport pair indicated by
+    // `pair_address` consists of the quote asset and the asset of interest
+    match price_source {
+        PriceSourceChecked::AstroportSpot { pair_address }
+        | PriceSourceChecked::AstroportTwap { pair_address, .. } => {
+            assert_astroport_pool_assets(querier, asset, pair_address, quote_asset)?;
         }
-        QueryMsg::AssetPrice { asset } => {
-            to_binary(&query_asset_price(deps, env, asset.get_reference())?)
+        PriceSourceChecked::AstroportEma {
+            pair_address,
+            half_life,
+            max_staleness,
+        } => {
+            if *half_life == 0 {
+                return Err(MarsError::InvalidParam {
+                    param_name: "half_life".to_string(),
+                    invalid_value: "0".to_string(),
+                    predicate: "> 0".to_string(),
+                }
+                .into());
+            }
+            if *max_staleness == 0 {
+                return Err(MarsError::InvalidParam {
+                    param_name: "max_staleness".to_string(),
+                    invalid_value: "0".to_string(),
+                    predicate: "> 0".to_string(),
+                }
+                .into());
+            }
+            assert_astroport_pool_assets(querier, asset, pair_address, quote_asset)?;
         }
-        QueryMsg::AssetPriceByReference { asset_reference } => {
-            to_binary(&query_asset_price(deps, env, asset_reference)?)
+        // fair pricing needs the pool invariant, so the pair type must be known and `asset` must be
+        // the pair's liquidity token
+        PriceSourceChecked::AstroportFairLiquidityToken { pair_address } => {
+            let pair_info = query_astroport_pair_info(querier, pair_address)?;
+            match pair_info.pair_type {
+                PairType::Xyk {} | PairType::Stable {} => (),
+                pair_type => {
+                    return Err(ContractError::UnsupportedPairType {
+                        pair_type: pair_type.to_string(),
+                    })
+                }
+            }
+            let liquidity_token = Asset::Cw20 {
+                contract_addr: pair_info.liquidity_token.to_string(),
+            };
+            if asset != &liquidity_token {
+                return Err(ContractError::InvalidPair {});
+            }
+        }
+        // the exchange rate must be readable from the contract's response
+        PriceSourceChecked::RedemptionRate {
+            contract,
+            query_msg_template,
+            response_path,
+            underlying_asset,
+        } => {
+            if underlying_asset == asset {
+                return Err(ContractError::RedemptionRateUnderlyingIsAsset {});
+            }
+            if !PRICE_SOURCES.has(deps.storage, &underlying_asset.get_reference()) {
+                return Err(ContractError::PriceSourceNotSet {});
+            }
+            query_redemption_rate(querier, contract, query_msg_template, response_path)?;
+        }
+        // native exchange rates are only available through Terra custom queries
+        #[cfg(not(feature = "terra"))]
+        PriceSourceChecked::Native { .. } => {
+            return Err(ContractError::NativePriceSourceNotSupported {});
+        }
+        PriceSourceChecked::Feed {
+            feeders,
+            min_feeders,
+            ..
+        } => {
+            if *min_feeders == 0 || *min_feeders as usize > feeders.len() {
+                return Err(ContractError::InvalidFeedMinFeeders {
+                    min_feeders: *min_feeders,
+                    feeders: feeders.len() as u32,
+                });
+            }
+            for (i, feeder) in feeders.iter().enumerate() {
+                if feeders[..i].contains(feeder) {
+                    return Err(ContractError::DuplicateFeeder {
+                        feeder: feeder.to_string(),
+                    });
+                }
+            }
+        }
+        PriceSourceChecked::Median {
+            sources,
+            min_sources,
+        } => {
+            if *min_sources == 0 || *min_sources as usize > sources.len() {
+                return Err(ContractError::InvalidMedianMinSources {
+                    min_sources: *min_sources,
+                    sources: sources.len() as u32,
+                });
+            }
+            for source in sources {
+                if let PriceSourceChecked::Median { .. } = source {
+                    return Err(ContractError::NestedMedianPriceSource {});
+                }
+                validate_price_source(deps, asset, source, quote_asset)?;
+            }
+        }
+        // the pair of the inner source is checked against the intermediate asset, which must be
+        // priced without depending on the asset of interest
+        PriceSourceChecked::Chained {
+            source,
+            quote_asset,
+        } => {
+            match source.as_ref() {
+                PriceSourceChecked::AstroportSpot { .. }
+                | PriceSourceChecked::AstroportTwap { .. } => (),
+                _ => return Err(ContractError::InvalidChainedPriceSource {}),
+            }
+            validate_price_source(deps, asset, source, quote_asset)?;
+            if !PRICE_SOURCES.has(deps.storage, &quote_asset.get_reference()) {
+                return Err(ContractError::PriceSourceNotSet {});
+            }
         }
+        _ => (),
     }
-}
 
-fn query_config(deps: Deps, _env: Env) -> StdResult<Config> {
-    CONFIG.load(deps.storage)
-}
+    // sources of a median are checked one by one above
+    if !matches!(price_source, PriceSourceChecked::Median { .. }) {
+        for dependency in price_source_dependencies(querier, price_source)? {
+            assert_no_price_source_cycle(deps, asset, &dependency)?;
+        }
+    }
 
-fn query_asset_price_source(deps: Deps, _env: Env, asset: Asset) -> StdResult<PriceSourceChecked> {
-    PRICE_SOURCES.load(deps.storage, &asset.get_reference())
+    Ok(())
 }
 
-fn query_asset_price(
-    deps: Deps,
-    env: Env,
-    asset_reference: Vec<u8>,
-) -> Result<Decimal, ContractError> {
-    let price_source = PRICE_SOURCES.load(deps.storage, &asset_reference)?;
-
+/// Whether the address is whitelisted in a feed used by the price source
+fn is_feeder(price_source: &PriceSourceChecked, address: &Addr) -> bool {
     match price_source {
-        PriceSourceChecked::Fixed { price } => Ok(price),
-
-        PriceSourceChecked::Native { denom } => {
-            let terra_querier = TerraQuerier::new(&deps.querier);
-
-            // NOTE: Exchange rate returns how much of the quote (second argument) is required to
-            // buy one unit of the base_denom (first argument).
-            // We want to know how much uusd we need to buy 1 of the target currency
-            let asset_prices_query = terra_querier
-                .query_exchange_rates(denom, vec!["uusd".to_string()])?
-                .exchange_rates
-                .pop();
-
-            match asset_prices_query {
-                Some(exchange_rate_item) => Ok(exchange_rate_item.exchange_rate.into()),
-                None => Err(ContractError::NativePriceNotFound {}),
-            }
-        }
-
-        // NOTE: Spot price is defined as the amount of UST to be returned when swapping `PROBE_AMOUNT`
-        // of the asset of interest, divided by `PROBE_AMOUNT`. In the current implementation,
-        // `PROBE_AMOUNT` is set to 1,000,000.
-        //
-        // For example, for MARS-UST pair, if swapping 1,000,000 umars returns 1,200,000 uusd (return
-        // amount plus commission), then 1 MARS = 1.2 UST.
-        //
-        // Why not just take the quotient of the two assets depths? (E.g. if the pool has 120 UST and
-        // 100 MARS, then 1 MARS = 1.2 UST) Because this only works for XYK pools, not StableSwap pools.
-        PriceSourceChecked::AstroportSpot { pair_address } => {
-            query_astroport_spot_price(&deps.querier, &pair_address)
+        PriceSourceChecked::Feed { feeders, .. } => feeders.contains(address),
+        PriceSourceChecked::Median { sources, .. } => {
+            sources.iter().any(|source| is_feeder(source, address))
         }
+        _ => false,
+    }
+}
 
+/// Astroport pair, window size, tolerance and quote asset of the TWAP sources used by a price
+/// source. `quote_asset` is the asset the price source is quoted in
+fn astroport_twaps(
+    price_source: &PriceSourceChecked,
+    quote_asset: &Asset,
+) -> Vec<(Addr, u64, u64, Asset)> {
+    match price_source {
         PriceSourceChecked::AstroportTwap {
             pair_address,
             window_size,
             tolerance,
-        } => {
-            let snapshots = ASTROPORT_TWAP_SNAPSHOTS.load(deps.storage, &asset_reference)?;
+        } => vec![(
+            pair_address.clone(),
+            *window_size,
+            *tolerance,
+            quote_asset.clone(),
+        )],
+        PriceSourceChecked::Median { sources, .. } => sources
+            .iter()
+            .flat_map(|source| astroport_twaps(source, quote_asset))
+            .collect(),
+        PriceSourceChecked::Chained {
+            source,
+            quote_asset,
+        } => astroport_twaps(source, quote_asset),
+        _ => vec![],
+    }
+}
 
-            // First, query the current TWAP snapshot
-            let current_snapshot = AstroportTwapSnapshot {
-                timestamp: env.block.time.seconds(),
-                price_cumulative: query_astroport_cumulative_price(&deps.querier, &pair_address)?,
-            };
+/// Astroport pair, half-life and max staleness of the EMA sources used by a price source
+fn astroport_emas(price_source: &PriceSourceChecked) -> Vec<(Addr, u64, u64)> {
+    match price_source {
+        PriceSourceChecked::AstroportEma {
+            pair_address,
+            half_life,
+            max_staleness,
+        } => vec![(pair_address.clone(), *half_life, *max_staleness)],
+        PriceSourceChecked::Median { sources, .. } => {
+            sources.iter().flat_map(astroport_emas).collect()
+        }
+        _ => vec![],
+    }
+}
 
-            // Find the oldest snapshot whose period from current snapshot is within the tolerable window
-            // We do this using a linear search, and quit as soon as we find one; otherwise throw error
-            let previous_snapshot = snapshots
-                .iter()
-                .find(|snapshot| period_diff(&current_snapshot, snapshot, window_size) <= tolerance)
-                .ok_or(ContractError::NoSnapshotWithinTolerance {})?;
-
-            // Handle the case if Astroport's cumulative price overflows. In this case, cumulative
-            // price warps back to zero, resulting in more recent cum. prices being smaller than
-            // earlier ones. (same behavior as in Solidity)
-            //
-            // Calculations below assumes the cumulative price doesn't overflows more than once during
-            // the period, which should always be the case in practice
-            let price_delta =
-                if current_snapshot.price_cumulative >= previous_snapshot.price_cumulative {
-                    current_snapshot.price_cumulative - previous_snapshot.price_cumulative
-                } else {
-                    current_snapshot
-                        .price_cumulative
-                        .checked_add(Uint128::MAX - previous_snapshot.price_cumulative)?
-                };
-            let period = current_snapshot.timestamp - previous_snapshot.timestamp;
-            // NOTE: Astroport introduces TWAP precision (https://github.com/astroport-fi/astroport/pull/143).
-            // We need to divide the result by price_precision: (price_delta / (time * price_precision)).
-            let price_precision = Uint128::from(10_u128.pow(TWAP_PRECISION.into()));
-            let price =
-                Decimal::from_ratio(price_delta, price_precision.checked_mul(period.into())?);
+/// A TWAP price is as old as the snapshot its window starts at, so a max age not exceeding the
+/// window size plus tolerance would make the asset's price always stale
+fn assert_max_age_exceeds_twap_windows(
+    max_age: Option<u64>,
+    price_source: &PriceSourceChecked,
+    fallback_price_source: Option<&PriceSourceChecked>,
+    base_asset: &Asset,
+) -> Result<(), ContractError> {
+    let max_age = match max_age {
+        Some(max_age) => max_age,
+        None => return Ok(()),
+    };
 
-            Ok(price)
+    let twaps = astroport_twaps(price_source, base_asset).into_iter().chain(
+        fallback_price_source
+            .into_iter()
+            .flat_map(|fallback_price_source| astroport_twaps(fallback_price_source, base_asset)),
+    );
+    for (_, window_size, tolerance, _) in twaps {
+        let max_window = window_size.saturating_add(tolerance);
+        if max_age <= max_window {
+            return Err(MarsError::InvalidParam {
+                param_name: "max_age".to_string(),
+                invalid_value: max_age.to_string(),
+                predicate: format!("> {} (TWAP window size plus tolerance)", max_window),
+            }
+            .into());
         }
+    }
 
-        // The value of each unit of the liquidity token is the total value of pool's two assets
-        // divided by the liquidity token's total supply
-        //
-        // NOTE: Price sources must exist for both assets in the pool
-        PriceSourceChecked::AstroportLiquidityToken { pair_address } => {
-            let pool = query_astroport_pool(&deps.querier, &pair_address)?;
-
-            let asset0: Asset = (&pool.assets[0].info).into();
-            let asset0_price = query_asset_price(deps, env.clone(), asset0.get_reference())?;
-            let asset0_value = asset0_price * pool.assets[0].amount;
+    Ok(())
+}
 
-            let asset1: Asset = (&pool.assets[1].info).into();
-            let asset1_price = query_asset_price(deps, env, asset1.get_reference())?;
-            let asset1_value = asset1_price * pool.assets[1].amount;
+/// TWAP snapshots and EMA prices are stored per asset, so the primary and fallback price sources
+/// of an asset must not use different TWAP or EMA sources, which would read each other's records
+fn assert_single_average_sources(
+    price_source: &PriceSourceChecked,
+    fallback_price_source: Option<&PriceSourceChecked>,
+    base_asset: &Asset,
+) -> Result<(), ContractError> {
+    let mut twaps = astroport_twaps(price_source, base_asset);
+    let mut emas = astroport_emas(price_source);
+    if let Some(fallback_price_source) = fallback_price_source {
+        twaps.extend(astroport_twaps(fallback_price_source, base_asset));
+        emas.extend(astroport_emas(fallback_price_source));
+    }
 
-            let price = Decimal::from_ratio(asset0_value + asset1_value, pool.total_share);
-            Ok(price)
-        }
+    if twaps.iter().any(|twap| twap != &twaps[0]) {
+        return Err(ContractError::MultipleTwapPriceSources {});
+    }
+    if emas.iter().any(|ema| ema != &emas[0]) {
+        return Err(ContractError::MultipleEmaPriceSources {});
+    }
 
-        PriceSourceChecked::Stluna { hub_address } => {
-            let stluna_exchange_rate = query_stluna_exchange_rate(&deps.querier, &hub_address)?;
+    Ok(())
+}
 
-            let luna_asset = Asset::Native {
+/// Assets whose prices are needed to compute a price from the given price source
+fn price_source_dependencies(
+    querier: &QuerierWrapper,
+    price_source: &PriceSourceChecked,
+) -> StdResult<Vec<Asset>> {
+    let dependencies = match price_source {
+        PriceSourceChecked::Chained { quote_asset, .. } => vec![quote_asset.clone()],
+        PriceSourceChecked::RedemptionRate {
+            underlying_asset, ..
+        } => vec![underlying_asset.clone()],
+        PriceSourceChecked::Stluna { .. } | PriceSourceChecked::Lunax { .. } => {
+            vec![Asset::Native {
                 denom: "uluna".to_string(),
-            };
-            let luna_price = query_asset_price(deps, env, luna_asset.get_reference())?;
-
-            let stluna_price = stluna_exchange_rate.checked_mul(luna_price)?;
-            Ok(stluna_price)
+            }]
+        }
+        PriceSourceChecked::AstroportLiquidityToken { pair_address }
+        | PriceSourceChecked::AstroportFairLiquidityToken { pair_address } => {
+            let pool = query_astroport_pool(querier, pair_address)?;
+            pool.assets
+                .iter()
+                .map(|asset| (&asset.info).into())
+                .collect()
         }
+        PriceSourceChecked::Median { sources, .. } => {
+            let mut dependencies = vec![];
+            for source in sources {
+                dependencies.extend(price_source_dependencies(querier, source)?);
+            }
+            dependencies
+        }
+        _ => vec![],
+    };
 
-        PriceSourceChecked::Lunax { staking_address } => {
-            let lunax_exchange_rate = query_lunax_exchange_rate(&deps.querier, &staking_address)?;
+    Ok(dependencies)
+}
 
-            let luna_asset = Asset::Native {
-                denom: "uluna".to_string(),
-            };
-            let luna_price = query_asset_price(deps, env, luna_asset.get_reference())?;
+/// Errors if pricing `dependency` requires the price of `asset`, by following the price sources
+/// (primary and fallback) of the dependency and of the assets they depend on
+fn assert_no_price_source_cycle(
+    deps: Deps,
+    asset: &Asset,
+    dependency: &Asset,
+) -> Result<(), ContractError> {
+    let mut to_visit = vec![dependency.clone()];
+    let mut visited: Vec<Asset> = vec![];
+
+    while let Some(current) = to_visit.pop() {
+        if &current == asset {
+            return Err(ContractError::PriceSourceCycle {});
+        }
+        if visited.contains(&current) {
+            continue;
+        }
 
-            let lunax_price = lunax_exchange_rate.checked_mul(luna_price)?;
-            Ok(lunax_price)
+        let reference = current.get_reference();
+        if let Some(price_source) = PRICE_SOURCES.may_load(deps.storage, &reference)? {
+            to_visit.extend(price_source_dependencies(&deps.querier, &price_source)?);
         }
+        if let Some(fallback) = FALLBACK_PRICE_SOURCES.may_load(deps.storage, &reference)? {
+            to_visit.extend(price_source_dependencies(
+                &deps.querier,
+                &fallback.price_source,
+            )?);
+        }
+        visited.push(current);
     }
-}
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn migrate(_deps: DepsMut, _env: Env, _msg: MigrateMsg) -> StdResult<Response> {
-    Ok(Response::default())
+    Ok(())
 }
 
-// HELPERS
-
-mod helpers {
-    use cosmwasm_std::{
-        to_binary, Addr, QuerierWrapper, QueryRequest, StdResult, Uint128, WasmQuery,
-    };
-
-    use mars_core::asset::Asset;
-    use mars_core::math::decimal::Decimal;
-    use mars_core::oracle::AstroportTwapSnapshot;
-
-    use crate::error::ContractError;
-
-    use astroport::{
-        asset::{Asset as AstroportAsset, AssetInfo as AstroportAssetInfo},
-        pair::{
-            CumulativePricesResponse, PoolResponse, QueryMsg as AstroportQueryMsg,
-            SimulationResponse,
-        },
-    };
-    use basset::hub::{QueryMsg as BAssetQueryMsg, StateResponse as BAssetStateResponse};
-    use stader::msg::{QueryMsg as StaderQueryMsg, QueryStateResponse as StaderStateResponse};
-
-    const PROBE_AMOUNT: Uint128 = Uint128::new(1_000_000);
+/// Modified from
+/// https://github.com/Uniswap/uniswap-v2-periphery/blob/master/contracts/examples/ExampleOracleSimple.sol
+pub fn execute_record_twap_snapshots(
+    deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
+    assets: Vec<Asset>,
+) -> Result<Response, ContractError> {
+    let timestamp = env.block.time.seconds();
+    let mut attrs: Vec<Attribute> = vec![];
+    let config = CONFIG.load(deps.storage)?;
+    let base_asset = base_asset(&config.base_denom);
 
-    pub fn diff(a: u64, b: u64) -> u64 {
-        if a > b {
-            a - b
-        } else {
-            b - a
+    for asset in assets {
+        let (asset_label, asset_reference, _) = asset.get_attributes();
+        let price_source = PRICE_SOURCES.load(deps.storage, &asset_reference)?;
+        let fallback = FALLBACK_PRICE_SOURCES.may_load(deps.storage, &asset_reference)?;
+
+        // Asset must be configured to use TWAP price source, either as primary or fallback. All
+        // the TWAP sources of an asset are the same, see `assert_single_average_sources`
+        let (pair_address, window_size, tolerance, quote_asset) =
+            astroport_twaps(&price_source, &base_asset)
+                .into_iter()
+                .chain(
+                    fallback
+                        .iter()
+                        .flat_map(|fallback| astroport_twaps(&fallback.price_source, &base_asset)),
+                )
+                .next()
+                .ok_or(ContractError::PriceSourceNotTwap {})?;
+
+        // A potential attack is to repeatly call `RecordTwapSnapshots` so that the asset's snapshots
+        // take up a lot of storage, making their purge expensive. To deter this, we reject a new
+        // snapshot if the most recent snapshot is less than `tolerance` seconds ago.
+        let latest_snapshot = ASTROPORT_TWAP_SNAPSHOTS
+            .prefix(&asset_reference)
+            .range(deps.storage, None, None, Order::Descending)
+            .next()
+            .transpose()?;
+        if let Some((_, latest_snapshot)) = latest_snapshot {
+            if timestamp - latest_snapshot.timestamp < tolerance {
+                continue;
+            }
         }
-    }
 
-    /// Calculate how much the period between two TWAP snapshots deviates from the desired window size
-    pub fn period_diff(
-        snapshot1: &AstroportTwapSnapshot,
-        snapshot2: &AstroportTwapSnapshot,
-        window_size: u64,
-    ) -> u64 {
-        diff(diff(snapshot1.timestamp, snapshot2.timestamp), window_size)
-    }
+        // Query new price data
+        let price_cumulative =
+            query_astroport_cumulative_price(&deps.querier, &pair_address, &quote_asset)?;
 
-    pub fn ust() -> AstroportAssetInfo {
-        AstroportAssetInfo::NativeToken {
-            denom: "uusd".to_string(),
+        // Purge snapshots that are too old, i.e. more than (window_size + tolerance) away from the
+        // current timestamp. These snapshots will never be used in the future for calculating
+        // average prices
+        let oldest_timestamp = timestamp.saturating_sub(window_size.saturating_add(tolerance));
+        let expired_snapshots = ASTROPORT_TWAP_SNAPSHOTS
+            .prefix(&asset_reference)
+            .range(
+                deps.storage,
+                None,
+                Some(Bound::exclusive(U64Key::new(oldest_timestamp))),
+                Order::Ascending,
+            )
+            .collect::<StdResult<Vec<_>>>()?;
+        for (_, snapshot) in expired_snapshots {
+            ASTROPORT_TWAP_SNAPSHOTS.remove(
+                deps.storage,
+                (&asset_reference, U64Key::new(snapshot.timestamp)),
+            );
         }
-    }
 
-    /// Assert the astroport pair indicated by `pair_address` consists of UST and `asset`
-    pub fn assert_astroport_pool_assets(
-        querier: &QuerierWrapper,
-        asset: &Asset,
-        pair_address: &Addr,
-    ) -> Result<(), ContractError> {
-        let pool = query_astroport_pool(querier, pair_address)?;
-        let asset0: Asset = (&pool.assets[0].info).into();
-        let asset1: Asset = (&pool.assets[1].info).into();
-        let ust: Asset = (&ust()).into();
+        ASTROPORT_TWAP_SNAPSHOTS.save(
+            deps.storage,
+            (&asset_reference, U64Key::new(timestamp)),
+            &AstroportTwapSnapshot {
+                timestamp,
+                price_cumulative,
+            },
+        )?;
 
-        if (asset0 == ust && &asset1 == asset) || (asset1 == ust && &asset0 == asset) {
-            Ok(())
-        } else {
-            Err(ContractError::InvalidPair {})
-        }
+        attrs.extend(vec![
+            attr("asset", asset_label),
+            attr("price_cumulative", price_cumulative),
+        ]);
     }
 
-    pub fn query_astroport_pool(
-        querier: &QuerierWrapper,
-        pair_address: &Addr,
-    ) -> StdResult<PoolResponse> {
-        querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
-            contract_addr: pair_address.to_string(),
-            msg: to_binary(&AstroportQueryMsg::Pool {})?,
-        }))
-    }
+    Ok(Response::new()
+        .add_attribute("action", "record_twap_snapshots")
+        .add_attribute("timestamp", timestamp.to_string())
+        .add_attributes(attrs))
+}
 
-    pub fn query_astroport_spot_price(
-        querier: &QuerierWrapper,
-        pair_address: &Addr,
-    ) -> Result<Decimal, ContractError> {
-        let response: PoolResponse = querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
-            contract_addr: pair_address.to_string(),
-            msg: to_binary(&AstroportQueryMsg::Pool {})?,
-        }))?;
+pub fn execute_record_ema_prices(
+    deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
+    assets: Vec<Asset>,
+) -> Result<Response, ContractError> {
+    let timestamp = env.block.time.seconds();
+    let mut attrs: Vec<Attribute> = vec![];
+    let config = CONFIG.load(deps.storage)?;
+    let base_asset = base_asset(&config.base_denom);
 
-        // During the configuration of the price source, we have asserted that the pool indeed consists
-        // of UST and the asset of interest
-        // Here,  we use the one asset in the pool that is *not* UST as `offer_asset` to simulate the swap
-        let offer_asset_info = if response.assets[0].info == ust() {
-            response.assets[1].info.clone()
-        } else {
-            response.assets[0].info.clone()
-        };
-        let offer_asset = AstroportAsset {
-            info: offer_asset_info,
-            amount: PROBE_AMOUNT,
+    for asset in assets {
+        let (asset_label, asset_reference, _) = asset.get_attributes();
+        let price_source = PRICE_SOURCES.load(deps.storage, &asset_reference)?;
+        let fallback = FALLBACK_PRICE_SOURCES.may_load(deps.storage, &asset_reference)?;
+
+        // Asset must be configured to use EMA price source, either as primary or fallback. All
+        // the EMA sources of an asset are the same, see `assert_single_average_sources`
+        let (pair_address, half_life, max_staleness) = astroport_emas(&price_source)
+            .into_iter()
+            .chain(
+                fallback
+                    .iter()
+                    .flat_map(|fallback| astroport_emas(&fallback.price_source)),
+            )
+            .next()
+            .ok_or(ContractError::PriceSourceNotEma {})?;
+
+        // The first recorded price is the spot price itself. Afterwards, the weight of the new
+        // spot price depends on the time elapsed since the last update, so calling this more
+        // often does not move the average faster. A stale average is updated as if it were
+        // `max_staleness` old, so a late record cannot replace it with a single spot price
+        let spot_price = query_astroport_spot_price(&deps.querier, &pair_address, &base_asset)?;
+        let price = match ASTROPORT_EMA_PRICES.may_load(deps.storage, &asset_reference)? {
+            Some(ema) => {
+                let capped_timestamp = timestamp.min(ema.timestamp.saturating_add(max_staleness));
+                update_ema(&ema, spot_price, capped_timestamp, half_life)?
+            }
+            None => spot_price,
         };
 
-        let response: SimulationResponse =
-            querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
-                contract_addr: pair_address.to_string(),
-                msg: to_binary(&AstroportQueryMsg::Simulation { offer_asset })?,
-            }))?;
+        ASTROPORT_EMA_PRICES.save(
+            deps.storage,
+            &asset_reference,
+            &PriceSnapshot { price, timestamp },
+        )?;
 
-        Ok(Decimal::from_ratio(
-            response.return_amount + response.commission_amount,
-            PROBE_AMOUNT,
-        ))
+        attrs.extend(vec![
+            attr("asset", asset_label),
+            attr("ema_price", price.to_string()),
+        ]);
     }
 
-    pub fn query_astroport_cumulative_price(
-        querier: &QuerierWrapper,
-        pair_address: &Addr,
-    ) -> StdResult<Uint128> {
-        let response: CumulativePricesResponse =
-            querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
-                contract_addr: pair_address.to_string(),
-                msg: to_binary(&AstroportQueryMsg::CumulativePrices {})?,
-            }))?;
+    Ok(Response::new()
+        .add_attribute("action", "record_ema_prices")
+        .add_attribute("timestamp", timestamp.to_string())
+        .add_attributes(attrs))
+}
 
-        // during the configuration of the price source, we have asserted that the pool indeed consists
-        // of UST and the asset of interest.
-        // Here, we return cumulative price of the one asset in the pool that is *not* UST
-        let price_cumulative = if response.assets[0].info == ust() {
-            response.price1_cumulative_last
-        } else {
-            response.price0_cumulative_last
-        };
-        Ok(price_cumulative)
-    }
+// QUERIES
 
-    pub fn query_stluna_exchange_rate(
-        querier: &QuerierWrapper,
-        hub_address: &Addr,
-    ) -> StdResult<Decimal> {
-        let response: BAssetStateResponse =
-            querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
-                contract_addr: hub_address.to_string(),
-                msg: to_binary(&BAssetQueryMsg::State {})?,
-            }))?;
-        Ok(response.stluna_exchange_rate.into())
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::Config {} => to_binary(&query_config(deps, env)?),
+        QueryMsg::AssetPriceSource { asset } => {
+            to_binary(&query_asset_price_source(deps, env, asset)?)
+        }
+        QueryMsg::AssetFallbackPriceSource { asset } => {
+            to_binary(&FALLBACK_PRICE_SOURCES.may_load(deps.storage, &asset.get_reference())?)
+        }
+        QueryMsg::AssetCircuitBreaker { asset } => {
+            to_binary(&query_asset_circuit_breaker(deps, env, asset)?)
+        }
+        QueryMsg::AssetFeedPrices { asset } => {
+            to_binary(&query_asset_feed_prices(deps, env, asset)?)
+        }
+        QueryMsg::AssetPrice { asset } => {
+            assert_not_paused(deps)?;
+            to_binary(&query_asset_price(deps, env, asset.get_reference())?)
+        }
+        QueryMsg::AssetPriceByReference { asset_reference } => {
+            assert_not_paused(deps)?;
+            to_binary(&query_asset_price(deps, env, asset_reference)?)
+        }
+        QueryMsg::AssetPriceWithMetadata { asset } => {
+            assert_not_paused(deps)?;
+            to_binary(&query_asset_price_with_metadata(
+                deps,
+                env,
+                asset.get_reference(),
+            )?)
+        }
+        QueryMsg::AssetPriceWithMetadataByReference { asset_reference } => {
+            assert_not_paused(deps)?;
+            to_binary(&query_asset_price_with_metadata(
+                deps,
+                env,
+                asset_reference,
+            )?)
+        }
     }
+}
 
-    pub fn query_lunax_exchange_rate(
-        querier: &QuerierWrapper,
-        staking_address: &Addr,
-    ) -> StdResult<Decimal> {
-        let response: StaderStateResponse =
-            querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
-                contract_addr: staking_address.to_string(),
-                msg: to_binary(&StaderQueryMsg::State {})?,
-            }))?;
-        Ok(response.state.exchange_rate.into())
+fn query_config(deps: Deps, _env: Env) -> StdResult<Config> {
+    CONFIG.load(deps.storage)
+}
+
+fn assert_not_paused(deps: Deps) -> Result<(), ContractError> {
+    if CONFIG.load(deps.storage)?.paused {
+        return Err(MarsError::Paused {}.into());
     }
+    Ok(())
 }
 
-// TESTS
+fn query_asset_price_source(deps: Deps, _env: Env, asset: Asset) -> StdResult<PriceSourceChecked> {
+    PRICE_SOURCES.load(deps.storage, &asset.get_reference())
+}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use astroport::asset::{Asset as AstroportAsset, AssetInfo, PairInfo};
-    use astroport::factory::PairType;
-    use astroport::pair::{CumulativePricesResponse, SimulationResponse};
-    use basset::hub::StateResponse;
-    use cosmwasm_std::testing::{mock_env, mock_info, MockApi, MockStorage};
-    use cosmwasm_std::Decimal as StdDecimal;
-    use cosmwasm_std::{from_binary, Addr, OwnedDeps};
-    use mars_core::testing::{mock_dependencies, mock_env_at_block_time, MarsMockQuerier};
-    use stader::msg::QueryStateResponse as StaderStateResponse;
-    use stader::state::State as StaderState;
+fn query_asset_circuit_breaker(
+    deps: Deps,
+    _env: Env,
+    asset: Asset,
+) -> StdResult<Option<CircuitBreakerResponse>> {
+    let asset_reference = asset.get_reference();
+    let circuit_breaker = match CIRCUIT_BREAKERS.may_load(deps.storage, &asset_reference)? {
+        Some(circuit_breaker) => circuit_breaker,
+        None => return Ok(None),
+    };
+    let state = CIRCUIT_BREAKER_STATES
+        .may_load(deps.storage, &asset_reference)?
+        .unwrap_or_default();
+
+    Ok(Some(CircuitBreakerResponse {
+        circuit_breaker,
+        state,
+    }))
+}
 
-    #[test]
-    fn test_proper_initialization() {
-        let mut deps = mock_dependencies(&[]);
+fn query_asset_feed_prices(
+    deps: Deps,
+    _env: Env,
+    asset: Asset,
+) -> StdResult<Vec<FeederPriceResponse>> {
+    FEED_PRICES
+        .prefix(&asset.get_reference())
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (feeder, feed_price) = item?;
+            Ok(FeederPriceResponse {
+                feeder: Addr::unchecked(String::from_utf8(feeder)?),
+                price: feed_price.price,
+                timestamp: feed_price.timestamp,
+            })
+        })
+        .collect()
+}
 
-        let msg = InstantiateMsg {
-            owner: String::from("owner"),
-        };
-        let info = mock_info("owner", &[]);
+fn query_asset_price(
+    deps: Deps,
+    env: Env,
+    asset_reference: Vec<u8>,
+) -> Result<Decimal, ContractError> {
+    Ok(query_asset_price_with_metadata(deps, env, asset_reference)?.price)
+}
 
-        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
+/// Price of the asset, erroring if its circuit breaker is tripped or would trip at this price
+fn query_asset_price_with_metadata(
+    deps: Deps,
+    env: Env,
+    asset_reference: Vec<u8>,
+) -> Result<AssetPriceWithMetadataResponse, ContractError> {
+    let circuit_breaker = CIRCUIT_BREAKERS.may_load(deps.storage, &asset_reference)?;
+    let state = CIRCUIT_BREAKER_STATES
+        .may_load(deps.storage, &asset_reference)?
+        .unwrap_or_default();
+    if state.tripped {
+        return Err(ContractError::CircuitBreakerTripped {});
+    }
+
+    let price = query_asset_price_ignoring_circuit_breaker(deps, env, &asset_reference)?;
+
+    if let Some(circuit_breaker) = circuit_breaker {
+        assert_circuit_breaker_not_breached(&circuit_breaker, &state, price.price)?;
+    }
+
+    Ok(price)
+}
+
+/// Price from the asset's price source. If the asset has a fallback price source, it is used when
+/// the primary one errors; when both return a price, they must not deviate more than allowed
+fn query_asset_price_ignoring_circuit_breaker(
+    deps: Deps,
+    env: Env,
+    asset_reference: &[u8],
+) -> Result<AssetPriceWithMetadataResponse, ContractError> {
+    let max_age = PRICE_MAX_AGES.may_load(deps.storage, asset_reference)?;
+    let price_source = PRICE_SOURCES.load(deps.storage, asset_reference)?;
+    let primary_price_source = price_source.to_string();
+    let primary_price = query_price_source(deps, env.clone(), asset_reference, price_source);
+
+    let fallback = match FALLBACK_PRICE_SOURCES.may_load(deps.storage, asset_reference)? {
+        Some(fallback) => fallback,
+        None => {
+            let (price, timestamp) = primary_price?;
+            return Ok(AssetPriceWithMetadataResponse {
+                price,
+                timestamp,
+                price_source: primary_price_source,
+                is_fallback: false,
+                max_age,
+            });
+        }
+    };
+    let fallback_price_source = fallback.price_source.to_string();
+    let fallback_price = query_price_source(deps, env, asset_reference, fallback.price_source);
+
+    let ((price, timestamp), is_fallback) = match (primary_price, fallback_price) {
+        (Ok(primary_price), Ok(fallback_price)) => {
+            assert_max_deviation(primary_price.0, fallback_price.0, fallback.max_deviation)?;
+            (primary_price, false)
+        }
+        (Ok(primary_price), Err(_)) => (primary_price, false),
+        (Err(_), Ok(fallback_price)) => (fallback_price, true),
+        (Err(err), Err(_)) => return Err(err),
+    };
+
+    Ok(AssetPriceWithMetadataResponse {
+        price,
+        timestamp,
+        price_source: if is_fallback {
+            fallback_price_source
+        } else {
+            primary_price_source
+        },
+        is_fallback,
+        max_age,
+    })
+}
+
+/// Price from the given price source along with the timestamp of the oldest data it was
+/// computed from: the TWAP snapshot the average starts at, the last recorded EMA price or the
+/// pushed feed prices. Prices read live from chain state are timestamped with the current block
+fn query_price_source(
+    deps: Deps,
+    env: Env,
+    asset_reference: &[u8],
+    price_source: PriceSourceChecked,
+) -> Result<(Decimal, u64), ContractError> {
+    let base_denom = CONFIG.load(deps.storage)?.base_denom;
+    let base_asset = base_asset(&base_denom);
+    let current_timestamp = env.block.time.seconds();
+
+    match price_source {
+        PriceSourceChecked::Fixed { price } => Ok((price, current_timestamp)),
+
+        PriceSourceChecked::Native { denom } => {
+            let price = query_native_price(deps, denom, base_denom)?;
+            Ok((price, current_timestamp))
+        }
+
+        // NOTE: Spot price is defined as the amount of the base denom to be returned when swapping
+        // `PROBE_AMOUNT`
+        // of the asset of interest, divided by `PROBE_AMOUNT`. In the current implementation,
+        // `PROBE_AMOUNT` is set to 1,000,000.
+        //
+        // For example, for MARS-UST pair, if swapping 1,000,000 umars returns 1,200,000 uusd (return
+        // amount plus commission), then 1 MARS = 1.2 UST.
+        //
+        // Why not just take the quotient of the two assets depths? (E.g. if the pool has 120 UST and
+        // 100 MARS, then 1 MARS = 1.2 UST) Because this only works for XYK pools, not StableSwap pools.
+        PriceSourceChecked::AstroportSpot { pair_address } => {
+            let price = query_astroport_spot_price(&deps.querier, &pair_address, &base_asset)?;
+            Ok((price, current_timestamp))
+        }
+
+        PriceSourceChecked::AstroportTwap {
+            pair_address,
+            window_size,
+            tolerance,
+        } => query_astroport_twap_price(
+            deps,
+            asset_reference,
+            &pair_address,
+            window_size,
+            tolerance,
+            &base_asset,
+            current_timestamp,
+        ),
+
+        PriceSourceChecked::AstroportEma {
+            pair_address,
+            half_life,
+            max_staleness,
+        } => {
+            let ema = ASTROPORT_EMA_PRICES
+                .may_load(deps.storage, asset_reference)?
+                .ok_or(ContractError::EmaPriceNotRecorded {})?;
+            if current_timestamp.saturating_sub(ema.timestamp) > max_staleness {
+                return Err(ContractError::EmaPriceStale {});
+            }
+            let spot_price = query_astroport_spot_price(&deps.querier, &pair_address, &base_asset)?;
+
+            let price = update_ema(&ema, spot_price, current_timestamp, half_life)?;
+            Ok((price, ema.timestamp))
+        }
+
+        // The value of each unit of the liquidity token is the total value of pool's two assets
+        // divided by the liquidity token's total supply
+        //
+        // NOTE: Price sources must exist for both assets in the pool
+        PriceSourceChecked::AstroportLiquidityToken { pair_address } => {
+            let pool = query_astroport_pool(&deps.querier, &pair_address)?;
+
+            let asset0: Asset = (&pool.assets[0].info).into();
+            let asset0_price =
+                query_asset_price_with_metadata(deps, env.clone(), asset0.get_reference())?;
+            let asset0_value = asset0_price.price * pool.assets[0].amount;
+
+            let asset1: Asset = (&pool.assets[1].info).into();
+            let asset1_price = query_asset_price_with_metadata(deps, env, asset1.get_reference())?;
+            let asset1_value = asset1_price.price * pool.assets[1].amount;
+
+            let price = Decimal::from_ratio(asset0_value + asset1_value, pool.total_share);
+            Ok((price, asset0_price.timestamp.min(asset1_price.timestamp)))
+        }
+
+        PriceSourceChecked::AstroportFairLiquidityToken { pair_address } => {
+            let pair_info = query_astroport_pair_info(&deps.querier, &pair_address)?;
+            let pool = query_astroport_pool(&deps.querier, &pair_address)?;
+
+            let asset0: Asset = (&pool.assets[0].info).into();
+            let asset0_price =
+                query_asset_price_with_metadata(deps, env.clone(), asset0.get_reference())?;
+
+            let asset1: Asset = (&pool.assets[1].info).into();
+            let asset1_price = query_asset_price_with_metadata(deps, env, asset1.get_reference())?;
+
+            let pool_value = match pair_info.pair_type {
+                // The reserves that minimize the pool value for the oracle prices while keeping
+                // the invariant `k = reserve0 * reserve1` are `sqrt(k * p1 / p0)` and
+                // `sqrt(k * p0 / p1)`, both worth `sqrt(k * p0 * p1)`. Since
+                // `k * p0 * p1 = value0 * value1`, the pool is worth `2 * sqrt(value0 * value1)`
+                PairType::Xyk {} => {
+                    let asset0_value = asset0_price.price * pool.assets[0].amount;
+                    let asset1_value = asset1_price.price * pool.assets[1].amount;
+                    let pool_value = uint256_sqrt(
+                        Uint256::from(asset0_value).checked_mul(Uint256::from(asset1_value))?,
+                    ) * Uint256::from(2u8);
+                    Uint128::try_from(pool_value)?
+                }
+                // `D` is the amount of assets the pool would hold if balanced, which swaps can
+                // only increase (through fees). Each unit is valued at the lowest of the two
+                // prices, as the pool could end up holding only the cheaper asset
+                PairType::Stable {} => {
+                    let amp = query_astroport_stable_amp(&deps.querier, &pair_address)?;
+                    let d = compute_stableswap_invariant(
+                        amp,
+                        pool.assets[0].amount,
+                        pool.assets[1].amount,
+                    )?;
+                    d * asset0_price.price.min(asset1_price.price)
+                }
+                pair_type => {
+                    return Err(ContractError::UnsupportedPairType {
+                        pair_type: pair_type.to_string(),
+                    })
+                }
+            };
+
+            let price = Decimal::from_ratio(pool_value, pool.total_share);
+            Ok((price, asset0_price.timestamp.min(asset1_price.timestamp)))
+        }
+
+        PriceSourceChecked::Stluna { hub_address } => {
+            let stluna_exchange_rate = query_stluna_exchange_rate(&deps.querier, &hub_address)?;
+
+            let luna_asset = Asset::Native {
+                denom: "uluna".to_string(),
+            };
+            let luna_price =
+                query_asset_price_with_metadata(deps, env, luna_asset.get_reference())?;
+
+            let stluna_price = stluna_exchange_rate.checked_mul(luna_price.price)?;
+            Ok((stluna_price, luna_price.timestamp))
+        }
+
+        PriceSourceChecked::Lunax { staking_address } => {
+            let lunax_exchange_rate = query_lunax_exchange_rate(&deps.querier, &staking_address)?;
+
+            let luna_asset = Asset::Native {
+                denom: "uluna".to_string(),
+            };
+            let luna_price =
+                query_asset_price_with_metadata(deps, env, luna_asset.get_reference())?;
+
+            let lunax_price = lunax_exchange_rate.checked_mul(luna_price.price)?;
+            Ok((lunax_price, luna_price.timestamp))
+        }
+
+        PriceSourceChecked::RedemptionRate {
+            contract,
+            query_msg_template,
+            response_path,
+            underlying_asset,
+        } => {
+            let redemption_rate = query_redemption_rate(
+                &deps.querier,
+                &contract,
+                &query_msg_template,
+                &response_path,
+            )?;
+
+            let underlying_price =
+                query_asset_price_with_metadata(deps, env, underlying_asset.get_reference())?;
+
+            let price = redemption_rate.checked_mul(underlying_price.price)?;
+            Ok((price, underlying_price.timestamp))
+        }
+
+        PriceSourceChecked::Feed {
+            feeders,
+            min_feeders,
+            max_age,
+        } => {
+            let mut prices = vec![];
+            let mut oldest_timestamp = current_timestamp;
+            for feeder in &feeders {
+                if let Some(feed_price) =
+                    FEED_PRICES.may_load(deps.storage, (asset_reference, feeder))?
+                {
+                    if current_timestamp - feed_price.timestamp <= max_age {
+                        prices.push(feed_price.price);
+                        oldest_timestamp = oldest_timestamp.min(feed_price.timestamp);
+                    }
+                }
+            }
+
+            if prices.len() < min_feeders as usize {
+                return Err(ContractError::NotEnoughFeedPrices {
+                    fresh: prices.len() as u32,
+                    min_feeders,
+                });
+            }
+
+            Ok((median(&mut prices), oldest_timestamp))
+        }
+
+        PriceSourceChecked::Chained {
+            source,
+            quote_asset,
+        } => {
+            let (price_in_quote_asset, timestamp) = match *source {
+                PriceSourceChecked::AstroportSpot { pair_address } => (
+                    query_astroport_spot_price(&deps.querier, &pair_address, &quote_asset)?,
+                    current_timestamp,
+                ),
+                PriceSourceChecked::AstroportTwap {
+                    pair_address,
+                    window_size,
+                    tolerance,
+                } => query_astroport_twap_price(
+                    deps,
+                    asset_reference,
+                    &pair_address,
+                    window_size,
+                    tolerance,
+                    &quote_asset,
+                    current_timestamp,
+                )?,
+                _ => return Err(ContractError::InvalidChainedPriceSource {}),
+            };
+
+            let quote_asset_price =
+                query_asset_price_with_metadata(deps, env, quote_asset.get_reference())?;
+
+            let price = price_in_quote_asset.checked_mul(quote_asset_price.price)?;
+            Ok((price, timestamp.min(quote_asset_price.timestamp)))
+        }
+
+        PriceSourceChecked::Median {
+            sources,
+            min_sources,
+        } => {
+            let (mut prices, timestamps): (Vec<Decimal>, Vec<u64>) = sources
+                .into_iter()
+                .filter_map(|source| {
+                    query_price_source(deps, env.clone(), asset_reference, source).ok()
+                })
+                .unzip();
+
+            if prices.len() < min_sources as usize {
+                return Err(ContractError::NotEnoughPriceSources {
+                    succeeded: prices.len() as u32,
+                    min_sources,
+                });
+            }
+
+            let oldest_timestamp = timestamps.into_iter().min().unwrap_or(current_timestamp);
+            Ok((median(&mut prices), oldest_timestamp))
+        }
+    }
+}
+
+/// Average price of the asset quoted in `quote_asset` over `window_size` seconds (+/- `tolerance`),
+/// computed from the recorded TWAP snapshots, along with the timestamp of the snapshot the
+/// average starts at
+fn query_astroport_twap_price(
+    deps: Deps,
+    asset_reference: &[u8],
+    pair_address: &Addr,
+    window_size: u64,
+    tolerance: u64,
+    quote_asset: &Asset,
+    current_timestamp: u64,
+) -> Result<(Decimal, u64), ContractError> {
+    // Find the oldest snapshot whose period from the current timestamp is within the tolerable
+    // window, i.e. taken between (window_size + tolerance) and (window_size - tolerance) seconds
+    // ago. Snapshots are keyed by timestamp, so this is a single range lookup; otherwise throw error
+    let min_timestamp = current_timestamp.saturating_sub(window_size.saturating_add(tolerance));
+    let max_timestamp = current_timestamp.saturating_sub(window_size.saturating_sub(tolerance));
+    let (_, previous_snapshot) = ASTROPORT_TWAP_SNAPSHOTS
+        .prefix(asset_reference)
+        .range(
+            deps.storage,
+            Some(Bound::inclusive(U64Key::new(min_timestamp))),
+            Some(Bound::inclusive(U64Key::new(max_timestamp))),
+            Order::Ascending,
+        )
+        .next()
+        .transpose()?
+        .ok_or(ContractError::NoSnapshotWithinTolerance {})?;
+
+    // Then, query the current TWAP snapshot
+    let current_snapshot = AstroportTwapSnapshot {
+        timestamp: current_timestamp,
+        price_cumulative: query_astroport_cumulative_price(
+            &deps.querier,
+            pair_address,
+            quote_asset,
+        )?,
+    };
+
+    // Handle the case if Astroport's cumulative price overflows. In this case, cumulative
+    // price warps back to zero, resulting in more recent cum. prices being smaller than
+    // earlier ones. (same behavior as in Solidity)
+    //
+    // Calculations below assumes the cumulative price doesn't overflows more than once during
+    // the period, which should always be the case in practice
+    let price_delta = if current_snapshot.price_cumulative >= previous_snapshot.price_cumulative {
+        current_snapshot.price_cumulative - previous_snapshot.price_cumulative
+    } else {
+        current_snapshot
+            .price_cumulative
+            .checked_add(Uint128::MAX - previous_snapshot.price_cumulative)?
+    };
+    let period = current_snapshot.timestamp - previous_snapshot.timestamp;
+    // NOTE: Astroport introduces TWAP precision (https://github.com/astroport-fi/astroport/pull/143).
+    // We need to divide the result by price_precision: (price_delta / (time * price_precision)).
+    let price_precision = Uint128::from(10_u128.pow(TWAP_PRECISION.into()));
+    let price = Decimal::from_ratio(price_delta, price_precision.checked_mul(period.into())?);
+
+    Ok((price, previous_snapshot.timestamp))
+}
+
+#[cfg(feature = "terra")]
+fn query_native_price(
+    deps: Deps,
+    denom: String,
+    base_denom: String,
+) -> Result<Decimal, ContractError> {
+    let terra_querier = terra_cosmwasm::TerraQuerier::new(&deps.querier);
+
+    // NOTE: Exchange rate returns how much of the quote (second argument) is required to
+    // buy one unit of the base_denom (first argument).
+    // We want to know how much of the base denom we need to buy 1 of the target currency
+    let asset_prices_query = terra_querier
+        .query_exchange_rates(denom, vec![base_denom])?
+        .exchange_rates
+        .pop();
+
+    match asset_prices_query {
+        Some(exchange_rate_item) => Ok(exchange_rate_item.exchange_rate.into()),
+        None => Err(ContractError::NativePriceNotFound {}),
+    }
+}
+
+#[cfg(not(feature = "terra"))]
+fn query_native_price(
+    _deps: Deps,
+    _denom: String,
+    _base_denom: String,
+) -> Result<Decimal, ContractError> {
+    Err(ContractError::NativePriceSourceNotSupported {})
+}
+
+// MIGRATION
+
+/// State migrations, run when migrating from a version older than theirs
+const MIGRATION_STEPS: &[MigrationStep] = &[MigrationStep {
+    version: Version::new(1, 1, 0),
+    migrate: migrate_v1_1_0,
+}];
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> StdResult<Response> {
+    let attributes = migrate_contract(
+        deps.storage,
+        CONTRACT_NAME,
+        CONTRACT_VERSION,
+        MIGRATION_STEPS,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attributes(attributes))
+}
+
+/// Moves TWAP snapshots to one entry per (asset, timestamp) and stores the fields added to the
+/// config in 1.1.0 (no address provider, not paused, uusd base denom)
+fn migrate_v1_1_0(storage: &mut dyn Storage) -> StdResult<()> {
+    migrate_twap_snapshots(storage)?;
+
+    let config = CONFIG.load(storage)?;
+    CONFIG.save(storage, &config)
+}
+
+/// Moves TWAP snapshots stored as one vector per asset to one entry per (asset, timestamp). The
+/// legacy vectors are removed once moved, so running it again does nothing
+fn migrate_twap_snapshots(storage: &mut dyn Storage) -> StdResult<()> {
+    let legacy_snapshots = LEGACY_ASTROPORT_TWAP_SNAPSHOTS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    for (asset_reference, snapshots) in legacy_snapshots {
+        for snapshot in snapshots {
+            ASTROPORT_TWAP_SNAPSHOTS.save(
+                storage,
+                (&asset_reference, U64Key::new(snapshot.timestamp)),
+                &snapshot,
+            )?;
+        }
+        LEGACY_ASTROPORT_TWAP_SNAPSHOTS.remove(storage, &asset_reference);
+    }
+
+    Ok(())
+}
+
+// HELPERS
+
+mod helpers {
+    use cosmwasm_std::{
+        from_binary, to_binary, to_vec, Addr, Binary, ContractResult, Empty, QuerierWrapper,
+        QueryRequest, StdError, StdResult, SystemResult, Uint128, Uint256, WasmQuery,
+    };
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+
+    use mars_core::asset::Asset;
+    use mars_core::math::decimal::Decimal;
+    use mars_core::oracle::{CircuitBreaker, CircuitBreakerState, PriceSnapshot};
+
+    use crate::error::ContractError;
+    use crate::json::extract_json_value;
+
+    use astroport::{
+        asset::{Asset as AstroportAsset, PairInfo},
+        pair::{
+            ConfigResponse as AstroportConfigResponse, CumulativePricesResponse, PoolResponse,
+            QueryMsg as AstroportQueryMsg, SimulationResponse, StablePoolConfig,
+        },
+    };
+    use basset::hub::{QueryMsg as BAssetQueryMsg, StateResponse as BAssetStateResponse};
+    use stader::msg::{QueryMsg as StaderQueryMsg, QueryStateResponse as StaderStateResponse};
+
+    const PROBE_AMOUNT: Uint128 = Uint128::new(1_000_000);
+
+    /// Fails if `fallback_price` deviates from `primary_price` more than `max_deviation` (relative
+    /// to `primary_price`)
+    pub fn assert_max_deviation(
+        primary_price: Decimal,
+        fallback_price: Decimal,
+        max_deviation: Decimal,
+    ) -> Result<(), ContractError> {
+        let abs_diff = if primary_price > fallback_price {
+            primary_price - fallback_price
+        } else {
+            fallback_price - primary_price
+        };
+
+        if abs_diff > primary_price.checked_mul(max_deviation)? {
+            return Err(ContractError::PriceSourcesDeviation {
+                primary_price,
+                fallback_price,
+                max_deviation,
+            });
+        }
+        Ok(())
+    }
+
+    /// Errors if the price is outside the circuit breaker bounds, or changed more than allowed from
+    /// the last recorded price
+    pub fn assert_circuit_breaker_not_breached(
+        circuit_breaker: &CircuitBreaker,
+        state: &CircuitBreakerState,
+        price: Decimal,
+    ) -> Result<(), ContractError> {
+        if let Some(min_price) = circuit_breaker.min_price {
+            if price < min_price {
+                return Err(ContractError::PriceBelowMin { price, min_price });
+            }
+        }
+
+        if let Some(max_price) = circuit_breaker.max_price {
+            if price > max_price {
+                return Err(ContractError::PriceAboveMax { price, max_price });
+            }
+        }
+
+        if let (Some(max_change), Some(last_price)) =
+            (circuit_breaker.max_change, &state.last_price)
+        {
+            let last_price = last_price.price;
+            let abs_diff = if price > last_price {
+                price - last_price
+            } else {
+                last_price - price
+            };
+
+            if abs_diff > last_price.checked_mul(max_change)? {
+                return Err(ContractError::PriceChangeExceeded {
+                    price,
+                    last_price,
+                    max_change,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Median of a non empty list of prices (average of the two middle ones for an even count)
+    pub fn median(prices: &mut [Decimal]) -> Decimal {
+        prices.sort();
+        let mid = prices.len() / 2;
+        if prices.len() % 2 == 1 {
+            prices[mid]
+        } else {
+            (prices[mid - 1] + prices[mid]) / Uint128::new(2)
+        }
+    }
+
+    /// Moving average `ema` updated at `timestamp` with the spot price: the average keeps a weight
+    /// of `2^(-elapsed / half_life)`, the spot price gets the rest
+    pub fn update_ema(
+        ema: &PriceSnapshot,
+        spot_price: Decimal,
+        timestamp: u64,
+        half_life: u64,
+    ) -> StdResult<Decimal> {
+        let ema_weight = ema_decay(timestamp.saturating_sub(ema.timestamp), half_life)?;
+        Ok(ema.price.checked_mul(ema_weight)?
+            + spot_price.checked_mul(Decimal::one() - ema_weight)?)
+    }
+
+    /// `2^(-elapsed / half_life)`: the integer part of the exponent halves the result, the
+    /// fractional part `r` is computed as `e^(-r * ln(2))` from its Taylor series
+    pub fn ema_decay(elapsed: u64, half_life: u64) -> StdResult<Decimal> {
+        // 2^-60 is already below the decimal precision
+        let halvings = elapsed / half_life;
+        if halvings >= 60 {
+            return Ok(Decimal::zero());
+        }
+
+        let ln_2 = Decimal::from_ratio(693_147_180_559_945_309u128, 10u128.pow(18));
+        let x = Decimal::from_ratio(elapsed % half_life, half_life).checked_mul(ln_2)?;
+
+        // the terms alternate in sign, so positive and negative ones are summed separately
+        let mut term = Decimal::one();
+        let mut positive_sum = Decimal::one();
+        let mut negative_sum = Decimal::zero();
+        for k in 1..=20u128 {
+            term = term.checked_mul(x)? / Uint128::new(k);
+            if term.is_zero() {
+                break;
+            }
+            if k % 2 == 1 {
+                negative_sum = negative_sum + term;
+            } else {
+                positive_sum = positive_sum + term;
+            }
+        }
+
+        Ok((positive_sum - negative_sum) / Uint128::new(1u128 << halvings))
+    }
+
+    pub fn base_asset(base_denom: &str) -> Asset {
+        Asset::Native {
+            denom: base_denom.to_string(),
+        }
+    }
+
+    /// Assert the astroport pair indicated by `pair_address` consists of `quote_asset` and `asset`
+    pub fn assert_astroport_pool_assets(
+        querier: &QuerierWrapper,
+        asset: &Asset,
+        pair_address: &Addr,
+        quote_asset: &Asset,
+    ) -> Result<(), ContractError> {
+        let pool = query_astroport_pool(querier, pair_address)?;
+        let asset0: Asset = (&pool.assets[0].info).into();
+        let asset1: Asset = (&pool.assets[1].info).into();
+
+        if (&asset0 == quote_asset && &asset1 == asset)
+            || (&asset1 == quote_asset && &asset0 == asset)
+        {
+            Ok(())
+        } else {
+            Err(ContractError::InvalidPair {})
+        }
+    }
+
+    pub fn query_astroport_pool(
+        querier: &QuerierWrapper,
+        pair_address: &Addr,
+    ) -> StdResult<PoolResponse> {
+        querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: pair_address.to_string(),
+            msg: to_binary(&AstroportQueryMsg::Pool {})?,
+        }))
+    }
+
+    pub fn query_astroport_pair_info(
+        querier: &QuerierWrapper,
+        pair_address: &Addr,
+    ) -> StdResult<PairInfo> {
+        querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: pair_address.to_string(),
+            msg: to_binary(&AstroportQueryMsg::Pair {})?,
+        }))
+    }
+
+    /// Amplification coefficient of an Astroport StableSwap pair
+    pub fn query_astroport_stable_amp(
+        querier: &QuerierWrapper,
+        pair_address: &Addr,
+    ) -> StdResult<Uint128> {
+        let config: AstroportConfigResponse =
+            querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr: pair_address.to_string(),
+                msg: to_binary(&AstroportQueryMsg::Config {})?,
+            }))?;
+        let params = config
+            .params
+            .ok_or_else(|| StdError::generic_err("StableSwap pair has no params"))?;
+        let stable_config: StablePoolConfig = from_binary(&params)?;
+        Ok(Uint128::new(1) * stable_config.amp)
+    }
+
+    /// StableSwap invariant `D` of a two assets pool, computed with Newton's method as in
+    /// Curve's `get_D` (with `amp` the amplification coefficient, i.e. `Ann / n`)
+    pub fn compute_stableswap_invariant(
+        amp: Uint128,
+        amount0: Uint128,
+        amount1: Uint128,
+    ) -> Result<Uint128, ContractError> {
+        const ITERATIONS: u8 = 64;
+        let n_coins = Uint256::from(2u8);
+
+        let amount0 = Uint256::from(amount0);
+        let amount1 = Uint256::from(amount1);
+        let sum = amount0 + amount1;
+        if amount0.is_zero() || amount1.is_zero() {
+            return Ok(Uint128::try_from(sum)?);
+        }
+
+        let leverage = Uint256::from(amp) * n_coins;
+        let mut d = sum;
+        for _ in 0..ITERATIONS {
+            // d_p = d^3 / (n^n * amount0 * amount1)
+            let d_p = d.checked_mul(d)? / (amount0 * n_coins);
+            let d_p = d_p.checked_mul(d)? / (amount1 * n_coins);
+            let d_previous = d;
+            d = (leverage.checked_mul(sum)? + d_p * n_coins).checked_mul(d)?
+                / (leverage.checked_sub(Uint256::from(1u8))? * d
+                    + (n_coins + Uint256::from(1u8)) * d_p);
+
+            let diff = if d > d_previous {
+                d - d_previous
+            } else {
+                d_previous - d
+            };
+            if diff <= Uint256::from(1u8) {
+                return Ok(Uint128::try_from(d)?);
+            }
+        }
+
+        Err(ContractError::StableSwapInvariantNotConverged {})
+    }
+
+    pub fn query_astroport_spot_price(
+        querier: &QuerierWrapper,
+        pair_address: &Addr,
+        quote_asset: &Asset,
+    ) -> Result<Decimal, ContractError> {
+        let response: PoolResponse = querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: pair_address.to_string(),
+            msg: to_binary(&AstroportQueryMsg::Pool {})?,
+        }))?;
+
+        // During the configuration of the price source, we have asserted that the pool indeed consists
+        // of the quote asset and the asset of interest
+        // Here,  we use the one asset in the pool that is *not* the quote asset as `offer_asset` to
+        // simulate the swap
+        let offer_asset_info = if &Asset::from(&response.assets[0].info) == quote_asset {
+            response.assets[1].info.clone()
+        } else {
+            response.assets[0].info.clone()
+        };
+        let offer_asset = AstroportAsset {
+            info: offer_asset_info,
+            amount: PROBE_AMOUNT,
+        };
+
+        let response: SimulationResponse =
+            querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr: pair_address.to_string(),
+                msg: to_binary(&AstroportQueryMsg::Simulation { offer_asset })?,
+            }))?;
+
+        Ok(Decimal::from_ratio(
+            response.return_amount + response.commission_amount,
+            PROBE_AMOUNT,
+        ))
+    }
+
+    pub fn query_astroport_cumulative_price(
+        querier: &QuerierWrapper,
+        pair_address: &Addr,
+        quote_asset: &Asset,
+    ) -> StdResult<Uint128> {
+        let response: CumulativePricesResponse =
+            querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr: pair_address.to_string(),
+                msg: to_binary(&AstroportQueryMsg::CumulativePrices {})?,
+            }))?;
+
+        // during the configuration of the price source, we have asserted that the pool indeed consists
+        // of the quote asset and the asset of interest.
+        // Here, we return cumulative price of the one asset in the pool that is *not* the quote asset
+        let price_cumulative = if &Asset::from(&response.assets[0].info) == quote_asset {
+            response.price1_cumulative_last
+        } else {
+            response.price0_cumulative_last
+        };
+        Ok(price_cumulative)
+    }
+
+    pub fn query_stluna_exchange_rate(
+        querier: &QuerierWrapper,
+        hub_address: &Addr,
+    ) -> StdResult<Decimal> {
+        let response: BAssetStateResponse =
+            querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr: hub_address.to_string(),
+                msg: to_binary(&BAssetQueryMsg::State {})?,
+            }))?;
+        Ok(response.stluna_exchange_rate.into())
+    }
+
+    /// Send a query message to any contract and read the exchange rate at `response_path` in the
+    /// JSON response
+    pub fn query_redemption_rate(
+        querier: &QuerierWrapper,
+        contract: &Addr,
+        query_msg: &Binary,
+        response_path: &[String],
+    ) -> StdResult<Decimal> {
+        let request: QueryRequest<Empty> = QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: contract.to_string(),
+            msg: query_msg.clone(),
+        });
+        let response = match querier.raw_query(&to_vec(&request)?) {
+            SystemResult::Err(system_err) => {
+                return Err(StdError::generic_err(format!(
+                    "Querier system error: {}",
+                    system_err
+                )))
+            }
+            SystemResult::Ok(ContractResult::Err(contract_err)) => {
+                return Err(StdError::generic_err(format!(
+                    "Querier contract error: {}",
+                    contract_err
+                )))
+            }
+            SystemResult::Ok(ContractResult::Ok(response)) => response,
+        };
+
+        let redemption_rate = extract_json_value(response.as_slice(), response_path)?;
+        Decimal::from_str(&redemption_rate)
+    }
+
+    pub fn query_lunax_exchange_rate(
+        querier: &QuerierWrapper,
+        staking_address: &Addr,
+    ) -> StdResult<Decimal> {
+        let response: StaderStateResponse =
+            querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr: staking_address.to_string(),
+                msg: to_binary(&StaderQueryMsg::State {})?,
+            }))?;
+        Ok(response.state.exchange_rate.into())
+    }
+}
+
+// TESTS
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use astroport::asset::{Asset as AstroportAsset, AssetInfo, PairInfo};
+    use astroport::factory::PairType;
+    use astroport::pair::{
+        ConfigResponse as AstroportConfigResponse, CumulativePricesResponse, PoolResponse,
+        SimulationResponse, StablePoolConfig,
+    };
+    #[cfg(feature = "terra")]
+    use basset::hub::StateResponse;
+    use cosmwasm_std::testing::{mock_env, mock_info, MockApi, MockStorage};
+    #[cfg(feature = "terra")]
+    use cosmwasm_std::Decimal as StdDecimal;
+    use cosmwasm_std::{from_binary, Addr, Binary, OwnedDeps, StdError};
+    use mars_core::oracle::msg::FeederPrice;
+    use mars_core::oracle::{
+        AssetPriceWithMetadataResponse, CircuitBreakerResponse, FallbackPriceSourceChecked,
+    };
+    use mars_core::testing::{mock_dependencies, mock_env_at_block_time, MarsMockQuerier};
+    #[cfg(feature = "terra")]
+    use stader::msg::QueryStateResponse as StaderStateResponse;
+    #[cfg(feature = "terra")]
+    use stader::state::State as StaderState;
+
+    #[test]
+    fn test_proper_initialization() {
+        let mut deps = mock_dependencies(&[]);
+
+        let msg = InstantiateMsg {
+            owner: String::from("owner"),
+            address_provider_address: Some(String::from("address_provider")),
+            base_denom: None,
+        };
+        let info = mock_info("owner", &[]);
+
+        let res = instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
         assert_eq!(0, res.messages.len());
 
-        let config = CONFIG.load(&deps.storage).unwrap();
-        assert_eq!(Addr::unchecked("owner"), config.owner);
-    }
+        let config = CONFIG.load(&deps.storage).unwrap();
+        assert_eq!(Addr::unchecked("owner"), config.owner);
+        assert_eq!("uusd".to_string(), config.base_denom);
+    }
+
+    #[test]
+    fn test_update_config() {
+        let mut deps = th_setup();
+
+        // only owner can update
+        {
+            let msg = ExecuteMsg::UpdateConfig {
+                owner: Some(String::from("new_owner")),
+                address_provider_address: None,
+            };
+            let info = mock_info("another_one", &[]);
+            let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+            assert_eq!(err, MarsError::Unauthorized {}.into());
+        }
+
+        let info = mock_info("owner", &[]);
+        // no change
+        {
+            let msg = ExecuteMsg::UpdateConfig {
+                owner: None,
+                address_provider_address: None,
+            };
+            execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+            let config = CONFIG.load(&deps.storage).unwrap();
+            assert_eq!(config.owner, Addr::unchecked("owner"));
+        }
+
+        // new owner
+        {
+            let msg = ExecuteMsg::UpdateConfig {
+                owner: Some(String::from("new_owner")),
+                address_provider_address: None,
+            };
+            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+
+            let config = CONFIG.load(&deps.storage).unwrap();
+            assert_eq!(config.owner, Addr::unchecked("new_owner"));
+        }
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut deps = th_setup();
+        let asset = Asset::Native {
+            denom: "uluna".to_string(),
+        };
+        PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                &asset.get_reference(),
+                &PriceSourceChecked::Fixed {
+                    price: Decimal::one(),
+                },
+            )
+            .unwrap();
+
+        // only owner or guardian can pause
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("somebody", &[]),
+            ExecuteMsg::Pause {},
+        )
+        .unwrap_err();
+        assert_eq!(err, MarsError::Unauthorized {}.into());
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("guardian", &[]),
+            ExecuteMsg::Pause {},
+        )
+        .unwrap();
+        assert!(CONFIG.load(&deps.storage).unwrap().paused);
+
+        // price queries fail while paused
+        let err = query(
+            deps.as_ref(),
+            mock_env(),
+            QueryMsg::AssetPrice {
+                asset: asset.clone(),
+            },
+        )
+        .unwrap_err();
+        assert_eq!(err, StdError::generic_err("Contract is paused"));
+
+        // only owner can unpause
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("guardian", &[]),
+            ExecuteMsg::Unpause {},
+        )
+        .unwrap_err();
+        assert_eq!(err, MarsError::Unauthorized {}.into());
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("owner", &[]),
+            ExecuteMsg::Unpause {},
+        )
+        .unwrap();
+
+        let price: Decimal =
+            from_binary(&query(deps.as_ref(), mock_env(), QueryMsg::AssetPrice { asset }).unwrap())
+                .unwrap();
+        assert_eq!(price, Decimal::one());
+    }
+
+    #[test]
+    fn test_set_asset() {
+        let mut deps = th_setup();
+
+        let msg = ExecuteMsg::SetAsset {
+            asset: Asset::Native {
+                denom: "luna".to_string(),
+            },
+            price_source: PriceSourceUnchecked::Native {
+                denom: "luna".to_string(),
+            },
+        };
+        let info = mock_info("another_one", &[]);
+        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(err, MarsError::Unauthorized {}.into());
+    }
+
+    #[test]
+    fn test_set_asset_fixed() {
+        let mut deps = th_setup();
+        let info = mock_info("owner", &[]);
+
+        let asset = Asset::Cw20 {
+            contract_addr: String::from("cw20token"),
+        };
+        let reference = asset.get_reference();
+        let msg = ExecuteMsg::SetAsset {
+            asset: asset,
+            price_source: PriceSourceUnchecked::Fixed {
+                price: Decimal::from_ratio(1_u128, 2_u128),
+            },
+        };
+        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+        let price_source = PRICE_SOURCES
+            .load(&deps.storage, reference.as_slice())
+            .unwrap();
+        assert_eq!(
+            price_source,
+            PriceSourceChecked::Fixed {
+                price: Decimal::from_ratio(1_u128, 2_u128)
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "terra")]
+    fn test_set_asset_native() {
+        let mut deps = th_setup();
+        let info = mock_info("owner", &[]);
+
+        let asset = Asset::Native {
+            denom: String::from("luna"),
+        };
+        let reference = asset.get_reference();
+        let msg = ExecuteMsg::SetAsset {
+            asset: asset,
+            price_source: PriceSourceUnchecked::Native {
+                denom: "luna".to_string(),
+            },
+        };
+        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+        let price_source = PRICE_SOURCES
+            .load(&deps.storage, reference.as_slice())
+            .unwrap();
+        assert_eq!(
+            price_source,
+            PriceSourceChecked::Native {
+                denom: "luna".to_string()
+            }
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "terra"))]
+    fn test_set_asset_native_not_supported() {
+        let mut deps = th_setup();
+        let info = mock_info("owner", &[]);
+
+        let msg = ExecuteMsg::SetAsset {
+            asset: Asset::Native {
+                denom: String::from("luna"),
+            },
+            price_source: PriceSourceUnchecked::Native {
+                denom: "luna".to_string(),
+            },
+        };
+        let error_res = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
+        assert_eq!(error_res, ContractError::NativePriceSourceNotSupported {});
+    }
+
+    #[test]
+    fn test_set_asset_astroport_spot() {
+        let mut deps = th_setup();
+        let info = mock_info("owner", &[]);
+
+        let offer_asset_info = AssetInfo::Token {
+            contract_addr: Addr::unchecked("cw20token"),
+        };
+        let ask_asset_info = AssetInfo::NativeToken {
+            denom: "uusd".to_string(),
+        };
+
+        deps.querier.set_astroport_pair(PairInfo {
+            asset_infos: [offer_asset_info.clone(), ask_asset_info.clone()],
+            contract_addr: Addr::unchecked("pair"),
+            liquidity_token: Addr::unchecked("lp"),
+            pair_type: PairType::Xyk {},
+        });
+
+        let asset = Asset::Cw20 {
+            contract_addr: "cw20token".to_string(),
+        };
+        let reference = asset.get_reference();
+        let msg = ExecuteMsg::SetAsset {
+            asset: asset,
+            price_source: PriceSourceUnchecked::AstroportSpot {
+                pair_address: "pair".to_string(),
+            },
+        };
+        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+        let price_source = PRICE_SOURCES
+            .load(&deps.storage, reference.as_slice())
+            .unwrap();
+        assert_eq!(
+            price_source,
+            PriceSourceChecked::AstroportSpot {
+                pair_address: Addr::unchecked("pair")
+            }
+        );
+    }
+
+    #[test]
+    fn test_set_asset_astroport_twap() {
+        let mut deps = th_setup();
+        let info = mock_info("owner", &[]);
+
+        let offer_asset_info = AssetInfo::Token {
+            contract_addr: Addr::unchecked("cw20token"),
+        };
+        let ask_asset_info = AssetInfo::NativeToken {
+            denom: "uusd".to_string(),
+        };
+
+        deps.querier.set_astroport_pair(PairInfo {
+            asset_infos: [offer_asset_info.clone(), ask_asset_info.clone()],
+            contract_addr: Addr::unchecked("pair"),
+            liquidity_token: Addr::unchecked("lp"),
+            pair_type: PairType::Xyk {},
+        });
+
+        let asset = Asset::Cw20 {
+            contract_addr: "cw20token".to_string(),
+        };
+        let reference = asset.get_reference();
+        let msg = ExecuteMsg::SetAsset {
+            asset: asset,
+            price_source: PriceSourceUnchecked::AstroportTwap {
+                pair_address: "pair".to_string(),
+                window_size: 3600,
+                tolerance: 600,
+            },
+        };
+        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+        let price_source = PRICE_SOURCES
+            .load(&deps.storage, reference.as_slice())
+            .unwrap();
+
+        assert_eq!(
+            price_source,
+            PriceSourceChecked::AstroportTwap {
+                pair_address: Addr::unchecked("pair"),
+                window_size: 3600,
+                tolerance: 600,
+            }
+        );
+    }
+
+    #[test]
+    fn test_set_asset_astroport_fair_liquidity_token() {
+        let mut deps = th_setup();
+        let set_asset = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                         contract_addr: &str| {
+            let msg = ExecuteMsg::SetAsset {
+                asset: Asset::Cw20 {
+                    contract_addr: contract_addr.to_string(),
+                },
+                price_source: PriceSourceUnchecked::AstroportFairLiquidityToken {
+                    pair_address: "pair".to_string(),
+                },
+            };
+            execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg)
+        };
+        let set_pair = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                        pair_type: PairType| {
+            deps.querier.set_astroport_pair(PairInfo {
+                asset_infos: [
+                    AssetInfo::Token {
+                        contract_addr: Addr::unchecked("cw20token"),
+                    },
+                    AssetInfo::NativeToken {
+                        denom: "uusd".to_string(),
+                    },
+                ],
+                contract_addr: Addr::unchecked("pair"),
+                liquidity_token: Addr::unchecked("lp"),
+                pair_type,
+            });
+        };
+
+        // pair type must be supported
+        set_pair(&mut deps, PairType::Custom("concentrated".to_string()));
+        let err = set_asset(&mut deps, "lp").unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::UnsupportedPairType {
+                pair_type: "custom-concentrated".to_string()
+            }
+        );
+
+        // asset must be the pair's liquidity token
+        set_pair(&mut deps, PairType::Stable {});
+        let err = set_asset(&mut deps, "cw20token").unwrap_err();
+        assert_eq!(err, ContractError::InvalidPair {});
+
+        set_asset(&mut deps, "lp").unwrap();
+        let price_source = PRICE_SOURCES.load(&deps.storage, b"lp").unwrap();
+        assert_eq!(
+            price_source,
+            PriceSourceChecked::AstroportFairLiquidityToken {
+                pair_address: Addr::unchecked("pair")
+            }
+        );
+    }
+
+    #[test]
+    fn test_set_asset_stluna() {
+        let mut deps = th_setup();
+        let info = mock_info("owner", &[]);
+
+        let asset = Asset::Cw20 {
+            contract_addr: String::from("stluna_token"),
+        };
+        let reference = asset.get_reference();
+        let msg = ExecuteMsg::SetAsset {
+            asset: asset,
+            price_source: PriceSourceUnchecked::Stluna {
+                hub_address: "stluna_hub_addr".to_string(),
+            },
+        };
+        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+        let price_source = PRICE_SOURCES
+            .load(&deps.storage, reference.as_slice())
+            .unwrap();
+        assert_eq!(
+            price_source,
+            PriceSourceChecked::Stluna {
+                hub_address: Addr::unchecked("stluna_hub_addr")
+            }
+        );
+    }
+
+    #[test]
+    fn test_set_asset_lunax() {
+        let mut deps = th_setup();
+        let info = mock_info("owner", &[]);
+
+        let asset = Asset::Cw20 {
+            contract_addr: String::from("lunax_token"),
+        };
+        let reference = asset.get_reference();
+        let msg = ExecuteMsg::SetAsset {
+            asset: asset,
+            price_source: PriceSourceUnchecked::Lunax {
+                staking_address: "lunax_staking_addr".to_string(),
+            },
+        };
+        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+        let price_source = PRICE_SOURCES
+            .load(&deps.storage, reference.as_slice())
+            .unwrap();
+        assert_eq!(
+            price_source,
+            PriceSourceChecked::Lunax {
+                staking_address: Addr::unchecked("lunax_staking_addr")
+            }
+        );
+    }
+
+    #[test]
+    fn test_set_asset_median() {
+        let mut deps = th_setup();
+        let asset = Asset::Cw20 {
+            contract_addr: String::from("cw20token"),
+        };
+        let fixed = |percent: u64| PriceSourceUnchecked::Fixed {
+            price: Decimal::percent(percent),
+        };
+
+        // min sources must be between one and the number of sources
+        for (sources, min_sources) in &[(vec![], 0), (vec![fixed(100)], 0), (vec![fixed(100)], 2)] {
+            let msg = ExecuteMsg::SetAsset {
+                asset: asset.clone(),
+                price_source: PriceSourceUnchecked::Median {
+                    sources: sources.clone(),
+                    min_sources: *min_sources,
+                },
+            };
+            let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap_err();
+            assert_eq!(
+                err,
+                ContractError::InvalidMedianMinSources {
+                    min_sources: *min_sources,
+                    sources: sources.len() as u32,
+                }
+            );
+        }
+
+        // medians cannot be nested
+        let msg = ExecuteMsg::SetAsset {
+            asset: asset.clone(),
+            price_source: PriceSourceUnchecked::Median {
+                sources: vec![
+                    fixed(100),
+                    PriceSourceUnchecked::Median {
+                        sources: vec![fixed(100)],
+                        min_sources: 1,
+                    },
+                ],
+                min_sources: 1,
+            },
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::NestedMedianPriceSource {});
+
+        // sub sources are validated
+        deps.querier.set_astroport_pair(PairInfo {
+            asset_infos: [
+                AssetInfo::NativeToken {
+                    denom: "uusd".to_string(),
+                },
+                AssetInfo::NativeToken {
+                    denom: "uluna".to_string(),
+                },
+            ],
+            contract_addr: Addr::unchecked("uusd_uluna_pair"),
+            liquidity_token: Addr::unchecked("lp"),
+            pair_type: PairType::Xyk {},
+        });
+        let msg = ExecuteMsg::SetAsset {
+            asset: asset.clone(),
+            price_source: PriceSourceUnchecked::Median {
+                sources: vec![
+                    fixed(100),
+                    PriceSourceUnchecked::AstroportSpot {
+                        pair_address: "uusd_uluna_pair".to_string(),
+                    },
+                ],
+                min_sources: 1,
+            },
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::InvalidPair {});
+
+        let price_source = PriceSourceUnchecked::Median {
+            sources: vec![fixed(100), fixed(110)],
+            min_sources: 2,
+        };
+        let msg = ExecuteMsg::SetAsset {
+            asset: asset.clone(),
+            price_source: price_source.clone(),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        assert_eq!(res.attributes[2], attr("price_source", "median"));
+
+        let stored = PRICE_SOURCES
+            .load(&deps.storage, &asset.get_reference())
+            .unwrap();
+        assert_eq!(stored, price_source.to_checked(&deps.api).unwrap());
+    }
+
+    #[test]
+    fn test_set_asset_feed() {
+        let mut deps = th_setup();
+        let asset = Asset::Cw20 {
+            contract_addr: String::from("cw20token"),
+        };
+        let feed = |feeders: &[&str], min_feeders: u32| PriceSourceUnchecked::Feed {
+            feeders: feeders.iter().map(|feeder| feeder.to_string()).collect(),
+            min_feeders,
+            max_age: 300,
+        };
+
+        // min feeders must be between one and the number of feeders
+        for (feeders, min_feeders) in &[(vec![], 0), (vec!["feeder_1"], 0), (vec!["feeder_1"], 2)] {
+            let msg = ExecuteMsg::SetAsset {
+                asset: asset.clone(),
+                price_source: feed(feeders, *min_feeders),
+            };
+            let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap_err();
+            assert_eq!(
+                err,
+                ContractError::InvalidFeedMinFeeders {
+                    min_feeders: *min_feeders,
+                    feeders: feeders.len() as u32,
+                }
+            );
+        }
+
+        // feeders cannot be duplicated
+        let msg = ExecuteMsg::SetAsset {
+            asset: asset.clone(),
+            price_source: feed(&["feeder_1", "feeder_2", "feeder_1"], 2),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::DuplicateFeeder {
+                feeder: "feeder_1".to_string()
+            }
+        );
+
+        let msg = ExecuteMsg::SetAsset {
+            asset: asset.clone(),
+            price_source: feed(&["feeder_1", "feeder_2"], 2),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        assert_eq!(res.attributes[2], attr("price_source", "feed"));
+    }
+
+    #[test]
+    fn test_set_asset_max_age() {
+        let mut deps = th_setup();
+        let asset = Asset::Cw20 {
+            contract_addr: String::from("cw20token"),
+        };
+        let asset_reference = asset.get_reference();
+        let set_max_age = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                           sender: &str,
+                           max_age: Option<u64>| {
+            let msg = ExecuteMsg::SetAssetMaxAge {
+                asset: asset.clone(),
+                max_age,
+            };
+            execute(deps.as_mut(), mock_env(), mock_info(sender, &[]), msg)
+        };
+
+        // only owner can set max age
+        let err = set_max_age(&mut deps, "another_user", Some(300)).unwrap_err();
+        assert_eq!(err, MarsError::Unauthorized {}.into());
+
+        // asset must have a price source
+        let err = set_max_age(&mut deps, "owner", Some(300)).unwrap_err();
+        assert_eq!(err, ContractError::PriceSourceNotSet {});
+
+        PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                &asset_reference,
+                &PriceSourceChecked::Fixed {
+                    price: Decimal::one(),
+                },
+            )
+            .unwrap();
+
+        let err = set_max_age(&mut deps, "owner", Some(0)).unwrap_err();
+        assert_eq!(
+            err,
+            MarsError::InvalidParam {
+                param_name: "max_age".to_string(),
+                invalid_value: "0".to_string(),
+                predicate: "> 0".to_string(),
+            }
+            .into()
+        );
+
+        let res = set_max_age(&mut deps, "owner", Some(300)).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "set_asset_max_age"),
+                attr("asset", "cw20token"),
+                attr("max_age", "300"),
+            ]
+        );
+        let max_age = PRICE_MAX_AGES
+            .load(&deps.storage, &asset_reference)
+            .unwrap();
+        assert_eq!(max_age, 300);
+
+        let res = set_max_age(&mut deps, "owner", None).unwrap();
+        assert_eq!(res.attributes[2], attr("max_age", "none"));
+        assert!(!PRICE_MAX_AGES.has(&deps.storage, &asset_reference));
+
+        // a TWAP price is as old as its window start, so the max age must exceed the window
+        FALLBACK_PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                &asset_reference,
+                &FallbackPriceSourceChecked {
+                    price_source: PriceSourceChecked::AstroportTwap {
+                        pair_address: Addr::unchecked("pair"),
+                        window_size: 600,
+                        tolerance: 60,
+                    },
+                    max_deviation: Decimal::percent(5),
+                },
+            )
+            .unwrap();
+        let err = set_max_age(&mut deps, "owner", Some(660)).unwrap_err();
+        assert_eq!(
+            err,
+            MarsError::InvalidParam {
+                param_name: "max_age".to_string(),
+                invalid_value: "660".to_string(),
+                predicate: "> 660 (TWAP window size plus tolerance)".to_string(),
+            }
+            .into()
+        );
+        set_max_age(&mut deps, "owner", Some(661)).unwrap();
+    }
+
+    #[test]
+    fn test_set_asset_redemption_rate() {
+        let mut deps = th_setup();
+        let query_msg = Binary::from(br#"{"state":{}}"#);
+        deps.querier.set_smart_query_response(
+            "hub",
+            query_msg.clone(),
+            Binary::from(br#"{"total_bonded":"1000","exchange_rate":"1.05"}"#),
+        );
+
+        let asset = Asset::Cw20 {
+            contract_addr: String::from("lsd"),
+        };
+        let underlying_asset = Asset::Native {
+            denom: "ustake".to_string(),
+        };
+        let redemption_rate = |response_path: &[&str], underlying_asset: &Asset| {
+            PriceSourceUnchecked::RedemptionRate {
+                contract: "hub".to_string(),
+                query_msg_template: query_msg.clone(),
+                response_path: response_path.iter().map(|key| key.to_string()).collect(),
+                underlying_asset: underlying_asset.clone(),
+            }
+        };
+        let set_asset = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                         price_source: PriceSourceUnchecked| {
+            let msg = ExecuteMsg::SetAsset {
+                asset: asset.clone(),
+                price_source,
+            };
+            execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg)
+        };
+
+        // underlying asset cannot be the asset itself
+        let err = set_asset(&mut deps, redemption_rate(&["exchange_rate"], &asset)).unwrap_err();
+        assert_eq!(err, ContractError::RedemptionRateUnderlyingIsAsset {});
+
+        // underlying asset must have a price source
+        let err = set_asset(
+            &mut deps,
+            redemption_rate(&["exchange_rate"], &underlying_asset),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::PriceSourceNotSet {});
+
+        let set_other_asset = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                               other_asset: &Asset,
+                               price_source: PriceSourceUnchecked| {
+            let msg = ExecuteMsg::SetAsset {
+                asset: other_asset.clone(),
+                price_source,
+            };
+            execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg)
+        };
+        set_other_asset(
+            &mut deps,
+            &underlying_asset,
+            PriceSourceUnchecked::Fixed {
+                price: Decimal::one(),
+            },
+        )
+        .unwrap();
+
+        // exchange rate must be found in the response
+        let err = set_asset(&mut deps, redemption_rate(&["rate"], &underlying_asset)).unwrap_err();
+        assert_eq!(err, StdError::generic_err("Key not found: rate").into());
+
+        let res = set_asset(
+            &mut deps,
+            redemption_rate(&["exchange_rate"], &underlying_asset),
+        )
+        .unwrap();
+        assert_eq!(res.attributes[2], attr("price_source", "redemption_rate"));
+
+        // the underlying asset cannot be priced through the asset, directly or not
+        let err = set_other_asset(
+            &mut deps,
+            &underlying_asset,
+            redemption_rate(&["exchange_rate"], &asset),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::PriceSourceCycle {});
+
+        let median = PriceSourceUnchecked::Median {
+            sources: vec![
+                PriceSourceUnchecked::Fixed {
+                    price: Decimal::one(),
+                },
+                redemption_rate(&["exchange_rate"], &asset),
+            ],
+            min_sources: 1,
+        };
+        let err = set_other_asset(&mut deps, &underlying_asset, median).unwrap_err();
+        assert_eq!(err, ContractError::PriceSourceCycle {});
+
+        // liquid staking sources depend on the price of luna
+        let stluna = Asset::Cw20 {
+            contract_addr: String::from("stluna"),
+        };
+        let luna = Asset::Native {
+            denom: "uluna".to_string(),
+        };
+        set_other_asset(
+            &mut deps,
+            &stluna,
+            PriceSourceUnchecked::Stluna {
+                hub_address: "stluna_hub".to_string(),
+            },
+        )
+        .unwrap();
+        let err = set_other_asset(
+            &mut deps,
+            &luna,
+            redemption_rate(&["exchange_rate"], &stluna),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::PriceSourceCycle {});
+    }
+
+    #[test]
+    fn test_set_asset_chained() {
+        let mut deps = th_setup();
+
+        deps.querier.set_astroport_pair(PairInfo {
+            asset_infos: [
+                AssetInfo::Token {
+                    contract_addr: Addr::unchecked("cw20token"),
+                },
+                AssetInfo::NativeToken {
+                    denom: "uluna".to_string(),
+                },
+            ],
+            contract_addr: Addr::unchecked("pair"),
+            liquidity_token: Addr::unchecked("lp"),
+            pair_type: PairType::Xyk {},
+        });
+
+        let asset = Asset::Cw20 {
+            contract_addr: "cw20token".to_string(),
+        };
+        let luna = Asset::Native {
+            denom: "uluna".to_string(),
+        };
+        let chained =
+            |source: PriceSourceUnchecked, quote_asset: &Asset| PriceSourceUnchecked::Chained {
+                source: Box::new(source),
+                quote_asset: quote_asset.clone(),
+            };
+        let spot = || PriceSourceUnchecked::AstroportSpot {
+            pair_address: "pair".to_string(),
+        };
+        let set_asset = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                         asset: &Asset,
+                         price_source: PriceSourceUnchecked| {
+            let msg = ExecuteMsg::SetAsset {
+                asset: asset.clone(),
+                price_source,
+            };
+            execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg)
+        };
+
+        // only astroport spot and twap sources can be chained
+        let err = set_asset(
+            &mut deps,
+            &asset,
+            chained(
+                PriceSourceUnchecked::Fixed {
+                    price: Decimal::one(),
+                },
+                &luna,
+            ),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::InvalidChainedPriceSource {});
+
+        // pair must consist of the asset and the quote asset
+        let uusd = Asset::Native {
+            denom: "uusd".to_string(),
+        };
+        let err = set_asset(&mut deps, &asset, chained(spot(), &uusd)).unwrap_err();
+        assert_eq!(err, ContractError::InvalidPair {});
+
+        // quote asset must have a price source
+        let err = set_asset(&mut deps, &asset, chained(spot(), &luna)).unwrap_err();
+        assert_eq!(err, ContractError::PriceSourceNotSet {});
+
+        set_asset(
+            &mut deps,
+            &luna,
+            PriceSourceUnchecked::Fixed {
+                price: Decimal::from_ratio(80u128, 1u128),
+            },
+        )
+        .unwrap();
+        let res = set_asset(&mut deps, &asset, chained(spot(), &luna)).unwrap();
+        assert_eq!(res.attributes[2], attr("price_source", "chained"));
+        let price_source = PRICE_SOURCES
+            .load(&deps.storage, &asset.get_reference())
+            .unwrap();
+        assert_eq!(
+            price_source,
+            PriceSourceChecked::Chained {
+                source: Box::new(PriceSourceChecked::AstroportSpot {
+                    pair_address: Addr::unchecked("pair"),
+                }),
+                quote_asset: luna.clone(),
+            }
+        );
+
+        // luna cannot be priced through the asset, neither as primary nor inside a median or a
+        // fallback
+        let err = set_asset(&mut deps, &luna, chained(spot(), &asset)).unwrap_err();
+        assert_eq!(err, ContractError::PriceSourceCycle {});
+
+        let median = PriceSourceUnchecked::Median {
+            sources: vec![
+                PriceSourceUnchecked::Fixed {
+                    price: Decimal::from_ratio(80u128, 1u128),
+                },
+                chained(spot(), &asset),
+            ],
+            min_sources: 1,
+        };
+        let err = set_asset(&mut deps, &luna, median).unwrap_err();
+        assert_eq!(err, ContractError::PriceSourceCycle {});
+
+        let msg = ExecuteMsg::SetAssetFallback {
+            asset: luna,
+            fallback: Some(FallbackPriceSourceUnchecked {
+                price_source: chained(spot(), &asset),
+                max_deviation: Decimal::percent(5),
+            }),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::PriceSourceCycle {});
+    }
+
+    #[test]
+    fn test_set_asset_fallback() {
+        let mut deps = th_setup();
+        let asset = Asset::Cw20 {
+            contract_addr: String::from("cw20token"),
+        };
+        let fallback = FallbackPriceSourceUnchecked {
+            price_source: PriceSourceUnchecked::Fixed {
+                price: Decimal::from_ratio(3_u128, 2_u128),
+            },
+            max_deviation: Decimal::percent(5),
+        };
+
+        // only owner can set fallback
+        let msg = ExecuteMsg::SetAssetFallback {
+            asset: asset.clone(),
+            fallback: Some(fallback.clone()),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("another_one", &[]),
+            msg,
+        )
+        .unwrap_err();
+        assert_eq!(err, MarsError::Unauthorized {}.into());
+
+        // primary price source must be set first
+        let msg = ExecuteMsg::SetAssetFallback {
+            asset: asset.clone(),
+            fallback: Some(fallback.clone()),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap_err();
+        assert_eq!(err, ContractError::PriceSourceNotSet {});
+
+        let msg = ExecuteMsg::SetAsset {
+            asset: asset.clone(),
+            price_source: PriceSourceUnchecked::Fixed {
+                price: Decimal::one(),
+            },
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+        // max deviation cannot be greater than one
+        let msg = ExecuteMsg::SetAssetFallback {
+            asset: asset.clone(),
+            fallback: Some(FallbackPriceSourceUnchecked {
+                max_deviation: Decimal::percent(101),
+                ..fallback.clone()
+            }),
+        };
+        let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap_err();
+        assert_eq!(
+            err,
+            MarsError::InvalidParam {
+                param_name: "max_deviation".to_string(),
+                invalid_value: "1.01".to_string(),
+                predicate: "<= 1".to_string(),
+            }
+            .into()
+        );
+
+        // set fallback
+        let msg = ExecuteMsg::SetAssetFallback {
+            asset: asset.clone(),
+            fallback: Some(fallback.clone()),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "set_asset_fallback"),
+                attr("asset", "cw20token"),
+                attr("fallback_price_source", "fixed"),
+            ]
+        );
+
+        let stored: Option<FallbackPriceSourceChecked> = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::AssetFallbackPriceSource {
+                    asset: asset.clone(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(stored, Some(fallback.to_checked(&deps.api).unwrap()));
+
+        // remove fallback
+        let msg = ExecuteMsg::SetAssetFallback {
+            asset: asset.clone(),
+            fallback: None,
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+        let stored: Option<FallbackPriceSourceChecked> = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::AssetFallbackPriceSource { asset },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(stored, None);
+    }
+
+    #[test]
+    fn test_set_asset_single_average_sources() {
+        let mut deps = th_setup();
+
+        let cw20_info = AssetInfo::Token {
+            contract_addr: Addr::unchecked("cw20token"),
+        };
+        for (pair, quote_denom) in &[("ust_pair", "uusd"), ("luna_pair", "uluna")] {
+            deps.querier.set_astroport_pair(PairInfo {
+                asset_infos: [
+                    cw20_info.clone(),
+                    AssetInfo::NativeToken {
+                        denom: quote_denom.to_string(),
+                    },
+                ],
+                contract_addr: Addr::unchecked(*pair),
+                liquidity_token: Addr::unchecked("lp"),
+                pair_type: PairType::Xyk {},
+            });
+        }
+
+        let asset = Asset::Cw20 {
+            contract_addr: "cw20token".to_string(),
+        };
+        let luna = Asset::Native {
+            denom: "uluna".to_string(),
+        };
+        let twap = |pair: &str| PriceSourceUnchecked::AstroportTwap {
+            pair_address: pair.to_string(),
+            window_size: 600,
+            tolerance: 60,
+        };
+        let luna_twap = || PriceSourceUnchecked::Chained {
+            source: Box::new(twap("luna_pair")),
+            quote_asset: luna.clone(),
+        };
+        let ema = |half_life: u64| PriceSourceUnchecked::AstroportEma {
+            pair_address: "ust_pair".to_string(),
+            half_life,
+            max_staleness: 7200,
+        };
+        let set_asset = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                         price_source: PriceSourceUnchecked| {
+            let msg = ExecuteMsg::SetAsset {
+                asset: asset.clone(),
+                price_source,
+            };
+            execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg)
+        };
+        let set_fallback = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                            price_source: PriceSourceUnchecked| {
+            let msg = ExecuteMsg::SetAssetFallback {
+                asset: asset.clone(),
+                fallback: Some(FallbackPriceSourceUnchecked {
+                    price_source,
+                    max_deviation: Decimal::percent(5),
+                }),
+            };
+            execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg)
+        };
+
+        let msg = ExecuteMsg::SetAsset {
+            asset: luna.clone(),
+            price_source: PriceSourceUnchecked::Fixed {
+                price: Decimal::from_ratio(80u128, 1u128),
+            },
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
+
+        // primary TWAP quoted in uusd cannot have a fallback TWAP quoted in uluna
+        set_asset(&mut deps, twap("ust_pair")).unwrap();
+        let err = set_fallback(&mut deps, luna_twap()).unwrap_err();
+        assert_eq!(err, ContractError::MultipleTwapPriceSources {});
+
+        // nor the other way around
+        set_fallback(&mut deps, twap("ust_pair")).unwrap();
+        let err = set_asset(&mut deps, luna_twap()).unwrap_err();
+        assert_eq!(err, ContractError::MultipleTwapPriceSources {});
+
+        // nor inside a median
+        let median = PriceSourceUnchecked::Median {
+            sources: vec![twap("ust_pair"), luna_twap()],
+            min_sources: 1,
+        };
+        let err = set_asset(&mut deps, median).unwrap_err();
+        assert_eq!(err, ContractError::MultipleTwapPriceSources {});
+
+        // EMA sources must be the same too
+        set_asset(&mut deps, ema(3_600)).unwrap();
+        let err = set_fallback(&mut deps, ema(600)).unwrap_err();
+        assert_eq!(err, ContractError::MultipleEmaPriceSources {});
+        set_fallback(&mut deps, ema(3_600)).unwrap();
+    }
+
+    #[test]
+    fn test_feed_prices() {
+        let mut deps = th_setup();
+        let asset = Asset::Cw20 {
+            contract_addr: String::from("cw20token"),
+        };
+        let asset_reference = asset.get_reference();
+        let other_asset = Asset::Cw20 {
+            contract_addr: String::from("othertoken"),
+        };
+
+        PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                &asset_reference,
+                &PriceSourceChecked::Feed {
+                    feeders: vec![
+                        Addr::unchecked("feeder_1"),
+                        Addr::unchecked("feeder_2"),
+                        Addr::unchecked("feeder_3"),
+                    ],
+                    min_feeders: 2,
+                    max_age: 300,
+                },
+            )
+            .unwrap();
+        // other asset uses the feed as fallback, inside a median
+        PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                &other_asset.get_reference(),
+                &PriceSourceChecked::Fixed {
+                    price: Decimal::one(),
+                },
+            )
+            .unwrap();
+        FALLBACK_PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                &other_asset.get_reference(),
+                &FallbackPriceSourceChecked {
+                    price_source: PriceSourceChecked::Median {
+                        sources: vec![PriceSourceChecked::Feed {
+                            feeders: vec![Addr::unchecked("feeder_4")],
+                            min_feeders: 1,
+                            max_age: 300,
+                        }],
+                        min_sources: 1,
+                    },
+                    max_deviation: Decimal::percent(5),
+                },
+            )
+            .unwrap();
+
+        let feed_prices = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                           feeder: &str,
+                           block_time: u64,
+                           prices: Vec<(Asset, Decimal)>| {
+            let msg = ExecuteMsg::FeedPrices {
+                prices: prices
+                    .into_iter()
+                    .map(|(asset, price)| FeederPrice { asset, price })
+                    .collect(),
+            };
+            execute(
+                deps.as_mut(),
+                mock_env_at_block_time(block_time),
+                mock_info(feeder, &[]),
+                msg,
+            )
+        };
+        let query_price = |deps: &OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                           block_time: u64| {
+            query_asset_price(
+                deps.as_ref(),
+                mock_env_at_block_time(block_time),
+                asset_reference.clone(),
+            )
+        };
+
+        // only whitelisted feeders can push prices
+        let err = feed_prices(
+            &mut deps,
+            "feeder_4",
+            1000,
+            vec![(asset.clone(), Decimal::one())],
+        )
+        .unwrap_err();
+        assert_eq!(err, MarsError::Unauthorized {}.into());
+
+        // assets without price source cannot be fed
+        let err = feed_prices(
+            &mut deps,
+            "feeder_1",
+            1000,
+            vec![(
+                Asset::Cw20 {
+                    contract_addr: "unknown".to_string(),
+                },
+                Decimal::one(),
+            )],
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::PriceSourceNotSet {});
+
+        // price cannot be zero
+        let err = feed_prices(
+            &mut deps,
+            "feeder_1",
+            1000,
+            vec![(asset.clone(), Decimal::zero())],
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::ZeroFeedPrice {});
+
+        let res = feed_prices(
+            &mut deps,
+            "feeder_1",
+            1000,
+            vec![(asset.clone(), Decimal::percent(100))],
+        )
+        .unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "feed_prices"),
+                attr("feeder", "feeder_1"),
+                attr("timestamp", "1000"),
+                attr("asset", "cw20token"),
+                attr("price", "1"),
+            ]
+        );
+
+        // a single feeder is not enough
+        let err = query_price(&deps, 1000).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::NotEnoughFeedPrices {
+                fresh: 1,
+                min_feeders: 2,
+            }
+        );
+
+        feed_prices(
+            &mut deps,
+            "feeder_2",
+            1100,
+            vec![(asset.clone(), Decimal::percent(110))],
+        )
+        .unwrap();
+        feed_prices(
+            &mut deps,
+            "feeder_3",
+            1200,
+            vec![(asset.clone(), Decimal::percent(200))],
+        )
+        .unwrap();
+
+        // median of the three prices
+        assert_eq!(query_price(&deps, 1250).unwrap(), Decimal::percent(110));
+
+        // feeder 1 price is too old, median of the other two
+        assert_eq!(query_price(&deps, 1301).unwrap(), Decimal::percent(155));
+
+        // only feeder 3 price is recent enough
+        let err = query_price(&deps, 1401).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::NotEnoughFeedPrices {
+                fresh: 1,
+                min_feeders: 2,
+            }
+        );
+
+        let feeder_prices: Vec<FeederPriceResponse> = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::AssetFeedPrices {
+                    asset: asset.clone(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            feeder_prices,
+            vec![
+                FeederPriceResponse {
+                    feeder: Addr::unchecked("feeder_1"),
+                    price: Decimal::percent(100),
+                    timestamp: 1000,
+                },
+                FeederPriceResponse {
+                    feeder: Addr::unchecked("feeder_2"),
+                    price: Decimal::percent(110),
+                    timestamp: 1100,
+                },
+                FeederPriceResponse {
+                    feeder: Addr::unchecked("feeder_3"),
+                    price: Decimal::percent(200),
+                    timestamp: 1200,
+                },
+            ]
+        );
+
+        // feeders of a fallback feed can push prices
+        feed_prices(
+            &mut deps,
+            "feeder_4",
+            1000,
+            vec![(other_asset.clone(), Decimal::percent(102))],
+        )
+        .unwrap();
+        let price = query_asset_price(
+            deps.as_ref(),
+            mock_env_at_block_time(1000),
+            other_asset.get_reference(),
+        )
+        .unwrap();
+        assert_eq!(price, Decimal::one());
+    }
+
+    #[test]
+    fn test_record_twap_snapshots() {
+        let mut deps = th_setup();
+        let info = mock_info("anyone", &[]);
+
+        let window_size = 3600;
+        let tolerance = 600;
+
+        let asset = Asset::Cw20 {
+            contract_addr: "cw20token".to_string(),
+        };
+        let reference = asset.get_reference();
+
+        // set price source to astroport TWAP
+        PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                reference.as_slice(),
+                &PriceSourceChecked::AstroportTwap {
+                    pair_address: Addr::unchecked("pair"),
+                    window_size,
+                    tolerance,
+                },
+            )
+            .unwrap();
+
+        // set cumulative price
+        let offer_asset_info = AssetInfo::Token {
+            contract_addr: Addr::unchecked("cw20token"),
+        };
+        let ask_asset_info = AssetInfo::NativeToken {
+            denom: "uusd".to_string(),
+        };
+
+        let mut cumulative_prices = CumulativePricesResponse {
+            assets: [
+                AstroportAsset {
+                    info: offer_asset_info,
+                    amount: Uint128::zero(),
+                },
+                AstroportAsset {
+                    info: ask_asset_info,
+                    amount: Uint128::zero(),
+                },
+            ],
+            total_share: Uint128::zero(),
+            price0_cumulative_last: Uint128::zero(), // token
+            price1_cumulative_last: Uint128::zero(), // uusd
+        };
+
+        // set the cumulative price
+        cumulative_prices.price0_cumulative_last = Uint128::new(1_000_000_000);
+        deps.querier
+            .set_astroport_pair_cumulative_prices("pair".to_string(), cumulative_prices.clone());
+
+        // record first snapshot
+        let snapshot_time = 100_000;
+
+        let msg = ExecuteMsg::RecordTwapSnapshots {
+            assets: vec![asset.clone()],
+        };
+
+        let response = execute(
+            deps.as_mut(),
+            mock_env_at_block_time(snapshot_time),
+            info.clone(),
+            msg.clone(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            response.attributes,
+            vec![
+                attr("action", "record_twap_snapshots"),
+                attr("timestamp", "100000"),
+                attr("asset", "cw20token"),
+                attr("price_cumulative", "1000000000"),
+            ]
+        );
+
+        let snapshots = th_twap_snapshots(&deps, &reference);
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].price_cumulative, Uint128::new(1_000_000_000));
+        assert_eq!(snapshots[0].timestamp, snapshot_time);
+
+        // update the cumulative price
+        cumulative_prices.price0_cumulative_last = Uint128::new(2_000_000000);
+        deps.querier
+            .set_astroport_pair_cumulative_prices("pair".to_string(), cumulative_prices.clone());
+
+        // try to record a second snapshot within `tolerance` seconds
+        let snapshot_too_soon_time = snapshot_time + tolerance - 1;
 
-    #[test]
-    fn test_update_config() {
-        let mut deps = th_setup();
+        let response = execute(
+            deps.as_mut(),
+            mock_env_at_block_time(snapshot_too_soon_time),
+            info.clone(),
+            msg.clone(),
+        )
+        .unwrap();
 
-        // only owner can update
-        {
-            let msg = ExecuteMsg::UpdateConfig {
-                owner: Some(String::from("new_owner")),
-            };
-            let info = mock_info("another_one", &[]);
-            let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
-            assert_eq!(err, MarsError::Unauthorized {}.into());
-        }
+        assert_eq!(
+            response.attributes,
+            vec![
+                attr("action", "record_twap_snapshots"),
+                attr("timestamp", "100599"),
+            ]
+        );
+        assert!(response.events.len() == 0);
 
-        let info = mock_info("owner", &[]);
-        // no change
-        {
-            let msg = ExecuteMsg::UpdateConfig { owner: None };
-            execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+        // record a second snapshot
+        let second_snapshot_time = snapshot_time + tolerance;
 
-            let config = CONFIG.load(&deps.storage).unwrap();
-            assert_eq!(config.owner, Addr::unchecked("owner"));
-        }
+        let response = execute(
+            deps.as_mut(),
+            mock_env_at_block_time(second_snapshot_time),
+            info.clone(),
+            msg.clone(),
+        )
+        .unwrap();
 
-        // new owner
-        {
-            let msg = ExecuteMsg::UpdateConfig {
-                owner: Some(String::from("new_owner")),
-            };
-            execute(deps.as_mut(), mock_env(), info, msg).unwrap();
+        assert_eq!(
+            response.attributes,
+            vec![
+                attr("action", "record_twap_snapshots"),
+                attr("timestamp", "100600"),
+                attr("asset", "cw20token"),
+                attr("price_cumulative", "2000000000"),
+            ]
+        );
 
-            let config = CONFIG.load(&deps.storage).unwrap();
-            assert_eq!(config.owner, Addr::unchecked("new_owner"));
-        }
-    }
+        let snapshots = th_twap_snapshots(&deps, &reference);
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[1].price_cumulative, Uint128::new(2_000_000000));
+        assert_eq!(snapshots[1].timestamp, second_snapshot_time);
 
-    #[test]
-    fn test_set_asset() {
-        let mut deps = th_setup();
+        // record a third snapshot and check that old snapshots are removed from state
+        let third_snapshot_time = second_snapshot_time + window_size + tolerance + 1;
 
-        let msg = ExecuteMsg::SetAsset {
-            asset: Asset::Native {
-                denom: "luna".to_string(),
-            },
-            price_source: PriceSourceUnchecked::Native {
-                denom: "luna".to_string(),
-            },
-        };
-        let info = mock_info("another_one", &[]);
-        let err = execute(deps.as_mut(), mock_env(), info, msg).unwrap_err();
-        assert_eq!(err, MarsError::Unauthorized {}.into());
+        execute(
+            deps.as_mut(),
+            mock_env_at_block_time(third_snapshot_time),
+            info.clone(),
+            msg.clone(),
+        )
+        .unwrap();
+
+        let snapshots = th_twap_snapshots(&deps, &reference);
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].price_cumulative, Uint128::new(2_000_000000));
+        assert_eq!(snapshots[0].timestamp, third_snapshot_time);
     }
 
     #[test]
-    fn test_set_asset_fixed() {
+    fn test_migrate_twap_snapshots() {
         let mut deps = th_setup();
-        let info = mock_info("owner", &[]);
-
-        let asset = Asset::Cw20 {
-            contract_addr: String::from("cw20token"),
-        };
-        let reference = asset.get_reference();
-        let msg = ExecuteMsg::SetAsset {
-            asset: asset,
-            price_source: PriceSourceUnchecked::Fixed {
-                price: Decimal::from_ratio(1_u128, 2_u128),
-            },
+        let snapshot = |timestamp: u64, price_cumulative: u128| AstroportTwapSnapshot {
+            timestamp,
+            price_cumulative: Uint128::new(price_cumulative),
         };
-        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
-
-        let price_source = PRICE_SOURCES
-            .load(&deps.storage, reference.as_slice())
+        LEGACY_ASTROPORT_TWAP_SNAPSHOTS
+            .save(
+                &mut deps.storage,
+                b"uluna",
+                &vec![snapshot(100_000, 1_000), snapshot(100_600, 2_000)],
+            )
+            .unwrap();
+        LEGACY_ASTROPORT_TWAP_SNAPSHOTS
+            .save(
+                &mut deps.storage,
+                b"cw20token",
+                &vec![snapshot(100_300, 5_000)],
+            )
             .unwrap();
+
+        // snapshots are stored as vectors up to 1.0.2
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "1.0.2").unwrap();
+        let res = migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
         assert_eq!(
-            price_source,
-            PriceSourceChecked::Fixed {
-                price: Decimal::from_ratio(1_u128, 2_u128)
-            }
+            res.attributes,
+            vec![
+                attr("action", "migrate"),
+                attr("previous_version", "1.0.2"),
+                attr("new_version", CONTRACT_VERSION),
+                attr("migration_steps", "1.1.0"),
+            ]
         );
-    }
-
-    #[test]
-    fn test_set_asset_native() {
-        let mut deps = th_setup();
-        let info = mock_info("owner", &[]);
 
-        let asset = Asset::Native {
-            denom: String::from("luna"),
-        };
-        let reference = asset.get_reference();
-        let msg = ExecuteMsg::SetAsset {
-            asset: asset,
-            price_source: PriceSourceUnchecked::Native {
-                denom: "luna".to_string(),
-            },
-        };
-        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+        assert_eq!(
+            th_twap_snapshots(&deps, b"uluna"),
+            vec![snapshot(100_000, 1_000), snapshot(100_600, 2_000)]
+        );
+        assert_eq!(
+            th_twap_snapshots(&deps, b"cw20token"),
+            vec![snapshot(100_300, 5_000)]
+        );
+        let legacy_snapshots = LEGACY_ASTROPORT_TWAP_SNAPSHOTS
+            .range(&deps.storage, None, None, Order::Ascending)
+            .count();
+        assert_eq!(legacy_snapshots, 0);
 
-        let price_source = PRICE_SOURCES
-            .load(&deps.storage, reference.as_slice())
+        // migrated snapshots are used for TWAP prices
+        PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                b"uluna",
+                &PriceSourceChecked::AstroportTwap {
+                    pair_address: Addr::unchecked("pair"),
+                    window_size: 600,
+                    tolerance: 60,
+                },
+            )
             .unwrap();
-        assert_eq!(
-            price_source,
-            PriceSourceChecked::Native {
-                denom: "luna".to_string()
-            }
+        deps.querier.set_astroport_pair_cumulative_prices(
+            "pair".to_string(),
+            CumulativePricesResponse {
+                assets: [
+                    AstroportAsset {
+                        info: AssetInfo::NativeToken {
+                            denom: "uluna".to_string(),
+                        },
+                        amount: Uint128::zero(),
+                    },
+                    AstroportAsset {
+                        info: AssetInfo::NativeToken {
+                            denom: "uusd".to_string(),
+                        },
+                        amount: Uint128::zero(),
+                    },
+                ],
+                total_share: Uint128::zero(),
+                price0_cumulative_last: Uint128::new(
+                    1_000 + 600 * 5 * 10u128.pow(TWAP_PRECISION.into()),
+                ),
+                price1_cumulative_last: Uint128::zero(),
+            },
         );
+        let price = query_asset_price(
+            deps.as_ref(),
+            mock_env_at_block_time(100_600),
+            b"uluna".to_vec(),
+        )
+        .unwrap();
+        assert_eq!(price, Decimal::from_ratio(5u128, 1u128));
     }
 
     #[test]
-    fn test_set_asset_astroport_spot() {
+    fn test_astroport_ema() {
         let mut deps = th_setup();
-        let info = mock_info("owner", &[]);
-
-        let offer_asset_info = AssetInfo::Token {
-            contract_addr: Addr::unchecked("cw20token"),
-        };
-        let ask_asset_info = AssetInfo::NativeToken {
-            denom: "uusd".to_string(),
+        let asset = Asset::Cw20 {
+            contract_addr: String::from("cw20token"),
         };
+        let asset_reference = asset.get_reference();
 
         deps.querier.set_astroport_pair(PairInfo {
-            asset_infos: [offer_asset_info.clone(), ask_asset_info.clone()],
+            asset_infos: [
+                AssetInfo::Token {
+                    contract_addr: Addr::unchecked("cw20token"),
+                },
+                AssetInfo::NativeToken {
+                    denom: "uusd".to_string(),
+                },
+            ],
             contract_addr: Addr::unchecked("pair"),
             liquidity_token: Addr::unchecked("lp"),
             pair_type: PairType::Xyk {},
         });
-
-        let asset = Asset::Cw20 {
-            contract_addr: "cw20token".to_string(),
+        let set_spot_price = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                              price: u128| {
+            deps.querier.set_astroport_pair_simulation(
+                "pair".to_string(),
+                SimulationResponse {
+                    return_amount: Uint128::new(price * 1_000_000),
+                    commission_amount: Uint128::zero(),
+                    spread_amount: Uint128::zero(),
+                },
+            );
         };
-        let reference = asset.get_reference();
+        let record_msg = ExecuteMsg::RecordEmaPrices {
+            assets: vec![asset.clone()],
+        };
+
+        // half life cannot be zero
         let msg = ExecuteMsg::SetAsset {
-            asset: asset,
-            price_source: PriceSourceUnchecked::AstroportSpot {
+            asset: asset.clone(),
+            price_source: PriceSourceUnchecked::AstroportEma {
                 pair_address: "pair".to_string(),
+                half_life: 0,
+                max_staleness: 7200,
             },
         };
-        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
-
-        let price_source = PRICE_SOURCES
-            .load(&deps.storage, reference.as_slice())
-            .unwrap();
+        let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap_err();
         assert_eq!(
-            price_source,
-            PriceSourceChecked::AstroportSpot {
-                pair_address: Addr::unchecked("pair")
+            err,
+            MarsError::InvalidParam {
+                param_name: "half_life".to_string(),
+                invalid_value: "0".to_string(),
+                predicate: "> 0".to_string(),
             }
+            .into()
         );
-    }
 
-    #[test]
-    fn test_set_asset_astroport_twap() {
-        let mut deps = th_setup();
-        let info = mock_info("owner", &[]);
+        // asset must use an EMA price source to be recorded
+        PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                &asset_reference,
+                &PriceSourceChecked::AstroportSpot {
+                    pair_address: Addr::unchecked("pair"),
+                },
+            )
+            .unwrap();
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("keeper", &[]),
+            record_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::PriceSourceNotEma {});
 
-        let offer_asset_info = AssetInfo::Token {
-            contract_addr: Addr::unchecked("cw20token"),
+        let msg = ExecuteMsg::SetAsset {
+            asset: asset.clone(),
+            price_source: PriceSourceUnchecked::AstroportEma {
+                pair_address: "pair".to_string(),
+                half_life: 3600,
+                max_staleness: 0,
+            },
         };
-        let ask_asset_info = AssetInfo::NativeToken {
-            denom: "uusd".to_string(),
+        let err = execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap_err();
+        assert_eq!(
+            err,
+            MarsError::InvalidParam {
+                param_name: "max_staleness".to_string(),
+                invalid_value: "0".to_string(),
+                predicate: "> 0".to_string(),
+            }
+            .into()
+        );
+
+        let msg = ExecuteMsg::SetAsset {
+            asset: asset.clone(),
+            price_source: PriceSourceUnchecked::AstroportEma {
+                pair_address: "pair".to_string(),
+                half_life: 3600,
+                max_staleness: 7200,
+            },
         };
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
 
-        deps.querier.set_astroport_pair(PairInfo {
-            asset_infos: [offer_asset_info.clone(), ask_asset_info.clone()],
-            contract_addr: Addr::unchecked("pair"),
-            liquidity_token: Addr::unchecked("lp"),
-            pair_type: PairType::Xyk {},
-        });
+        // no price until a first record
+        set_spot_price(&mut deps, 10);
+        let err = query_asset_price(
+            deps.as_ref(),
+            mock_env_at_block_time(10_000),
+            asset_reference.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::EmaPriceNotRecorded {});
+
+        // first record is the spot price
+        let res = execute(
+            deps.as_mut(),
+            mock_env_at_block_time(10_000),
+            mock_info("keeper", &[]),
+            record_msg.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "record_ema_prices"),
+                attr("timestamp", "10000"),
+                attr("asset", "cw20token"),
+                attr("ema_price", "10"),
+            ]
+        );
+
+        // after a half life, the average moves halfway to the new spot price, whether it is
+        // recorded or only queried
+        set_spot_price(&mut deps, 20);
+        let price = query_asset_price(
+            deps.as_ref(),
+            mock_env_at_block_time(13_600),
+            asset_reference.clone(),
+        )
+        .unwrap();
+        assert_eq!(price, Decimal::from_ratio(15u128, 1u128));
 
-        let asset = Asset::Cw20 {
-            contract_addr: "cw20token".to_string(),
-        };
-        let reference = asset.get_reference();
-        let msg = ExecuteMsg::SetAsset {
-            asset: asset,
-            price_source: PriceSourceUnchecked::AstroportTwap {
-                pair_address: "pair".to_string(),
-                window_size: 3600,
-                tolerance: 600,
-            },
-        };
-        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+        // the price is as old as the last record
+        let price_with_metadata = query_asset_price_with_metadata(
+            deps.as_ref(),
+            mock_env_at_block_time(13_600),
+            asset_reference.clone(),
+        )
+        .unwrap();
+        assert_eq!(price_with_metadata.timestamp, 10_000);
 
-        let price_source = PRICE_SOURCES
-            .load(&deps.storage, reference.as_slice())
+        execute(
+            deps.as_mut(),
+            mock_env_at_block_time(13_600),
+            mock_info("keeper", &[]),
+            record_msg.clone(),
+        )
+        .unwrap();
+        let ema = ASTROPORT_EMA_PRICES
+            .load(&deps.storage, &asset_reference)
             .unwrap();
-
         assert_eq!(
-            price_source,
-            PriceSourceChecked::AstroportTwap {
-                pair_address: Addr::unchecked("pair"),
-                window_size: 3600,
-                tolerance: 600,
+            ema,
+            PriceSnapshot {
+                price: Decimal::from_ratio(15u128, 1u128),
+                timestamp: 13_600,
             }
         );
-    }
 
-    #[test]
-    fn test_set_asset_stluna() {
-        let mut deps = th_setup();
-        let info = mock_info("owner", &[]);
+        // queried in the same block, the recorded average is returned as is
+        set_spot_price(&mut deps, 100);
+        let price = query_asset_price(
+            deps.as_ref(),
+            mock_env_at_block_time(13_600),
+            asset_reference.clone(),
+        )
+        .unwrap();
+        assert_eq!(price, Decimal::from_ratio(15u128, 1u128));
 
-        let asset = Asset::Cw20 {
-            contract_addr: String::from("stluna_token"),
-        };
-        let reference = asset.get_reference();
-        let msg = ExecuteMsg::SetAsset {
-            asset: asset,
-            price_source: PriceSourceUnchecked::Stluna {
-                hub_address: "stluna_hub_addr".to_string(),
-            },
-        };
-        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+        // an average older than max staleness cannot be queried
+        set_spot_price(&mut deps, 35);
+        let err = query_asset_price(
+            deps.as_ref(),
+            mock_env_at_block_time(20_801),
+            asset_reference.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::EmaPriceStale {});
 
-        let price_source = PRICE_SOURCES
-            .load(&deps.storage, reference.as_slice())
+        // a late record weighs the spot price as if max staleness (two half lives) had elapsed
+        execute(
+            deps.as_mut(),
+            mock_env_at_block_time(100_000),
+            mock_info("keeper", &[]),
+            record_msg.clone(),
+        )
+        .unwrap();
+        let ema = ASTROPORT_EMA_PRICES
+            .load(&deps.storage, &asset_reference)
             .unwrap();
         assert_eq!(
-            price_source,
-            PriceSourceChecked::Stluna {
-                hub_address: Addr::unchecked("stluna_hub_addr")
+            ema,
+            PriceSnapshot {
+                price: Decimal::from_ratio(30u128, 1u128),
+                timestamp: 100_000,
             }
         );
-    }
-
-    #[test]
-    fn test_set_asset_lunax() {
-        let mut deps = th_setup();
-        let info = mock_info("owner", &[]);
 
-        let asset = Asset::Cw20 {
-            contract_addr: String::from("lunax_token"),
-        };
-        let reference = asset.get_reference();
+        // a max staleness large enough to overflow the capped timestamp does not cap it
         let msg = ExecuteMsg::SetAsset {
-            asset: asset,
-            price_source: PriceSourceUnchecked::Lunax {
-                staking_address: "lunax_staking_addr".to_string(),
+            asset: asset.clone(),
+            price_source: PriceSourceUnchecked::AstroportEma {
+                pair_address: "pair".to_string(),
+                half_life: 3600,
+                max_staleness: u64::MAX,
             },
         };
-        execute(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+        execute(deps.as_mut(), mock_env(), mock_info("owner", &[]), msg).unwrap();
 
-        let price_source = PRICE_SOURCES
-            .load(&deps.storage, reference.as_slice())
+        set_spot_price(&mut deps, 40);
+        execute(
+            deps.as_mut(),
+            mock_env_at_block_time(103_600),
+            mock_info("keeper", &[]),
+            record_msg,
+        )
+        .unwrap();
+        let ema = ASTROPORT_EMA_PRICES
+            .load(&deps.storage, &asset_reference)
             .unwrap();
         assert_eq!(
-            price_source,
-            PriceSourceChecked::Lunax {
-                staking_address: Addr::unchecked("lunax_staking_addr")
+            ema,
+            PriceSnapshot {
+                price: Decimal::from_ratio(35u128, 1u128),
+                timestamp: 103_600,
             }
         );
     }
 
     #[test]
-    fn test_record_twap_snapshots() {
+    fn test_set_circuit_breaker() {
         let mut deps = th_setup();
-        let info = mock_info("anyone", &[]);
-
-        let window_size = 3600;
-        let tolerance = 600;
-
         let asset = Asset::Cw20 {
-            contract_addr: "cw20token".to_string(),
+            contract_addr: String::from("cw20token"),
+        };
+        let asset_reference = asset.get_reference();
+        let set_circuit_breaker =
+            |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+             sender: &str,
+             circuit_breaker: Option<CircuitBreaker>| {
+                let msg = ExecuteMsg::SetCircuitBreaker {
+                    asset: asset.clone(),
+                    circuit_breaker,
+                };
+                execute(deps.as_mut(), mock_env(), mock_info(sender, &[]), msg)
+            };
+        let circuit_breaker = CircuitBreaker {
+            min_price: Some(Decimal::percent(90)),
+            max_price: Some(Decimal::percent(110)),
+            max_change: Some(Decimal::percent(5)),
+            window: 3600,
         };
-        let reference = asset.get_reference();
 
-        // set price source to astroport TWAP
+        // only owner can set a circuit breaker
+        let err =
+            set_circuit_breaker(&mut deps, "guardian", Some(circuit_breaker.clone())).unwrap_err();
+        assert_eq!(err, MarsError::Unauthorized {}.into());
+
+        // asset must have a price source
+        let err =
+            set_circuit_breaker(&mut deps, "owner", Some(circuit_breaker.clone())).unwrap_err();
+        assert_eq!(err, ContractError::PriceSourceNotSet {});
+
         PRICE_SOURCES
             .save(
                 &mut deps.storage,
-                reference.as_slice(),
-                &PriceSourceChecked::AstroportTwap {
-                    pair_address: Addr::unchecked("pair"),
-                    window_size,
-                    tolerance,
+                &asset_reference,
+                &PriceSourceChecked::Fixed {
+                    price: Decimal::one(),
                 },
             )
             .unwrap();
 
-        // set cumulative price
-        let offer_asset_info = AssetInfo::Token {
-            contract_addr: Addr::unchecked("cw20token"),
-        };
-        let ask_asset_info = AssetInfo::NativeToken {
-            denom: "uusd".to_string(),
-        };
-
-        let mut cumulative_prices = CumulativePricesResponse {
-            assets: [
-                AstroportAsset {
-                    info: offer_asset_info,
-                    amount: Uint128::zero(),
+        let invalid_circuit_breakers = vec![
+            (
+                CircuitBreaker {
+                    min_price: Some(Decimal::percent(120)),
+                    ..circuit_breaker.clone()
                 },
-                AstroportAsset {
-                    info: ask_asset_info,
-                    amount: Uint128::zero(),
+                MarsError::InvalidParam {
+                    param_name: "min_price".to_string(),
+                    invalid_value: "1.2".to_string(),
+                    predicate: "<= 1.1 (max_price)".to_string(),
                 },
-            ],
-            total_share: Uint128::zero(),
-            price0_cumulative_last: Uint128::zero(), // token
-            price1_cumulative_last: Uint128::zero(), // uusd
-        };
+            ),
+            (
+                CircuitBreaker {
+                    max_change: Some(Decimal::zero()),
+                    ..circuit_breaker.clone()
+                },
+                MarsError::InvalidParam {
+                    param_name: "max_change".to_string(),
+                    invalid_value: "0".to_string(),
+                    predicate: "> 0".to_string(),
+                },
+            ),
+            (
+                CircuitBreaker {
+                    window: 0,
+                    ..circuit_breaker.clone()
+                },
+                MarsError::InvalidParam {
+                    param_name: "window".to_string(),
+                    invalid_value: "0".to_string(),
+                    predicate: "> 0".to_string(),
+                },
+            ),
+        ];
+        for (invalid_circuit_breaker, expected_err) in invalid_circuit_breakers {
+            let err =
+                set_circuit_breaker(&mut deps, "owner", Some(invalid_circuit_breaker)).unwrap_err();
+            assert_eq!(err, expected_err.into());
+        }
 
-        // set the cumulative price
-        cumulative_prices.price0_cumulative_last = Uint128::new(1_000_000000);
-        deps.querier
-            .set_astroport_pair_cumulative_prices("pair".to_string(), cumulative_prices.clone());
+        let res = set_circuit_breaker(&mut deps, "owner", Some(circuit_breaker.clone())).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "set_circuit_breaker"),
+                attr("asset", "cw20token"),
+                attr("circuit_breaker", "set"),
+            ]
+        );
+        let res: Option<CircuitBreakerResponse> = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::AssetCircuitBreaker {
+                    asset: asset.clone(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            res,
+            Some(CircuitBreakerResponse {
+                circuit_breaker,
+                state: CircuitBreakerState::default(),
+            })
+        );
 
-        // record first snapshot
-        let snapshot_time = 100_000;
+        let res = set_circuit_breaker(&mut deps, "owner", None).unwrap();
+        assert_eq!(res.attributes[2], attr("circuit_breaker", "none"));
+        assert!(!CIRCUIT_BREAKERS.has(&deps.storage, &asset_reference));
+    }
 
-        let msg = ExecuteMsg::RecordTwapSnapshots {
-            assets: vec![asset.clone()],
+    #[test]
+    fn test_circuit_breaker() {
+        let mut deps = th_setup();
+        let asset = Asset::Cw20 {
+            contract_addr: String::from("cw20token"),
         };
+        let asset_reference = asset.get_reference();
 
-        let response = execute(
-            deps.as_mut(),
-            mock_env_at_block_time(snapshot_time),
-            info.clone(),
-            msg.clone(),
-        )
-        .unwrap();
+        let set_price = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                         price: Decimal| {
+            PRICE_SOURCES
+                .save(
+                    &mut deps.storage,
+                    &asset_reference,
+                    &PriceSourceChecked::Fixed { price },
+                )
+                .unwrap();
+        };
+        let check = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                     block_time: u64| {
+            execute(
+                deps.as_mut(),
+                mock_env_at_block_time(block_time),
+                mock_info("anyone", &[]),
+                ExecuteMsg::CheckCircuitBreakers {
+                    assets: vec![asset.clone()],
+                },
+            )
+        };
+        let query_price = |deps: &OwnedDeps<MockStorage, MockApi, MarsMockQuerier>| {
+            query_asset_price(
+                deps.as_ref(),
+                mock_env_at_block_time(10_000),
+                asset_reference.clone(),
+            )
+        };
+
+        set_price(&mut deps, Decimal::one());
 
+        // asset must have a circuit breaker to be checked
+        let err = check(&mut deps, 1000).unwrap_err();
+        assert_eq!(err, ContractError::CircuitBreakerNotSet {});
+
+        CIRCUIT_BREAKERS
+            .save(
+                &mut deps.storage,
+                &asset_reference,
+                &CircuitBreaker {
+                    min_price: Some(Decimal::percent(50)),
+                    max_price: None,
+                    max_change: Some(Decimal::percent(10)),
+                    window: 3600,
+                },
+            )
+            .unwrap();
+
+        // first check records the price
+        let res = check(&mut deps, 1000).unwrap();
         assert_eq!(
-            response.attributes,
+            res.attributes,
             vec![
-                attr("action", "record_twap_snapshots"),
-                attr("timestamp", "100000"),
+                attr("action", "check_circuit_breakers"),
+                attr("timestamp", "1000"),
                 attr("asset", "cw20token"),
-                attr("price_cumulative", "1000000000"),
+                attr("price", "1"),
+                attr("status", "recorded"),
             ]
         );
 
-        let snapshots = ASTROPORT_TWAP_SNAPSHOTS
-            .load(deps.as_ref().storage, &reference)
+        // price is only recorded again once the window has elapsed
+        set_price(&mut deps, Decimal::percent(108));
+        let res = check(&mut deps, 4599).unwrap();
+        assert_eq!(res.attributes[4], attr("status", "ok"));
+        let res = check(&mut deps, 4600).unwrap();
+        assert_eq!(res.attributes[4], attr("status", "recorded"));
+        let state = CIRCUIT_BREAKER_STATES
+            .load(&deps.storage, &asset_reference)
             .unwrap();
-        assert_eq!(snapshots.len(), 1);
-        assert_eq!(snapshots[0].price_cumulative, Uint128::new(1_000_000000));
-        assert_eq!(snapshots[0].timestamp, snapshot_time);
-
-        // update the cumulative price
-        cumulative_prices.price0_cumulative_last = Uint128::new(2_000_000000);
-        deps.querier
-            .set_astroport_pair_cumulative_prices("pair".to_string(), cumulative_prices.clone());
-
-        // try to record a second snapshot within `tolerance` seconds
-        let snapshot_too_soon_time = snapshot_time + tolerance - 1;
+        assert_eq!(
+            state.last_price,
+            Some(PriceSnapshot {
+                price: Decimal::percent(108),
+                timestamp: 4600,
+            })
+        );
 
-        let response = execute(
-            deps.as_mut(),
-            mock_env_at_block_time(snapshot_too_soon_time),
-            info.clone(),
-            msg.clone(),
-        )
-        .unwrap();
+        // price queries fail if the price moved too much from the last recorded one, even before
+        // the breaker is tripped
+        set_price(&mut deps, Decimal::percent(120));
+        assert_eq!(
+            query_price(&deps).unwrap_err(),
+            ContractError::PriceChangeExceeded {
+                price: Decimal::percent(120),
+                last_price: Decimal::percent(108),
+                max_change: Decimal::percent(10),
+            }
+        );
 
+        // a breach that does not last a window does not trip the breaker
+        let res = check(&mut deps, 5000).unwrap();
+        assert_eq!(res.attributes[4], attr("status", "breached"));
+        let state = CIRCUIT_BREAKER_STATES
+            .load(&deps.storage, &asset_reference)
+            .unwrap();
+        assert_eq!(state.breached_since, Some(5000));
+        set_price(&mut deps, Decimal::percent(108));
+        let res = check(&mut deps, 5100).unwrap();
+        assert_eq!(res.attributes[4], attr("status", "ok"));
+        set_price(&mut deps, Decimal::percent(120));
+        let res = check(&mut deps, 8600).unwrap();
+        assert_eq!(res.attributes[4], attr("status", "breached"));
+
+        // checking trips the breaker once the breach held for a window, and price queries keep
+        // failing after the price recovers
+        let res = check(&mut deps, 12_199).unwrap();
+        assert_eq!(res.attributes[4], attr("status", "breached"));
+        let res = check(&mut deps, 12_200).unwrap();
+        assert_eq!(res.attributes[4], attr("status", "tripped"));
+        set_price(&mut deps, Decimal::percent(108));
         assert_eq!(
-            response.attributes,
-            vec![
-                attr("action", "record_twap_snapshots"),
-                attr("timestamp", "100599"),
-            ]
+            query_price(&deps).unwrap_err(),
+            ContractError::CircuitBreakerTripped {}
+        );
+        let res = check(&mut deps, 13_000).unwrap();
+        assert_eq!(
+            res.attributes[2..],
+            [attr("asset", "cw20token"), attr("status", "tripped")]
         );
-        assert!(response.events.len() == 0);
 
-        // record a second snapshot
-        let second_snapshot_time = snapshot_time + tolerance;
+        // only owner or guardian can reset
+        let reset_msg = ExecuteMsg::ResetCircuitBreaker {
+            asset: asset.clone(),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("somebody", &[]),
+            reset_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, MarsError::Unauthorized {}.into());
 
-        let response = execute(
+        let res = execute(
             deps.as_mut(),
-            mock_env_at_block_time(second_snapshot_time),
-            info.clone(),
-            msg.clone(),
+            mock_env(),
+            mock_info("guardian", &[]),
+            reset_msg,
         )
         .unwrap();
-
         assert_eq!(
-            response.attributes,
+            res.attributes,
             vec![
-                attr("action", "record_twap_snapshots"),
-                attr("timestamp", "100600"),
+                attr("action", "reset_circuit_breaker"),
                 attr("asset", "cw20token"),
-                attr("price_cumulative", "2000000000"),
             ]
         );
+        assert_eq!(query_price(&deps).unwrap(), Decimal::percent(108));
 
-        let snapshots = ASTROPORT_TWAP_SNAPSHOTS
-            .load(deps.as_ref().storage, &reference)
-            .unwrap();
-        assert_eq!(snapshots.len(), 2);
-        assert_eq!(snapshots[1].price_cumulative, Uint128::new(2_000_000000));
-        assert_eq!(snapshots[1].timestamp, second_snapshot_time);
-
-        // record a third snapshot and check that old snapshots are removed from state
-        let third_snapshot_time = second_snapshot_time + window_size + tolerance + 1;
-
-        execute(
-            deps.as_mut(),
-            mock_env_at_block_time(third_snapshot_time),
-            info.clone(),
-            msg.clone(),
-        )
-        .unwrap();
-
-        let snapshots = ASTROPORT_TWAP_SNAPSHOTS
-            .load(deps.as_ref().storage, &reference)
-            .unwrap();
-        assert_eq!(snapshots.len(), 1);
-        assert_eq!(snapshots[0].price_cumulative, Uint128::new(2_000_000000));
-        assert_eq!(snapshots[0].timestamp, third_snapshot_time);
+        // static bounds apply without any recorded price
+        set_price(&mut deps, Decimal::percent(49));
+        assert_eq!(
+            query_price(&deps).unwrap_err(),
+            ContractError::PriceBelowMin {
+                price: Decimal::percent(49),
+                min_price: Decimal::percent(50),
+            }
+        );
+        let res = check(&mut deps, 20_000).unwrap();
+        assert_eq!(res.attributes[4], attr("status", "breached"));
+        let res = check(&mut deps, 23_600).unwrap();
+        assert_eq!(res.attributes[4], attr("status", "tripped"));
     }
 
     #[test]
@@ -1004,6 +4155,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "terra")]
     fn test_query_asset_price_native() {
         let mut deps = th_setup();
         let asset = Asset::Native {
@@ -1039,6 +4191,338 @@ mod tests {
         assert_eq!(price, Decimal::from_ratio(4_u128, 1_u128));
     }
 
+    #[test]
+    #[cfg(feature = "terra")]
+    fn test_query_asset_price_native_custom_base_denom() {
+        let mut deps = th_setup();
+        let mut config = CONFIG.load(&deps.storage).unwrap();
+        config.base_denom = "uluna".to_string();
+        CONFIG.save(&mut deps.storage, &config).unwrap();
+
+        let asset = Asset::Native {
+            denom: String::from("nativecoin"),
+        };
+        let asset_reference = asset.get_reference();
+
+        deps.querier.set_native_exchange_rates(
+            "nativecoin".to_string(),
+            &[
+                ("uusd".to_string(), Decimal::from_ratio(4_u128, 1_u128)),
+                ("uluna".to_string(), Decimal::from_ratio(1_u128, 20_u128)),
+            ],
+        );
+
+        PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                asset_reference.as_slice(),
+                &PriceSourceChecked::Native {
+                    denom: "nativecoin".to_string(),
+                },
+            )
+            .unwrap();
+
+        let price: Decimal = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::AssetPriceByReference { asset_reference },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(price, Decimal::from_ratio(1_u128, 20_u128));
+    }
+
+    #[test]
+    fn test_query_asset_price_median() {
+        let mut deps = th_setup();
+        let asset = Asset::Cw20 {
+            contract_addr: String::from("cw20token"),
+        };
+        let asset_reference = asset.get_reference();
+
+        let fixed = |percent: u64| PriceSourceChecked::Fixed {
+            price: Decimal::percent(percent),
+        };
+        // errors as there are no snapshots
+        let failing = PriceSourceChecked::AstroportTwap {
+            pair_address: Addr::unchecked("pair"),
+            window_size: 3600,
+            tolerance: 600,
+        };
+
+        let query_median = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                            sources: Vec<PriceSourceChecked>,
+                            min_sources: u32| {
+            PRICE_SOURCES
+                .save(
+                    &mut deps.storage,
+                    &asset_reference,
+                    &PriceSourceChecked::Median {
+                        sources,
+                        min_sources,
+                    },
+                )
+                .unwrap();
+            query_asset_price(deps.as_ref(), mock_env(), asset_reference.clone())
+        };
+
+        // odd number of prices
+        let price = query_median(&mut deps, vec![fixed(120), fixed(100), fixed(500)], 3).unwrap();
+        assert_eq!(price, Decimal::percent(120));
+
+        // even number of prices, failing sources are ignored
+        let price = query_median(
+            &mut deps,
+            vec![
+                fixed(120),
+                failing.clone(),
+                fixed(100),
+                fixed(500),
+                fixed(90),
+            ],
+            3,
+        )
+        .unwrap();
+        assert_eq!(price, Decimal::percent(110));
+
+        // not enough sources returned a price
+        let err =
+            query_median(&mut deps, vec![fixed(120), failing.clone(), failing], 2).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::NotEnoughPriceSources {
+                succeeded: 1,
+                min_sources: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_query_asset_price_with_fallback() {
+        let mut deps = th_setup();
+        let asset = Asset::Cw20 {
+            contract_addr: String::from("cw20token"),
+        };
+        let asset_reference = asset.get_reference();
+
+        let set_sources = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                           primary: PriceSourceChecked,
+                           fallback_price: Decimal| {
+            PRICE_SOURCES
+                .save(&mut deps.storage, &asset_reference, &primary)
+                .unwrap();
+            FALLBACK_PRICE_SOURCES
+                .save(
+                    &mut deps.storage,
+                    &asset_reference,
+                    &FallbackPriceSourceChecked {
+                        price_source: PriceSourceChecked::Fixed {
+                            price: fallback_price,
+                        },
+                        max_deviation: Decimal::percent(5),
+                    },
+                )
+                .unwrap();
+        };
+        let query_price = |deps: &OwnedDeps<MockStorage, MockApi, MarsMockQuerier>| {
+            query_asset_price(deps.as_ref(), mock_env(), asset_reference.clone())
+        };
+
+        // primary price is returned if within deviation from the fallback one
+        set_sources(
+            &mut deps,
+            PriceSourceChecked::Fixed {
+                price: Decimal::one(),
+            },
+            Decimal::percent(105),
+        );
+        assert_eq!(query_price(&deps).unwrap(), Decimal::one());
+
+        // fail closed when the sources disagree too much
+        set_sources(
+            &mut deps,
+            PriceSourceChecked::Fixed {
+                price: Decimal::one(),
+            },
+            Decimal::percent(94),
+        );
+        assert_eq!(
+            query_price(&deps).unwrap_err(),
+            ContractError::PriceSourcesDeviation {
+                primary_price: Decimal::one(),
+                fallback_price: Decimal::percent(94),
+                max_deviation: Decimal::percent(5),
+            }
+        );
+
+        // fallback price is returned if primary source errors (TWAP without snapshots)
+        let twap_source = PriceSourceChecked::AstroportTwap {
+            pair_address: Addr::unchecked("pair"),
+            window_size: 3600,
+            tolerance: 600,
+        };
+        set_sources(&mut deps, twap_source.clone(), Decimal::percent(94));
+        assert_eq!(query_price(&deps).unwrap(), Decimal::percent(94));
+
+        // primary error is returned if both sources error
+        FALLBACK_PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                &asset_reference,
+                &FallbackPriceSourceChecked {
+                    price_source: twap_source,
+                    max_deviation: Decimal::percent(5),
+                },
+            )
+            .unwrap();
+        let err = query_price(&deps).unwrap_err();
+        assert_eq!(err, ContractError::NoSnapshotWithinTolerance {});
+    }
+
+    #[test]
+    fn test_query_asset_price_with_metadata() {
+        let mut deps = th_setup();
+        let asset = Asset::Cw20 {
+            contract_addr: String::from("cw20token"),
+        };
+        let asset_reference = asset.get_reference();
+
+        PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                &asset_reference,
+                &PriceSourceChecked::Feed {
+                    feeders: vec![Addr::unchecked("feeder_1"), Addr::unchecked("feeder_2")],
+                    min_feeders: 1,
+                    max_age: 600,
+                },
+            )
+            .unwrap();
+        PRICE_MAX_AGES
+            .save(&mut deps.storage, &asset_reference, &300)
+            .unwrap();
+        for (feeder, price, timestamp) in &[
+            ("feeder_1", Decimal::percent(100), 1000),
+            ("feeder_2", Decimal::percent(110), 1100),
+        ] {
+            FEED_PRICES
+                .save(
+                    &mut deps.storage,
+                    (&asset_reference, &Addr::unchecked(*feeder)),
+                    &FeedPrice {
+                        price: *price,
+                        timestamp: *timestamp,
+                    },
+                )
+                .unwrap();
+        }
+
+        let query_price = |deps: &OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                           block_time: u64| {
+            let res = query(
+                deps.as_ref(),
+                mock_env_at_block_time(block_time),
+                QueryMsg::AssetPriceWithMetadata {
+                    asset: asset.clone(),
+                },
+            )
+            .unwrap();
+            from_binary::<AssetPriceWithMetadataResponse>(&res).unwrap()
+        };
+
+        // timestamp is the one of the oldest feed price used
+        let res = query_price(&deps, 1200);
+        assert_eq!(
+            res,
+            AssetPriceWithMetadataResponse {
+                price: Decimal::percent(105),
+                timestamp: 1000,
+                price_source: "feed".to_string(),
+                is_fallback: false,
+                max_age: Some(300),
+            }
+        );
+        assert!(!res.is_stale(1300));
+        assert!(res.is_stale(1301));
+
+        // prices read from chain state are timestamped with the current block
+        FALLBACK_PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                &asset_reference,
+                &FallbackPriceSourceChecked {
+                    price_source: PriceSourceChecked::Fixed {
+                        price: Decimal::percent(120),
+                    },
+                    max_deviation: Decimal::percent(20),
+                },
+            )
+            .unwrap();
+        let res = query_price(&deps, 2000);
+        assert_eq!(
+            res,
+            AssetPriceWithMetadataResponse {
+                price: Decimal::percent(120),
+                timestamp: 2000,
+                price_source: "fixed".to_string(),
+                is_fallback: true,
+                max_age: Some(300),
+            }
+        );
+        assert!(!res.is_stale(2000));
+    }
+
+    #[test]
+    fn test_query_asset_price_redemption_rate() {
+        let mut deps = th_setup();
+        let query_msg = Binary::from(br#"{"state":{}}"#);
+        deps.querier.set_smart_query_response(
+            "hub",
+            query_msg.clone(),
+            Binary::from(br#"{"state":{"total_bonded":"1000","exchange_rate":1.05}}"#),
+        );
+
+        let asset_reference = b"lsd".to_vec();
+        PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                &asset_reference,
+                &PriceSourceChecked::RedemptionRate {
+                    contract: Addr::unchecked("hub"),
+                    query_msg_template: query_msg,
+                    response_path: vec!["state".to_string(), "exchange_rate".to_string()],
+                    underlying_asset: Asset::Native {
+                        denom: "ustake".to_string(),
+                    },
+                },
+            )
+            .unwrap();
+
+        // underlying asset must have a price
+        let err =
+            query_asset_price(deps.as_ref(), mock_env(), asset_reference.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            StdError::not_found("mars_core::oracle::PriceSource<cosmwasm_std::addresses::Addr>")
+                .into()
+        );
+
+        PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                b"ustake",
+                &PriceSourceChecked::Fixed {
+                    price: Decimal::from_ratio(3u128, 2u128),
+                },
+            )
+            .unwrap();
+        let price = query_asset_price(deps.as_ref(), mock_env(), asset_reference).unwrap();
+        assert_eq!(price, Decimal::from_ratio(1575u128, 1000u128));
+    }
+
     #[test]
     fn test_query_asset_price_astroport_spot() {
         let mut deps = th_setup();
@@ -1216,9 +4700,297 @@ mod tests {
                 (query_time - snapshot_time) * 10_u64.pow(TWAP_PRECISION.into())
             )
         );
+
+        // the price is as old as the snapshot the average starts at
+        let price_with_metadata = query_asset_price_with_metadata(
+            deps.as_ref(),
+            mock_env_at_block_time(query_time),
+            asset_reference,
+        )
+        .unwrap();
+        assert_eq!(price_with_metadata.timestamp, snapshot_time);
+    }
+
+    #[test]
+    fn test_query_asset_price_chained() {
+        let mut deps = th_setup();
+        let asset = Asset::Cw20 {
+            contract_addr: String::from("cw20token"),
+        };
+        let asset_reference = asset.get_reference();
+        let luna = Asset::Native {
+            denom: "uluna".to_string(),
+        };
+
+        deps.querier.set_astroport_pair(PairInfo {
+            asset_infos: [
+                AssetInfo::Token {
+                    contract_addr: Addr::unchecked("cw20token"),
+                },
+                AssetInfo::NativeToken {
+                    denom: "uluna".to_string(),
+                },
+            ],
+            contract_addr: Addr::unchecked("pair"),
+            liquidity_token: Addr::unchecked("lp"),
+            pair_type: PairType::Xyk {},
+        });
+        deps.querier.set_astroport_pair_simulation(
+            "pair".to_string(),
+            SimulationResponse {
+                return_amount: Uint128::new(24_000),
+                commission_amount: Uint128::new(1_000),
+                spread_amount: Uint128::zero(),
+            },
+        );
+
+        PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                &asset_reference,
+                &PriceSourceChecked::Chained {
+                    source: Box::new(PriceSourceChecked::AstroportSpot {
+                        pair_address: Addr::unchecked("pair"),
+                    }),
+                    quote_asset: luna.clone(),
+                },
+            )
+            .unwrap();
+
+        // quote asset must have a price
+        let err =
+            query_asset_price(deps.as_ref(), mock_env(), asset_reference.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            StdError::not_found("mars_core::oracle::PriceSource<cosmwasm_std::addresses::Addr>")
+                .into()
+        );
+
+        // price in luna (0.025) times the luna price
+        PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                &luna.get_reference(),
+                &PriceSourceChecked::Fixed {
+                    price: Decimal::from_ratio(80u128, 1u128),
+                },
+            )
+            .unwrap();
+        let price = query_asset_price(deps.as_ref(), mock_env(), asset_reference.clone()).unwrap();
+        assert_eq!(price, Decimal::from_ratio(2u128, 1u128));
+
+        // twap snapshots of a chained twap source record the price quoted in luna
+        PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                &asset_reference,
+                &PriceSourceChecked::Chained {
+                    source: Box::new(PriceSourceChecked::AstroportTwap {
+                        pair_address: Addr::unchecked("pair"),
+                        window_size: 3600,
+                        tolerance: 600,
+                    }),
+                    quote_asset: luna,
+                },
+            )
+            .unwrap();
+        deps.querier.set_astroport_pair_cumulative_prices(
+            "pair".to_string(),
+            CumulativePricesResponse {
+                assets: [
+                    AstroportAsset {
+                        info: AssetInfo::Token {
+                            contract_addr: Addr::unchecked("cw20token"),
+                        },
+                        amount: Uint128::zero(),
+                    },
+                    AstroportAsset {
+                        info: AssetInfo::NativeToken {
+                            denom: "uluna".to_string(),
+                        },
+                        amount: Uint128::zero(),
+                    },
+                ],
+                total_share: Uint128::zero(),
+                price0_cumulative_last: Uint128::new(1_000_000_000),
+                price1_cumulative_last: Uint128::new(7_000_000_000),
+            },
+        );
+
+        let msg = ExecuteMsg::RecordTwapSnapshots {
+            assets: vec![asset],
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg).unwrap();
+        assert_eq!(
+            res.attributes[3],
+            attr("price_cumulative", "1000000000".to_string())
+        );
+    }
+
+    #[test]
+    fn test_query_asset_price_astroport_fair_liquidity_token() {
+        let mut deps = th_setup();
+        let lp_reference = b"lp".to_vec();
+        let pool_assets = |amount0: u128, amount1: u128| {
+            [
+                AstroportAsset {
+                    info: AssetInfo::Token {
+                        contract_addr: Addr::unchecked("cw20token"),
+                    },
+                    amount: Uint128::new(amount0),
+                },
+                AstroportAsset {
+                    info: AssetInfo::NativeToken {
+                        denom: "uusd".to_string(),
+                    },
+                    amount: Uint128::new(amount1),
+                },
+            ]
+        };
+        let set_pool = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                        amount0: u128,
+                        amount1: u128| {
+            deps.querier.set_astroport_pair_pool(
+                "pair".to_string(),
+                PoolResponse {
+                    assets: pool_assets(amount0, amount1),
+                    total_share: Uint128::new(2_000_000),
+                },
+            );
+        };
+        let set_pair_type = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                             pair_type: PairType| {
+            let [asset0, asset1] = pool_assets(0, 0);
+            deps.querier.set_astroport_pair(PairInfo {
+                asset_infos: [asset0.info, asset1.info],
+                contract_addr: Addr::unchecked("pair"),
+                liquidity_token: Addr::unchecked("lp"),
+                pair_type,
+            });
+        };
+        let set_price = |deps: &mut OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+                         reference: &[u8],
+                         price: Decimal| {
+            PRICE_SOURCES
+                .save(
+                    &mut deps.storage,
+                    reference,
+                    &PriceSourceChecked::Fixed { price },
+                )
+                .unwrap();
+        };
+
+        PRICE_SOURCES
+            .save(
+                &mut deps.storage,
+                &lp_reference,
+                &PriceSourceChecked::AstroportFairLiquidityToken {
+                    pair_address: Addr::unchecked("pair"),
+                },
+            )
+            .unwrap();
+        set_price(&mut deps, b"cw20token", Decimal::from_ratio(4u128, 1u128));
+        set_price(&mut deps, b"uusd", Decimal::one());
+
+        // XYK pool at the oracle price: 1M tokens worth 4M uusd and 4M uusd
+        set_pair_type(&mut deps, PairType::Xyk {});
+        set_pool(&mut deps, 1_000_000, 4_000_000);
+        let price = query_asset_price(deps.as_ref(), mock_env(), lp_reference.clone()).unwrap();
+        assert_eq!(price, Decimal::from_ratio(4u128, 1u128));
+
+        // a large swap skewing the reserves (with the same k) doesn't change the price, while the
+        // reserves would be worth 10M uusd
+        set_pool(&mut deps, 2_000_000, 2_000_000);
+        let price = query_asset_price(deps.as_ref(), mock_env(), lp_reference.clone()).unwrap();
+        assert_eq!(price, Decimal::from_ratio(4u128, 1u128));
+
+        // StableSwap pool
+        set_pair_type(&mut deps, PairType::Stable {});
+        deps.querier.set_astroport_pair_config(
+            "pair".to_string(),
+            AstroportConfigResponse {
+                block_time_last: 0,
+                params: Some(
+                    to_binary(&StablePoolConfig {
+                        amp: cosmwasm_std::Decimal::from_ratio(100u128, 1u128),
+                    })
+                    .unwrap(),
+                ),
+            },
+        );
+        set_price(&mut deps, b"cw20token", Decimal::one());
+
+        // balanced pool: D is the sum of the reserves
+        set_pool(&mut deps, 1_000_000, 1_000_000);
+        let price = query_asset_price(deps.as_ref(), mock_env(), lp_reference.clone()).unwrap();
+        assert_eq!(price, Decimal::one());
+
+        // imbalanced pool: D is slightly below the sum of the reserves, each unit valued at the
+        // lowest price
+        set_price(&mut deps, b"cw20token", Decimal::percent(99));
+        set_pool(&mut deps, 1_500_000, 500_000);
+        let d = compute_stableswap_invariant(
+            Uint128::new(100),
+            Uint128::new(1_500_000),
+            Uint128::new(500_000),
+        )
+        .unwrap();
+        assert!(d < Uint128::new(2_000_000) && d > Uint128::new(1_990_000));
+        let price = query_asset_price(deps.as_ref(), mock_env(), lp_reference).unwrap();
+        assert_eq!(
+            price,
+            Decimal::from_ratio(d * Decimal::percent(99), Uint128::new(2_000_000))
+        );
+    }
+
+    #[test]
+    fn test_ema_decay() {
+        assert_eq!(ema_decay(0, 3600).unwrap(), Decimal::one());
+        assert_eq!(ema_decay(3600, 3600).unwrap(), Decimal::percent(50));
+        assert_eq!(ema_decay(7200, 3600).unwrap(), Decimal::percent(25));
+        assert_eq!(ema_decay(60 * 3600, 3600).unwrap(), Decimal::zero());
+
+        // 2^-0.5 and 2^-1.25, accurate to 1e-15
+        let tolerance = Decimal::from_ratio(1u128, 10u128.pow(15));
+        for (elapsed, expected) in &[
+            (1800u64, "0.707106781186547524"),
+            (4500u64, "0.420448207626857271"),
+        ] {
+            let decay = ema_decay(*elapsed, 3600).unwrap();
+            let expected = expected.parse::<Decimal>().unwrap();
+            let diff = if decay > expected {
+                decay - expected
+            } else {
+                expected - decay
+            };
+            assert!(diff < tolerance, "{} != {}", decay, expected);
+        }
+    }
+
+    #[test]
+    fn test_compute_stableswap_invariant() {
+        let compute = |amp: u128, amount0: u128, amount1: u128| {
+            compute_stableswap_invariant(
+                Uint128::new(amp),
+                Uint128::new(amount0),
+                Uint128::new(amount1),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(compute(100, 0, 0), Uint128::zero());
+        assert_eq!(compute(100, 1_000_000, 0), Uint128::new(1_000_000));
+        assert_eq!(compute(100, 1_000_000, 1_000_000), Uint128::new(2_000_000));
+
+        // the lower the amplification, the further from the sum an imbalanced pool's D is
+        let d_amp_1 = compute(1, 1_500_000, 500_000);
+        let d_amp_100 = compute(100, 1_500_000, 500_000);
+        assert!(d_amp_1 < d_amp_100);
+        assert!(d_amp_100 < Uint128::new(2_000_000));
     }
 
     #[test]
+    #[cfg(feature = "terra")]
     fn test_query_asset_price_stluna() {
         let mut deps = th_setup();
 
@@ -1289,6 +5061,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "terra")]
     fn test_query_asset_price_lunax() {
         let mut deps = th_setup();
 
@@ -1365,10 +5138,23 @@ mod tests {
 
         let msg = InstantiateMsg {
             owner: String::from("owner"),
+            address_provider_address: Some(String::from("address_provider")),
+            base_denom: None,
         };
         let info = mock_info("owner", &[]);
         instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
 
         deps
     }
+
+    fn th_twap_snapshots(
+        deps: &OwnedDeps<MockStorage, MockApi, MarsMockQuerier>,
+        asset_reference: &[u8],
+    ) -> Vec<AstroportTwapSnapshot> {
+        ASTROPORT_TWAP_SNAPSHOTS
+            .prefix(asset_reference)
+            .range(&deps.storage, None, None, Order::Ascending)
+            .map(|item| item.unwrap().1)
+            .collect()
+    }
 }
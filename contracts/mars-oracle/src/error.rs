@@ -1,5 +1,6 @@
-use cosmwasm_std::{OverflowError, StdError};
+use cosmwasm_std::{ConversionOverflowError, OverflowError, StdError};
 use mars_core::error::MarsError;
+use mars_core::math::decimal::Decimal;
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -13,17 +14,105 @@ pub enum ContractError {
     #[error("{0}")]
     Overflow(#[from] OverflowError),
 
+    #[error("{0}")]
+    ConversionOverflow(#[from] ConversionOverflowError),
+
     #[error("Price source is not TWAP")]
     PriceSourceNotTwap {},
 
+    #[error("Price source is not EMA")]
+    PriceSourceNotEma {},
+
+    #[error("No EMA price recorded")]
+    EmaPriceNotRecorded {},
+
+    #[error("EMA price was last recorded more than max staleness ago")]
+    EmaPriceStale {},
+
     #[error("Native price not found")]
     NativePriceNotFound {},
 
+    #[error("Native price source is only supported on Terra")]
+    NativePriceSourceNotSupported {},
+
     #[error("No TWAP snapshot within tolerance")]
     NoSnapshotWithinTolerance {},
 
     #[error("Invalid pair")]
     InvalidPair {},
+
+    #[error("Astroport pair type {pair_type} is not supported")]
+    UnsupportedPairType { pair_type: String },
+
+    #[error("StableSwap invariant did not converge")]
+    StableSwapInvariantNotConverged {},
+
+    #[error(
+        "Median price source min sources should be between 1 and {sources}, got {min_sources}"
+    )]
+    InvalidMedianMinSources { min_sources: u32, sources: u32 },
+
+    #[error("Redemption rate underlying asset cannot be the asset itself")]
+    RedemptionRateUnderlyingIsAsset {},
+
+    #[error("Chained price sources only support Astroport spot and TWAP sources")]
+    InvalidChainedPriceSource {},
+
+    #[error("Price source depends on the price of the asset itself")]
+    PriceSourceCycle {},
+
+    #[error("Median price source cannot contain another median")]
+    NestedMedianPriceSource {},
+
+    #[error("Asset can only use one Astroport TWAP source, fallback included")]
+    MultipleTwapPriceSources {},
+
+    #[error("Asset can only use one Astroport EMA source, fallback included")]
+    MultipleEmaPriceSources {},
+
+    #[error("Only {succeeded} price sources returned a price, {min_sources} required")]
+    NotEnoughPriceSources { succeeded: u32, min_sources: u32 },
+
+    #[error("Feed price source min feeders should be between 1 and {feeders}, got {min_feeders}")]
+    InvalidFeedMinFeeders { min_feeders: u32, feeders: u32 },
+
+    #[error("Feeder {feeder} is listed more than once")]
+    DuplicateFeeder { feeder: String },
+
+    #[error("Feed price cannot be zero")]
+    ZeroFeedPrice {},
+
+    #[error("Only {fresh} feeders pushed a recent enough price, {min_feeders} required")]
+    NotEnoughFeedPrices { fresh: u32, min_feeders: u32 },
+
+    #[error("Asset has no price source")]
+    PriceSourceNotSet {},
+
+    #[error("Primary price {primary_price} and fallback price {fallback_price} deviate more than {max_deviation}")]
+    PriceSourcesDeviation {
+        primary_price: Decimal,
+        fallback_price: Decimal,
+        max_deviation: Decimal,
+    },
+
+    #[error("Asset has no circuit breaker")]
+    CircuitBreakerNotSet {},
+
+    #[error("Circuit breaker is tripped, asset price is unavailable until it is reset")]
+    CircuitBreakerTripped {},
+
+    #[error("Price {price} is below the circuit breaker min price {min_price}")]
+    PriceBelowMin { price: Decimal, min_price: Decimal },
+
+    #[error("Price {price} is above the circuit breaker max price {max_price}")]
+    PriceAboveMax { price: Decimal, max_price: Decimal },
+
+    #[error("Price {price} changed more than {max_change} from last recorded price {last_price}")]
+    PriceChangeExceeded {
+        price: Decimal,
+        last_price: Decimal,
+        max_change: Decimal,
+    },
 }
 
 impl From<ContractError> for StdError {
@@ -0,0 +1,243 @@
+//! Minimal JSON reader used to extract a value from the response of an arbitrary contract query.
+//! `serde-json-wasm` can only deserialize into known types, and `serde_json` can't be used on
+//! chain as it relies on floats, so the response is walked by hand.
+
+use cosmwasm_std::{StdError, StdResult};
+
+/// Returns the value found by following `path` (a list of object keys) in the `json` document.
+/// The value must be a JSON string, whose content is returned, or a JSON number, whose literal
+/// is returned
+pub fn extract_json_value(json: &[u8], path: &[String]) -> StdResult<String> {
+    let mut reader = Reader { json, pos: 0 };
+
+    for key in path {
+        reader.find_key(key)?;
+    }
+
+    let value = match reader.peek()? {
+        b'"' => reader.read_string()?,
+        b'-' | b'0'..=b'9' => reader.read_literal()?,
+        _ => return Err(invalid_json("value is not a string or a number")),
+    };
+
+    Ok(value)
+}
+
+fn invalid_json(reason: &str) -> StdError {
+    StdError::generic_err(format!("Invalid JSON: {}", reason))
+}
+
+struct Reader<'a> {
+    json: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Next non whitespace byte, without consuming it
+    fn peek(&mut self) -> StdResult<u8> {
+        while let Some(byte) = self.json.get(self.pos) {
+            match byte {
+                b' ' | b'\n' | b'\r' | b'\t' => self.pos += 1,
+                _ => return Ok(*byte),
+            }
+        }
+        Err(invalid_json("unexpected end of input"))
+    }
+
+    fn expect(&mut self, expected: u8) -> StdResult<()> {
+        if self.peek()? != expected {
+            return Err(invalid_json(&format!("expected '{}'", expected as char)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Moves to the value of `key` in the object starting at the current position
+    fn find_key(&mut self, key: &str) -> StdResult<()> {
+        self.expect(b'{')?;
+        if self.peek()? == b'}' {
+            return Err(StdError::generic_err(format!("Key not found: {}", key)));
+        }
+
+        loop {
+            let current_key = self.read_string()?;
+            self.expect(b':')?;
+            if current_key == key {
+                return Ok(());
+            }
+            self.skip_value()?;
+
+            match self.peek()? {
+                b',' => self.pos += 1,
+                b'}' => return Err(StdError::generic_err(format!("Key not found: {}", key))),
+                _ => return Err(invalid_json("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn read_string(&mut self) -> StdResult<String> {
+        self.expect(b'"')?;
+
+        let mut bytes = vec![];
+        loop {
+            let byte = *self
+                .json
+                .get(self.pos)
+                .ok_or_else(|| invalid_json("unterminated string"))?;
+            self.pos += 1;
+
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let escaped = *self
+                        .json
+                        .get(self.pos)
+                        .ok_or_else(|| invalid_json("unterminated string"))?;
+                    self.pos += 1;
+
+                    let unescaped = match escaped {
+                        b'"' | b'\\' | b'/' => escaped as char,
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let hex = self
+                                .json
+                                .get(self.pos..self.pos + 4)
+                                .and_then(|hex| std::str::from_utf8(hex).ok())
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .ok_or_else(|| invalid_json("invalid unicode escape"))?;
+                            self.pos += 4;
+                            std::char::from_u32(hex)
+                                .ok_or_else(|| invalid_json("invalid unicode escape"))?
+                        }
+                        _ => return Err(invalid_json("invalid escape")),
+                    };
+                    let mut buffer = [0; 4];
+                    bytes.extend_from_slice(unescaped.encode_utf8(&mut buffer).as_bytes());
+                }
+                _ => bytes.push(byte),
+            }
+        }
+
+        String::from_utf8(bytes).map_err(|_| invalid_json("invalid UTF-8 string"))
+    }
+
+    /// Reads a number, `true`, `false` or `null`
+    fn read_literal(&mut self) -> StdResult<String> {
+        self.peek()?;
+        let start = self.pos;
+        while let Some(byte) = self.json.get(self.pos) {
+            match byte {
+                b',' | b'}' | b']' | b' ' | b'\n' | b'\r' | b'\t' => break,
+                _ => self.pos += 1,
+            }
+        }
+        if start == self.pos {
+            return Err(invalid_json("expected a value"));
+        }
+
+        String::from_utf8(self.json[start..self.pos].to_vec())
+            .map_err(|_| invalid_json("invalid UTF-8 literal"))
+    }
+
+    fn skip_value(&mut self) -> StdResult<()> {
+        match self.peek()? {
+            b'"' => {
+                self.read_string()?;
+            }
+            b'{' => self.skip_sequence(b'{', b'}', true)?,
+            b'[' => self.skip_sequence(b'[', b']', false)?,
+            _ => {
+                self.read_literal()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips an object (`is_object`) or an array, whose elements are skipped recursively
+    fn skip_sequence(&mut self, open: u8, close: u8, is_object: bool) -> StdResult<()> {
+        self.expect(open)?;
+        if self.peek()? == close {
+            self.pos += 1;
+            return Ok(());
+        }
+
+        loop {
+            if is_object {
+                self.read_string()?;
+                self.expect(b':')?;
+            }
+            self.skip_value()?;
+
+            match self.peek()? {
+                b',' => self.pos += 1,
+                byte if byte == close => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                _ => return Err(invalid_json("unterminated object or array")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn test_extract_json_value() {
+        let json = br#"{
+            "total_native_token": "1000000",
+            "validators": [{"address": "val1", "weight": 1}, {"address": "val2"}],
+            "paused": false,
+            "nested": {"empty": {}, "list": [], "state": {"exchange_rate": "1.052", "rate": 1.2}},
+            "escaped \"key\"": "value\n\u00e9"
+        }"#;
+
+        assert_eq!(
+            extract_json_value(json, &path(&["total_native_token"])).unwrap(),
+            "1000000"
+        );
+        assert_eq!(
+            extract_json_value(json, &path(&["nested", "state", "exchange_rate"])).unwrap(),
+            "1.052"
+        );
+        assert_eq!(
+            extract_json_value(json, &path(&["nested", "state", "rate"])).unwrap(),
+            "1.2"
+        );
+        assert_eq!(
+            extract_json_value(json, &path(&["escaped \"key\""])).unwrap(),
+            "value\né"
+        );
+
+        assert_eq!(
+            extract_json_value(json, &path(&["nested", "state", "missing"])).unwrap_err(),
+            StdError::generic_err("Key not found: missing")
+        );
+        assert_eq!(
+            extract_json_value(json, &path(&["nested", "empty", "missing"])).unwrap_err(),
+            StdError::generic_err("Key not found: missing")
+        );
+        assert_eq!(
+            extract_json_value(json, &path(&["validators", "address"])).unwrap_err(),
+            StdError::generic_err("Invalid JSON: expected '{'")
+        );
+        assert_eq!(
+            extract_json_value(json, &path(&["paused"])).unwrap_err(),
+            StdError::generic_err("Invalid JSON: value is not a string or a number")
+        );
+        assert_eq!(
+            extract_json_value(br#"{"rate": "1.0"#, &path(&["rate"])).unwrap_err(),
+            StdError::generic_err("Invalid JSON: unterminated string")
+        );
+    }
+}
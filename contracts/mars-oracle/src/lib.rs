@@ -1,5 +1,6 @@
 pub mod contract;
 pub mod error;
+pub mod json;
 pub mod state;
 
 pub use mars_core::oracle::*;
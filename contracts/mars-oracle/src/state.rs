@@ -1,7 +1,23 @@
-use cw_storage_plus::{Item, Map};
+use cosmwasm_std::Addr;
+use cw_storage_plus::{Item, Map, U64Key};
 
-use crate::{AstroportTwapSnapshot, Config, PriceSourceChecked};
+use crate::{
+    AstroportTwapSnapshot, CircuitBreaker, CircuitBreakerState, Config, FallbackPriceSourceChecked,
+    FeedPrice, PriceSnapshot, PriceSourceChecked,
+};
 
 pub const CONFIG: Item<Config> = Item::new("config");
 pub const PRICE_SOURCES: Map<&[u8], PriceSourceChecked> = Map::new("price_configs");
-pub const ASTROPORT_TWAP_SNAPSHOTS: Map<&[u8], Vec<AstroportTwapSnapshot>> = Map::new("snapshots");
+pub const FALLBACK_PRICE_SOURCES: Map<&[u8], FallbackPriceSourceChecked> =
+    Map::new("fallback_price_sources");
+pub const ASTROPORT_TWAP_SNAPSHOTS: Map<(&[u8], U64Key), AstroportTwapSnapshot> =
+    Map::new("twap_snapshots");
+/// Snapshots stored as one vector per asset, only read to migrate them to `ASTROPORT_TWAP_SNAPSHOTS`
+pub const LEGACY_ASTROPORT_TWAP_SNAPSHOTS: Map<&[u8], Vec<AstroportTwapSnapshot>> =
+    Map::new("snapshots");
+pub const ASTROPORT_EMA_PRICES: Map<&[u8], PriceSnapshot> = Map::new("astroport_ema_prices");
+pub const PRICE_MAX_AGES: Map<&[u8], u64> = Map::new("price_max_ages");
+pub const CIRCUIT_BREAKERS: Map<&[u8], CircuitBreaker> = Map::new("circuit_breakers");
+pub const CIRCUIT_BREAKER_STATES: Map<&[u8], CircuitBreakerState> =
+    Map::new("circuit_breaker_states");
+pub const FEED_PRICES: Map<(&[u8], &Addr), FeedPrice> = Map::new("feed_prices");
@@ -3,7 +3,9 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use mars_protocol_rewards_collector::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
+use mars_core::swapping::SwapRoute;
+
+use mars_protocol_rewards_collector::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
 use mars_protocol_rewards_collector::{AssetConfig, Config};
 
 fn main() {
@@ -15,7 +17,9 @@ fn main() {
     export_schema(&schema_for!(InstantiateMsg), &out_dir);
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
 
     export_schema(&schema_for!(Config), &out_dir);
     export_schema(&schema_for!(AssetConfig), &out_dir);
+    export_schema(&schema_for!(SwapRoute), &out_dir);
 }
@@ -1,25 +1,33 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_binary, Binary, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Response, StdResult, Uint128,
-    WasmMsg,
+    to_binary, Binary, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Order, Response, StdResult,
+    Storage, Uint128, WasmMsg,
 };
+use cw2::set_contract_version;
+use cw_storage_plus::Bound;
 
 use astroport::asset::AssetInfo;
 
 use mars_core::asset::{build_send_asset_with_tax_deduction_msg, get_asset_balance, Asset};
 use mars_core::error::MarsError;
 use mars_core::helpers::{option_string_to_addr, zero_address};
-use mars_core::swapping::execute_swap;
+use mars_core::math::decimal::Decimal;
+use mars_core::swapping::{execute_swap, swap_route_key, OracleDeviation, SwapRoute, SwapSlippage};
+use mars_core::versioning::{migrate_contract, MigrationStep, Version};
 
 use mars_core::address_provider::{self, MarsContract};
 use mars_core::red_bank;
 
 use crate::error::ContractError;
-use crate::msg::{CreateOrUpdateConfig, ExecuteMsg, InstantiateMsg, QueryMsg};
-use crate::state::{ASSET_CONFIG, CONFIG};
+use crate::msg::{CreateOrUpdateConfig, ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
+use crate::state::{ASSET_CONFIG, CONFIG, SWAP_ROUTES};
 use crate::{AssetConfig, Config};
 
+// version info for migration info
+const CONTRACT_NAME: &str = "crates.io:protocol-rewards-collector";
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 // INIT
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -29,6 +37,8 @@ pub fn instantiate(
     _info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
     // Destructuring a struct’s fields into separate variables in order to force
     // compile error if we add more params
     let CreateOrUpdateConfig {
@@ -38,6 +48,8 @@ pub fn instantiate(
         treasury_fee_share,
         astroport_factory_address,
         astroport_max_spread,
+        swap_max_oracle_deviation,
+        swap_venue,
     } = msg.config;
 
     // All fields should be available
@@ -67,6 +79,11 @@ pub fn instantiate(
             zero_address(),
         )?,
         astroport_max_spread: astroport_max_spread.unwrap(),
+        swap_max_oracle_deviation,
+        swap_venue: swap_venue
+            .map(|swap_venue| swap_venue.to_checked(deps.api))
+            .transpose()?
+            .unwrap_or_default(),
     };
 
     config.validate()?;
@@ -99,12 +116,21 @@ pub fn execute(
         ExecuteMsg::SwapAssetToUusd {
             offer_asset_info,
             amount,
+            minimum_receive,
         } => Ok(execute_swap_asset_to_uusd(
             deps,
             env,
             offer_asset_info,
             amount,
+            minimum_receive,
         )?),
+        ExecuteMsg::SetSwapRoute {
+            offer_asset_info,
+            hops,
+        } => execute_set_swap_route(deps, env, info, offer_asset_info, hops),
+        ExecuteMsg::SetSwapMaxOracleDeviation { max_deviation } => {
+            execute_set_swap_max_oracle_deviation(deps, env, info, max_deviation)
+        }
         ExecuteMsg::ExecuteCosmosMsg(cosmos_msg) => {
             Ok(execute_execute_cosmos_msg(deps, env, info, cosmos_msg)?)
         }
@@ -133,6 +159,8 @@ pub fn execute_update_config(
         treasury_fee_share,
         astroport_factory_address,
         astroport_max_spread,
+        swap_max_oracle_deviation,
+        swap_venue,
     } = new_config;
 
     config.owner = option_string_to_addr(deps.api, owner, config.owner)?;
@@ -149,6 +177,12 @@ pub fn execute_update_config(
         config.astroport_factory_address,
     )?;
     config.astroport_max_spread = astroport_max_spread.unwrap_or(config.astroport_max_spread);
+    if swap_max_oracle_deviation.is_some() {
+        config.swap_max_oracle_deviation = swap_max_oracle_deviation;
+    }
+    if let Some(swap_venue) = swap_venue {
+        config.swap_venue = swap_venue.to_checked(deps.api)?;
+    }
 
     config.validate()?;
 
@@ -329,26 +363,107 @@ pub fn execute_swap_asset_to_uusd(
     env: Env,
     offer_asset_info: AssetInfo,
     amount: Option<Uint128>,
+    minimum_receive: Option<Uint128>,
 ) -> StdResult<Response> {
     let config = CONFIG.load(deps.storage)?;
 
-    let ask_asset_info = AssetInfo::NativeToken {
-        denom: "uusd".to_string(),
+    let ask_asset_info = uusd_asset_info();
+
+    let route = SWAP_ROUTES
+        .may_load(deps.storage, &swap_route_key(&offer_asset_info))?
+        .unwrap_or_else(|| SwapRoute::direct(offer_asset_info));
+    let slippage = SwapSlippage {
+        max_spread: Some(config.astroport_max_spread),
+        minimum_receive,
+        max_oracle_deviation: config.swap_max_oracle_deviation.map(|max_deviation| {
+            OracleDeviation {
+                address_provider_address: config.address_provider_address.clone(),
+                max_deviation,
+            }
+        }),
     };
-
-    let astroport_max_spread = Some(config.astroport_max_spread);
+    let swap_adapter = config.swap_venue.adapter(config.astroport_factory_address);
 
     execute_swap(
         deps,
         env,
-        offer_asset_info,
+        route,
         ask_asset_info,
         amount,
-        config.astroport_factory_address,
-        astroport_max_spread,
+        swap_adapter.as_ref(),
+        slippage,
     )
 }
 
+/// Set the route used to swap an asset to uusd
+pub fn execute_set_swap_route(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    offer_asset_info: AssetInfo,
+    hops: Vec<AssetInfo>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != config.owner {
+        return Err(MarsError::Unauthorized {}.into());
+    }
+
+    let key = swap_route_key(&offer_asset_info);
+    let route = SwapRoute {
+        offer_asset_info,
+        hops,
+    };
+    route.validate(&uusd_asset_info())?;
+
+    if route.hops.is_empty() {
+        SWAP_ROUTES.remove(deps.storage, &key);
+    } else {
+        SWAP_ROUTES.save(deps.storage, &key, &route)?;
+    }
+
+    let hops: Vec<String> = route.hops.iter().map(|hop| hop.to_string()).collect();
+    let res = Response::new()
+        .add_attribute("action", "set_swap_route")
+        .add_attribute("offer_asset", route.offer_asset_info.to_string())
+        .add_attribute("route", hops.join(","));
+    Ok(res)
+}
+
+pub fn execute_set_swap_max_oracle_deviation(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    max_deviation: Option<Decimal>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+
+    if info.sender != config.owner {
+        return Err(MarsError::Unauthorized {}.into());
+    }
+
+    config.swap_max_oracle_deviation = max_deviation;
+    config.validate()?;
+
+    CONFIG.save(deps.storage, &config)?;
+
+    let res = Response::new()
+        .add_attribute("action", "set_swap_max_oracle_deviation")
+        .add_attribute(
+            "max_deviation",
+            max_deviation.map_or("none".to_string(), |max_deviation| {
+                max_deviation.to_string()
+            }),
+        );
+    Ok(res)
+}
+
+fn uusd_asset_info() -> AssetInfo {
+    AssetInfo::NativeToken {
+        denom: "uusd".to_string(),
+    }
+}
+
 /// Execute Cosmos message
 pub fn execute_execute_cosmos_msg(
     deps: DepsMut,
@@ -376,6 +491,12 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::Config {} => to_binary(&query_config(deps)?),
         QueryMsg::AssetConfig { asset } => to_binary(&query_asset_config(deps, asset)?),
+        QueryMsg::SwapRoute { offer_asset_info } => {
+            to_binary(&query_swap_route(deps, offer_asset_info)?)
+        }
+        QueryMsg::SwapRoutes { start_after, limit } => {
+            to_binary(&query_swap_routes(deps, start_after, limit)?)
+        }
     }
 }
 
@@ -395,12 +516,73 @@ fn query_asset_config(deps: Deps, asset: Asset) -> StdResult<AssetConfig> {
     Ok(asset_config)
 }
 
+fn query_swap_route(deps: Deps, offer_asset_info: AssetInfo) -> StdResult<SwapRoute> {
+    let route = SWAP_ROUTES
+        .may_load(deps.storage, &swap_route_key(&offer_asset_info))?
+        .unwrap_or_else(|| SwapRoute::direct(offer_asset_info));
+
+    Ok(route)
+}
+
+const PAGINATION_DEFAULT_LIMIT: u32 = 10;
+const PAGINATION_MAX_LIMIT: u32 = 30;
+
+fn query_swap_routes(
+    deps: Deps,
+    start_after: Option<AssetInfo>,
+    limit: Option<u32>,
+) -> StdResult<Vec<SwapRoute>> {
+    let start = start_after.map(|asset_info| Bound::exclusive(swap_route_key(&asset_info)));
+    let limit = limit
+        .unwrap_or(PAGINATION_DEFAULT_LIMIT)
+        .min(PAGINATION_MAX_LIMIT) as usize;
+
+    SWAP_ROUTES
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (_, route) = item?;
+            Ok(route)
+        })
+        .collect()
+}
+
+// MIGRATION
+
+/// State migrations, run when migrating from a version older than theirs
+const MIGRATION_STEPS: &[MigrationStep] = &[MigrationStep {
+    version: Version::new(1, 1, 0),
+    migrate: migrate_v1_1_0,
+}];
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let attributes = migrate_contract(
+        deps.storage,
+        CONTRACT_NAME,
+        CONTRACT_VERSION,
+        MIGRATION_STEPS,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attributes(attributes))
+}
+
+/// Stores the swap settings added to the config in 1.1.0 (no oracle deviation, Astroport venue)
+fn migrate_v1_1_0(storage: &mut dyn Storage) -> StdResult<()> {
+    let config = CONFIG.load(storage)?;
+    CONFIG.save(storage, &config)
+}
+
 // TESTS
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use mars_core::swapping::{PoolManagerExecuteMsg, SwapVenueChecked, SwapVenueUnchecked};
 
+    use astroport::asset::Asset as AstroportAsset;
     use cosmwasm_std::{
         attr, coin, from_binary,
         testing::{mock_env, MockApi, MockStorage, MOCK_CONTRACT_ADDR},
@@ -430,6 +612,8 @@ mod tests {
             treasury_fee_share: Some(Decimal::from_ratio(1u128, 10u128)),
             astroport_factory_address: Some("astroport".to_string()),
             astroport_max_spread: Some(astroport_max_spread),
+            swap_max_oracle_deviation: None,
+            swap_venue: None,
         };
 
         let info = mock_info("owner");
@@ -444,6 +628,8 @@ mod tests {
             treasury_fee_share: None,
             astroport_factory_address: None,
             astroport_max_spread: None,
+            swap_max_oracle_deviation: None,
+            swap_venue: None,
         };
         let msg = InstantiateMsg {
             config: empty_config,
@@ -531,6 +717,7 @@ mod tests {
         assert_eq!(value.treasury_fee_share, treasury_fee_share);
         assert_eq!(value.astroport_factory_address, "astroport");
         assert_eq!(value.astroport_max_spread, astroport_max_spread);
+        assert_eq!(value.swap_venue, SwapVenueChecked::default());
     }
 
     #[test]
@@ -547,6 +734,8 @@ mod tests {
             treasury_fee_share: Some(treasury_fee_share),
             astroport_factory_address: Some("astroport".to_string()),
             astroport_max_spread: Some(astroport_max_spread),
+            swap_max_oracle_deviation: None,
+            swap_venue: None,
         };
 
         // *
@@ -632,6 +821,10 @@ mod tests {
             treasury_fee_share: Some(treasury_fee_share),
             astroport_factory_address: Some("new_astroport".to_string()),
             astroport_max_spread: Some(astroport_max_spread),
+            swap_max_oracle_deviation: None,
+            swap_venue: Some(SwapVenueUnchecked::PoolManager {
+                address: "pool_manager".to_string(),
+            }),
         };
         let msg = ExecuteMsg::UpdateConfig {
             config: config.clone(),
@@ -664,6 +857,12 @@ mod tests {
             new_config.astroport_max_spread,
             config.astroport_max_spread.unwrap()
         );
+        assert_eq!(
+            new_config.swap_venue,
+            SwapVenueChecked::PoolManager {
+                address: Addr::unchecked("pool_manager")
+            }
+        );
     }
 
     #[test]
@@ -1196,6 +1395,185 @@ mod tests {
 
     // TEST HELPERS
 
+    #[test]
+    fn test_set_swap_max_oracle_deviation() {
+        let mut deps = th_setup(&[]);
+
+        let msg = ExecuteMsg::UpdateConfig {
+            config: CreateOrUpdateConfig {
+                owner: None,
+                address_provider_address: None,
+                safety_fund_fee_share: None,
+                treasury_fee_share: None,
+                astroport_factory_address: None,
+                astroport_max_spread: None,
+                swap_max_oracle_deviation: Some(Decimal::percent(5)),
+                swap_venue: Some(SwapVenueUnchecked::PoolManager {
+                    address: "pool_manager".to_string(),
+                }),
+            },
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("owner"), msg).unwrap();
+
+        // only owner can set it
+        let msg = ExecuteMsg::SetSwapMaxOracleDeviation {
+            max_deviation: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("somebody"),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Mars(MarsError::Unauthorized {}));
+
+        // owner can remove it
+        let res = execute(deps.as_mut(), mock_env(), mock_info("owner"), msg).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "set_swap_max_oracle_deviation"),
+                attr("max_deviation", "none"),
+            ]
+        );
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        assert_eq!(config.swap_max_oracle_deviation, None);
+
+        // and set it again
+        let msg = ExecuteMsg::SetSwapMaxOracleDeviation {
+            max_deviation: Some(Decimal::percent(3)),
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("owner"), msg).unwrap();
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        assert_eq!(config.swap_max_oracle_deviation, Some(Decimal::percent(3)));
+    }
+
+    #[test]
+    fn test_swap_routes() {
+        let mut deps = th_setup(&[]);
+
+        let msg = ExecuteMsg::UpdateConfig {
+            config: CreateOrUpdateConfig {
+                owner: None,
+                address_provider_address: None,
+                safety_fund_fee_share: None,
+                treasury_fee_share: None,
+                astroport_factory_address: None,
+                astroport_max_spread: None,
+                swap_max_oracle_deviation: Some(Decimal::percent(5)),
+                swap_venue: Some(SwapVenueUnchecked::PoolManager {
+                    address: "pool_manager".to_string(),
+                }),
+            },
+        };
+        execute(deps.as_mut(), mock_env(), mock_info("owner"), msg).unwrap();
+
+        let cw20_asset_info = AssetInfo::Token {
+            contract_addr: Addr::unchecked("somecw20"),
+        };
+        let uluna_asset_info = AssetInfo::NativeToken {
+            denom: "uluna".to_string(),
+        };
+        deps.querier.set_cw20_balances(
+            Addr::unchecked("somecw20"),
+            &[(Addr::unchecked(MOCK_CONTRACT_ADDR), Uint128::new(3_000))],
+        );
+        deps.querier
+            .set_oracle_price(b"somecw20".to_vec(), Decimal::from_ratio(1u128, 2u128));
+
+        // only owner can set swap routes
+        let msg = ExecuteMsg::SetSwapRoute {
+            offer_asset_info: cw20_asset_info.clone(),
+            hops: vec![uluna_asset_info.clone()],
+        };
+        let error_res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("somebody"),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(error_res, MarsError::Unauthorized {}.into());
+
+        // route cannot go through the same asset twice
+        let invalid_msg = ExecuteMsg::SetSwapRoute {
+            offer_asset_info: cw20_asset_info.clone(),
+            hops: vec![uluna_asset_info.clone(), uluna_asset_info.clone()],
+        };
+        let error_res =
+            execute(deps.as_mut(), mock_env(), mock_info("owner"), invalid_msg).unwrap_err();
+        assert_eq!(
+            error_res,
+            StdError::generic_err("Swap route goes through uluna more than once").into()
+        );
+
+        execute(deps.as_mut(), mock_env(), mock_info("owner"), msg).unwrap();
+
+        let routes: Vec<SwapRoute> = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::SwapRoutes {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            routes,
+            vec![SwapRoute {
+                offer_asset_info: cw20_asset_info.clone(),
+                hops: vec![uluna_asset_info.clone()],
+            }]
+        );
+
+        // assets without route are swapped directly
+        let route: SwapRoute = from_binary(
+            &query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::SwapRoute {
+                    offer_asset_info: uluna_asset_info.clone(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(route, SwapRoute::direct(uluna_asset_info.clone()));
+
+        // swap goes through the route on the pool manager
+        let msg = ExecuteMsg::SwapAssetToUusd {
+            offer_asset_info: cw20_asset_info.clone(),
+            amount: None,
+            minimum_receive: Some(Uint128::new(2_500)),
+        };
+        let res = execute(deps.as_mut(), mock_env(), mock_info("somebody"), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "somecw20".to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Send {
+                    contract: "pool_manager".to_string(),
+                    amount: Uint128::new(3_000),
+                    msg: to_binary(&PoolManagerExecuteMsg::SwapRoute {
+                        offer_asset: AstroportAsset {
+                            info: cw20_asset_info,
+                            amount: Uint128::new(3_000),
+                        },
+                        path: vec![uluna_asset_info, uusd_asset_info()],
+                        minimum_receive: Some(Uint128::new(2_500)),
+                    })
+                    .unwrap(),
+                })
+                .unwrap(),
+                funds: vec![],
+            }))]
+        );
+    }
+
     fn th_setup(contract_balances: &[Coin]) -> OwnedDeps<MockStorage, MockApi, MarsMockQuerier> {
         let mut deps = mock_dependencies(contract_balances);
         let info = mock_info("owner");
@@ -1206,6 +1584,8 @@ mod tests {
             treasury_fee_share: Some(Decimal::percent(20)),
             astroport_factory_address: Some("astroport".to_string()),
             astroport_max_spread: Some(StdDecimal::percent(1)),
+            swap_max_oracle_deviation: None,
+            swap_venue: None,
         };
         let msg = InstantiateMsg { config };
         instantiate(deps.as_mut(), mock_env(), info, msg).unwrap();
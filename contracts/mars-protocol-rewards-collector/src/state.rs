@@ -1,6 +1,9 @@
 use cw_storage_plus::{Item, Map};
 
+use mars_core::swapping::SwapRoute;
+
 use crate::{AssetConfig, Config};
 
 pub const CONFIG: Item<Config> = Item::new("config");
 pub const ASSET_CONFIG: Map<&[u8], AssetConfig> = Map::new("assets");
+pub const SWAP_ROUTES: Map<&[u8], SwapRoute> = Map::new("swap_routes");
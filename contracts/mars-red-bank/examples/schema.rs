@@ -3,10 +3,11 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use mars_red_bank::msg::{ExecuteMsg, InstantiateMsg, QueryMsg, ReceiveMsg};
+use mars_red_bank::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, ReceiveMsg};
 use mars_red_bank::{
-    ConfigResponse, Market, MarketsListResponse, UserAssetDebtResponse, UserCollateralResponse,
-    UserDebtResponse, UserPositionResponse,
+    ConfigResponse, CreditLineResponse, Market, MarketHistoryResponse, MarketRiskParamsResponse,
+    MarketsListResponse, OutflowCapacityResponse, UserAssetDebtResponse, UserCollateralResponse,
+    UserDebtResponse, UserPositionResponse, WithdrawQueueResponse,
 };
 
 fn main() {
@@ -18,13 +19,20 @@ fn main() {
     export_schema(&schema_for!(InstantiateMsg), &out_dir);
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
     export_schema(&schema_for!(ReceiveMsg), &out_dir);
 
     export_schema(&schema_for!(ConfigResponse), &out_dir);
     export_schema(&schema_for!(Market), &out_dir);
+    export_schema(&schema_for!(MarketRiskParamsResponse), &out_dir);
+    export_schema(&schema_for!(MarketHistoryResponse), &out_dir);
     export_schema(&schema_for!(MarketsListResponse), &out_dir);
     export_schema(&schema_for!(UserDebtResponse), &out_dir);
     export_schema(&schema_for!(UserAssetDebtResponse), &out_dir);
     export_schema(&schema_for!(UserCollateralResponse), &out_dir);
     export_schema(&schema_for!(UserPositionResponse), &out_dir);
+    export_schema(&schema_for!(UserPnlResponse), &out_dir);
+    export_schema(&schema_for!(CreditLineResponse), &out_dir);
+    export_schema(&schema_for!(OutflowCapacityResponse), &out_dir);
+    export_schema(&schema_for!(WithdrawQueueResponse), &out_dir);
 }
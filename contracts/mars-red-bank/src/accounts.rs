@@ -5,20 +5,21 @@ use mars_core::helpers::cw20_get_balance;
 use mars_core::math::decimal::Decimal;
 
 use mars_core::oracle;
+use mars_core::oracle::AssetPriceWithMetadataResponse;
 
 use crate::contract::{get_bit, market_get_from_index};
 use crate::error::ContractError;
 use crate::interest_rates::{get_underlying_debt_amount, get_underlying_liquidity_amount};
-use crate::state::DEBTS;
-use crate::{Debt, User, UserHealthStatus};
+use crate::state::{CREDIT_LINE_TERMS, DEBTS};
+use crate::{CreditLineStatus, Debt, User, UserHealthStatus};
 
 /// User global position
 pub struct UserPosition {
-    pub total_collateral_in_uusd: Uint128,
-    pub total_debt_in_uusd: Uint128,
-    pub total_collateralized_debt_in_uusd: Uint128,
-    pub max_debt_in_uusd: Uint128,
-    pub weighted_liquidation_threshold_in_uusd: Uint128,
+    pub total_collateral_in_base_asset: Uint128,
+    pub total_debt_in_base_asset: Uint128,
+    pub total_collateralized_debt_in_base_asset: Uint128,
+    pub max_debt_in_base_asset: Uint128,
+    pub weighted_liquidation_threshold_in_base_asset: Uint128,
     pub health_status: UserHealthStatus,
     pub asset_positions: Vec<UserAssetPosition>,
 }
@@ -36,10 +37,40 @@ impl UserPosition {
             .find(|ap| ap.asset_reference.as_slice() == asset_reference);
 
         match asset_position {
-            Some(position) => Ok(position.asset_price),
+            Some(position) => Ok(position.asset_price.price),
             None => Err(ContractError::price_not_found(asset_label)),
         }
     }
+
+    /// Errors if any of the prices used to build the position is older than the maximum age set
+    /// for its asset in the oracle. To be called before operations that could leave the protocol
+    /// with bad debt if valued at an outdated price (borrow, withdraw, liquidate)
+    pub fn assert_prices_not_stale(&self, block_time: u64) -> Result<(), ContractError> {
+        for asset_position in &self.asset_positions {
+            assert_price_not_stale(
+                &asset_position.asset_label,
+                &asset_position.asset_price,
+                block_time,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Errors if the price returned by the oracle is older than the asset's maximum price age
+pub fn assert_price_not_stale(
+    asset_label: &str,
+    price: &AssetPriceWithMetadataResponse,
+    block_time: u64,
+) -> Result<(), ContractError> {
+    match price.max_age {
+        Some(max_age) if price.is_stale(block_time) => Err(ContractError::StalePrice {
+            label: asset_label.to_string(),
+            timestamp: price.timestamp,
+            max_age,
+        }),
+        _ => Ok(()),
+    }
 }
 
 /// User asset settlement
@@ -52,12 +83,13 @@ pub struct UserAssetPosition {
     pub uncollateralized_debt: bool,
     pub max_ltv: Decimal,
     pub liquidation_threshold: Decimal,
-    pub asset_price: Decimal,
+    /// Price used to value the position, along with the metadata needed to check its staleness
+    pub asset_price: AssetPriceWithMetadataResponse,
 }
 
 /// Calculates the user data across the markets.
-/// This includes the total debt/collateral balances in uusd,
-/// the max debt in uusd, the average Liquidation threshold, and the Health factor.
+/// This includes the total debt/collateral balances in the oracle's base asset,
+/// the max debt in base asset, the average Liquidation threshold, and the Health factor.
 pub fn get_user_position(
     deps: Deps,
     block_time: u64,
@@ -75,49 +107,50 @@ pub fn get_user_position(
         block_time,
     )?;
 
-    let mut total_collateral_in_uusd = Uint128::zero();
-    let mut total_debt_in_uusd = Uint128::zero();
-    let mut total_collateralized_debt_in_uusd = Uint128::zero();
-    let mut max_debt_in_uusd = Uint128::zero();
-    let mut weighted_liquidation_threshold_in_uusd = Uint128::zero();
+    let mut total_collateral_in_base_asset = Uint128::zero();
+    let mut total_debt_in_base_asset = Uint128::zero();
+    let mut total_collateralized_debt_in_base_asset = Uint128::zero();
+    let mut max_debt_in_base_asset = Uint128::zero();
+    let mut weighted_liquidation_threshold_in_base_asset = Uint128::zero();
 
     for user_asset_position in &user_asset_positions {
-        let asset_price = user_asset_position.asset_price;
-        let collateral_in_uusd = user_asset_position.collateral_amount * asset_price;
-        total_collateral_in_uusd = total_collateral_in_uusd.checked_add(collateral_in_uusd)?;
+        let asset_price = user_asset_position.asset_price.price;
+        let collateral_in_base_asset = user_asset_position.collateral_amount * asset_price;
+        total_collateral_in_base_asset =
+            total_collateral_in_base_asset.checked_add(collateral_in_base_asset)?;
 
-        max_debt_in_uusd =
-            max_debt_in_uusd.checked_add(collateral_in_uusd * user_asset_position.max_ltv)?;
-        weighted_liquidation_threshold_in_uusd = weighted_liquidation_threshold_in_uusd
-            .checked_add(collateral_in_uusd * user_asset_position.liquidation_threshold)?;
+        max_debt_in_base_asset = max_debt_in_base_asset
+            .checked_add(collateral_in_base_asset * user_asset_position.max_ltv)?;
+        weighted_liquidation_threshold_in_base_asset = weighted_liquidation_threshold_in_base_asset
+            .checked_add(collateral_in_base_asset * user_asset_position.liquidation_threshold)?;
 
-        let debt_in_uusd = user_asset_position.debt_amount * asset_price;
-        total_debt_in_uusd = total_debt_in_uusd.checked_add(debt_in_uusd)?;
+        let debt_in_base_asset = user_asset_position.debt_amount * asset_price;
+        total_debt_in_base_asset = total_debt_in_base_asset.checked_add(debt_in_base_asset)?;
 
         if !user_asset_position.uncollateralized_debt {
-            total_collateralized_debt_in_uusd =
-                total_collateralized_debt_in_uusd.checked_add(debt_in_uusd)?;
+            total_collateralized_debt_in_base_asset =
+                total_collateralized_debt_in_base_asset.checked_add(debt_in_base_asset)?;
         }
     }
 
     // When computing health factor we should not take debt into account that has been given
     // an uncollateralized loan limit
-    let health_status = if total_collateralized_debt_in_uusd.is_zero() {
+    let health_status = if total_collateralized_debt_in_base_asset.is_zero() {
         UserHealthStatus::NotBorrowing
     } else {
         let health_factor = Decimal::from_ratio(
-            weighted_liquidation_threshold_in_uusd,
-            total_collateralized_debt_in_uusd,
+            weighted_liquidation_threshold_in_base_asset,
+            total_collateralized_debt_in_base_asset,
         );
         UserHealthStatus::Borrowing(health_factor)
     };
 
     let user_position = UserPosition {
-        total_collateral_in_uusd,
-        total_debt_in_uusd,
-        total_collateralized_debt_in_uusd,
-        max_debt_in_uusd,
-        weighted_liquidation_threshold_in_uusd,
+        total_collateral_in_base_asset,
+        total_debt_in_base_asset,
+        total_collateralized_debt_in_base_asset,
+        max_debt_in_base_asset,
+        weighted_liquidation_threshold_in_base_asset,
         health_status,
         asset_positions: user_asset_positions,
     };
@@ -138,6 +171,11 @@ fn get_user_asset_positions(
 ) -> StdResult<Vec<UserAssetPosition>> {
     let mut ret: Vec<UserAssetPosition> = vec![];
 
+    if user.collateral_assets.is_zero() && user.borrowed_assets.is_zero() {
+        return Ok(ret);
+    }
+    let base_denom = oracle::helpers::query_base_denom(deps.querier, oracle_address.clone())?;
+
     for i in 0_u32..market_count {
         let user_is_using_as_collateral = get_bit(user.collateral_assets, i)?;
         let user_is_borrowing = get_bit(user.borrowed_assets, i)?;
@@ -160,8 +198,8 @@ fn get_user_asset_positions(
 
             (
                 collateral_amount,
-                market.max_loan_to_value,
-                market.liquidation_threshold,
+                market.max_loan_to_value_at(block_time)?,
+                market.liquidation_threshold_at(block_time)?,
             )
         } else {
             (Uint128::zero(), Decimal::zero(), Decimal::zero())
@@ -175,7 +213,17 @@ fn get_user_asset_positions(
             let debt_amount =
                 get_underlying_debt_amount(user_debt.amount_scaled, &market, block_time)?;
 
-            (debt_amount, user_debt.uncollateralized)
+            // Debt drawn from a credit line whose grace period is over is treated as
+            // collateralized so the position can be liquidated
+            let uncollateralized_debt = user_debt.uncollateralized
+                && match CREDIT_LINE_TERMS
+                    .may_load(deps.storage, (asset_reference_vec.as_slice(), user_address))?
+                {
+                    Some(terms) => terms.status(block_time) != CreditLineStatus::Delinquent,
+                    None => true,
+                };
+
+            (debt_amount, uncollateralized_debt)
         } else {
             (Uint128::zero(), false)
         };
@@ -195,12 +243,14 @@ fn get_user_asset_positions(
             },
         };
 
-        let asset_price = oracle::helpers::query_price(
+        let asset_price = oracle::helpers::query_price_with_metadata(
             deps.querier,
             oracle_address.clone(),
+            &base_denom,
             &asset_label,
             asset_reference_vec.clone(),
             market.asset_type,
+            block_time,
         )?;
 
         let user_asset_position = UserAssetPosition {
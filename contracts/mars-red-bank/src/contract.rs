@@ -3,12 +3,14 @@ use std::str;
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    from_binary, to_binary, Addr, Binary, Coin, CosmosMsg, Deps, DepsMut, Env, Event, MessageInfo,
-    Order, Response, StdError, StdResult, Uint128, WasmMsg,
+    from_binary, to_binary, Addr, Binary, Coin, ContractResult, CosmosMsg, Deps, DepsMut, Empty,
+    Env, Event, MessageInfo, Order, Reply, Response, StdError, StdResult, Storage, SubMsg, Uint128,
+    WasmMsg,
 };
+use cw2::set_contract_version;
 use cw20::{Cw20ExecuteMsg, Cw20ReceiveMsg, MinterResponse};
 use cw20_base::msg::InstantiateMarketingInfo;
-use cw_storage_plus::U32Key;
+use cw_storage_plus::{Bound, U32Key, U64Key};
 
 use mars_core::address_provider::{self, MarsContract};
 use mars_core::ma_token;
@@ -17,29 +19,57 @@ use mars_core::asset::{
     build_send_asset_with_tax_deduction_msg, get_asset_balance, Asset, AssetType,
 };
 use mars_core::error::MarsError;
-use mars_core::helpers::{cw20_get_balance, cw20_get_symbol, option_string_to_addr, zero_address};
+use mars_core::helpers::{
+    cw20_get_balance, cw20_get_symbol, cw20_get_total_supply, option_string_to_addr, read_be_u64,
+    zero_address,
+};
 use mars_core::math::decimal::Decimal;
+use mars_core::versioning::{migrate_contract, MigrationStep, Version};
 
-use crate::accounts::get_user_position;
+use crate::accounts::{assert_price_not_stale, get_user_position};
 use crate::error::ContractError;
 use crate::interest_rate_models::init_interest_rate_model;
 use crate::interest_rates::{
-    apply_accumulated_interests, get_scaled_debt_amount, get_scaled_liquidity_amount,
+    apply_accrued_interest_premium, apply_accumulated_interests, compute_pending_interest_premium,
+    distribute_interest_premium, get_scaled_debt_amount, get_scaled_liquidity_amount,
     get_underlying_debt_amount, get_underlying_liquidity_amount, update_interest_rates,
+    SCALING_FACTOR,
 };
 use crate::msg::{
-    CreateOrUpdateConfig, ExecuteMsg, InitOrUpdateAssetParams, InstantiateMsg, QueryMsg, ReceiveMsg,
+    CreateOrUpdateConfig, ExecuteMsg, InitOrUpdateAssetParams, InstantiateMsg, MigrateMsg,
+    OutflowLimitParams, QueryMsg, ReceiveMsg,
 };
 use crate::state::{
-    CONFIG, DEBTS, GLOBAL_STATE, MARKETS, MARKET_REFERENCES_BY_INDEX,
-    MARKET_REFERENCES_BY_MA_TOKEN, UNCOLLATERALIZED_LOAN_LIMITS, USERS,
+    CONFIG, CREDIT_LINE_TERMS, DEBTS, GLOBAL_STATE, MARKETS, MARKET_REFERENCES_BY_INDEX,
+    MARKET_REFERENCES_BY_MA_TOKEN, MARKET_SNAPSHOTS, OUTFLOW_LIMITS, UNCOLLATERALIZED_LOAN_LIMITS,
+    USERS, USER_PRINCIPALS, USER_WITHDRAW_REQUESTS, WITHDRAW_QUEUE_OFFSETS, WITHDRAW_REQUESTS,
+    WITHDRAW_REQUEST_COUNT,
 };
 use crate::{
-    Config, ConfigResponse, Debt, GlobalState, Market, MarketInfo, MarketsListResponse, User,
-    UserAssetCollateralResponse, UserAssetDebtResponse, UserCollateralResponse, UserDebtResponse,
-    UserHealthStatus, UserPositionResponse,
+    Config, ConfigResponse, CreditLineResponse, CreditLineStatus, CreditLineTerms, Debt,
+    GlobalState, Market, MarketHistoryResponse, MarketInfo, MarketRiskParamsResponse,
+    MarketsListResponse, OutflowCapacityResponse, OutflowLimit, User, UserAssetCollateralResponse,
+    UserAssetDebtResponse, UserAssetPnlResponse, UserCollateralResponse, UserDebtResponse,
+    UserHealthStatus, UserPnlResponse, UserPositionResponse, WithdrawQueueResponse,
+    WithdrawRequest, WithdrawRequestResponse, MIN_RISK_PARAMS_RAMP_DURATION,
 };
 
+// Withdraw queue pagination and processing defaults
+const WITHDRAW_QUEUE_DEFAULT_LIMIT: u32 = 10;
+const WITHDRAW_QUEUE_MAX_LIMIT: u32 = 30;
+/// Requests paid out by deposits and repayments, so they don't carry the cost of a long queue
+const WITHDRAW_QUEUE_AUTO_PROCESS_LIMIT: u32 = 3;
+/// Reply id of the withdraw queue processing triggered by deposits and repayments
+const PROCESS_WITHDRAW_QUEUE_REPLY_ID: u64 = 1;
+
+// Market history pagination
+const MARKET_HISTORY_DEFAULT_LIMIT: u32 = 10;
+const MARKET_HISTORY_MAX_LIMIT: u32 = 100;
+
+// version info for migration info
+const CONTRACT_NAME: &str = "crates.io:red-bank";
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 // INIT
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -49,6 +79,8 @@ pub fn instantiate(
     _info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
     // Destructuring a struct’s fields into separate variables in order to force
     // compile error if we add more params
     let CreateOrUpdateConfig {
@@ -56,6 +88,8 @@ pub fn instantiate(
         address_provider_address,
         ma_token_code_id,
         close_factor,
+        market_snapshot_interval,
+        market_snapshot_max_count,
     } = msg.config;
 
     // All fields should be available
@@ -77,13 +111,21 @@ pub fn instantiate(
         )?,
         ma_token_code_id: ma_token_code_id.unwrap(),
         close_factor: close_factor.unwrap(),
+        market_snapshot_interval: market_snapshot_interval.unwrap_or(0),
+        market_snapshot_max_count: market_snapshot_max_count.unwrap_or(0),
     };
 
     config.validate()?;
 
     CONFIG.save(deps.storage, &config)?;
 
-    GLOBAL_STATE.save(deps.storage, &GlobalState { market_count: 0 })?;
+    GLOBAL_STATE.save(
+        deps.storage,
+        &GlobalState {
+            market_count: 0,
+            paused: false,
+        },
+    )?;
 
     Ok(Response::default())
 }
@@ -121,11 +163,48 @@ pub fn execute(
             user_address,
             asset,
             new_limit,
+            expiry,
+            interest_premium,
+            grace_period,
+        } => {
+            let user_addr = deps.api.addr_validate(&user_address)?;
+            execute_update_uncollateralized_loan_limit(
+                deps,
+                env,
+                info,
+                user_addr,
+                asset,
+                new_limit,
+                expiry,
+                interest_premium,
+                grace_period,
+            )
+        }
+
+        ExecuteMsg::DeclareDefault {
+            user_address,
+            asset,
         } => {
             let user_addr = deps.api.addr_validate(&user_address)?;
-            execute_update_uncollateralized_loan_limit(deps, env, info, user_addr, asset, new_limit)
+            execute_declare_default(deps, env, info, user_addr, asset)
+        }
+
+        ExecuteMsg::ProcessWithdrawQueue { asset, limit } => {
+            execute_process_withdraw_queue(deps, env, info, asset, limit)
+        }
+
+        ExecuteMsg::CancelWithdrawRequest { asset, request_id } => {
+            execute_cancel_withdraw_request(deps, env, info, asset, request_id)
         }
 
+        ExecuteMsg::UpdateOutflowLimit { asset, limit } => {
+            execute_update_outflow_limit(deps, env, info, asset, limit)
+        }
+
+        ExecuteMsg::Pause { asset } => execute_pause(deps, env, info, asset),
+
+        ExecuteMsg::Unpause { asset } => execute_unpause(deps, env, info, asset),
+
         ExecuteMsg::DepositNative {
             denom,
             on_behalf_of,
@@ -282,6 +361,10 @@ pub fn execute_receive_cw20(
                 receive_ma_token,
             )
         }
+        ReceiveMsg::QueueWithdraw { recipient } => {
+            let user_addr = deps.api.addr_validate(&cw20_msg.sender)?;
+            execute_queue_withdraw(deps, env, info, user_addr, recipient, cw20_msg.amount)
+        }
     }
 }
 
@@ -305,6 +388,8 @@ pub fn execute_update_config(
         address_provider_address,
         ma_token_code_id,
         close_factor,
+        market_snapshot_interval,
+        market_snapshot_max_count,
     } = new_config;
 
     // Update config
@@ -316,6 +401,10 @@ pub fn execute_update_config(
     )?;
     config.ma_token_code_id = ma_token_code_id.unwrap_or(config.ma_token_code_id);
     config.close_factor = close_factor.unwrap_or(config.close_factor);
+    config.market_snapshot_interval =
+        market_snapshot_interval.unwrap_or(config.market_snapshot_interval);
+    config.market_snapshot_max_count =
+        market_snapshot_max_count.unwrap_or(config.market_snapshot_max_count);
 
     // Validate config
     config.validate()?;
@@ -457,8 +546,20 @@ pub fn create_market(
         active,
         deposit_enabled,
         borrow_enabled,
+        frozen,
+        risk_params_ramp_end_time,
     } = params;
 
+    // There are no previous values to ramp from
+    if let Some(end_time) = risk_params_ramp_end_time {
+        return Err(MarsError::InvalidParam {
+            param_name: "risk_params_ramp_end_time".to_string(),
+            invalid_value: end_time.to_string(),
+            predicate: "None on init".to_string(),
+        }
+        .into());
+    }
+
     // All fields should be available
     let available = borrow_rate.is_some()
         && max_loan_to_value.is_some()
@@ -495,6 +596,9 @@ pub fn create_market(
         active: active.unwrap(),
         deposit_enabled: deposit_enabled.unwrap(),
         borrow_enabled: borrow_enabled.unwrap(),
+        frozen: frozen.unwrap_or(false),
+        paused: false,
+        risk_params_ramp: None,
     };
 
     new_market.validate()?;
@@ -529,7 +633,7 @@ pub fn execute_init_asset_token_callback(
 
 /// Update asset with new params.
 pub fn execute_update_asset(
-    deps: DepsMut,
+    mut deps: DepsMut,
     env: Env,
     info: MessageInfo,
     asset: Asset,
@@ -558,6 +662,8 @@ pub fn execute_update_asset(
                 active,
                 deposit_enabled,
                 borrow_enabled,
+                frozen,
+                risk_params_ramp_end_time,
             } = asset_params;
 
             // If reserve factor or interest rates are updated we update indexes with
@@ -584,28 +690,70 @@ pub fn execute_update_asset(
                 )?;
             }
 
+            let block_time = env.block.time.seconds();
+            let previous_target_liquidation_threshold =
+                market.liquidation_threshold_at(u64::MAX)?;
+
             let mut updated_market = Market {
-                max_loan_to_value: max_loan_to_value.unwrap_or(market.max_loan_to_value),
                 reserve_factor: reserve_factor.unwrap_or(market.reserve_factor),
-                liquidation_threshold: liquidation_threshold
-                    .unwrap_or(market.liquidation_threshold),
                 liquidation_bonus: liquidation_bonus.unwrap_or(market.liquidation_bonus),
                 active: active.unwrap_or(market.active),
                 deposit_enabled: deposit_enabled.unwrap_or(market.deposit_enabled),
                 borrow_enabled: borrow_enabled.unwrap_or(market.borrow_enabled),
+                frozen: frozen.unwrap_or(market.frozen),
                 ..market
             };
 
-            if let Some(params) = interest_rate_model_params {
-                updated_market.interest_rate_model =
-                    init_interest_rate_model(params, env.block.time.seconds())?;
+            // Risk params and interest rate model params only change through a ramp. The params
+            // that are not set keep the target of the ongoing ramp, if any
+            if max_loan_to_value.is_some()
+                || liquidation_threshold.is_some()
+                || interest_rate_model_params.is_some()
+            {
+                let target_max_loan_to_value = match max_loan_to_value {
+                    Some(max_loan_to_value) => max_loan_to_value,
+                    None => updated_market.max_loan_to_value_at(u64::MAX)?,
+                };
+                let target_liquidation_threshold = match liquidation_threshold {
+                    Some(liquidation_threshold) => liquidation_threshold,
+                    None => updated_market.liquidation_threshold_at(u64::MAX)?,
+                };
+                let target_interest_rate_model_params = match interest_rate_model_params {
+                    Some(params) => {
+                        params.validate()?;
+                        Some(params)
+                    }
+                    None => updated_market
+                        .risk_params_ramp
+                        .as_ref()
+                        .and_then(|ramp| ramp.target_interest_rate_model_params.clone()),
+                };
+                updated_market.start_risk_params_ramp(
+                    target_max_loan_to_value,
+                    target_liquidation_threshold,
+                    target_interest_rate_model_params,
+                    block_time,
+                    risk_params_ramp_end_time.unwrap_or(block_time + MIN_RISK_PARAMS_RAMP_DURATION),
+                )?;
+            }
+
+            // Lowering the liquidation threshold of a frozen market could make positions
+            // liquidatable while users are trying to exit them. The target is compared with the
+            // previous one so a ramp already in progress doesn't block updates
+            if updated_market.frozen
+                && updated_market.liquidation_threshold_at(u64::MAX)?
+                    < previous_target_liquidation_threshold
+            {
+                return Err(ContractError::CannotLowerLiquidationThresholdWhenFrozen {
+                    asset: asset_label,
+                });
             }
 
             updated_market.validate()?;
 
             if should_update_interest_rates {
                 response = update_interest_rates(
-                    &deps,
+                    &mut deps,
                     &env,
                     &mut updated_market,
                     Uint128::zero(),
@@ -626,12 +774,15 @@ pub fn execute_update_asset(
 
 /// Update uncollateralized loan limit by a given amount in uusd
 pub fn execute_update_uncollateralized_loan_limit(
-    deps: DepsMut,
-    _env: Env,
+    mut deps: DepsMut,
+    env: Env,
     info: MessageInfo,
     user_address: Addr,
     asset: Asset,
     new_limit: Uint128,
+    expiry: Option<u64>,
+    interest_premium: Option<Decimal>,
+    grace_period: Option<u64>,
 ) -> Result<Response, ContractError> {
     // Get config
     let config = CONFIG.load(deps.storage)?;
@@ -641,6 +792,7 @@ pub fn execute_update_uncollateralized_loan_limit(
         return Err(MarsError::Unauthorized {}.into());
     }
 
+    let block_time = env.block.time.seconds();
     let (asset_label, asset_reference, _) = asset.get_attributes();
 
     // Check that the user has no collateralized debt
@@ -660,12 +812,92 @@ pub fn execute_update_uncollateralized_loan_limit(
         };
     }
 
+    if let Some(expiry) = expiry {
+        if expiry <= block_time {
+            return Err(ContractError::InvalidCreditLineExpiry { expiry });
+        }
+    }
+
+    let mut response = Response::new();
+
+    // Premium accrued under the previous terms is added to the debt before they get overridden
+    if let Some(mut previous_terms) =
+        CREDIT_LINE_TERMS.may_load(deps.storage, (asset_reference.as_slice(), &user_address))?
+    {
+        let debt_option =
+            DEBTS.may_load(deps.storage, (asset_reference.as_slice(), &user_address))?;
+        if let Some(mut debt) = debt_option {
+            if !previous_terms.interest_premium.is_zero() && !debt.amount_scaled.is_zero() {
+                let mut market = MARKETS.load(deps.storage, asset_reference.as_slice())?;
+                let protocol_rewards_collector_address = address_provider::helpers::query_address(
+                    &deps.querier,
+                    config.address_provider_address,
+                    MarsContract::ProtocolRewardsCollector,
+                )?;
+                response = apply_accumulated_interests(
+                    &env,
+                    protocol_rewards_collector_address.clone(),
+                    &mut market,
+                    response,
+                )?;
+                let interest_premium_scaled = apply_accrued_interest_premium(
+                    &mut previous_terms,
+                    &market,
+                    &mut debt,
+                    block_time,
+                )?;
+                market.debt_total_scaled = market
+                    .debt_total_scaled
+                    .checked_add(interest_premium_scaled)?;
+                response = distribute_interest_premium(
+                    &deps.querier,
+                    protocol_rewards_collector_address,
+                    &mut market,
+                    interest_premium_scaled,
+                    response,
+                )?;
+                response = update_interest_rates(
+                    &mut deps,
+                    &env,
+                    &mut market,
+                    Uint128::zero(),
+                    &asset_label,
+                    response,
+                )?;
+                MARKETS.save(deps.storage, asset_reference.as_slice(), &market)?;
+                DEBTS.save(
+                    deps.storage,
+                    (asset_reference.as_slice(), &user_address),
+                    &debt,
+                )?;
+            }
+        }
+    }
+
     UNCOLLATERALIZED_LOAN_LIMITS.save(
         deps.storage,
         (asset_reference.as_slice(), &user_address),
         &new_limit,
     )?;
 
+    if new_limit.is_zero() {
+        CREDIT_LINE_TERMS.remove(deps.storage, (asset_reference.as_slice(), &user_address));
+    } else {
+        let terms = CreditLineTerms {
+            expiry,
+            interest_premium: interest_premium.unwrap_or_else(Decimal::zero),
+            grace_period: grace_period.unwrap_or(0),
+            premium_last_accrued: block_time,
+            defaulted: false,
+        };
+        terms.validate()?;
+        CREDIT_LINE_TERMS.save(
+            deps.storage,
+            (asset_reference.as_slice(), &user_address),
+            &terms,
+        )?;
+    }
+
     DEBTS.update(
         deps.storage,
         (asset_reference.as_slice(), &user_address),
@@ -680,683 +912,721 @@ pub fn execute_update_uncollateralized_loan_limit(
         },
     )?;
 
-    let res = Response::new()
+    response = response
         .add_attribute("action", "update_uncollateralized_loan_limit")
         .add_attribute("user", user_address.as_str())
         .add_attribute("asset", asset_label)
         .add_attribute("new_allowance", new_limit.to_string());
-    Ok(res)
+    Ok(response)
 }
 
-/// Execute deposits and mint corresponding ma_tokens
-pub fn execute_deposit(
-    deps: DepsMut,
+/// Declare a credit line whose grace period is over in default. The outstanding debt
+/// (including the accrued interest premium) is written down, which means the loss is
+/// absorbed by the market's depositors.
+pub fn execute_declare_default(
+    mut deps: DepsMut,
     env: Env,
-    _info: MessageInfo,
-    sender_address: Addr,
-    on_behalf_of: Option<String>,
-    asset_reference: &[u8],
-    asset_label: &str,
-    deposit_amount: Uint128,
+    info: MessageInfo,
+    user_address: Addr,
+    asset: Asset,
 ) -> Result<Response, ContractError> {
-    let user_address = if let Some(address) = on_behalf_of {
-        deps.api.addr_validate(&address)?
-    } else {
-        sender_address.clone()
-    };
+    let config = CONFIG.load(deps.storage)?;
 
-    let mut market = MARKETS.load(deps.storage, asset_reference)?;
-    if !market.active {
-        return Err(ContractError::MarketNotActive {
-            asset: asset_label.to_string(),
-        });
-    }
-    if !market.deposit_enabled {
-        return Err(ContractError::DepositNotEnabled {
-            asset: asset_label.to_string(),
-        });
+    if info.sender != config.owner {
+        return Err(MarsError::Unauthorized {}.into());
     }
 
-    // Cannot deposit zero amount
-    if deposit_amount.is_zero() {
-        return Err(ContractError::InvalidDepositAmount {
-            asset: asset_label.to_string(),
-        });
-    }
+    let block_time = env.block.time.seconds();
+    let (asset_label, asset_reference, _) = asset.get_attributes();
 
-    let mut user = USERS
-        .may_load(deps.storage, &user_address)?
-        .unwrap_or_default();
+    let mut terms = CREDIT_LINE_TERMS
+        .may_load(deps.storage, (asset_reference.as_slice(), &user_address))?
+        .ok_or(ContractError::CreditLineNotFound {})?;
 
-    let mut response = Response::new();
-    let has_deposited_asset = get_bit(user.collateral_assets, market.index)?;
-    if !has_deposited_asset {
-        set_bit(&mut user.collateral_assets, market.index)?;
-        USERS.save(deps.storage, &user_address, &user)?;
-        response = response.add_event(build_collateral_position_changed_event(
-            asset_label,
-            true,
-            user_address.to_string(),
-        ));
+    let status = terms.status(block_time);
+    if status != CreditLineStatus::Delinquent {
+        return Err(ContractError::CannotDeclareDefault { status });
     }
 
-    let config = CONFIG.load(deps.storage)?;
+    let mut market = MARKETS.load(deps.storage, asset_reference.as_slice())?;
+    let mut debt = DEBTS.load(deps.storage, (asset_reference.as_slice(), &user_address))?;
 
-    // update indexes and interest rates
     let protocol_rewards_collector_address = address_provider::helpers::query_address(
         &deps.querier,
         config.address_provider_address,
         MarsContract::ProtocolRewardsCollector,
     )?;
+
+    let mut response = Response::new();
+
     response = apply_accumulated_interests(
         &env,
+        protocol_rewards_collector_address.clone(),
+        &mut market,
+        response,
+    )?;
+    let interest_premium_scaled =
+        apply_accrued_interest_premium(&mut terms, &market, &mut debt, block_time)?;
+    market.debt_total_scaled = market
+        .debt_total_scaled
+        .checked_add(interest_premium_scaled)?;
+    response = distribute_interest_premium(
+        &deps.querier,
         protocol_rewards_collector_address,
         &mut market,
+        interest_premium_scaled,
         response,
     )?;
+
+    let written_down_amount = get_underlying_debt_amount(debt.amount_scaled, &market, block_time)?;
+
+    // Depositors share the loss pro rata: the liquidity index is lowered by the written down
+    // amount per unit of scaled liquidity. maTokens minted to the protocol rewards collector in
+    // this same transaction are not part of the supply yet, so the index is lowered slightly
+    // more than needed, in favor of the market's solvency. A write down that would take the whole
+    // index is rejected, as a zero index would break the scaling of liquidity amounts
+    let liquidity_total_scaled =
+        cw20_get_total_supply(&deps.querier, market.ma_token_address.clone())?;
+    if !liquidity_total_scaled.is_zero() {
+        let written_down_per_unit = Decimal::from_ratio(
+            written_down_amount.checked_mul(SCALING_FACTOR)?,
+            liquidity_total_scaled,
+        );
+        market.liquidity_index = market
+            .liquidity_index
+            .checked_sub(written_down_per_unit)
+            .ok()
+            .filter(|liquidity_index| !liquidity_index.is_zero())
+            .ok_or(ContractError::WriteDownExceedsLiquidity {
+                written_down_amount,
+            })?;
+    }
+
+    // Write down the user's debt
+    market.debt_total_scaled = market.debt_total_scaled.checked_sub(debt.amount_scaled)?;
+    debt.amount_scaled = Uint128::zero();
+    debt.uncollateralized = false;
+    DEBTS.save(
+        deps.storage,
+        (asset_reference.as_slice(), &user_address),
+        &debt,
+    )?;
+
     response = update_interest_rates(
-        &deps,
+        &mut deps,
         &env,
         &mut market,
         Uint128::zero(),
-        asset_label,
+        &asset_label,
         response,
     )?;
-    MARKETS.save(deps.storage, asset_reference, &market)?;
+    MARKETS.save(deps.storage, asset_reference.as_slice(), &market)?;
 
-    if market.liquidity_index.is_zero() {
-        return Err(ContractError::InvalidLiquidityIndex {});
+    // Close the credit line
+    terms.defaulted = true;
+    CREDIT_LINE_TERMS.save(
+        deps.storage,
+        (asset_reference.as_slice(), &user_address),
+        &terms,
+    )?;
+    UNCOLLATERALIZED_LOAN_LIMITS.save(
+        deps.storage,
+        (asset_reference.as_slice(), &user_address),
+        &Uint128::zero(),
+    )?;
+
+    let mut user = USERS.load(deps.storage, &user_address)?;
+    if get_bit(user.borrowed_assets, market.index)? {
+        unset_bit(&mut user.borrowed_assets, market.index)?;
+        USERS.save(deps.storage, &user_address, &user)?;
+        response = response.add_event(build_debt_position_changed_event(
+            &asset_label,
+            false,
+            user_address.to_string(),
+        ));
     }
-    let mint_amount =
-        get_scaled_liquidity_amount(deposit_amount, &market, env.block.time.seconds())?;
 
     response = response
-        .add_attribute("action", "deposit")
+        .add_event(
+            Event::new("credit_line_defaulted")
+                .add_attribute("asset", asset_label.as_str())
+                .add_attribute("user", user_address.as_str())
+                .add_attribute("written_down_amount", written_down_amount),
+        )
+        .add_attribute("action", "declare_default")
         .add_attribute("asset", asset_label)
-        .add_attribute("sender", sender_address)
         .add_attribute("user", user_address.as_str())
-        .add_attribute("amount", deposit_amount)
-        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: market.ma_token_address.into(),
-            msg: to_binary(&Cw20ExecuteMsg::Mint {
-                recipient: user_address.into(),
-                amount: mint_amount,
-            })?,
-            funds: vec![],
-        }));
-
+        .add_attribute("written_down_amount", written_down_amount);
     Ok(response)
 }
 
-/// Burns sent maAsset in exchange of underlying asset
-pub fn execute_withdraw(
+/// Lock sent maTokens in the market's withdraw queue
+pub fn execute_queue_withdraw(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
-    asset: Asset,
-    amount: Option<Uint128>,
-    recipient_address: Option<String>,
+    user_address: Addr,
+    recipient: Option<String>,
+    amount_scaled: Uint128,
 ) -> Result<Response, ContractError> {
-    let withdrawer_addr = info.sender;
+    // Only maTokens can be sent to the queue
+    let asset_reference = MARKET_REFERENCES_BY_MA_TOKEN
+        .may_load(deps.storage, &info.sender)?
+        .ok_or(ContractError::InvalidMaToken {})?;
+    let market = MARKETS.load(deps.storage, asset_reference.as_slice())?;
+    let asset_label = String::from_utf8(asset_reference.clone()).expect("Found invalid UTF-8");
+
+    if !market.active {
+        return Err(ContractError::MarketNotActive { asset: asset_label });
+    }
+    assert_market_not_paused(deps.storage, &market, &asset_label)?;
+
+    let recipient_address = match recipient {
+        Some(address) => deps.api.addr_validate(&address)?,
+        None => user_address.clone(),
+    };
+
+    let request_id = WITHDRAW_REQUEST_COUNT
+        .may_load(deps.storage)?
+        .unwrap_or_default();
+    WITHDRAW_REQUEST_COUNT.save(deps.storage, &(request_id + 1))?;
+    let queue_offset_scaled = WITHDRAW_QUEUE_OFFSETS
+        .may_load(deps.storage, asset_reference.as_slice())?
+        .unwrap_or_default();
+    WITHDRAW_QUEUE_OFFSETS.save(
+        deps.storage,
+        asset_reference.as_slice(),
+        &queue_offset_scaled.checked_add(amount_scaled)?,
+    )?;
+    WITHDRAW_REQUESTS.save(
+        deps.storage,
+        (asset_reference.as_slice(), U64Key::new(request_id)),
+        &WithdrawRequest {
+            user_address: user_address.clone(),
+            recipient_address: recipient_address.clone(),
+            amount_scaled,
+            queue_offset_scaled,
+            created_at: env.block.time.seconds(),
+        },
+    )?;
+    USER_WITHDRAW_REQUESTS.save(
+        deps.storage,
+        (
+            (asset_reference.as_slice(), &user_address),
+            U64Key::new(request_id),
+        ),
+        &Empty {},
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "queue_withdraw")
+        .add_attribute("asset", asset_label)
+        .add_attribute("user", user_address)
+        .add_attribute("recipient", recipient_address)
+        .add_attribute("request_id", request_id.to_string())
+        .add_attribute("amount_scaled", amount_scaled))
+}
 
+/// Pay out queued withdraw requests in FIFO order with the market's available liquidity.
+/// Requests that cannot be fully paid out are partially filled and stay first in the queue
+pub fn execute_process_withdraw_queue(
+    mut deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
+    asset: Asset,
+    limit: Option<u32>,
+) -> Result<Response, ContractError> {
     let (asset_label, asset_reference, asset_type) = asset.get_attributes();
     let mut market = MARKETS.load(deps.storage, asset_reference.as_slice())?;
 
     if !market.active {
         return Err(ContractError::MarketNotActive { asset: asset_label });
     }
+    assert_market_not_paused(deps.storage, &market, &asset_label)?;
 
-    let asset_ma_addr = market.ma_token_address.clone();
-    let withdrawer_balance_scaled_before =
-        cw20_get_balance(&deps.querier, asset_ma_addr, withdrawer_addr.clone())?;
-    let withdrawer_balance_before = get_underlying_liquidity_amount(
-        withdrawer_balance_scaled_before,
-        &market,
-        env.block.time.seconds(),
+    let block_time = env.block.time.seconds();
+    let limit = limit
+        .unwrap_or(WITHDRAW_QUEUE_DEFAULT_LIMIT)
+        .min(WITHDRAW_QUEUE_MAX_LIMIT) as usize;
+
+    let config = CONFIG.load(deps.storage)?;
+    let protocol_rewards_collector_address = address_provider::helpers::query_address(
+        &deps.querier,
+        config.address_provider_address,
+        MarsContract::ProtocolRewardsCollector,
+    )?;
+    let mut response = apply_accumulated_interests(
+        &env,
+        protocol_rewards_collector_address,
+        &mut market,
+        Response::new(),
     )?;
 
-    if withdrawer_balance_scaled_before.is_zero() {
-        return Err(ContractError::UserNoBalance { asset: asset_label });
-    }
+    let mut available_liquidity = get_available_withdraw_liquidity(
+        deps.as_ref(),
+        &env,
+        asset_reference.as_slice(),
+        &asset_label,
+        asset_type,
+    )?;
 
-    let withdraw_amount = match amount {
-        Some(amount) => {
-            // Check user has sufficient balance to send back
-            if amount.is_zero() || amount > withdrawer_balance_before {
-                return Err(ContractError::InvalidWithdrawAmount { asset: asset_label });
-            };
-            amount
-        }
-        None => {
-            // If no amount is specified, the full balance is withdrawn
-            withdrawer_balance_before
+    let requests = WITHDRAW_REQUESTS
+        .prefix(asset_reference.as_slice())
+        .range(deps.storage, None, None, Order::Ascending)
+        .take(limit)
+        .collect::<StdResult<Vec<_>>>()?;
+
+    let mut total_paid_amount = Uint128::zero();
+    let mut total_burn_amount = Uint128::zero();
+    for (key, mut request) in requests {
+        if available_liquidity.is_zero() {
+            break;
         }
-    };
-
-    let config = CONFIG.load(deps.storage)?;
-
-    let mut addresses_query = address_provider::helpers::query_addresses(
-        &deps.querier,
-        config.address_provider_address,
-        vec![MarsContract::Oracle, MarsContract::ProtocolRewardsCollector],
-    )?;
-    let protocol_rewards_collector_address = addresses_query.pop().unwrap();
-    let oracle_address = addresses_query.pop().unwrap();
+        let request_id = read_be_u64(&key)?;
+        let request_key = (asset_reference.as_slice(), U64Key::new(request_id));
+
+        let request_amount =
+            get_underlying_liquidity_amount(request.amount_scaled, &market, block_time)?;
+        let paid_amount = request_amount.min(available_liquidity);
+        let remaining_amount_scaled = get_scaled_liquidity_amount(
+            request_amount.checked_sub(paid_amount)?,
+            &market,
+            block_time,
+        )?;
+        let burn_amount = request.amount_scaled.checked_sub(remaining_amount_scaled)?;
 
-    let mut withdrawer = match USERS.may_load(deps.storage, &withdrawer_addr)? {
-        Some(user) => user,
-        None => {
-            // No address should withdraw without an existing user position already in
-            // storage (If this happens the protocol did something wrong). The exception is
-            // the protocol_rewards_collector which gets minted token without depositing
-            // nor receiving a transfer from another user.
-            if withdrawer_addr != protocol_rewards_collector_address {
-                return Err(ContractError::ExistingUserPositionRequired {});
-            }
-            User::default()
+        if remaining_amount_scaled.is_zero() {
+            WITHDRAW_REQUESTS.remove(deps.storage, request_key);
+            USER_WITHDRAW_REQUESTS.remove(
+                deps.storage,
+                (
+                    (asset_reference.as_slice(), &request.user_address),
+                    U64Key::new(request_id),
+                ),
+            );
+        } else {
+            request.amount_scaled = remaining_amount_scaled;
+            request.queue_offset_scaled = request.queue_offset_scaled.checked_add(burn_amount)?;
+            WITHDRAW_REQUESTS.save(deps.storage, request_key, &request)?;
         }
-    };
-    let asset_as_collateral = get_bit(withdrawer.collateral_assets, market.index)?;
-    let user_is_borrowing = !withdrawer.borrowed_assets.is_zero();
 
-    // if asset is used as collateral and user is borrowing we need to validate health factor after withdraw,
-    // otherwise no reasons to block the withdraw
-    if asset_as_collateral && user_is_borrowing {
-        let global_state = GLOBAL_STATE.load(deps.storage)?;
+        if !paid_amount.is_zero() {
+            response = response.add_message(build_send_asset_with_tax_deduction_msg(
+                deps.as_ref(),
+                request.recipient_address.clone(),
+                asset_label.clone(),
+                asset_type,
+                paid_amount,
+            )?);
+        }
 
-        let user_position = get_user_position(
-            deps.as_ref(),
-            env.block.time.seconds(),
-            &withdrawer_addr,
-            oracle_address,
-            &withdrawer,
-            global_state.market_count,
+        record_principal_change(
+            deps.storage,
+            asset_reference.as_slice(),
+            &request.user_address,
+            PrincipalChange::Withdraw,
+            paid_amount,
         )?;
 
-        let withdraw_asset_price =
-            user_position.get_asset_price(asset_reference.as_slice(), &asset_label)?;
-
-        let withdraw_amount_in_uusd = withdraw_amount * withdraw_asset_price;
+        available_liquidity = available_liquidity.checked_sub(paid_amount)?;
+        total_paid_amount = total_paid_amount.checked_add(paid_amount)?;
+        total_burn_amount = total_burn_amount.checked_add(burn_amount)?;
 
-        let weighted_liquidation_threshold_in_uusd_after_withdraw = user_position
-            .weighted_liquidation_threshold_in_uusd
-            .checked_sub(withdraw_amount_in_uusd * market.liquidation_threshold)?;
-        let health_factor_after_withdraw = Decimal::from_ratio(
-            weighted_liquidation_threshold_in_uusd_after_withdraw,
-            user_position.total_collateralized_debt_in_uusd,
+        response = response.add_event(
+            Event::new("withdraw_request_paid")
+                .add_attribute("request_id", request_id.to_string())
+                .add_attribute("user", request.user_address.as_str())
+                .add_attribute("recipient", request.recipient_address.as_str())
+                .add_attribute("amount", paid_amount)
+                .add_attribute("burn_amount", burn_amount)
+                .add_attribute("filled", remaining_amount_scaled.is_zero().to_string()),
         );
-        if health_factor_after_withdraw < Decimal::one() {
-            return Err(ContractError::InvalidHealthFactorAfterWithdraw {});
-        }
-    }
-
-    let mut response = Response::new();
-
-    // if amount to withdraw equals the user's balance then unset collateral bit
-    if asset_as_collateral && withdraw_amount == withdrawer_balance_before {
-        unset_bit(&mut withdrawer.collateral_assets, market.index)?;
-        USERS.save(deps.storage, &withdrawer_addr, &withdrawer)?;
-        response = response.add_event(build_collateral_position_changed_event(
-            asset_label.as_str(),
-            false,
-            withdrawer_addr.to_string(),
-        ));
     }
 
-    // update indexes and interest rates
-    response = apply_accumulated_interests(
-        &env,
-        protocol_rewards_collector_address,
-        &mut market,
-        response,
+    record_outflow(
+        deps.storage,
+        asset_reference.as_slice(),
+        &asset_label,
+        total_paid_amount,
+        block_time,
     )?;
     response = update_interest_rates(
-        &deps,
+        &mut deps,
         &env,
         &mut market,
-        withdraw_amount,
+        total_paid_amount,
         &asset_label,
         response,
     )?;
     MARKETS.save(deps.storage, asset_reference.as_slice(), &market)?;
 
-    // burn maToken
-    let withdrawer_balance_after = withdrawer_balance_before.checked_sub(withdraw_amount)?;
-    let withdrawer_balance_scaled_after =
-        get_scaled_liquidity_amount(withdrawer_balance_after, &market, env.block.time.seconds())?;
+    // Locked maTokens are held by this contract
+    if !total_burn_amount.is_zero() {
+        response = response.add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: market.ma_token_address.to_string(),
+            msg: to_binary(&ma_token::msg::ExecuteMsg::Burn {
+                user: env.contract.address.to_string(),
+                amount: total_burn_amount,
+            })?,
+            funds: vec![],
+        }));
+    }
 
-    let burn_amount =
-        withdrawer_balance_scaled_before.checked_sub(withdrawer_balance_scaled_after)?;
-    response = response.add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+    Ok(response
+        .add_attribute("action", "process_withdraw_queue")
+        .add_attribute("asset", asset_label)
+        .add_attribute("paid_amount", total_paid_amount)
+        .add_attribute("burn_amount", total_burn_amount))
+}
+
+/// Remove a queued withdraw request and send back the maTokens locked in it
+pub fn execute_cancel_withdraw_request(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    asset: Asset,
+    request_id: u64,
+) -> Result<Response, ContractError> {
+    let (asset_label, asset_reference, _) = asset.get_attributes();
+    let market = MARKETS.load(deps.storage, asset_reference.as_slice())?;
+
+    let request_key = (asset_reference.as_slice(), U64Key::new(request_id));
+    let request = WITHDRAW_REQUESTS
+        .may_load(deps.storage, request_key.clone())?
+        .ok_or(ContractError::WithdrawRequestNotFound { request_id })?;
+    if info.sender != request.user_address {
+        return Err(MarsError::Unauthorized {}.into());
+    }
+
+    WITHDRAW_REQUESTS.remove(deps.storage, request_key);
+    USER_WITHDRAW_REQUESTS.remove(
+        deps.storage,
+        (
+            (asset_reference.as_slice(), &request.user_address),
+            U64Key::new(request_id),
+        ),
+    );
+
+    // Locked maTokens are held by this contract
+    let transfer_msg = CosmosMsg::Wasm(WasmMsg::Execute {
         contract_addr: market.ma_token_address.to_string(),
-        msg: to_binary(&ma_token::msg::ExecuteMsg::Burn {
-            user: withdrawer_addr.to_string(),
-            amount: burn_amount,
+        msg: to_binary(&Cw20ExecuteMsg::Transfer {
+            recipient: request.user_address.to_string(),
+            amount: request.amount_scaled,
         })?,
         funds: vec![],
-    }));
+    });
 
-    // send underlying asset to user or another recipient
-    let recipient_address = if let Some(address) = recipient_address {
-        deps.api.addr_validate(&address)?
-    } else {
-        withdrawer_addr.clone()
-    };
-    response = response.add_message(build_send_asset_with_tax_deduction_msg(
-        deps.as_ref(),
-        recipient_address.clone(),
-        asset_label.clone(),
-        asset_type,
-        withdraw_amount,
-    )?);
-
-    response = response
-        .add_attribute("action", "withdraw")
-        .add_attribute("asset", asset_label.as_str())
-        .add_attribute("user", withdrawer_addr.as_str())
-        .add_attribute("recipient", recipient_address.as_str())
-        .add_attribute("burn_amount", burn_amount)
-        .add_attribute("withdraw_amount", withdraw_amount);
-    Ok(response)
+    Ok(Response::new()
+        .add_message(transfer_msg)
+        .add_attribute("action", "cancel_withdraw_request")
+        .add_attribute("asset", asset_label)
+        .add_attribute("user", request.user_address)
+        .add_attribute("request_id", request_id.to_string())
+        .add_attribute("amount_scaled", request.amount_scaled))
 }
 
-/// Add debt for the borrower and send the borrowed funds
-pub fn execute_borrow(
+/// Update (or remove if None) the max net outflow allowed for a market over a rolling window
+pub fn execute_update_outflow_limit(
     deps: DepsMut,
     env: Env,
     info: MessageInfo,
     asset: Asset,
-    borrow_amount: Uint128,
-    recipient_address: Option<String>,
+    limit: Option<OutflowLimitParams>,
 ) -> Result<Response, ContractError> {
-    let borrower_address = info.sender;
-    let (asset_label, asset_reference, asset_type) = asset.get_attributes();
-
-    // Cannot borrow zero amount
-    if borrow_amount.is_zero() {
-        return Err(ContractError::InvalidBorrowAmount { asset: asset_label });
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(MarsError::Unauthorized {}.into());
     }
 
-    // Load market and user state
-    let global_state = GLOBAL_STATE.load(deps.storage)?;
-    let mut borrow_market = MARKETS.load(deps.storage, asset_reference.as_slice())?;
-
-    if !borrow_market.active {
-        return Err(ContractError::MarketNotActive { asset: asset_label });
-    }
-    if !borrow_market.borrow_enabled {
-        return Err(ContractError::BorrowNotEnabled { asset: asset_label });
+    let (asset_label, asset_reference, _) = asset.get_attributes();
+    if MARKETS
+        .may_load(deps.storage, asset_reference.as_slice())?
+        .is_none()
+    {
+        return Err(ContractError::AssetNotInitialized {});
     }
 
-    let uncollateralized_loan_limit = UNCOLLATERALIZED_LOAN_LIMITS
-        .may_load(
-            deps.storage,
-            (asset_reference.as_slice(), &borrower_address),
-        )?
-        .unwrap_or_else(Uint128::zero);
-    let mut user: User = match USERS.may_load(deps.storage, &borrower_address)? {
-        Some(user) => user,
-        None => {
-            if uncollateralized_loan_limit.is_zero() {
-                return Err(ContractError::UserNoCollateral {});
-            }
-            // If User has some uncollateralized_loan_limit, then we don't require an existing debt position and initialize a new one.
-            User::default()
+    let block_time = env.block.time.seconds();
+    let mut response = Response::new()
+        .add_attribute("action", "update_outflow_limit")
+        .add_attribute("asset", &asset_label);
+
+    match limit {
+        Some(OutflowLimitParams { max_amount, window }) => {
+            // Outflow accounted under the previous limit still counts against the new one
+            let current_outflow = OUTFLOW_LIMITS
+                .may_load(deps.storage, asset_reference.as_slice())?
+                .map(|limit| limit.current_outflow_at(block_time))
+                .unwrap_or_else(Uint128::zero);
+            let outflow_limit = OutflowLimit {
+                max_amount,
+                window,
+                current_outflow,
+                last_updated: block_time,
+            };
+            outflow_limit.validate()?;
+            OUTFLOW_LIMITS.save(deps.storage, asset_reference.as_slice(), &outflow_limit)?;
+
+            response = response
+                .add_attribute("max_amount", max_amount)
+                .add_attribute("window", window.to_string());
         }
-    };
+        None => OUTFLOW_LIMITS.remove(deps.storage, asset_reference.as_slice()),
+    }
 
-    let is_borrowing_asset = get_bit(user.borrowed_assets, borrow_market.index)?;
+    Ok(response)
+}
 
+/// Pause a market or, if no asset is given, the whole contract (only owner or guardian can call)
+pub fn execute_pause(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    asset: Option<Asset>,
+) -> Result<Response, ContractError> {
     let config = CONFIG.load(deps.storage)?;
-
-    let mut addresses_query = address_provider::helpers::query_addresses(
+    address_provider::helpers::assert_owner_or_guardian(
         &deps.querier,
         config.address_provider_address,
-        vec![MarsContract::Oracle, MarsContract::ProtocolRewardsCollector],
+        &config.owner,
+        &info.sender,
     )?;
-    let protocol_rewards_collector_address = addresses_query.pop().unwrap();
-    let oracle_address = addresses_query.pop().unwrap();
 
-    // Check if user can borrow specified amount
-    let mut uncollateralized_debt = false;
-    if uncollateralized_loan_limit.is_zero() {
-        // Collateralized loan: check max ltv is not exceeded
-        let user_position = get_user_position(
-            deps.as_ref(),
-            env.block.time.seconds(),
-            &borrower_address,
-            oracle_address.clone(),
-            &user,
-            global_state.market_count,
-        )?;
+    let label = set_paused(deps, asset, true)?;
 
-        let borrow_asset_price = if is_borrowing_asset {
-            // if user was already borrowing, get price from user position
-            user_position.get_asset_price(asset_reference.as_slice(), &asset_label)?
-        } else {
-            mars_core::oracle::helpers::query_price(
-                deps.querier,
-                oracle_address,
-                &asset_label,
-                asset_reference.clone(),
-                asset_type,
-            )?
-        };
+    Ok(Response::new()
+        .add_attribute("action", "pause")
+        .add_attribute("asset", label))
+}
 
-        let borrow_amount_in_uusd = borrow_amount * borrow_asset_price;
+/// Unpause a market or, if no asset is given, the whole contract (only owner can call)
+pub fn execute_unpause(
+    deps: DepsMut,
+    _env: Env,
+    info: MessageInfo,
+    asset: Option<Asset>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(MarsError::Unauthorized {}.into());
+    }
 
-        let total_debt_in_uusd_after_borrow = user_position
-            .total_debt_in_uusd
-            .checked_add(borrow_amount_in_uusd)?;
-        if total_debt_in_uusd_after_borrow > user_position.max_debt_in_uusd {
-            return Err(ContractError::BorrowAmountExceedsGivenCollateral {});
-        }
-    } else {
-        // Uncollateralized loan: check borrow amount plus debt does not exceed uncollateralized loan limit
-        uncollateralized_debt = true;
+    let label = set_paused(deps, asset, false)?;
 
-        let borrower_debt = DEBTS
-            .may_load(
-                deps.storage,
-                (asset_reference.as_slice(), &borrower_address),
-            )?
-            .unwrap_or(Debt {
-                amount_scaled: Uint128::zero(),
-                uncollateralized: uncollateralized_debt,
-            });
+    Ok(Response::new()
+        .add_attribute("action", "unpause")
+        .add_attribute("asset", label))
+}
 
-        let asset_market = MARKETS.load(deps.storage, asset_reference.as_slice())?;
-        let debt_amount = get_underlying_debt_amount(
-            borrower_debt.amount_scaled,
-            &asset_market,
-            env.block.time.seconds(),
-        )?;
-
-        let debt_after_borrow = debt_amount.checked_add(borrow_amount)?;
-        if debt_after_borrow > uncollateralized_loan_limit {
-            return Err(ContractError::BorrowAmountExceedsUncollateralizedLoanLimit {});
+/// Sets paused flag on the given market or on the global state if no asset is given.
+/// Returns the label of the paused asset ("all" when global)
+fn set_paused(deps: DepsMut, asset: Option<Asset>, paused: bool) -> Result<String, ContractError> {
+    match asset {
+        Some(asset) => {
+            let (asset_label, asset_reference, _) = asset.get_attributes();
+            let mut market = MARKETS
+                .may_load(deps.storage, asset_reference.as_slice())?
+                .ok_or(ContractError::AssetNotInitialized {})?;
+            market.paused = paused;
+            MARKETS.save(deps.storage, asset_reference.as_slice(), &market)?;
+            Ok(asset_label)
+        }
+        None => {
+            GLOBAL_STATE.update(deps.storage, |mut global_state| -> StdResult<_> {
+                global_state.paused = paused;
+                Ok(global_state)
+            })?;
+            Ok(String::from("all"))
         }
     }
-
-    let mut response = Response::new();
-
-    response = apply_accumulated_interests(
-        &env,
-        protocol_rewards_collector_address,
-        &mut borrow_market,
-        response,
-    )?;
-
-    // Set borrowing asset for user
-    if !is_borrowing_asset {
-        set_bit(&mut user.borrowed_assets, borrow_market.index)?;
-        USERS.save(deps.storage, &borrower_address, &user)?;
-        response = response.add_event(build_debt_position_changed_event(
-            asset_label.as_str(),
-            true,
-            borrower_address.to_string(),
-        ));
-    }
-
-    // Set new debt
-    let mut debt = DEBTS
-        .may_load(
-            deps.storage,
-            (asset_reference.as_slice(), &borrower_address),
-        )?
-        .unwrap_or(Debt {
-            amount_scaled: Uint128::zero(),
-            uncollateralized: uncollateralized_debt,
-        });
-    let borrow_amount_scaled =
-        get_scaled_debt_amount(borrow_amount, &borrow_market, env.block.time.seconds())?;
-    debt.amount_scaled = debt.amount_scaled.checked_add(borrow_amount_scaled)?;
-    DEBTS.save(
-        deps.storage,
-        (asset_reference.as_slice(), &borrower_address),
-        &debt,
-    )?;
-
-    borrow_market.debt_total_scaled += borrow_amount_scaled;
-
-    response = update_interest_rates(
-        &deps,
-        &env,
-        &mut borrow_market,
-        borrow_amount,
-        &asset_label,
-        response,
-    )?;
-    MARKETS.save(deps.storage, asset_reference.as_slice(), &borrow_market)?;
-
-    // Send borrow amount to borrower or another recipient
-    let recipient_address = if let Some(address) = recipient_address {
-        deps.api.addr_validate(&address)?
-    } else {
-        borrower_address.clone()
-    };
-    response = response.add_message(build_send_asset_with_tax_deduction_msg(
-        deps.as_ref(),
-        recipient_address.clone(),
-        asset_label.clone(),
-        asset_type,
-        borrow_amount,
-    )?);
-
-    response = response
-        .add_attribute("action", "borrow")
-        .add_attribute("asset", asset_label.as_str())
-        .add_attribute("user", borrower_address.as_str())
-        .add_attribute("recipient", recipient_address.as_str())
-        .add_attribute("amount", borrow_amount);
-    Ok(response)
 }
 
-/// Handle the repay of native tokens. Refund extra funds if they exist
-pub fn execute_repay(
-    deps: DepsMut,
+/// Execute deposits and mint corresponding ma_tokens
+pub fn execute_deposit(
+    mut deps: DepsMut,
     env: Env,
     _info: MessageInfo,
     sender_address: Addr,
     on_behalf_of: Option<String>,
     asset_reference: &[u8],
-    asset_label: String,
-    repay_amount: Uint128,
-    asset_type: AssetType,
+    asset_label: &str,
+    deposit_amount: Uint128,
 ) -> Result<Response, ContractError> {
     let user_address = if let Some(address) = on_behalf_of {
-        let on_behalf_of_addr = deps.api.addr_validate(&address)?;
-        // Uncollateralized loans should not have 'on behalf of' because it creates accounting complexity for them
-        match UNCOLLATERALIZED_LOAN_LIMITS
-            .may_load(deps.storage, (asset_reference, &on_behalf_of_addr))?
-        {
-            Some(limit) if !limit.is_zero() => {
-                return Err(ContractError::CannotRepayUncollateralizedLoanOnBehalfOf {})
-            }
-            _ => on_behalf_of_addr,
-        }
+        deps.api.addr_validate(&address)?
     } else {
         sender_address.clone()
     };
 
     let mut market = MARKETS.load(deps.storage, asset_reference)?;
-
     if !market.active {
-        return Err(ContractError::MarketNotActive { asset: asset_label });
+        return Err(ContractError::MarketNotActive {
+            asset: asset_label.to_string(),
+        });
+    }
+    assert_market_not_paused(deps.storage, &market, asset_label)?;
+    if !market.deposit_enabled {
+        return Err(ContractError::DepositNotEnabled {
+            asset: asset_label.to_string(),
+        });
+    }
+    if market.frozen {
+        return Err(ContractError::MarketFrozen {
+            asset: asset_label.to_string(),
+        });
     }
 
-    // Cannot repay zero amount
-    if repay_amount.is_zero() {
-        return Err(ContractError::InvalidRepayAmount { asset: asset_label });
+    // Cannot deposit zero amount
+    if deposit_amount.is_zero() {
+        return Err(ContractError::InvalidDepositAmount {
+            asset: asset_label.to_string(),
+        });
     }
 
-    // Check new debt
-    let mut debt = DEBTS.load(deps.storage, (asset_reference, &user_address))?;
+    record_inflow(
+        deps.storage,
+        asset_reference,
+        deposit_amount,
+        env.block.time.seconds(),
+    )?;
 
-    if debt.amount_scaled.is_zero() {
-        return Err(ContractError::CannotRepayZeroDebt {});
+    let mut user = USERS
+        .may_load(deps.storage, &user_address)?
+        .unwrap_or_default();
+
+    let mut response = Response::new();
+    let has_deposited_asset = get_bit(user.collateral_assets, market.index)?;
+    if !has_deposited_asset {
+        set_bit(&mut user.collateral_assets, market.index)?;
+        USERS.save(deps.storage, &user_address, &user)?;
+        response = response.add_event(build_collateral_position_changed_event(
+            asset_label,
+            true,
+            user_address.to_string(),
+        ));
     }
 
     let config = CONFIG.load(deps.storage)?;
 
+    // update indexes and interest rates
     let protocol_rewards_collector_address = address_provider::helpers::query_address(
         &deps.querier,
         config.address_provider_address,
         MarsContract::ProtocolRewardsCollector,
     )?;
-
-    let mut response = Response::new();
-
     response = apply_accumulated_interests(
         &env,
         protocol_rewards_collector_address,
         &mut market,
         response,
     )?;
-
-    let debt_amount_scaled_before = debt.amount_scaled;
-    let debt_amount_before =
-        get_underlying_debt_amount(debt.amount_scaled, &market, env.block.time.seconds())?;
-
-    // If repay amount exceeds debt, refund any excess amounts
-    let mut refund_amount = Uint128::zero();
-    let mut debt_amount_after = Uint128::zero();
-    if repay_amount > debt_amount_before {
-        refund_amount = repay_amount - debt_amount_before;
-        let refund_msg = build_send_asset_with_tax_deduction_msg(
-            deps.as_ref(),
-            user_address.clone(),
-            asset_label.clone(),
-            asset_type,
-            refund_amount,
-        )?;
-        response = response.add_message(refund_msg);
-    } else {
-        debt_amount_after = debt_amount_before - repay_amount;
-    }
-
-    let debt_amount_scaled_after =
-        get_scaled_debt_amount(debt_amount_after, &market, env.block.time.seconds())?;
-    debt.amount_scaled = debt_amount_scaled_after;
-    DEBTS.save(deps.storage, (asset_reference, &user_address), &debt)?;
-
-    let debt_amount_scaled_delta =
-        debt_amount_scaled_before.checked_sub(debt_amount_scaled_after)?;
-
-    market.debt_total_scaled = market
-        .debt_total_scaled
-        .checked_sub(debt_amount_scaled_delta)?;
-
     response = update_interest_rates(
-        &deps,
+        &mut deps,
         &env,
         &mut market,
         Uint128::zero(),
-        &asset_label,
+        asset_label,
         response,
     )?;
     MARKETS.save(deps.storage, asset_reference, &market)?;
 
-    if debt.amount_scaled.is_zero() {
-        // Remove asset from borrowed assets
-        let mut user = USERS.load(deps.storage, &user_address)?;
-        unset_bit(&mut user.borrowed_assets, market.index)?;
-        USERS.save(deps.storage, &user_address, &user)?;
-        response = response.add_event(build_debt_position_changed_event(
-            &asset_label,
-            false,
-            user_address.to_string(),
-        ));
+    if market.liquidity_index.is_zero() {
+        return Err(ContractError::InvalidLiquidityIndex {});
     }
+    let mint_amount =
+        get_scaled_liquidity_amount(deposit_amount, &market, env.block.time.seconds())?;
+
+    record_principal_change(
+        deps.storage,
+        asset_reference,
+        &user_address,
+        PrincipalChange::Deposit,
+        deposit_amount,
+    )?;
 
     response = response
-        .add_attribute("action", "repay")
+        .add_attribute("action", "deposit")
         .add_attribute("asset", asset_label)
         .add_attribute("sender", sender_address)
-        .add_attribute("user", user_address)
-        .add_attribute("amount", repay_amount.checked_sub(refund_amount)?);
+        .add_attribute("user", user_address.as_str())
+        .add_attribute("amount", deposit_amount)
+        .add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: market.ma_token_address.into(),
+            msg: to_binary(&Cw20ExecuteMsg::Mint {
+                recipient: user_address.into(),
+                amount: mint_amount,
+            })?,
+            funds: vec![],
+        }));
+
+    if let Some(msg) = build_process_withdraw_queue_msg(
+        deps.storage,
+        &env,
+        asset_reference,
+        asset_label,
+        market.asset_type,
+    )? {
+        response = response.add_submessage(msg);
+    }
+
     Ok(response)
 }
 
-/// Execute loan liquidations on under-collateralized loans
-pub fn execute_liquidate(
+/// Burns sent maAsset in exchange of underlying asset
+pub fn execute_withdraw(
     mut deps: DepsMut,
     env: Env,
-    _info: MessageInfo,
-    liquidator_address: Addr,
-    collateral_asset: Asset,
-    debt_asset: Asset,
-    user_address: Addr,
-    sent_debt_asset_amount: Uint128,
-    receive_ma_token: bool,
+    info: MessageInfo,
+    asset: Asset,
+    amount: Option<Uint128>,
+    recipient_address: Option<String>,
 ) -> Result<Response, ContractError> {
-    let block_time = env.block.time.seconds();
-    let (debt_asset_label, debt_asset_reference, debt_asset_type) = debt_asset.get_attributes();
+    let withdrawer_addr = info.sender;
 
-    // 1. Validate liquidation
-    // If user (contract) has a positive uncollateralized limit then the user
-    // cannot be liquidated
-    if let Some(limit) = UNCOLLATERALIZED_LOAN_LIMITS.may_load(
-        deps.storage,
-        (debt_asset_reference.as_slice(), &user_address),
-    )? {
-        if !limit.is_zero() {
-            return Err(ContractError::CannotLiquidateWhenPositiveUncollateralizedLoanLimit {});
-        }
-    };
+    let (asset_label, asset_reference, asset_type) = asset.get_attributes();
+    let mut market = MARKETS.load(deps.storage, asset_reference.as_slice())?;
 
-    // liquidator must send positive amount of funds in the debt asset
-    if sent_debt_asset_amount.is_zero() {
-        return Err(ContractError::InvalidLiquidateAmount {
-            asset: debt_asset_label,
-        });
+    if !market.active {
+        return Err(ContractError::MarketNotActive { asset: asset_label });
     }
+    assert_market_not_paused(deps.storage, &market, &asset_label)?;
 
-    let (collateral_asset_label, collateral_asset_reference, collateral_asset_type) =
-        collateral_asset.get_attributes();
-
-    let collateral_market = MARKETS.load(deps.storage, collateral_asset_reference.as_slice())?;
-
-    if !collateral_market.active {
-        return Err(ContractError::MarketNotActive {
-            asset: collateral_asset_label,
-        });
-    }
+    let asset_ma_addr = market.ma_token_address.clone();
+    let withdrawer_balance_scaled_before =
+        cw20_get_balance(&deps.querier, asset_ma_addr, withdrawer_addr.clone())?;
+    let withdrawer_balance_before = get_underlying_liquidity_amount(
+        withdrawer_balance_scaled_before,
+        &market,
+        env.block.time.seconds(),
+    )?;
 
-    let mut user = USERS.load(deps.storage, &user_address)?;
-    let using_collateral_asset_as_collateral =
-        get_bit(user.collateral_assets, collateral_market.index)?;
-    if !using_collateral_asset_as_collateral {
-        return Err(ContractError::CannotLiquidateWhenCollateralUnset {
-            asset: collateral_asset_label,
-        });
+    if withdrawer_balance_scaled_before.is_zero() {
+        return Err(ContractError::UserNoBalance { asset: asset_label });
     }
 
-    // check if user has available collateral in specified collateral asset to be liquidated
-    let user_collateral_balance_scaled = cw20_get_balance(
-        &deps.querier,
-        collateral_market.ma_token_address.clone(),
-        user_address.clone(),
-    )?;
-    let user_collateral_balance = get_underlying_liquidity_amount(
-        user_collateral_balance_scaled,
-        &collateral_market,
-        block_time,
-    )?;
-    if user_collateral_balance.is_zero() {
-        return Err(ContractError::CannotLiquidateWhenNoCollateralBalance {});
-    }
+    let withdraw_amount = match amount {
+        Some(amount) => {
+            // Check user has sufficient balance to send back
+            if amount.is_zero() || amount > withdrawer_balance_before {
+                return Err(ContractError::InvalidWithdrawAmount { asset: asset_label });
+            };
+            amount
+        }
+        None => {
+            // If no amount is specified, the full balance is withdrawn
+            withdrawer_balance_before
+        }
+    };
 
-    // check if user has outstanding debt in the deposited asset that needs to be repayed
-    let mut user_debt = DEBTS.load(
+    record_outflow(
         deps.storage,
-        (debt_asset_reference.as_slice(), &user_address),
+        asset_reference.as_slice(),
+        &asset_label,
+        withdraw_amount,
+        env.block.time.seconds(),
     )?;
-    if user_debt.amount_scaled.is_zero() {
-        return Err(ContractError::CannotLiquidateWhenNoDebtBalance {});
-    }
 
-    // 2. Compute health factor
     let config = CONFIG.load(deps.storage)?;
 
     let mut addresses_query = address_provider::helpers::query_addresses(
@@ -1367,149 +1637,883 @@ pub fn execute_liquidate(
     let protocol_rewards_collector_address = addresses_query.pop().unwrap();
     let oracle_address = addresses_query.pop().unwrap();
 
-    let global_state = GLOBAL_STATE.load(deps.storage)?;
-    let user_position = get_user_position(
-        deps.as_ref(),
-        block_time,
-        &user_address,
-        oracle_address,
-        &user,
-        global_state.market_count,
-    )?;
-
-    let health_factor = match user_position.health_status {
-        // NOTE: Should not get in practice as it would fail on the debt asset check
-        UserHealthStatus::NotBorrowing => {
-            return Err(ContractError::CannotLiquidateWhenNoDebtBalance {})
+    let mut withdrawer = match USERS.may_load(deps.storage, &withdrawer_addr)? {
+        Some(user) => user,
+        None => {
+            // No address should withdraw without an existing user position already in
+            // storage (If this happens the protocol did something wrong). The exception is
+            // the protocol_rewards_collector which gets minted token without depositing
+            // nor receiving a transfer from another user.
+            if withdrawer_addr != protocol_rewards_collector_address {
+                return Err(ContractError::ExistingUserPositionRequired {});
+            }
+            User::default()
         }
-        UserHealthStatus::Borrowing(hf) => hf,
     };
+    let asset_as_collateral = get_bit(withdrawer.collateral_assets, market.index)?;
+    let user_is_borrowing = !withdrawer.borrowed_assets.is_zero();
 
-    // if health factor is not less than one user cannot be liquidated
-    if health_factor >= Decimal::one() {
-        return Err(ContractError::CannotLiquidateHealthyPosition {});
-    }
+    // if asset is used as collateral and user is borrowing we need to validate health factor after withdraw,
+    // otherwise no reasons to block the withdraw
+    if asset_as_collateral && user_is_borrowing {
+        let global_state = GLOBAL_STATE.load(deps.storage)?;
 
-    let collateral_and_debt_are_the_same_asset = debt_asset_reference == collateral_asset_reference;
+        let user_position = get_user_position(
+            deps.as_ref(),
+            env.block.time.seconds(),
+            &withdrawer_addr,
+            oracle_address,
+            &withdrawer,
+            global_state.market_count,
+        )?;
+        user_position.assert_prices_not_stale(env.block.time.seconds())?;
 
-    let debt_market = if !collateral_and_debt_are_the_same_asset {
-        MARKETS.load(deps.storage, debt_asset_reference.as_slice())?
-    } else {
-        collateral_market.clone()
-    };
+        let withdraw_asset_price =
+            user_position.get_asset_price(asset_reference.as_slice(), &asset_label)?;
 
-    if !debt_market.active {
-        return Err(ContractError::MarketNotActive {
-            asset: debt_asset_label,
-        });
-    }
+        let withdraw_amount_in_base_asset = withdraw_amount * withdraw_asset_price;
 
-    // 3. Compute debt to repay and collateral to liquidate
-    let collateral_price = user_position.get_asset_price(
-        collateral_asset_reference.as_slice(),
-        &collateral_asset_label,
-    )?;
-    let debt_price =
-        user_position.get_asset_price(debt_asset_reference.as_slice(), &debt_asset_label)?;
+        let weighted_liquidation_threshold_in_base_asset_after_withdraw = user_position
+            .weighted_liquidation_threshold_in_base_asset
+            .checked_sub(
+                withdraw_amount_in_base_asset
+                    * market.liquidation_threshold_at(env.block.time.seconds())?,
+            )?;
+        let health_factor_after_withdraw = Decimal::from_ratio(
+            weighted_liquidation_threshold_in_base_asset_after_withdraw,
+            user_position.total_collateralized_debt_in_base_asset,
+        );
+        if health_factor_after_withdraw < Decimal::one() {
+            return Err(ContractError::InvalidHealthFactorAfterWithdraw {});
+        }
+    }
 
     let mut response = Response::new();
 
-    let user_debt_asset_total_debt =
-        get_underlying_debt_amount(user_debt.amount_scaled, &debt_market, block_time)?;
-
-    let (debt_amount_to_repay, collateral_amount_to_liquidate, refund_amount) =
-        liquidation_compute_amounts(
-            collateral_price,
-            debt_price,
-            config.close_factor,
-            user_collateral_balance,
-            collateral_market.liquidation_bonus,
-            user_debt_asset_total_debt,
-            sent_debt_asset_amount,
-        )?;
-
-    // 4. Update collateral positions and market depending on whether the liquidator elects to
-    // receive ma_tokens or the underlying asset
-    if receive_ma_token {
-        response = process_ma_token_transfer_to_liquidator(
-            deps.branch(),
-            block_time,
-            &user_address,
-            &liquidator_address,
-            &collateral_asset_label,
-            &collateral_market,
-            collateral_amount_to_liquidate,
-            response,
-        )?;
-    } else {
-        response = process_underlying_asset_transfer_to_liquidator(
-            deps.branch(),
-            &env,
-            &user_address,
-            &liquidator_address,
-            collateral_asset_label.clone(),
-            collateral_asset_type,
-            &collateral_market,
-            collateral_amount_to_liquidate,
-            response,
-        )?;
-    }
-
-    // if max collateral to liquidate equals the user's balance then unset collateral bit
-    if collateral_amount_to_liquidate == user_collateral_balance {
-        unset_bit(&mut user.collateral_assets, collateral_market.index)?;
-        USERS.save(deps.storage, &user_address, &user)?;
+    // if amount to withdraw equals the user's balance then unset collateral bit
+    if asset_as_collateral && withdraw_amount == withdrawer_balance_before {
+        unset_bit(&mut withdrawer.collateral_assets, market.index)?;
+        USERS.save(deps.storage, &withdrawer_addr, &withdrawer)?;
         response = response.add_event(build_collateral_position_changed_event(
-            collateral_asset_label.as_str(),
+            asset_label.as_str(),
             false,
-            user_address.to_string(),
+            withdrawer_addr.to_string(),
         ));
     }
 
-    // 5. Compute and update user new debt
-    let user_debt_asset_debt_amount_after =
-        user_debt_asset_total_debt.checked_sub(debt_amount_to_repay)?;
-    let user_debt_asset_debt_amount_scaled_after = get_scaled_debt_amount(
-        user_debt_asset_debt_amount_after,
-        &debt_market,
-        env.block.time.seconds(),
+    // update indexes and interest rates
+    response = apply_accumulated_interests(
+        &env,
+        protocol_rewards_collector_address,
+        &mut market,
+        response,
     )?;
+    response = update_interest_rates(
+        &mut deps,
+        &env,
+        &mut market,
+        withdraw_amount,
+        &asset_label,
+        response,
+    )?;
+    MARKETS.save(deps.storage, asset_reference.as_slice(), &market)?;
 
-    // Compute delta so it can be substracted to total debt
-    let debt_amount_scaled_delta = user_debt
-        .amount_scaled
-        .checked_sub(user_debt_asset_debt_amount_scaled_after)?;
-
-    user_debt.amount_scaled = user_debt_asset_debt_amount_scaled_after;
-
-    DEBTS.save(
+    record_principal_change(
         deps.storage,
-        (debt_asset_reference.as_slice(), &user_address),
-        &user_debt,
+        asset_reference.as_slice(),
+        &withdrawer_addr,
+        PrincipalChange::Withdraw,
+        withdraw_amount,
     )?;
 
-    let debt_market_debt_total_scaled_after = debt_market
-        .debt_total_scaled
-        .checked_sub(debt_amount_scaled_delta)?;
+    // burn maToken
+    let withdrawer_balance_after = withdrawer_balance_before.checked_sub(withdraw_amount)?;
+    let withdrawer_balance_scaled_after =
+        get_scaled_liquidity_amount(withdrawer_balance_after, &market, env.block.time.seconds())?;
 
-    // 6. Update markets depending on whether the collateral and debt markets are the same
-    // and whether the liquidator receives ma_tokens (no change in liquidity) or underlying asset
-    // (changes liquidity)
-    if collateral_and_debt_are_the_same_asset {
-        // NOTE: for the sake of clarity copy attributes from collateral market and
-        // give generic naming. Debt market could have been used as well
-        let mut asset_market_after = collateral_market;
-        let asset_reference = collateral_asset_reference;
-        let asset_label = &collateral_asset_label;
+    let burn_amount =
+        withdrawer_balance_scaled_before.checked_sub(withdrawer_balance_scaled_after)?;
+    response = response.add_message(CosmosMsg::Wasm(WasmMsg::Execute {
+        contract_addr: market.ma_token_address.to_string(),
+        msg: to_binary(&ma_token::msg::ExecuteMsg::Burn {
+            user: withdrawer_addr.to_string(),
+            amount: burn_amount,
+        })?,
+        funds: vec![],
+    }));
 
-        response = apply_accumulated_interests(
-            &env,
-            protocol_rewards_collector_address,
-            &mut asset_market_after,
+    // send underlying asset to user or another recipient
+    let recipient_address = if let Some(address) = recipient_address {
+        deps.api.addr_validate(&address)?
+    } else {
+        withdrawer_addr.clone()
+    };
+    response = response.add_message(build_send_asset_with_tax_deduction_msg(
+        deps.as_ref(),
+        recipient_address.clone(),
+        asset_label.clone(),
+        asset_type,
+        withdraw_amount,
+    )?);
+
+    response = response
+        .add_attribute("action", "withdraw")
+        .add_attribute("asset", asset_label.as_str())
+        .add_attribute("user", withdrawer_addr.as_str())
+        .add_attribute("recipient", recipient_address.as_str())
+        .add_attribute("burn_amount", burn_amount)
+        .add_attribute("withdraw_amount", withdraw_amount);
+    Ok(response)
+}
+
+/// Add debt for the borrower and send the borrowed funds
+pub fn execute_borrow(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    asset: Asset,
+    borrow_amount: Uint128,
+    recipient_address: Option<String>,
+) -> Result<Response, ContractError> {
+    let borrower_address = info.sender;
+    let block_time = env.block.time.seconds();
+    let (asset_label, asset_reference, asset_type) = asset.get_attributes();
+
+    // Cannot borrow zero amount
+    if borrow_amount.is_zero() {
+        return Err(ContractError::InvalidBorrowAmount { asset: asset_label });
+    }
+
+    // Load market and user state
+    let global_state = GLOBAL_STATE.load(deps.storage)?;
+    let mut borrow_market = MARKETS.load(deps.storage, asset_reference.as_slice())?;
+
+    if !borrow_market.active {
+        return Err(ContractError::MarketNotActive { asset: asset_label });
+    }
+    assert_market_not_paused(deps.storage, &borrow_market, &asset_label)?;
+    record_outflow(
+        deps.storage,
+        asset_reference.as_slice(),
+        &asset_label,
+        borrow_amount,
+        block_time,
+    )?;
+    if !borrow_market.borrow_enabled {
+        return Err(ContractError::BorrowNotEnabled { asset: asset_label });
+    }
+    if borrow_market.frozen {
+        return Err(ContractError::MarketFrozen { asset: asset_label });
+    }
+
+    let uncollateralized_loan_limit = UNCOLLATERALIZED_LOAN_LIMITS
+        .may_load(
+            deps.storage,
+            (asset_reference.as_slice(), &borrower_address),
+        )?
+        .unwrap_or_else(Uint128::zero);
+
+    // Debt can only be drawn from a credit line before it expires. Limits set before credit
+    // line terms existed have no stored terms and never expire
+    let mut credit_line_terms = if uncollateralized_loan_limit.is_zero() {
+        None
+    } else {
+        let terms = CREDIT_LINE_TERMS
+            .may_load(
+                deps.storage,
+                (asset_reference.as_slice(), &borrower_address),
+            )?
+            .unwrap_or_else(|| CreditLineTerms::perpetual(block_time));
+        let status = terms.status(block_time);
+        if status != CreditLineStatus::Active {
+            return Err(ContractError::CreditLineNotActive { status });
+        }
+        Some(terms)
+    };
+
+    let mut user: User = match USERS.may_load(deps.storage, &borrower_address)? {
+        Some(user) => user,
+        None => {
+            if uncollateralized_loan_limit.is_zero() {
+                return Err(ContractError::UserNoCollateral {});
+            }
+            // If User has some uncollateralized_loan_limit, then we don't require an existing debt position and initialize a new one.
+            User::default()
+        }
+    };
+
+    let is_borrowing_asset = get_bit(user.borrowed_assets, borrow_market.index)?;
+
+    let config = CONFIG.load(deps.storage)?;
+
+    let mut addresses_query = address_provider::helpers::query_addresses(
+        &deps.querier,
+        config.address_provider_address,
+        vec![MarsContract::Oracle, MarsContract::ProtocolRewardsCollector],
+    )?;
+    let protocol_rewards_collector_address = addresses_query.pop().unwrap();
+    let oracle_address = addresses_query.pop().unwrap();
+
+    // Check if user can borrow specified amount
+    let mut uncollateralized_debt = false;
+    if uncollateralized_loan_limit.is_zero() {
+        // Collateralized loan: check max ltv is not exceeded
+        let user_position = get_user_position(
+            deps.as_ref(),
+            env.block.time.seconds(),
+            &borrower_address,
+            oracle_address.clone(),
+            &user,
+            global_state.market_count,
+        )?;
+        user_position.assert_prices_not_stale(env.block.time.seconds())?;
+
+        let borrow_asset_price = if is_borrowing_asset {
+            // if user was already borrowing, get price from user position
+            user_position.get_asset_price(asset_reference.as_slice(), &asset_label)?
+        } else {
+            let base_denom =
+                mars_core::oracle::helpers::query_base_denom(deps.querier, oracle_address.clone())?;
+            let borrow_asset_price = mars_core::oracle::helpers::query_price_with_metadata(
+                deps.querier,
+                oracle_address,
+                &base_denom,
+                &asset_label,
+                asset_reference.clone(),
+                asset_type,
+                env.block.time.seconds(),
+            )?;
+            assert_price_not_stale(&asset_label, &borrow_asset_price, env.block.time.seconds())?;
+            borrow_asset_price.price
+        };
+
+        let borrow_amount_in_base_asset = borrow_amount * borrow_asset_price;
+
+        let total_debt_in_base_asset_after_borrow = user_position
+            .total_debt_in_base_asset
+            .checked_add(borrow_amount_in_base_asset)?;
+        if total_debt_in_base_asset_after_borrow > user_position.max_debt_in_base_asset {
+            return Err(ContractError::BorrowAmountExceedsGivenCollateral {});
+        }
+    } else {
+        // Uncollateralized loan: check borrow amount plus debt does not exceed uncollateralized loan limit
+        uncollateralized_debt = true;
+
+        let borrower_debt = DEBTS
+            .may_load(
+                deps.storage,
+                (asset_reference.as_slice(), &borrower_address),
+            )?
+            .unwrap_or(Debt {
+                amount_scaled: Uint128::zero(),
+                uncollateralized: uncollateralized_debt,
+            });
+
+        let asset_market = MARKETS.load(deps.storage, asset_reference.as_slice())?;
+        let debt_amount = get_underlying_debt_amount(
+            borrower_debt.amount_scaled,
+            &asset_market,
+            env.block.time.seconds(),
+        )?;
+        let pending_interest_premium = match &credit_line_terms {
+            Some(terms) => compute_pending_interest_premium(terms, debt_amount, block_time)?,
+            None => Uint128::zero(),
+        };
+
+        let debt_after_borrow = debt_amount
+            .checked_add(pending_interest_premium)?
+            .checked_add(borrow_amount)?;
+        if debt_after_borrow > uncollateralized_loan_limit {
+            return Err(ContractError::BorrowAmountExceedsUncollateralizedLoanLimit {});
+        }
+    }
+
+    let mut response = Response::new();
+
+    response = apply_accumulated_interests(
+        &env,
+        protocol_rewards_collector_address.clone(),
+        &mut borrow_market,
+        response,
+    )?;
+
+    // Set borrowing asset for user
+    if !is_borrowing_asset {
+        set_bit(&mut user.borrowed_assets, borrow_market.index)?;
+        USERS.save(deps.storage, &borrower_address, &user)?;
+        response = response.add_event(build_debt_position_changed_event(
+            asset_label.as_str(),
+            true,
+            borrower_address.to_string(),
+        ));
+    }
+
+    // Set new debt
+    let mut debt = DEBTS
+        .may_load(
+            deps.storage,
+            (asset_reference.as_slice(), &borrower_address),
+        )?
+        .unwrap_or(Debt {
+            amount_scaled: Uint128::zero(),
+            uncollateralized: uncollateralized_debt,
+        });
+    if let Some(terms) = credit_line_terms.as_mut() {
+        let interest_premium_scaled =
+            apply_accrued_interest_premium(terms, &borrow_market, &mut debt, block_time)?;
+        borrow_market.debt_total_scaled = borrow_market
+            .debt_total_scaled
+            .checked_add(interest_premium_scaled)?;
+        response = distribute_interest_premium(
+            &deps.querier,
+            protocol_rewards_collector_address,
+            &mut borrow_market,
+            interest_premium_scaled,
+            response,
+        )?;
+        CREDIT_LINE_TERMS.save(
+            deps.storage,
+            (asset_reference.as_slice(), &borrower_address),
+            terms,
+        )?;
+    }
+    let borrow_amount_scaled =
+        get_scaled_debt_amount(borrow_amount, &borrow_market, env.block.time.seconds())?;
+    debt.amount_scaled = debt.amount_scaled.checked_add(borrow_amount_scaled)?;
+    DEBTS.save(
+        deps.storage,
+        (asset_reference.as_slice(), &borrower_address),
+        &debt,
+    )?;
+
+    borrow_market.debt_total_scaled += borrow_amount_scaled;
+
+    record_principal_change(
+        deps.storage,
+        asset_reference.as_slice(),
+        &borrower_address,
+        PrincipalChange::Borrow,
+        borrow_amount,
+    )?;
+
+    response = update_interest_rates(
+        &mut deps,
+        &env,
+        &mut borrow_market,
+        borrow_amount,
+        &asset_label,
+        response,
+    )?;
+    MARKETS.save(deps.storage, asset_reference.as_slice(), &borrow_market)?;
+
+    // Send borrow amount to borrower or another recipient
+    let recipient_address = if let Some(address) = recipient_address {
+        deps.api.addr_validate(&address)?
+    } else {
+        borrower_address.clone()
+    };
+    response = response.add_message(build_send_asset_with_tax_deduction_msg(
+        deps.as_ref(),
+        recipient_address.clone(),
+        asset_label.clone(),
+        asset_type,
+        borrow_amount,
+    )?);
+
+    response = response
+        .add_attribute("action", "borrow")
+        .add_attribute("asset", asset_label.as_str())
+        .add_attribute("user", borrower_address.as_str())
+        .add_attribute("recipient", recipient_address.as_str())
+        .add_attribute("amount", borrow_amount);
+    Ok(response)
+}
+
+/// Handle the repay of native tokens. Refund extra funds if they exist
+pub fn execute_repay(
+    mut deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
+    sender_address: Addr,
+    on_behalf_of: Option<String>,
+    asset_reference: &[u8],
+    asset_label: String,
+    repay_amount: Uint128,
+    asset_type: AssetType,
+) -> Result<Response, ContractError> {
+    let user_address = if let Some(address) = on_behalf_of {
+        let on_behalf_of_addr = deps.api.addr_validate(&address)?;
+        // Uncollateralized loans should not have 'on behalf of' because it creates accounting complexity for them
+        match UNCOLLATERALIZED_LOAN_LIMITS
+            .may_load(deps.storage, (asset_reference, &on_behalf_of_addr))?
+        {
+            Some(limit) if !limit.is_zero() => {
+                return Err(ContractError::CannotRepayUncollateralizedLoanOnBehalfOf {})
+            }
+            _ => on_behalf_of_addr,
+        }
+    } else {
+        sender_address.clone()
+    };
+
+    let mut market = MARKETS.load(deps.storage, asset_reference)?;
+
+    if !market.active {
+        return Err(ContractError::MarketNotActive { asset: asset_label });
+    }
+    assert_market_not_paused(deps.storage, &market, &asset_label)?;
+
+    // Cannot repay zero amount
+    if repay_amount.is_zero() {
+        return Err(ContractError::InvalidRepayAmount { asset: asset_label });
+    }
+
+    // Check new debt
+    let mut debt = DEBTS.load(deps.storage, (asset_reference, &user_address))?;
+
+    if debt.amount_scaled.is_zero() {
+        return Err(ContractError::CannotRepayZeroDebt {});
+    }
+
+    let config = CONFIG.load(deps.storage)?;
+
+    let protocol_rewards_collector_address = address_provider::helpers::query_address(
+        &deps.querier,
+        config.address_provider_address,
+        MarsContract::ProtocolRewardsCollector,
+    )?;
+
+    let mut response = Response::new();
+
+    response = apply_accumulated_interests(
+        &env,
+        protocol_rewards_collector_address.clone(),
+        &mut market,
+        response,
+    )?;
+
+    if let Some(mut terms) =
+        CREDIT_LINE_TERMS.may_load(deps.storage, (asset_reference, &user_address))?
+    {
+        let interest_premium_scaled = apply_accrued_interest_premium(
+            &mut terms,
+            &market,
+            &mut debt,
+            env.block.time.seconds(),
+        )?;
+        market.debt_total_scaled = market
+            .debt_total_scaled
+            .checked_add(interest_premium_scaled)?;
+        response = distribute_interest_premium(
+            &deps.querier,
+            protocol_rewards_collector_address,
+            &mut market,
+            interest_premium_scaled,
+            response,
+        )?;
+        CREDIT_LINE_TERMS.save(deps.storage, (asset_reference, &user_address), &terms)?;
+    }
+
+    let debt_amount_scaled_before = debt.amount_scaled;
+    let debt_amount_before =
+        get_underlying_debt_amount(debt.amount_scaled, &market, env.block.time.seconds())?;
+
+    // If repay amount exceeds debt, refund any excess amounts
+    let mut refund_amount = Uint128::zero();
+    let mut debt_amount_after = Uint128::zero();
+    if repay_amount > debt_amount_before {
+        refund_amount = repay_amount - debt_amount_before;
+        let refund_msg = build_send_asset_with_tax_deduction_msg(
+            deps.as_ref(),
+            user_address.clone(),
+            asset_label.clone(),
+            asset_type,
+            refund_amount,
+        )?;
+        response = response.add_message(refund_msg);
+    } else {
+        debt_amount_after = debt_amount_before - repay_amount;
+    }
+
+    record_inflow(
+        deps.storage,
+        asset_reference,
+        repay_amount.checked_sub(refund_amount)?,
+        env.block.time.seconds(),
+    )?;
+    record_principal_change(
+        deps.storage,
+        asset_reference,
+        &user_address,
+        PrincipalChange::Repay,
+        repay_amount.checked_sub(refund_amount)?,
+    )?;
+
+    let debt_amount_scaled_after =
+        get_scaled_debt_amount(debt_amount_after, &market, env.block.time.seconds())?;
+    debt.amount_scaled = debt_amount_scaled_after;
+    DEBTS.save(deps.storage, (asset_reference, &user_address), &debt)?;
+
+    let debt_amount_scaled_delta =
+        debt_amount_scaled_before.checked_sub(debt_amount_scaled_after)?;
+
+    market.debt_total_scaled = market
+        .debt_total_scaled
+        .checked_sub(debt_amount_scaled_delta)?;
+
+    response = update_interest_rates(
+        &mut deps,
+        &env,
+        &mut market,
+        Uint128::zero(),
+        &asset_label,
+        response,
+    )?;
+    MARKETS.save(deps.storage, asset_reference, &market)?;
+
+    if debt.amount_scaled.is_zero() {
+        // Remove asset from borrowed assets
+        let mut user = USERS.load(deps.storage, &user_address)?;
+        unset_bit(&mut user.borrowed_assets, market.index)?;
+        USERS.save(deps.storage, &user_address, &user)?;
+        response = response.add_event(build_debt_position_changed_event(
+            &asset_label,
+            false,
+            user_address.to_string(),
+        ));
+    }
+
+    response = response
+        .add_attribute("action", "repay")
+        .add_attribute("asset", &asset_label)
+        .add_attribute("sender", sender_address)
+        .add_attribute("user", user_address)
+        .add_attribute("amount", repay_amount.checked_sub(refund_amount)?);
+
+    if let Some(msg) = build_process_withdraw_queue_msg(
+        deps.storage,
+        &env,
+        asset_reference,
+        &asset_label,
+        asset_type,
+    )? {
+        response = response.add_submessage(msg);
+    }
+
+    Ok(response)
+}
+
+/// Execute loan liquidations on under-collateralized loans
+pub fn execute_liquidate(
+    mut deps: DepsMut,
+    env: Env,
+    _info: MessageInfo,
+    liquidator_address: Addr,
+    collateral_asset: Asset,
+    debt_asset: Asset,
+    user_address: Addr,
+    sent_debt_asset_amount: Uint128,
+    receive_ma_token: bool,
+) -> Result<Response, ContractError> {
+    let block_time = env.block.time.seconds();
+    let (debt_asset_label, debt_asset_reference, debt_asset_type) = debt_asset.get_attributes();
+
+    // 1. Validate liquidation
+    // If user (contract) has a positive uncollateralized limit then the user
+    // cannot be liquidated, unless the credit line's grace period is over
+    let mut credit_line_terms = CREDIT_LINE_TERMS.may_load(
+        deps.storage,
+        (debt_asset_reference.as_slice(), &user_address),
+    )?;
+    if let Some(limit) = UNCOLLATERALIZED_LOAN_LIMITS.may_load(
+        deps.storage,
+        (debt_asset_reference.as_slice(), &user_address),
+    )? {
+        let is_delinquent = match &credit_line_terms {
+            Some(terms) => terms.status(block_time) == CreditLineStatus::Delinquent,
+            None => false,
+        };
+        if !limit.is_zero() && !is_delinquent {
+            return Err(ContractError::CannotLiquidateWhenPositiveUncollateralizedLoanLimit {});
+        }
+    };
+
+    // liquidator must send positive amount of funds in the debt asset
+    if sent_debt_asset_amount.is_zero() {
+        return Err(ContractError::InvalidLiquidateAmount {
+            asset: debt_asset_label,
+        });
+    }
+
+    let (collateral_asset_label, collateral_asset_reference, collateral_asset_type) =
+        collateral_asset.get_attributes();
+
+    let collateral_market = MARKETS.load(deps.storage, collateral_asset_reference.as_slice())?;
+
+    if !collateral_market.active {
+        return Err(ContractError::MarketNotActive {
+            asset: collateral_asset_label,
+        });
+    }
+    assert_market_not_paused(deps.storage, &collateral_market, &collateral_asset_label)?;
+
+    let mut user = USERS.load(deps.storage, &user_address)?;
+    let using_collateral_asset_as_collateral =
+        get_bit(user.collateral_assets, collateral_market.index)?;
+    if !using_collateral_asset_as_collateral {
+        return Err(ContractError::CannotLiquidateWhenCollateralUnset {
+            asset: collateral_asset_label,
+        });
+    }
+
+    // check if user has available collateral in specified collateral asset to be liquidated
+    let user_collateral_balance_scaled = cw20_get_balance(
+        &deps.querier,
+        collateral_market.ma_token_address.clone(),
+        user_address.clone(),
+    )?;
+    let user_collateral_balance = get_underlying_liquidity_amount(
+        user_collateral_balance_scaled,
+        &collateral_market,
+        block_time,
+    )?;
+    if user_collateral_balance.is_zero() {
+        return Err(ContractError::CannotLiquidateWhenNoCollateralBalance {});
+    }
+
+    // check if user has outstanding debt in the deposited asset that needs to be repayed
+    let mut user_debt = DEBTS.load(
+        deps.storage,
+        (debt_asset_reference.as_slice(), &user_address),
+    )?;
+    if user_debt.amount_scaled.is_zero() {
+        return Err(ContractError::CannotLiquidateWhenNoDebtBalance {});
+    }
+
+    // 2. Compute health factor
+    let config = CONFIG.load(deps.storage)?;
+
+    let mut addresses_query = address_provider::helpers::query_addresses(
+        &deps.querier,
+        config.address_provider_address,
+        vec![MarsContract::Oracle, MarsContract::ProtocolRewardsCollector],
+    )?;
+    let protocol_rewards_collector_address = addresses_query.pop().unwrap();
+    let oracle_address = addresses_query.pop().unwrap();
+
+    let global_state = GLOBAL_STATE.load(deps.storage)?;
+    let user_position = get_user_position(
+        deps.as_ref(),
+        block_time,
+        &user_address,
+        oracle_address,
+        &user,
+        global_state.market_count,
+    )?;
+    user_position.assert_prices_not_stale(block_time)?;
+
+    let health_factor = match user_position.health_status {
+        // NOTE: Should not get in practice as it would fail on the debt asset check
+        UserHealthStatus::NotBorrowing => {
+            return Err(ContractError::CannotLiquidateWhenNoDebtBalance {})
+        }
+        UserHealthStatus::Borrowing(hf) => hf,
+    };
+
+    // if health factor is not less than one user cannot be liquidated
+    if health_factor >= Decimal::one() {
+        return Err(ContractError::CannotLiquidateHealthyPosition {});
+    }
+
+    let collateral_and_debt_are_the_same_asset = debt_asset_reference == collateral_asset_reference;
+
+    let debt_market = if !collateral_and_debt_are_the_same_asset {
+        MARKETS.load(deps.storage, debt_asset_reference.as_slice())?
+    } else {
+        collateral_market.clone()
+    };
+
+    if !debt_market.active {
+        return Err(ContractError::MarketNotActive {
+            asset: debt_asset_label,
+        });
+    }
+    assert_market_not_paused(deps.storage, &debt_market, &debt_asset_label)?;
+
+    // Premium is added to the market's total debt when computing its value after the
+    // liquidation, as accumulated interests are applied to the market later on
+    let interest_premium_scaled = match credit_line_terms.as_mut() {
+        Some(terms) => {
+            let interest_premium_scaled =
+                apply_accrued_interest_premium(terms, &debt_market, &mut user_debt, block_time)?;
+            CREDIT_LINE_TERMS.save(
+                deps.storage,
+                (debt_asset_reference.as_slice(), &user_address),
+                terms,
+            )?;
+            interest_premium_scaled
+        }
+        None => Uint128::zero(),
+    };
+
+    // 3. Compute debt to repay and collateral to liquidate
+    let collateral_price = user_position.get_asset_price(
+        collateral_asset_reference.as_slice(),
+        &collateral_asset_label,
+    )?;
+    let debt_price =
+        user_position.get_asset_price(debt_asset_reference.as_slice(), &debt_asset_label)?;
+
+    let mut response = Response::new();
+
+    let user_debt_asset_total_debt =
+        get_underlying_debt_amount(user_debt.amount_scaled, &debt_market, block_time)?;
+
+    let (debt_amount_to_repay, collateral_amount_to_liquidate, refund_amount) =
+        liquidation_compute_amounts(
+            collateral_price,
+            debt_price,
+            config.close_factor,
+            user_collateral_balance,
+            collateral_market.liquidation_bonus,
+            user_debt_asset_total_debt,
+            sent_debt_asset_amount,
+        )?;
+
+    // Repaid debt frees up outflow capacity on the debt market while collateral sent as the
+    // underlying asset counts as an outflow from the collateral market. Liquidations are not
+    // subject to the limit as blocking them would leave bad debt in the protocol
+    record_inflow(
+        deps.storage,
+        debt_asset_reference.as_slice(),
+        debt_amount_to_repay,
+        block_time,
+    )?;
+    if !receive_ma_token {
+        record_exempt_outflow(
+            deps.storage,
+            collateral_asset_reference.as_slice(),
+            collateral_amount_to_liquidate,
+            block_time,
+        )?;
+    }
+
+    // The liquidated user repays debt and withdraws collateral. If the liquidator receives
+    // maTokens it is accounted as a deposit for them
+    record_principal_change(
+        deps.storage,
+        debt_asset_reference.as_slice(),
+        &user_address,
+        PrincipalChange::Repay,
+        debt_amount_to_repay,
+    )?;
+    record_principal_change(
+        deps.storage,
+        collateral_asset_reference.as_slice(),
+        &user_address,
+        PrincipalChange::Withdraw,
+        collateral_amount_to_liquidate,
+    )?;
+    if receive_ma_token {
+        record_principal_change(
+            deps.storage,
+            collateral_asset_reference.as_slice(),
+            &liquidator_address,
+            PrincipalChange::Deposit,
+            collateral_amount_to_liquidate,
+        )?;
+    }
+
+    // 4. Update collateral positions and market depending on whether the liquidator elects to
+    // receive ma_tokens or the underlying asset
+    if receive_ma_token {
+        response = process_ma_token_transfer_to_liquidator(
+            deps.branch(),
+            block_time,
+            &user_address,
+            &liquidator_address,
+            &collateral_asset_label,
+            &collateral_market,
+            collateral_amount_to_liquidate,
+            response,
+        )?;
+    } else {
+        response = process_underlying_asset_transfer_to_liquidator(
+            deps.branch(),
+            &env,
+            &user_address,
+            &liquidator_address,
+            collateral_asset_label.clone(),
+            collateral_asset_type,
+            &collateral_market,
+            collateral_amount_to_liquidate,
+            response,
+        )?;
+    }
+
+    // if max collateral to liquidate equals the user's balance then unset collateral bit
+    if collateral_amount_to_liquidate == user_collateral_balance {
+        unset_bit(&mut user.collateral_assets, collateral_market.index)?;
+        USERS.save(deps.storage, &user_address, &user)?;
+        response = response.add_event(build_collateral_position_changed_event(
+            collateral_asset_label.as_str(),
+            false,
+            user_address.to_string(),
+        ));
+    }
+
+    // 5. Compute and update user new debt
+    let user_debt_asset_debt_amount_after =
+        user_debt_asset_total_debt.checked_sub(debt_amount_to_repay)?;
+    let user_debt_asset_debt_amount_scaled_after = get_scaled_debt_amount(
+        user_debt_asset_debt_amount_after,
+        &debt_market,
+        env.block.time.seconds(),
+    )?;
+
+    // Compute delta so it can be substracted to total debt
+    let debt_amount_scaled_delta = user_debt
+        .amount_scaled
+        .checked_sub(user_debt_asset_debt_amount_scaled_after)?;
+
+    user_debt.amount_scaled = user_debt_asset_debt_amount_scaled_after;
+
+    DEBTS.save(
+        deps.storage,
+        (debt_asset_reference.as_slice(), &user_address),
+        &user_debt,
+    )?;
+
+    let debt_market_debt_total_scaled_after = debt_market
+        .debt_total_scaled
+        .checked_add(interest_premium_scaled)?
+        .checked_sub(debt_amount_scaled_delta)?;
+
+    // 6. Update markets depending on whether the collateral and debt markets are the same
+    // and whether the liquidator receives ma_tokens (no change in liquidity) or underlying asset
+    // (changes liquidity)
+    if collateral_and_debt_are_the_same_asset {
+        // NOTE: for the sake of clarity copy attributes from collateral market and
+        // give generic naming. Debt market could have been used as well
+        let mut asset_market_after = collateral_market;
+        let asset_reference = collateral_asset_reference;
+        let asset_label = &collateral_asset_label;
+
+        response = apply_accumulated_interests(
+            &env,
+            protocol_rewards_collector_address.clone(),
+            &mut asset_market_after,
             response,
         )?;
 
         asset_market_after.debt_total_scaled = debt_market_debt_total_scaled_after;
+        response = distribute_interest_premium(
+            &deps.querier,
+            protocol_rewards_collector_address,
+            &mut asset_market_after,
+            interest_premium_scaled,
+            response,
+        )?;
 
         let mut less_liquidity = refund_amount;
 
@@ -1518,7 +2522,7 @@ pub fn execute_liquidate(
         };
 
         response = update_interest_rates(
-            &deps,
+            &mut deps,
             &env,
             &mut asset_market_after,
             less_liquidity,
@@ -1543,7 +2547,7 @@ pub fn execute_liquidate(
             )?;
 
             response = update_interest_rates(
-                &deps,
+                &mut deps,
                 &env,
                 &mut collateral_market_after,
                 collateral_amount_to_liquidate,
@@ -1562,15 +2566,22 @@ pub fn execute_liquidate(
 
         response = apply_accumulated_interests(
             &env,
-            protocol_rewards_collector_address,
+            protocol_rewards_collector_address.clone(),
             &mut debt_market_after,
             response,
         )?;
 
         debt_market_after.debt_total_scaled = debt_market_debt_total_scaled_after;
+        response = distribute_interest_premium(
+            &deps.querier,
+            protocol_rewards_collector_address,
+            &mut debt_market_after,
+            interest_premium_scaled,
+            response,
+        )?;
 
         response = update_interest_rates(
-            &deps,
+            &mut deps,
             &env,
             &mut debt_market_after,
             refund_amount,
@@ -1628,11 +2639,12 @@ fn process_ma_token_transfer_to_liquidator(
         .may_load(deps.storage, liquidator_addr)?
         .unwrap_or_default();
 
-    // Set liquidator's deposited bit to true if not already true
+    // Set liquidator's deposited bit to true if not already true (unless market is frozen,
+    // in which case the asset cannot be enabled as collateral)
     // NOTE: previous checks should ensure amount to be sent is not zero
     let liquidator_is_using_as_collateral =
         get_bit(liquidator.collateral_assets, collateral_market.index)?;
-    if !liquidator_is_using_as_collateral {
+    if !liquidator_is_using_as_collateral && !collateral_market.frozen {
         set_bit(&mut liquidator.collateral_assets, collateral_market.index)?;
         USERS.save(deps.storage, liquidator_addr, &liquidator)?;
         response = response.add_event(build_collateral_position_changed_event(
@@ -1739,13 +2751,13 @@ fn liquidation_compute_amounts(
         sent_debt_asset_amount
     };
 
-    // Collateral: debt to repay in uusd times the liquidation
+    // Collateral: debt to repay in base asset times the liquidation
     // bonus
-    let debt_amount_to_repay_in_uusd = debt_amount_to_repay * debt_price;
-    let collateral_amount_to_liquidate_in_uusd =
-        debt_amount_to_repay_in_uusd * (Decimal::one() + liquidation_bonus);
+    let debt_amount_to_repay_in_base_asset = debt_amount_to_repay * debt_price;
+    let collateral_amount_to_liquidate_in_base_asset =
+        debt_amount_to_repay_in_base_asset * (Decimal::one() + liquidation_bonus);
     let mut collateral_amount_to_liquidate = Decimal::divide_uint128_by_decimal(
-        collateral_amount_to_liquidate_in_uusd,
+        collateral_amount_to_liquidate_in_base_asset,
         collateral_price,
     )?;
 
@@ -1788,8 +2800,14 @@ pub fn execute_update_asset_collateral_status(
 
     let (collateral_asset_label, collateral_asset_reference, _) = asset.get_attributes();
     let collateral_market = MARKETS.load(deps.storage, collateral_asset_reference.as_slice())?;
+    assert_market_not_paused(deps.storage, &collateral_market, &collateral_asset_label)?;
     let has_collateral_asset = get_bit(user.collateral_assets, collateral_market.index)?;
     if !has_collateral_asset && enable {
+        if collateral_market.frozen {
+            return Err(ContractError::MarketFrozen {
+                asset: collateral_asset_label,
+            });
+        }
         let collateral_ma_address = collateral_market.ma_token_address;
         let user_collateral_balance =
             cw20_get_balance(&deps.querier, collateral_ma_address, user_address.clone())?;
@@ -1830,6 +2848,7 @@ pub fn execute_update_asset_collateral_status(
         )?;
         // if health factor is less than one after disabling collateral we can't process further
         if let UserHealthStatus::Borrowing(health_factor) = user_position.health_status {
+            user_position.assert_prices_not_stale(env.block.time.seconds())?;
             if health_factor < Decimal::one() {
                 return Err(ContractError::InvalidHealthFactorAfterDisablingCollateral {});
             }
@@ -1867,36 +2886,42 @@ pub fn execute_finalize_liquidity_token_transfer(
     // Get liquidity token market
     let market_reference = MARKET_REFERENCES_BY_MA_TOKEN.load(deps.storage, &info.sender)?;
     let market = MARKETS.load(deps.storage, market_reference.as_slice())?;
+    let asset_label = String::from_utf8(market_reference.clone()).expect("Found invalid UTF-8");
+    assert_market_not_paused(deps.storage, &market, &asset_label)?;
 
-    // Check user health factor is above 1
-    let global_state = GLOBAL_STATE.load(deps.storage)?;
-    let mut from_user = USERS.load(deps.storage, &from_address)?;
-    let config = CONFIG.load(deps.storage)?;
-    let oracle_address = address_provider::helpers::query_address(
-        &deps.querier,
-        config.address_provider_address,
-        MarsContract::Oracle,
-    )?;
-    let user_position = get_user_position(
-        deps.as_ref(),
-        env.block.time.seconds(),
-        &from_address,
-        oracle_address,
-        &from_user,
-        global_state.market_count,
-    )?;
-    if let UserHealthStatus::Borrowing(health_factor) = user_position.health_status {
-        if health_factor < Decimal::one() {
-            return Err(ContractError::CannotTransferTokenWhenInvalidHealthFactor {});
-        }
-    }
+    // maTokens sent back from the withdraw queue (on cancellation) come from this contract,
+    // which has no position. The user's principal was not changed when they were queued
+    let from_withdraw_queue = from_address == env.contract.address;
 
-    let asset_label = String::from_utf8(market_reference).expect("Found invalid UTF-8");
     let mut events = vec![];
 
-    // Update users's positions
-    if from_address != to_address {
-        if from_previous_balance.checked_sub(amount)?.is_zero() {
+    if !from_withdraw_queue {
+        // Check user health factor is above 1
+        let global_state = GLOBAL_STATE.load(deps.storage)?;
+        let mut from_user = USERS.load(deps.storage, &from_address)?;
+        let config = CONFIG.load(deps.storage)?;
+        let oracle_address = address_provider::helpers::query_address(
+            &deps.querier,
+            config.address_provider_address,
+            MarsContract::Oracle,
+        )?;
+        let user_position = get_user_position(
+            deps.as_ref(),
+            env.block.time.seconds(),
+            &from_address,
+            oracle_address,
+            &from_user,
+            global_state.market_count,
+        )?;
+        // a transfer moves collateral out of the account like a withdrawal does
+        if let UserHealthStatus::Borrowing(health_factor) = user_position.health_status {
+            user_position.assert_prices_not_stale(env.block.time.seconds())?;
+            if health_factor < Decimal::one() {
+                return Err(ContractError::CannotTransferTokenWhenInvalidHealthFactor {});
+            }
+        }
+
+        if from_address != to_address && from_previous_balance.checked_sub(amount)?.is_zero() {
             unset_bit(&mut from_user.collateral_assets, market.index)?;
             USERS.save(deps.storage, &from_address, &from_user)?;
             events.push(build_collateral_position_changed_event(
@@ -1905,8 +2930,38 @@ pub fn execute_finalize_liquidity_token_transfer(
                 from_address.to_string(),
             ))
         }
+    }
+
+    // Update users's positions
+    if from_address != to_address {
+        // Transfers move principal from sender to recipient at the current underlying value.
+        // maTokens locked in the withdraw queue are accounted when paid out
+        if to_address != env.contract.address && !from_withdraw_queue {
+            let underlying_amount =
+                get_underlying_liquidity_amount(amount, &market, env.block.time.seconds())?;
+            record_principal_change(
+                deps.storage,
+                market_reference.as_slice(),
+                &from_address,
+                PrincipalChange::Withdraw,
+                underlying_amount,
+            )?;
+            record_principal_change(
+                deps.storage,
+                market_reference.as_slice(),
+                &to_address,
+                PrincipalChange::Deposit,
+                underlying_amount,
+            )?;
+        }
 
-        if to_previous_balance.is_zero() && !amount.is_zero() {
+        // Receiving tokens from a frozen market does not enable them as collateral. Neither do
+        // maTokens locked in the withdraw queue (sent to this contract)
+        if to_previous_balance.is_zero()
+            && !amount.is_zero()
+            && !market.frozen
+            && to_address != env.contract.address
+        {
             let mut to_user = USERS
                 .may_load(deps.storage, &to_address)?
                 .unwrap_or_default();
@@ -1926,6 +2981,26 @@ pub fn execute_finalize_liquidity_token_transfer(
     Ok(res)
 }
 
+// REPLY
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn reply(_deps: DepsMut, _env: Env, msg: Reply) -> Result<Response, ContractError> {
+    match msg.id {
+        // Queue processing triggered by a deposit or repayment only replies on error, which is
+        // swallowed so the deposit or repayment goes through. Requests stay in the queue
+        PROCESS_WITHDRAW_QUEUE_REPLY_ID => {
+            let error = match msg.result {
+                ContractResult::Err(error) => error,
+                ContractResult::Ok(_) => String::new(),
+            };
+            Ok(Response::new().add_event(
+                Event::new("process_withdraw_queue_failed").add_attribute("error", error),
+            ))
+        }
+        id => Err(StdError::generic_err(format!("Unknown reply id: {}", id)).into()),
+    }
+}
+
 // QUERIES
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -1935,8 +3010,18 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
 
         QueryMsg::Market { asset } => to_binary(&query_market(deps, asset)?),
 
+        QueryMsg::MarketRiskParams { asset } => {
+            to_binary(&query_market_risk_params(deps, env, asset)?)
+        }
+
         QueryMsg::MarketsList {} => to_binary(&query_markets_list(deps)?),
 
+        QueryMsg::MarketHistory {
+            asset,
+            start_after,
+            limit,
+        } => to_binary(&query_market_history(deps, asset, start_after, limit)?),
+
         QueryMsg::UserDebt { user_address } => {
             let address = deps.api.addr_validate(&user_address)?;
             to_binary(&query_user_debt(deps, env, address)?)
@@ -1967,6 +3052,41 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
             )?)
         }
 
+        QueryMsg::CreditLine {
+            user_address,
+            asset,
+        } => {
+            let user_address = deps.api.addr_validate(&user_address)?;
+            to_binary(&query_credit_line(deps, env, user_address, asset)?)
+        }
+
+        QueryMsg::WithdrawQueue {
+            asset,
+            start_after,
+            limit,
+        } => to_binary(&query_withdraw_queue(deps, env, asset, start_after, limit)?),
+
+        QueryMsg::UserWithdrawRequests {
+            user_address,
+            asset,
+            start_after,
+            limit,
+        } => {
+            let user_address = deps.api.addr_validate(&user_address)?;
+            to_binary(&query_user_withdraw_requests(
+                deps,
+                env,
+                user_address,
+                asset,
+                start_after,
+                limit,
+            )?)
+        }
+
+        QueryMsg::OutflowCapacity { asset } => {
+            to_binary(&query_outflow_capacity(deps, env, asset)?)
+        }
+
         QueryMsg::ScaledLiquidityAmount { asset, amount } => {
             to_binary(&query_scaled_liquidity_amount(deps, env, asset, amount)?)
         }
@@ -1995,23 +3115,185 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
             amount_scaled,
         )?),
 
-        QueryMsg::UserPosition { user_address } => {
-            let address = deps.api.addr_validate(&user_address)?;
-            to_binary(&query_user_position(deps, env, address)?)
-        }
+        QueryMsg::UserPnl { user_address } => {
+            let address = deps.api.addr_validate(&user_address)?;
+            to_binary(&query_user_pnl(deps, env, address)?)
+        }
+
+        QueryMsg::UserPosition { user_address } => {
+            let address = deps.api.addr_validate(&user_address)?;
+            to_binary(&query_user_position(deps, env, address)?)
+        }
+    }
+}
+
+pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let money_market = GLOBAL_STATE.load(deps.storage)?;
+
+    Ok(ConfigResponse {
+        owner: config.owner,
+        address_provider_address: config.address_provider_address,
+        ma_token_code_id: config.ma_token_code_id,
+        market_count: money_market.market_count,
+        close_factor: config.close_factor,
+        paused: money_market.paused,
+        market_snapshot_interval: config.market_snapshot_interval,
+        market_snapshot_max_count: config.market_snapshot_max_count,
+    })
+}
+
+pub fn query_withdraw_queue(
+    deps: Deps,
+    env: Env,
+    asset: Asset,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<WithdrawQueueResponse> {
+    let limit = limit
+        .unwrap_or(WITHDRAW_QUEUE_DEFAULT_LIMIT)
+        .min(WITHDRAW_QUEUE_MAX_LIMIT) as usize;
+    let start = start_after.map(|id| Bound::exclusive(U64Key::new(id)));
+
+    let (asset_label, asset_reference, asset_type) = asset.get_attributes();
+    let queue = WithdrawQueueState::load(
+        deps,
+        &env,
+        asset_reference.as_slice(),
+        &asset_label,
+        asset_type,
+    )?;
+
+    let requests = WITHDRAW_REQUESTS
+        .prefix(asset_reference.as_slice())
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (key, request) = item?;
+            queue.build_request_response(read_be_u64(&key)?, request)
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(WithdrawQueueResponse { requests })
+}
+
+pub fn query_user_withdraw_requests(
+    deps: Deps,
+    env: Env,
+    user_address: Addr,
+    asset: Asset,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<WithdrawQueueResponse> {
+    let limit = limit
+        .unwrap_or(WITHDRAW_QUEUE_DEFAULT_LIMIT)
+        .min(WITHDRAW_QUEUE_MAX_LIMIT) as usize;
+    let start = start_after.map(|id| Bound::exclusive(U64Key::new(id)));
+
+    let (asset_label, asset_reference, asset_type) = asset.get_attributes();
+    let queue = WithdrawQueueState::load(
+        deps,
+        &env,
+        asset_reference.as_slice(),
+        &asset_label,
+        asset_type,
+    )?;
+
+    let requests = USER_WITHDRAW_REQUESTS
+        .prefix((asset_reference.as_slice(), &user_address))
+        .keys(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|key| {
+            let request_id = read_be_u64(&key)?;
+            let request = WITHDRAW_REQUESTS.load(
+                deps.storage,
+                (asset_reference.as_slice(), U64Key::new(request_id)),
+            )?;
+            queue.build_request_response(request_id, request)
+        })
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(WithdrawQueueResponse { requests })
+}
+
+/// Market state needed to compute queue positions and expected fills at the current block
+struct WithdrawQueueState {
+    market: Market,
+    block_time: u64,
+    available_liquidity: Uint128,
+    /// Offset of the first request in the queue (everything before it was paid out or cancelled)
+    head_offset_scaled: Uint128,
+}
+
+impl WithdrawQueueState {
+    fn load(
+        deps: Deps,
+        env: &Env,
+        asset_reference: &[u8],
+        asset_label: &str,
+        asset_type: AssetType,
+    ) -> StdResult<Self> {
+        let market = MARKETS.load(deps.storage, asset_reference)?;
+        let available_liquidity =
+            get_available_withdraw_liquidity(deps, env, asset_reference, asset_label, asset_type)?;
+        let head_offset_scaled = WITHDRAW_REQUESTS
+            .prefix(asset_reference)
+            .range(deps.storage, None, None, Order::Ascending)
+            .next()
+            .transpose()?
+            .map(|(_, request)| request.queue_offset_scaled)
+            .unwrap_or_default();
+
+        Ok(WithdrawQueueState {
+            market,
+            block_time: env.block.time.seconds(),
+            available_liquidity,
+            head_offset_scaled,
+        })
+    }
+
+    fn build_request_response(
+        &self,
+        id: u64,
+        request: WithdrawRequest,
+    ) -> StdResult<WithdrawRequestResponse> {
+        let amount =
+            get_underlying_liquidity_amount(request.amount_scaled, &self.market, self.block_time)?;
+        let amount_ahead = get_underlying_liquidity_amount(
+            request
+                .queue_offset_scaled
+                .checked_sub(self.head_offset_scaled)?,
+            &self.market,
+            self.block_time,
+        )?;
+        let expected_fill = amount.min(self.available_liquidity.saturating_sub(amount_ahead));
+
+        Ok(WithdrawRequestResponse {
+            id,
+            user_address: request.user_address,
+            recipient_address: request.recipient_address,
+            amount_scaled: request.amount_scaled,
+            amount,
+            amount_ahead,
+            expected_fill,
+            created_at: request.created_at,
+        })
     }
 }
 
-pub fn query_config(deps: Deps) -> StdResult<ConfigResponse> {
-    let config = CONFIG.load(deps.storage)?;
-    let money_market = GLOBAL_STATE.load(deps.storage)?;
-
-    Ok(ConfigResponse {
-        owner: config.owner,
-        address_provider_address: config.address_provider_address,
-        ma_token_code_id: config.ma_token_code_id,
-        market_count: money_market.market_count,
-        close_factor: config.close_factor,
+pub fn query_outflow_capacity(
+    deps: Deps,
+    env: Env,
+    asset: Asset,
+) -> StdResult<OutflowCapacityResponse> {
+    let outflow_limit = OUTFLOW_LIMITS.may_load(deps.storage, &asset.get_reference())?;
+    let remaining_capacity = outflow_limit
+        .as_ref()
+        .map(|limit| limit.remaining_capacity(env.block.time.seconds()));
+
+    Ok(OutflowCapacityResponse {
+        limit: outflow_limit,
+        remaining_capacity,
     })
 }
 
@@ -2030,6 +3312,65 @@ pub fn query_market(deps: Deps, asset: Asset) -> StdResult<Market> {
     Ok(market)
 }
 
+pub fn query_market_risk_params(
+    deps: Deps,
+    env: Env,
+    asset: Asset,
+) -> StdResult<MarketRiskParamsResponse> {
+    let market = query_market(deps, asset)?;
+    let block_time = env.block.time.seconds();
+
+    let max_loan_to_value = market.max_loan_to_value_at(block_time)?;
+    let liquidation_threshold = market.liquidation_threshold_at(block_time)?;
+    let interest_rate_model_params = market.interest_rate_model_params_at(block_time)?;
+    let response = match market.risk_params_ramp {
+        Some(ramp) if ramp.end_time > block_time => MarketRiskParamsResponse {
+            max_loan_to_value,
+            liquidation_threshold,
+            interest_rate_model_params: interest_rate_model_params.clone(),
+            target_max_loan_to_value: ramp.target_max_loan_to_value,
+            target_liquidation_threshold: ramp.target_liquidation_threshold,
+            target_interest_rate_model_params: ramp
+                .target_interest_rate_model_params
+                .unwrap_or(interest_rate_model_params),
+            ramp_end_time: Some(ramp.end_time),
+        },
+        _ => MarketRiskParamsResponse {
+            max_loan_to_value,
+            liquidation_threshold,
+            interest_rate_model_params: interest_rate_model_params.clone(),
+            target_max_loan_to_value: max_loan_to_value,
+            target_liquidation_threshold: liquidation_threshold,
+            target_interest_rate_model_params: interest_rate_model_params,
+            ramp_end_time: None,
+        },
+    };
+
+    Ok(response)
+}
+
+pub fn query_market_history(
+    deps: Deps,
+    asset: Asset,
+    start_after: Option<u64>,
+    limit: Option<u32>,
+) -> StdResult<MarketHistoryResponse> {
+    let asset_reference = asset.get_reference();
+    let limit = limit
+        .unwrap_or(MARKET_HISTORY_DEFAULT_LIMIT)
+        .min(MARKET_HISTORY_MAX_LIMIT) as usize;
+    let start = start_after.map(|timestamp| Bound::exclusive(U64Key::new(timestamp)));
+
+    let snapshots = MARKET_SNAPSHOTS
+        .prefix(asset_reference.as_slice())
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| item.map(|(_, snapshot)| snapshot))
+        .collect::<StdResult<Vec<_>>>()?;
+
+    Ok(MarketHistoryResponse { snapshots })
+}
+
 pub fn query_markets_list(deps: Deps) -> StdResult<MarketsListResponse> {
     let markets_list: StdResult<Vec<_>> = MARKETS
         .range(deps.storage, None, None, Order::Ascending)
@@ -2090,6 +3431,72 @@ pub fn query_user_debt(deps: Deps, env: Env, user_address: Addr) -> StdResult<Us
     Ok(UserDebtResponse { debts: debts? })
 }
 
+pub fn query_user_pnl(deps: Deps, env: Env, user_address: Addr) -> StdResult<UserPnlResponse> {
+    let block_time = env.block.time.seconds();
+
+    let mut markets = vec![];
+    for item in MARKETS.range(deps.storage, None, None, Order::Ascending) {
+        let (asset_reference, market) = item?;
+        let principal = match USER_PRINCIPALS
+            .may_load(deps.storage, (asset_reference.as_slice(), &user_address))?
+        {
+            Some(principal) => principal,
+            None => continue,
+        };
+        let (denom, asset_label) =
+            get_asset_identifiers(deps, asset_reference.clone(), market.asset_type)?;
+
+        let collateral_scaled = cw20_get_balance(
+            &deps.querier,
+            market.ma_token_address.clone(),
+            user_address.clone(),
+        )?;
+        let collateral = get_underlying_liquidity_amount(collateral_scaled, &market, block_time)?;
+        // maTokens locked in the withdraw queue are still part of the user's principal until
+        // paid out
+        let queued_scaled = USER_WITHDRAW_REQUESTS
+            .prefix((asset_reference.as_slice(), &user_address))
+            .keys(deps.storage, None, None, Order::Ascending)
+            .try_fold(Uint128::zero(), |total, key| -> StdResult<_> {
+                let request = WITHDRAW_REQUESTS.load(
+                    deps.storage,
+                    (asset_reference.as_slice(), U64Key::new(read_be_u64(&key)?)),
+                )?;
+                Ok(total.checked_add(request.amount_scaled)?)
+            })?;
+        let queued = get_underlying_liquidity_amount(queued_scaled, &market, block_time)?;
+        let debt =
+            match DEBTS.may_load(deps.storage, (asset_reference.as_slice(), &user_address))? {
+                Some(debt) => get_underlying_debt_amount(debt.amount_scaled, &market, block_time)?,
+                None => Uint128::zero(),
+            };
+
+        // Rounding on scaled amounts can make these off by one unit so they saturate at zero
+        let interest_earned = collateral
+            .checked_add(queued)?
+            .checked_add(principal.withdrawn)?
+            .saturating_sub(principal.deposited);
+        let interest_paid = debt
+            .checked_add(principal.repaid)?
+            .saturating_sub(principal.borrowed);
+
+        markets.push(UserAssetPnlResponse {
+            denom,
+            asset_label,
+            asset_reference,
+            asset_type: market.asset_type,
+            principal,
+            collateral,
+            queued,
+            debt,
+            interest_earned,
+            interest_paid,
+        });
+    }
+
+    Ok(UserPnlResponse { markets })
+}
+
 pub fn query_user_asset_debt(
     deps: Deps,
     env: Env,
@@ -2167,6 +3574,43 @@ pub fn query_uncollateralized_loan_limit(
     }
 }
 
+pub fn query_credit_line(
+    deps: Deps,
+    env: Env,
+    user_address: Addr,
+    asset: Asset,
+) -> StdResult<CreditLineResponse> {
+    let block_time = env.block.time.seconds();
+    let asset_reference = asset.get_reference();
+    let limit = query_uncollateralized_loan_limit(deps, user_address.clone(), asset)?;
+
+    let terms = CREDIT_LINE_TERMS
+        .may_load(deps.storage, (asset_reference.as_slice(), &user_address))?
+        .unwrap_or_else(|| CreditLineTerms::perpetual(block_time));
+
+    let debt_amount = match DEBTS
+        .may_load(deps.storage, (asset_reference.as_slice(), &user_address))?
+    {
+        Some(debt) => {
+            let market = MARKETS.load(deps.storage, asset_reference.as_slice())?;
+            let debt_amount = get_underlying_debt_amount(debt.amount_scaled, &market, block_time)?;
+            debt_amount.checked_add(compute_pending_interest_premium(
+                &terms,
+                debt_amount,
+                block_time,
+            )?)?
+        }
+        None => Uint128::zero(),
+    };
+
+    Ok(CreditLineResponse {
+        limit,
+        status: terms.status(block_time),
+        terms,
+        debt_amount,
+    })
+}
+
 pub fn query_scaled_liquidity_amount(
     deps: Deps,
     env: Env,
@@ -2235,16 +3679,57 @@ pub fn query_user_position(
     )?;
 
     Ok(UserPositionResponse {
-        total_collateral_in_uusd: user_position.total_collateral_in_uusd,
-        total_debt_in_uusd: user_position.total_debt_in_uusd,
-        total_collateralized_debt_in_uusd: user_position.total_collateralized_debt_in_uusd,
-        max_debt_in_uusd: user_position.max_debt_in_uusd,
-        weighted_liquidation_threshold_in_uusd: user_position
-            .weighted_liquidation_threshold_in_uusd,
+        total_collateral_in_base_asset: user_position.total_collateral_in_base_asset,
+        total_debt_in_base_asset: user_position.total_debt_in_base_asset,
+        total_collateralized_debt_in_base_asset: user_position
+            .total_collateralized_debt_in_base_asset,
+        max_debt_in_base_asset: user_position.max_debt_in_base_asset,
+        weighted_liquidation_threshold_in_base_asset: user_position
+            .weighted_liquidation_threshold_in_base_asset,
         health_status: user_position.health_status,
     })
 }
 
+// MIGRATION
+
+/// State migrations, run when migrating from a version older than theirs
+const MIGRATION_STEPS: &[MigrationStep] = &[MigrationStep {
+    version: Version::new(1, 1, 0),
+    migrate: migrate_v1_1_0,
+}];
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let attributes = migrate_contract(
+        deps.storage,
+        CONTRACT_NAME,
+        CONTRACT_VERSION,
+        MIGRATION_STEPS,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attributes(attributes))
+}
+
+/// Stores the fields added in 1.1.0 to the config (snapshots disabled), the global state and the
+/// markets (not paused nor frozen, no risk parameters ramp)
+fn migrate_v1_1_0(storage: &mut dyn Storage) -> StdResult<()> {
+    let config = CONFIG.load(storage)?;
+    CONFIG.save(storage, &config)?;
+    let global_state = GLOBAL_STATE.load(storage)?;
+    GLOBAL_STATE.save(storage, &global_state)?;
+
+    let markets = MARKETS
+        .range(storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<_>>>()?;
+    for (market_reference, market) in markets {
+        MARKETS.save(storage, &market_reference, &market)?;
+    }
+
+    Ok(())
+}
+
 // EVENTS
 
 fn build_collateral_position_changed_event(label: &str, enabled: bool, user_addr: String) -> Event {
@@ -2302,6 +3787,185 @@ fn get_asset_denom(deps: Deps, asset_label: &str, asset_type: AssetType) -> StdR
     }
 }
 
+enum PrincipalChange {
+    Deposit,
+    Withdraw,
+    Borrow,
+    Repay,
+}
+
+/// Adds the underlying amount to the user's cumulative principal for the market
+fn record_principal_change(
+    storage: &mut dyn Storage,
+    asset_reference: &[u8],
+    user_address: &Addr,
+    change: PrincipalChange,
+    amount: Uint128,
+) -> StdResult<()> {
+    if amount.is_zero() {
+        return Ok(());
+    }
+
+    USER_PRINCIPALS.update(
+        storage,
+        (asset_reference, user_address),
+        |principal| -> StdResult<_> {
+            let mut principal = principal.unwrap_or_default();
+            let total = match change {
+                PrincipalChange::Deposit => &mut principal.deposited,
+                PrincipalChange::Withdraw => &mut principal.withdrawn,
+                PrincipalChange::Borrow => &mut principal.borrowed,
+                PrincipalChange::Repay => &mut principal.repaid,
+            };
+            *total = total.checked_add(amount)?;
+            Ok(principal)
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Liquidity that can be used to pay out withdraw requests: the contract balance of the
+/// asset, capped by the market's remaining outflow capacity
+fn get_available_withdraw_liquidity(
+    deps: Deps,
+    env: &Env,
+    asset_reference: &[u8],
+    asset_label: &str,
+    asset_type: AssetType,
+) -> StdResult<Uint128> {
+    let contract_balance = get_asset_balance(
+        deps,
+        env.contract.address.clone(),
+        asset_label.to_string(),
+        asset_type,
+    )?;
+    match OUTFLOW_LIMITS.may_load(deps.storage, asset_reference)? {
+        Some(outflow_limit) => {
+            Ok(contract_balance.min(outflow_limit.remaining_capacity(env.block.time.seconds())))
+        }
+        None => Ok(contract_balance),
+    }
+}
+
+/// Builds a message for this contract to process the market's withdraw queue if there are
+/// pending requests, so liquidity coming from deposits and repayments pays them out. Processing
+/// errors are handled in `reply` and don't revert the deposit or repayment
+fn build_process_withdraw_queue_msg(
+    storage: &dyn Storage,
+    env: &Env,
+    asset_reference: &[u8],
+    asset_label: &str,
+    asset_type: AssetType,
+) -> StdResult<Option<SubMsg>> {
+    let has_pending_requests = WITHDRAW_REQUESTS
+        .prefix(asset_reference)
+        .range(storage, None, None, Order::Ascending)
+        .next()
+        .is_some();
+    if !has_pending_requests {
+        return Ok(None);
+    }
+
+    let asset = match asset_type {
+        AssetType::Native => Asset::Native {
+            denom: asset_label.to_string(),
+        },
+        AssetType::Cw20 => Asset::Cw20 {
+            contract_addr: asset_label.to_string(),
+        },
+    };
+    Ok(Some(SubMsg::reply_on_error(
+        CosmosMsg::Wasm(WasmMsg::Execute {
+            contract_addr: env.contract.address.to_string(),
+            msg: to_binary(&ExecuteMsg::ProcessWithdrawQueue {
+                asset,
+                limit: Some(WITHDRAW_QUEUE_AUTO_PROCESS_LIMIT),
+            })?,
+            funds: vec![],
+        }),
+        PROCESS_WITHDRAW_QUEUE_REPLY_ID,
+    )))
+}
+
+/// Adds amount to the market's net outflow. Errors if the outflow limit would be exceeded
+fn record_outflow(
+    storage: &mut dyn Storage,
+    asset_reference: &[u8],
+    asset_label: &str,
+    amount: Uint128,
+    block_time: u64,
+) -> Result<(), ContractError> {
+    if let Some(mut outflow_limit) = OUTFLOW_LIMITS.may_load(storage, asset_reference)? {
+        let remaining_capacity = outflow_limit.remaining_capacity(block_time);
+        if amount > remaining_capacity {
+            return Err(ContractError::OutflowLimitExceeded {
+                asset: asset_label.to_string(),
+                amount,
+                remaining_capacity,
+            });
+        }
+        outflow_limit.current_outflow = outflow_limit
+            .current_outflow_at(block_time)
+            .checked_add(amount)?;
+        outflow_limit.last_updated = block_time;
+        OUTFLOW_LIMITS.save(storage, asset_reference, &outflow_limit)?;
+    }
+    Ok(())
+}
+
+/// Adds amount to the market's net outflow without enforcing the limit (i.e: capacity left to
+/// other outflows is reduced but the call never fails because of it)
+fn record_exempt_outflow(
+    storage: &mut dyn Storage,
+    asset_reference: &[u8],
+    amount: Uint128,
+    block_time: u64,
+) -> StdResult<()> {
+    if let Some(mut outflow_limit) = OUTFLOW_LIMITS.may_load(storage, asset_reference)? {
+        outflow_limit.current_outflow = outflow_limit
+            .current_outflow_at(block_time)
+            .checked_add(amount)?;
+        outflow_limit.last_updated = block_time;
+        OUTFLOW_LIMITS.save(storage, asset_reference, &outflow_limit)?;
+    }
+    Ok(())
+}
+
+/// Deducts amount from the market's net outflow (deposits and repayments free up capacity)
+fn record_inflow(
+    storage: &mut dyn Storage,
+    asset_reference: &[u8],
+    amount: Uint128,
+    block_time: u64,
+) -> StdResult<()> {
+    if let Some(mut outflow_limit) = OUTFLOW_LIMITS.may_load(storage, asset_reference)? {
+        outflow_limit.current_outflow = outflow_limit
+            .current_outflow_at(block_time)
+            .saturating_sub(amount);
+        outflow_limit.last_updated = block_time;
+        OUTFLOW_LIMITS.save(storage, asset_reference, &outflow_limit)?;
+    }
+    Ok(())
+}
+
+/// Returns an error if either the contract or the given market is paused
+fn assert_market_not_paused(
+    storage: &dyn Storage,
+    market: &Market,
+    asset_label: &str,
+) -> Result<(), ContractError> {
+    if GLOBAL_STATE.load(storage)?.paused {
+        return Err(MarsError::Paused {}.into());
+    }
+    if market.paused {
+        return Err(ContractError::MarketPaused {
+            asset: asset_label.to_string(),
+        });
+    }
+    Ok(())
+}
+
 pub fn market_get_from_index(deps: &Deps, index: u32) -> StdResult<(Vec<u8>, Market)> {
     let asset_reference_vec =
         match MARKET_REFERENCES_BY_INDEX.load(deps.storage, U32Key::new(index)) {
@@ -2375,7 +4039,7 @@ mod tests {
         get_updated_borrow_index, get_updated_liquidity_index, ScalingOperation, SCALING_FACTOR,
     };
     use crate::msg::CreateOrUpdateConfig;
-    use crate::MarketError;
+    use crate::{MarketError, RiskParamsRamp, UserPrincipal, MAX_LOAN_TO_VALUE_MAX_DELTA_PERCENT};
 
     #[test]
     fn test_proper_initialization() {
@@ -2388,6 +4052,8 @@ mod tests {
             address_provider_address: Some("address_provider".to_string()),
             ma_token_code_id: Some(10u64),
             close_factor: None,
+            market_snapshot_interval: None,
+            market_snapshot_max_count: None,
         };
 
         // *
@@ -2398,6 +4064,8 @@ mod tests {
             address_provider_address: None,
             ma_token_code_id: None,
             close_factor: None,
+            market_snapshot_interval: None,
+            market_snapshot_max_count: None,
         };
         let msg = InstantiateMsg {
             config: empty_config,
@@ -2463,6 +4131,8 @@ mod tests {
             address_provider_address: Some("address_provider".to_string()),
             ma_token_code_id: Some(20u64),
             close_factor: Some(close_factor),
+            market_snapshot_interval: None,
+            market_snapshot_max_count: None,
         };
         let msg = InstantiateMsg {
             config: init_config.clone(),
@@ -2512,6 +4182,8 @@ mod tests {
             address_provider_address: Some("new_address_provider".to_string()),
             ma_token_code_id: Some(40u64),
             close_factor: Some(close_factor),
+            market_snapshot_interval: None,
+            market_snapshot_max_count: None,
         };
         let msg = ExecuteMsg::UpdateConfig {
             config: config.clone(),
@@ -2547,6 +4219,8 @@ mod tests {
             address_provider_address: Some("address_provider".to_string()),
             ma_token_code_id: Some(5u64),
             close_factor: Some(Decimal::from_ratio(1u128, 2u128)),
+            market_snapshot_interval: None,
+            market_snapshot_max_count: None,
         };
         let msg = InstantiateMsg { config };
         let info = mock_info("owner");
@@ -2574,6 +4248,8 @@ mod tests {
             active: Some(true),
             deposit_enabled: Some(true),
             borrow_enabled: Some(true),
+            frozen: Some(false),
+            risk_params_ramp_end_time: None,
         };
         let cw20_addr = Addr::unchecked("otherasset");
 
@@ -2611,6 +4287,32 @@ mod tests {
             assert_eq!(error_res, MarsError::InstantiateParamsUnavailable {}.into());
         }
 
+        // init asset with a risk params ramp
+        {
+            let invalid_asset_params = InitOrUpdateAssetParams {
+                risk_params_ramp_end_time: Some(env.block.time.seconds() + 86_400),
+                ..asset_params.clone()
+            };
+            let msg = ExecuteMsg::InitAsset {
+                asset: Asset::Native {
+                    denom: "someasset".to_string(),
+                },
+                asset_params: invalid_asset_params,
+                asset_symbol: None,
+            };
+            let info = mock_info("owner");
+            let error_res = execute(deps.as_mut(), env.clone(), info, msg).unwrap_err();
+            assert_eq!(
+                error_res,
+                MarsError::InvalidParam {
+                    param_name: "risk_params_ramp_end_time".to_string(),
+                    invalid_value: (env.block.time.seconds() + 86_400).to_string(),
+                    predicate: "None on init".to_string(),
+                }
+                .into()
+            );
+        }
+
         // init asset with max_loan_to_value greater than 1
         {
             let invalid_asset_params = InitOrUpdateAssetParams {
@@ -3004,6 +4706,8 @@ mod tests {
             active: Some(true),
             deposit_enabled: Some(true),
             borrow_enabled: Some(true),
+            frozen: Some(false),
+            risk_params_ramp_end_time: None,
         };
         let msg = ExecuteMsg::InitAsset {
             asset: Asset::Native {
@@ -3068,6 +4772,8 @@ mod tests {
             address_provider_address: Some("address_provider".to_string()),
             ma_token_code_id: Some(5u64),
             close_factor: Some(Decimal::from_ratio(1u128, 2u128)),
+            market_snapshot_interval: None,
+            market_snapshot_max_count: None,
         };
         let msg = InstantiateMsg { config };
         let info = mock_info("owner");
@@ -3097,6 +4803,8 @@ mod tests {
             active: Some(true),
             deposit_enabled: Some(true),
             borrow_enabled: Some(true),
+            frozen: Some(false),
+            risk_params_ramp_end_time: None,
         };
 
         // non owner is not authorized
@@ -3325,6 +5033,8 @@ mod tests {
                 active: Some(true),
                 deposit_enabled: Some(true),
                 borrow_enabled: Some(true),
+                frozen: Some(false),
+                risk_params_ramp_end_time: None,
             };
             let msg = ExecuteMsg::UpdateAsset {
                 asset: Asset::Native {
@@ -3337,9 +5047,10 @@ mod tests {
 
             let new_market = MARKETS.load(&deps.storage, b"someasset").unwrap();
             assert_eq!(0, new_market.index);
+            // risk params are ramped to the new values
             assert_eq!(
                 asset_params.max_loan_to_value.unwrap(),
-                new_market.max_loan_to_value
+                new_market.max_loan_to_value_at(u64::MAX).unwrap()
             );
             assert_eq!(
                 asset_params.reserve_factor.unwrap(),
@@ -3347,7 +5058,11 @@ mod tests {
             );
             assert_eq!(
                 asset_params.liquidation_threshold.unwrap(),
-                new_market.liquidation_threshold
+                new_market.liquidation_threshold_at(u64::MAX).unwrap()
+            );
+            assert_eq!(
+                new_market.risk_params_ramp.unwrap().end_time,
+                env.block.time.seconds() + MIN_RISK_PARAMS_RAMP_DURATION
             );
             assert_eq!(
                 asset_params.liquidation_bonus.unwrap(),
@@ -3394,6 +5109,8 @@ mod tests {
                 active: None,
                 deposit_enabled: None,
                 borrow_enabled: None,
+                frozen: None,
+                risk_params_ramp_end_time: None,
             };
             let msg = ExecuteMsg::UpdateAsset {
                 asset: Asset::Native {
@@ -3452,15 +5169,294 @@ mod tests {
                     market_dynamic_ir_params.update_threshold_seconds
                 );
 
-                assert_eq!(1, market_dynamic_ir_state.txs_since_last_borrow_rate_update);
-                assert_eq!(
-                    env.block.time.seconds(),
-                    market_dynamic_ir_state.borrow_rate_last_updated
-                );
-            } else {
-                panic!("INCORRECT STRATEGY")
-            }
-        }
+                assert_eq!(1, market_dynamic_ir_state.txs_since_last_borrow_rate_update);
+                assert_eq!(
+                    env.block.time.seconds(),
+                    market_dynamic_ir_state.borrow_rate_last_updated
+                );
+            } else {
+                panic!("INCORRECT STRATEGY")
+            }
+        }
+    }
+
+    #[test]
+    fn test_update_asset_risk_params_ramp() {
+        let mut deps = th_setup(&[]);
+        let start_time = 10_000_000;
+
+        let mock_market = Market {
+            max_loan_to_value: Decimal::percent(50),
+            liquidation_threshold: Decimal::percent(60),
+            ..Default::default()
+        };
+        th_init_market(deps.as_mut(), b"someasset", &mock_market);
+        let asset = Asset::Native {
+            denom: "someasset".to_string(),
+        };
+        let empty_asset_params = InitOrUpdateAssetParams {
+            initial_borrow_rate: None,
+            max_loan_to_value: None,
+            reserve_factor: None,
+            liquidation_threshold: None,
+            liquidation_bonus: None,
+            interest_rate_model_params: None,
+            active: None,
+            deposit_enabled: None,
+            borrow_enabled: None,
+            frozen: None,
+            risk_params_ramp_end_time: None,
+        };
+
+        let ramp_duration = MIN_RISK_PARAMS_RAMP_DURATION;
+
+        // changes larger than the max delta are rejected
+        let msg = ExecuteMsg::UpdateAsset {
+            asset: asset.clone(),
+            asset_params: InitOrUpdateAssetParams {
+                max_loan_to_value: Some(Decimal::percent(30)),
+                risk_params_ramp_end_time: Some(start_time + ramp_duration),
+                ..empty_asset_params.clone()
+            },
+        };
+        let error_res = execute(
+            deps.as_mut(),
+            mock_env_at_block_time(start_time),
+            mock_info("owner"),
+            msg,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error_res,
+            ContractError::Market(MarketError::RiskParamDeltaTooLarge {
+                param_name: "max_loan_to_value".to_string(),
+                current: Decimal::percent(50),
+                target: Decimal::percent(30),
+                max_delta: Decimal::percent(MAX_LOAN_TO_VALUE_MAX_DELTA_PERCENT),
+            })
+        );
+
+        // ramps shorter than the min duration (or ending in the past) are rejected
+        for end_time in [start_time - 1, start_time + ramp_duration - 1] {
+            let msg = ExecuteMsg::UpdateAsset {
+                asset: asset.clone(),
+                asset_params: InitOrUpdateAssetParams {
+                    max_loan_to_value: Some(Decimal::percent(45)),
+                    risk_params_ramp_end_time: Some(end_time),
+                    ..empty_asset_params.clone()
+                },
+            };
+            let error_res = execute(
+                deps.as_mut(),
+                mock_env_at_block_time(start_time),
+                mock_info("owner"),
+                msg,
+            )
+            .unwrap_err();
+            assert_eq!(
+                error_res,
+                ContractError::Market(MarketError::RiskParamsRampTooShort {
+                    end_time,
+                    min_end_time: start_time + ramp_duration,
+                })
+            );
+        }
+
+        // ramp down max loan to value and up liquidation threshold
+        let msg = ExecuteMsg::UpdateAsset {
+            asset: asset.clone(),
+            asset_params: InitOrUpdateAssetParams {
+                max_loan_to_value: Some(Decimal::percent(40)),
+                liquidation_threshold: Some(Decimal::percent(70)),
+                risk_params_ramp_end_time: Some(start_time + ramp_duration),
+                ..empty_asset_params.clone()
+            },
+        };
+        execute(
+            deps.as_mut(),
+            mock_env_at_block_time(start_time),
+            mock_info("owner"),
+            msg,
+        )
+        .unwrap();
+
+        let interest_rate_model_params = mock_market.interest_rate_model.params();
+        let expected_risk_params = MarketRiskParamsResponse {
+            max_loan_to_value: Decimal::from_ratio(475u128, 1000u128),
+            liquidation_threshold: Decimal::from_ratio(625u128, 1000u128),
+            interest_rate_model_params: interest_rate_model_params.clone(),
+            target_max_loan_to_value: Decimal::percent(40),
+            target_liquidation_threshold: Decimal::percent(70),
+            target_interest_rate_model_params: interest_rate_model_params,
+            ramp_end_time: Some(start_time + ramp_duration),
+        };
+        let res = query_market_risk_params(
+            deps.as_ref(),
+            mock_env_at_block_time(start_time + ramp_duration / 4),
+            asset.clone(),
+        )
+        .unwrap();
+        assert_eq!(res, expected_risk_params);
+
+        // updates that don't set risk params leave the ongoing ramp untouched
+        let market_before = MARKETS.load(&deps.storage, b"someasset").unwrap();
+        let msg = ExecuteMsg::UpdateAsset {
+            asset: asset.clone(),
+            asset_params: InitOrUpdateAssetParams {
+                borrow_enabled: Some(false),
+                ..empty_asset_params.clone()
+            },
+        };
+        execute(
+            deps.as_mut(),
+            mock_env_at_block_time(start_time + ramp_duration / 4),
+            mock_info("owner"),
+            msg,
+        )
+        .unwrap();
+        let market = MARKETS.load(&deps.storage, b"someasset").unwrap();
+        assert_eq!(market.risk_params_ramp, market_before.risk_params_ramp);
+        let res = query_market_risk_params(
+            deps.as_ref(),
+            mock_env_at_block_time(start_time + ramp_duration / 4),
+            asset.clone(),
+        )
+        .unwrap();
+        assert_eq!(res, expected_risk_params);
+
+        // replacing an ongoing ramp checks max deltas against the values it started from
+        let msg = ExecuteMsg::UpdateAsset {
+            asset: asset.clone(),
+            asset_params: InitOrUpdateAssetParams {
+                max_loan_to_value: Some(Decimal::percent(38)),
+                ..empty_asset_params.clone()
+            },
+        };
+        let error_res = execute(
+            deps.as_mut(),
+            mock_env_at_block_time(start_time + ramp_duration / 2),
+            mock_info("owner"),
+            msg,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error_res,
+            ContractError::Market(MarketError::RiskParamDeltaTooLarge {
+                param_name: "max_loan_to_value".to_string(),
+                current: Decimal::percent(50),
+                target: Decimal::percent(38),
+                max_delta: Decimal::percent(MAX_LOAN_TO_VALUE_MAX_DELTA_PERCENT),
+            })
+        );
+
+        // a new ramp starts from the effective values and lasts the min duration by default.
+        // The param that is not set keeps the previous target
+        let msg = ExecuteMsg::UpdateAsset {
+            asset: asset.clone(),
+            asset_params: InitOrUpdateAssetParams {
+                max_loan_to_value: Some(Decimal::percent(42)),
+                ..empty_asset_params.clone()
+            },
+        };
+        let update_time = start_time + ramp_duration / 2;
+        execute(
+            deps.as_mut(),
+            mock_env_at_block_time(update_time),
+            mock_info("owner"),
+            msg,
+        )
+        .unwrap();
+
+        let market = MARKETS.load(&deps.storage, b"someasset").unwrap();
+        assert_eq!(market.max_loan_to_value, Decimal::percent(45));
+        assert_eq!(market.liquidation_threshold, Decimal::percent(65));
+        assert_eq!(
+            market.risk_params_ramp,
+            Some(RiskParamsRamp {
+                target_max_loan_to_value: Decimal::percent(42),
+                target_liquidation_threshold: Decimal::percent(70),
+                target_interest_rate_model_params: None,
+                start_time: update_time,
+                end_time: update_time + ramp_duration,
+            })
+        );
+
+        // once the ramp is over, deltas are checked against the values it reached
+        let msg = ExecuteMsg::UpdateAsset {
+            asset: asset.clone(),
+            asset_params: InitOrUpdateAssetParams {
+                max_loan_to_value: Some(Decimal::percent(32)),
+                ..empty_asset_params.clone()
+            },
+        };
+        execute(
+            deps.as_mut(),
+            mock_env_at_block_time(update_time + ramp_duration),
+            mock_info("owner"),
+            msg,
+        )
+        .unwrap();
+
+        let res = query_market_risk_params(
+            deps.as_ref(),
+            mock_env_at_block_time(update_time + 3 * ramp_duration),
+            asset.clone(),
+        )
+        .unwrap();
+        assert_eq!(res.max_loan_to_value, Decimal::percent(32));
+        assert_eq!(res.liquidation_threshold, Decimal::percent(70));
+        assert_eq!(res.ramp_end_time, None);
+
+        // interest rate model params are interpolated, update thresholds apply at the end
+        let initial_ir_params = match mock_market.interest_rate_model.params() {
+            InterestRateModelParams::Dynamic(params) => params,
+            _ => panic!("Wrong interest rate model type"),
+        };
+        let target_ir_params = DynamicInterestRateModelParams {
+            min_borrow_rate: Decimal::percent(10),
+            optimal_utilization_rate: Decimal::percent(80),
+            update_threshold_txs: 5,
+            ..initial_ir_params.clone()
+        };
+        let update_time = update_time + 3 * ramp_duration;
+        let msg = ExecuteMsg::UpdateAsset {
+            asset: asset.clone(),
+            asset_params: InitOrUpdateAssetParams {
+                interest_rate_model_params: Some(InterestRateModelParams::Dynamic(
+                    target_ir_params.clone(),
+                )),
+                ..empty_asset_params
+            },
+        };
+        execute(
+            deps.as_mut(),
+            mock_env_at_block_time(update_time),
+            mock_info("owner"),
+            msg,
+        )
+        .unwrap();
+
+        let res = query_market_risk_params(
+            deps.as_ref(),
+            mock_env_at_block_time(update_time + ramp_duration / 2),
+            asset,
+        )
+        .unwrap();
+        assert_eq!(
+            res.interest_rate_model_params,
+            InterestRateModelParams::Dynamic(DynamicInterestRateModelParams {
+                min_borrow_rate: Decimal::percent(5),
+                optimal_utilization_rate: Decimal::percent(40),
+                ..initial_ir_params
+            })
+        );
+        assert_eq!(
+            res.target_interest_rate_model_params,
+            InterestRateModelParams::Dynamic(target_ir_params)
+        );
+        // risk params keep their previous targets
+        assert_eq!(res.max_loan_to_value, Decimal::percent(32));
+        assert_eq!(res.liquidation_threshold, Decimal::percent(70));
     }
 
     #[test]
@@ -3472,6 +5468,8 @@ mod tests {
             address_provider_address: Some("address_provider".to_string()),
             ma_token_code_id: Some(5u64),
             close_factor: Some(Decimal::from_ratio(1u128, 2u128)),
+            market_snapshot_interval: None,
+            market_snapshot_max_count: None,
         };
         let msg = InstantiateMsg { config };
         let info = mock_info("owner");
@@ -3501,6 +5499,8 @@ mod tests {
             active: Some(true),
             deposit_enabled: Some(true),
             borrow_enabled: Some(true),
+            frozen: Some(false),
+            risk_params_ramp_end_time: None,
         };
 
         let msg = ExecuteMsg::InitAsset {
@@ -3547,9 +5547,62 @@ mod tests {
         };
         let info = mock_info("owner");
         let env = mock_env_at_block_time(2_000_000);
-        let res = execute(deps.as_mut(), env, info, msg).unwrap();
+        execute(deps.as_mut(), env, info, msg).unwrap();
 
-        // Verify if IR model is updated
+        // New IR model is ramped to, the change of model applies at the end of the ramp
+        let ramp_end_time = 2_000_000 + MIN_RISK_PARAMS_RAMP_DURATION;
+        let market_during_ramp = MARKETS.load(&deps.storage, b"someasset").unwrap();
+        assert_eq!(
+            market_during_ramp.interest_rate_model.params(),
+            market_before.interest_rate_model.params()
+        );
+        let risk_params = query_market_risk_params(
+            deps.as_ref(),
+            mock_env_at_block_time(ramp_end_time - 1),
+            Asset::Native {
+                denom: "someasset".to_string(),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            risk_params.interest_rate_model_params,
+            market_before.interest_rate_model.params()
+        );
+        assert_eq!(
+            risk_params.target_interest_rate_model_params,
+            InterestRateModelParams::Linear(linear_ir_params.clone())
+        );
+        assert_eq!(risk_params.ramp_end_time, Some(ramp_end_time));
+
+        // Indices should have been updated using previous interest rate
+        let expected_indices = th_get_expected_indices(&market_before, 2_000_000);
+        assert_eq!(
+            market_during_ramp.liquidity_index,
+            expected_indices.liquidity
+        );
+        assert_eq!(market_during_ramp.borrow_index, expected_indices.borrow);
+        assert_eq!(market_during_ramp.indexes_last_updated, 2_000_000);
+
+        // The first interest update after the ramp ends switches to the new model
+        let msg = ExecuteMsg::UpdateAsset {
+            asset: Asset::Native {
+                denom: "someasset".to_string(),
+            },
+            asset_params: InitOrUpdateAssetParams {
+                reserve_factor: Some(Decimal::from_ratio(3u128, 100u128)),
+                max_loan_to_value: None,
+                liquidation_threshold: None,
+                interest_rate_model_params: None,
+                ..asset_params_with_linear_ir.clone()
+            },
+        };
+        let res = execute(
+            deps.as_mut(),
+            mock_env_at_block_time(ramp_end_time),
+            mock_info("owner"),
+            msg,
+        )
+        .unwrap();
         let new_market = MARKETS.load(&deps.storage, b"someasset").unwrap();
         assert_eq!(
             new_market.interest_rate_model,
@@ -3557,12 +5610,7 @@ mod tests {
                 params: linear_ir_params.clone()
             }
         );
-
-        // Indices should have been updated using previous interest rate
-        let expected_indices = th_get_expected_indices(&market_before, 2_000_000);
-        assert_eq!(new_market.liquidity_index, expected_indices.liquidity);
-        assert_eq!(new_market.borrow_index, expected_indices.borrow);
-        assert_eq!(new_market.indexes_last_updated, 2_000_000);
+        assert_eq!(new_market.risk_params_ramp, None);
 
         // Interest rate should have been recomputed using new strategy and values
         let expected_borrow_rate =
@@ -3636,6 +5684,8 @@ mod tests {
             active: None,
             deposit_enabled: None,
             borrow_enabled: None,
+            frozen: None,
+            risk_params_ramp_end_time: None,
         };
         let msg = ExecuteMsg::UpdateAsset {
             asset: Asset::Native {
@@ -4699,7 +6749,7 @@ mod tests {
 
         // Calculate how much to withdraw to have health factor equal to one
         let how_much_to_withdraw = {
-            let token_1_weighted_lt_in_uusd = compute_underlying_amount(
+            let token_1_weighted_lt_in_base_asset = compute_underlying_amount(
                 ma_token_1_balance_scaled,
                 get_updated_liquidity_index(&market_1_initial, env.block.time.seconds()).unwrap(),
                 ScalingOperation::Truncate,
@@ -4707,7 +6757,7 @@ mod tests {
             .unwrap()
                 * market_1_initial.liquidation_threshold
                 * token_1_exchange_rate;
-            let token_3_weighted_lt_in_uusd = compute_underlying_amount(
+            let token_3_weighted_lt_in_base_asset = compute_underlying_amount(
                 ma_token_3_balance_scaled,
                 get_updated_liquidity_index(&market_3_initial, env.block.time.seconds()).unwrap(),
                 ScalingOperation::Truncate,
@@ -4715,10 +6765,10 @@ mod tests {
             .unwrap()
                 * market_3_initial.liquidation_threshold
                 * token_3_exchange_rate;
-            let weighted_liquidation_threshold_in_uusd =
-                token_1_weighted_lt_in_uusd + token_3_weighted_lt_in_uusd;
+            let weighted_liquidation_threshold_in_base_asset =
+                token_1_weighted_lt_in_base_asset + token_3_weighted_lt_in_base_asset;
 
-            let total_collateralized_debt_in_uusd = compute_underlying_amount(
+            let total_collateralized_debt_in_base_asset = compute_underlying_amount(
                 token_2_debt_scaled,
                 get_updated_borrow_index(&market_2_initial, env.block.time.seconds()).unwrap(),
                 ScalingOperation::Ceil,
@@ -4727,13 +6777,17 @@ mod tests {
                 * token_2_exchange_rate;
 
             // How much to withdraw in uusd to have health factor equal to one
-            let how_much_to_withdraw_in_uusd = Decimal::divide_uint128_by_decimal(
-                weighted_liquidation_threshold_in_uusd - total_collateralized_debt_in_uusd,
+            let how_much_to_withdraw_in_base_asset = Decimal::divide_uint128_by_decimal(
+                weighted_liquidation_threshold_in_base_asset
+                    - total_collateralized_debt_in_base_asset,
                 market_3_initial.liquidation_threshold,
             )
             .unwrap();
-            Decimal::divide_uint128_by_decimal(how_much_to_withdraw_in_uusd, token_3_exchange_rate)
-                .unwrap()
+            Decimal::divide_uint128_by_decimal(
+                how_much_to_withdraw_in_base_asset,
+                token_3_exchange_rate,
+            )
+            .unwrap()
         };
 
         // Withdraw token3 with failure
@@ -5112,28 +7166,193 @@ mod tests {
         );
 
         // *
-        // Borrow cw20 token
+        // Borrow cw20 token
+        // *
+        let block_time = mock_market_1.indexes_last_updated + 10000u64;
+        let borrow_amount = Uint128::from(2400u128);
+
+        let msg = ExecuteMsg::Borrow {
+            asset: Asset::Cw20 {
+                contract_addr: cw20_contract_addr.to_string(),
+            },
+            amount: borrow_amount,
+            recipient: None,
+        };
+
+        let env = mock_env_at_block_time(block_time);
+        let info = mock_info("borrower");
+
+        let res = execute(deps.as_mut(), env, info, msg).unwrap();
+
+        let expected_params_cw20 = th_get_expected_indices_and_rates(
+            &market_1_initial,
+            block_time,
+            available_liquidity_cw20,
+            TestUtilizationDeltaInfo {
+                less_liquidity: borrow_amount,
+                more_debt: borrow_amount,
+                ..Default::default()
+            },
+        );
+
+        // check correct messages and logging
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: cw20_contract_addr.to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: borrower_addr.to_string(),
+                    amount: borrow_amount.into(),
+                })
+                .unwrap(),
+                funds: vec![]
+            }))]
+        );
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "borrow"),
+                attr("asset", "borrowedcoincw20"),
+                attr("user", "borrower"),
+                attr("recipient", "borrower"),
+                attr("amount", borrow_amount.to_string()),
+            ]
+        );
+        assert_eq!(
+            res.events,
+            vec![
+                build_debt_position_changed_event("borrowedcoincw20", true, "borrower".to_string()),
+                th_build_interests_updated_event("borrowedcoincw20", &expected_params_cw20)
+            ]
+        );
+
+        let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
+        assert!(get_bit(user.borrowed_assets, 0).unwrap());
+        assert!(!get_bit(user.borrowed_assets, 1).unwrap());
+
+        let debt = DEBTS
+            .load(
+                &deps.storage,
+                (cw20_contract_addr.as_bytes(), &borrower_addr),
+            )
+            .unwrap();
+        let expected_debt_scaled_1_after_borrow = compute_scaled_amount(
+            Uint128::from(borrow_amount),
+            expected_params_cw20.borrow_index,
+            ScalingOperation::Ceil,
+        )
+        .unwrap();
+
+        let market_1_after_borrow = MARKETS
+            .load(&deps.storage, cw20_contract_addr.as_bytes())
+            .unwrap();
+
+        assert_eq!(expected_debt_scaled_1_after_borrow, debt.amount_scaled);
+        assert_eq!(
+            expected_debt_scaled_1_after_borrow,
+            market_1_after_borrow.debt_total_scaled
+        );
+        assert_eq!(
+            expected_params_cw20.borrow_rate,
+            market_1_after_borrow.borrow_rate
+        );
+        assert_eq!(
+            expected_params_cw20.liquidity_rate,
+            market_1_after_borrow.liquidity_rate
+        );
+
+        // *
+        // Borrow cw20 token (again)
+        // *
+        let borrow_amount = Uint128::from(1200u128);
+        let block_time = market_1_after_borrow.indexes_last_updated + 20000u64;
+
+        let msg = ExecuteMsg::Borrow {
+            asset: Asset::Cw20 {
+                contract_addr: cw20_contract_addr.to_string(),
+            },
+            amount: borrow_amount,
+            recipient: None,
+        };
+
+        let env = mock_env_at_block_time(block_time);
+        let info = mock_info("borrower");
+
+        execute(deps.as_mut(), env, info, msg).unwrap();
+
+        let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
+        assert!(get_bit(user.borrowed_assets, 0).unwrap());
+        assert!(!get_bit(user.borrowed_assets, 1).unwrap());
+
+        let expected_params_cw20 = th_get_expected_indices_and_rates(
+            &market_1_after_borrow,
+            block_time,
+            available_liquidity_cw20,
+            TestUtilizationDeltaInfo {
+                less_liquidity: borrow_amount,
+                more_debt: borrow_amount,
+                ..Default::default()
+            },
+        );
+        let debt = DEBTS
+            .load(
+                &deps.storage,
+                (cw20_contract_addr.as_bytes(), &borrower_addr),
+            )
+            .unwrap();
+        let market_1_after_borrow_again = MARKETS
+            .load(&deps.storage, cw20_contract_addr.as_bytes())
+            .unwrap();
+
+        let expected_debt_scaled_1_after_borrow_again = expected_debt_scaled_1_after_borrow
+            + compute_scaled_amount(
+                Uint128::from(borrow_amount),
+                expected_params_cw20.borrow_index,
+                ScalingOperation::Ceil,
+            )
+            .unwrap();
+        assert_eq!(
+            expected_debt_scaled_1_after_borrow_again,
+            debt.amount_scaled
+        );
+        assert_eq!(
+            expected_debt_scaled_1_after_borrow_again,
+            market_1_after_borrow_again.debt_total_scaled
+        );
+        assert_eq!(
+            expected_params_cw20.borrow_rate,
+            market_1_after_borrow_again.borrow_rate
+        );
+        assert_eq!(
+            expected_params_cw20.liquidity_rate,
+            market_1_after_borrow_again.liquidity_rate
+        );
+
+        // *
+        // Borrow native coin
         // *
-        let block_time = mock_market_1.indexes_last_updated + 10000u64;
-        let borrow_amount = Uint128::from(2400u128);
 
+        let borrow_amount = Uint128::from(4000u128);
+        let block_time = market_1_after_borrow_again.indexes_last_updated + 3000u64;
+        let env = mock_env_at_block_time(block_time);
+        let info = mock_info("borrower");
         let msg = ExecuteMsg::Borrow {
-            asset: Asset::Cw20 {
-                contract_addr: cw20_contract_addr.to_string(),
+            asset: Asset::Native {
+                denom: String::from("borrowedcoinnative"),
             },
             amount: borrow_amount,
             recipient: None,
         };
-
-        let env = mock_env_at_block_time(block_time);
-        let info = mock_info("borrower");
-
         let res = execute(deps.as_mut(), env, info, msg).unwrap();
 
-        let expected_params_cw20 = th_get_expected_indices_and_rates(
-            &market_1_initial,
+        let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
+        assert!(get_bit(user.borrowed_assets, 0).unwrap());
+        assert!(get_bit(user.borrowed_assets, 1).unwrap());
+
+        let expected_params_native = th_get_expected_indices_and_rates(
+            &market_2_initial,
             block_time,
-            available_liquidity_cw20,
+            available_liquidity_native,
             TestUtilizationDeltaInfo {
                 less_liquidity: borrow_amount,
                 more_debt: borrow_amount,
@@ -5144,21 +7363,23 @@ mod tests {
         // check correct messages and logging
         assert_eq!(
             res.messages,
-            vec![SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: cw20_contract_addr.to_string(),
-                msg: to_binary(&Cw20ExecuteMsg::Transfer {
-                    recipient: borrower_addr.to_string(),
-                    amount: borrow_amount.into(),
-                })
-                .unwrap(),
-                funds: vec![]
+            vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                to_address: "borrower".to_string(),
+                amount: vec![deduct_tax(
+                    deps.as_ref(),
+                    Coin {
+                        denom: String::from("borrowedcoinnative"),
+                        amount: borrow_amount.into(),
+                    }
+                )
+                .unwrap()],
             }))]
         );
         assert_eq!(
             res.attributes,
             vec![
                 attr("action", "borrow"),
-                attr("asset", "borrowedcoincw20"),
+                attr("asset", "borrowedcoinnative"),
                 attr("user", "borrower"),
                 attr("recipient", "borrower"),
                 attr("amount", borrow_amount.to_string()),
@@ -5167,1119 +7388,1855 @@ mod tests {
         assert_eq!(
             res.events,
             vec![
-                build_debt_position_changed_event("borrowedcoincw20", true, "borrower".to_string()),
-                th_build_interests_updated_event("borrowedcoincw20", &expected_params_cw20)
+                build_debt_position_changed_event(
+                    "borrowedcoinnative",
+                    true,
+                    "borrower".to_string()
+                ),
+                th_build_interests_updated_event("borrowedcoinnative", &expected_params_native)
             ]
         );
 
-        let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
-        assert!(get_bit(user.borrowed_assets, 0).unwrap());
-        assert!(!get_bit(user.borrowed_assets, 1).unwrap());
-
-        let debt = DEBTS
-            .load(
-                &deps.storage,
-                (cw20_contract_addr.as_bytes(), &borrower_addr),
-            )
+        let debt2 = DEBTS
+            .load(&deps.storage, (b"borrowedcoinnative", &borrower_addr))
             .unwrap();
-        let expected_debt_scaled_1_after_borrow = compute_scaled_amount(
+        let market_2_after_borrow_2 = MARKETS.load(&deps.storage, b"borrowedcoinnative").unwrap();
+
+        let expected_debt_scaled_2_after_borrow_2 = compute_scaled_amount(
             Uint128::from(borrow_amount),
-            expected_params_cw20.borrow_index,
+            expected_params_native.borrow_index,
             ScalingOperation::Ceil,
         )
         .unwrap();
-
-        let market_1_after_borrow = MARKETS
-            .load(&deps.storage, cw20_contract_addr.as_bytes())
-            .unwrap();
-
-        assert_eq!(expected_debt_scaled_1_after_borrow, debt.amount_scaled);
+        assert_eq!(expected_debt_scaled_2_after_borrow_2, debt2.amount_scaled);
         assert_eq!(
-            expected_debt_scaled_1_after_borrow,
-            market_1_after_borrow.debt_total_scaled
+            expected_debt_scaled_2_after_borrow_2,
+            market_2_after_borrow_2.debt_total_scaled
         );
         assert_eq!(
-            expected_params_cw20.borrow_rate,
-            market_1_after_borrow.borrow_rate
+            expected_params_native.borrow_rate,
+            market_2_after_borrow_2.borrow_rate
         );
         assert_eq!(
-            expected_params_cw20.liquidity_rate,
-            market_1_after_borrow.liquidity_rate
+            expected_params_native.liquidity_rate,
+            market_2_after_borrow_2.liquidity_rate
         );
 
         // *
-        // Borrow cw20 token (again)
+        // Borrow native coin again (should fail due to insufficient collateral)
         // *
-        let borrow_amount = Uint128::from(1200u128);
-        let block_time = market_1_after_borrow.indexes_last_updated + 20000u64;
 
+        let env = mock_env(MockEnvParams::default());
+        let info = mock_info("borrower");
         let msg = ExecuteMsg::Borrow {
-            asset: Asset::Cw20 {
-                contract_addr: cw20_contract_addr.to_string(),
+            asset: Asset::Native {
+                denom: String::from("borrowedcoinnative"),
             },
-            amount: borrow_amount,
+            amount: Uint128::from(83968_u128),
             recipient: None,
         };
+        let error_res = execute(deps.as_mut(), env, info, msg).unwrap_err();
+        assert_eq!(
+            error_res,
+            ContractError::BorrowAmountExceedsGivenCollateral {}
+        );
 
+        // *
+        // Repay zero native debt (should fail)
+        // *
         let env = mock_env_at_block_time(block_time);
         let info = mock_info("borrower");
+        let msg = ExecuteMsg::RepayNative {
+            denom: String::from("borrowedcoinnative"),
+            on_behalf_of: None,
+        };
+        let error_res = execute(deps.as_mut(), env, info, msg).unwrap_err();
+        assert_eq!(
+            error_res,
+            ContractError::InvalidNativeCoinsSent {
+                denom: "borrowedcoinnative".to_string()
+            }
+        );
 
-        execute(deps.as_mut(), env, info, msg).unwrap();
+        // *
+        // Repay some native debt
+        // *
+        let repay_amount = Uint128::from(2000u128);
+        let block_time = market_2_after_borrow_2.indexes_last_updated + 8000u64;
+        let env = mock_env_at_block_time(block_time);
+        let info = cosmwasm_std::testing::mock_info(
+            "borrower",
+            &[coin(repay_amount.into(), "borrowedcoinnative")],
+        );
+        let msg = ExecuteMsg::RepayNative {
+            denom: String::from("borrowedcoinnative"),
+            on_behalf_of: None,
+        };
+        let res = execute(deps.as_mut(), env, info, msg).unwrap();
+
+        let expected_params_native = th_get_expected_indices_and_rates(
+            &market_2_after_borrow_2,
+            block_time,
+            available_liquidity_native,
+            TestUtilizationDeltaInfo {
+                less_debt: repay_amount,
+                user_current_debt_scaled: expected_debt_scaled_2_after_borrow_2,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(res.messages, vec![]);
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "repay"),
+                attr("asset", "borrowedcoinnative"),
+                attr("sender", "borrower"),
+                attr("user", "borrower"),
+                attr("amount", repay_amount.to_string()),
+            ]
+        );
+        assert_eq!(
+            res.events,
+            vec![th_build_interests_updated_event(
+                "borrowedcoinnative",
+                &expected_params_native
+            )]
+        );
+
+        let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
+        assert!(get_bit(user.borrowed_assets, 0).unwrap());
+        assert!(get_bit(user.borrowed_assets, 1).unwrap());
+
+        let debt2 = DEBTS
+            .load(&deps.storage, (b"borrowedcoinnative", &borrower_addr))
+            .unwrap();
+        let market_2_after_repay_some_2 =
+            MARKETS.load(&deps.storage, b"borrowedcoinnative").unwrap();
+
+        let expected_debt_scaled_2_after_repay_some_2 = expected_debt_scaled_2_after_borrow_2
+            - compute_scaled_amount(
+                Uint128::from(repay_amount),
+                expected_params_native.borrow_index,
+                ScalingOperation::Ceil,
+            )
+            .unwrap();
+        assert_eq!(
+            expected_debt_scaled_2_after_repay_some_2,
+            debt2.amount_scaled
+        );
+        assert_eq!(
+            expected_debt_scaled_2_after_repay_some_2,
+            market_2_after_repay_some_2.debt_total_scaled
+        );
+        assert_eq!(
+            expected_params_native.borrow_rate,
+            market_2_after_repay_some_2.borrow_rate
+        );
+        assert_eq!(
+            expected_params_native.liquidity_rate,
+            market_2_after_repay_some_2.liquidity_rate
+        );
+
+        // *
+        // Repay all native debt
+        // *
+        let block_time = market_2_after_repay_some_2.indexes_last_updated + 10000u64;
+        // need this to compute the repay amount
+        let expected_params_native = th_get_expected_indices_and_rates(
+            &market_2_after_repay_some_2,
+            block_time,
+            available_liquidity_native,
+            TestUtilizationDeltaInfo {
+                less_debt: Uint128::from(9999999999999_u128), // hack: Just do a big number to repay all debt,
+                user_current_debt_scaled: expected_debt_scaled_2_after_repay_some_2,
+                ..Default::default()
+            },
+        );
+
+        let repay_amount: u128 = compute_underlying_amount(
+            expected_debt_scaled_2_after_repay_some_2,
+            expected_params_native.borrow_index,
+            ScalingOperation::Ceil,
+        )
+        .unwrap()
+        .into();
+
+        let env = mock_env_at_block_time(block_time);
+        let info = cosmwasm_std::testing::mock_info(
+            "borrower",
+            &[coin(repay_amount, "borrowedcoinnative")],
+        );
+        let msg = ExecuteMsg::RepayNative {
+            denom: String::from("borrowedcoinnative"),
+            on_behalf_of: None,
+        };
+        let res = execute(deps.as_mut(), env, info, msg).unwrap();
+
+        assert_eq!(res.messages, vec![]);
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "repay"),
+                attr("asset", "borrowedcoinnative"),
+                attr("sender", "borrower"),
+                attr("user", "borrower"),
+                attr("amount", repay_amount.to_string()),
+            ]
+        );
+        assert_eq!(
+            res.events,
+            vec![
+                th_build_interests_updated_event("borrowedcoinnative", &expected_params_native),
+                build_debt_position_changed_event(
+                    "borrowedcoinnative",
+                    false,
+                    "borrower".to_string()
+                ),
+            ]
+        );
 
         let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
         assert!(get_bit(user.borrowed_assets, 0).unwrap());
         assert!(!get_bit(user.borrowed_assets, 1).unwrap());
 
-        let expected_params_cw20 = th_get_expected_indices_and_rates(
-            &market_1_after_borrow,
-            block_time,
-            available_liquidity_cw20,
-            TestUtilizationDeltaInfo {
-                less_liquidity: borrow_amount,
-                more_debt: borrow_amount,
-                ..Default::default()
-            },
-        );
-        let debt = DEBTS
-            .load(
-                &deps.storage,
-                (cw20_contract_addr.as_bytes(), &borrower_addr),
-            )
-            .unwrap();
-        let market_1_after_borrow_again = MARKETS
-            .load(&deps.storage, cw20_contract_addr.as_bytes())
+        let debt2 = DEBTS
+            .load(&deps.storage, (b"borrowedcoinnative", &borrower_addr))
             .unwrap();
+        let market_2_after_repay_all_2 =
+            MARKETS.load(&deps.storage, b"borrowedcoinnative").unwrap();
 
-        let expected_debt_scaled_1_after_borrow_again = expected_debt_scaled_1_after_borrow
-            + compute_scaled_amount(
-                Uint128::from(borrow_amount),
-                expected_params_cw20.borrow_index,
-                ScalingOperation::Ceil,
-            )
-            .unwrap();
-        assert_eq!(
-            expected_debt_scaled_1_after_borrow_again,
-            debt.amount_scaled
-        );
-        assert_eq!(
-            expected_debt_scaled_1_after_borrow_again,
-            market_1_after_borrow_again.debt_total_scaled
-        );
-        assert_eq!(
-            expected_params_cw20.borrow_rate,
-            market_1_after_borrow_again.borrow_rate
-        );
+        assert_eq!(Uint128::zero(), debt2.amount_scaled);
         assert_eq!(
-            expected_params_cw20.liquidity_rate,
-            market_1_after_borrow_again.liquidity_rate
+            Uint128::zero(),
+            market_2_after_repay_all_2.debt_total_scaled
         );
 
         // *
-        // Borrow native coin
+        // Repay more native debt (should fail)
         // *
-
-        let borrow_amount = Uint128::from(4000u128);
-        let block_time = market_1_after_borrow_again.indexes_last_updated + 3000u64;
-        let env = mock_env_at_block_time(block_time);
-        let info = mock_info("borrower");
-        let msg = ExecuteMsg::Borrow {
-            asset: Asset::Native {
-                denom: String::from("borrowedcoinnative"),
-            },
-            amount: borrow_amount,
-            recipient: None,
+        let env = mock_env(MockEnvParams::default());
+        let info =
+            cosmwasm_std::testing::mock_info("borrower", &[coin(2000, "borrowedcoinnative")]);
+        let msg = ExecuteMsg::RepayNative {
+            denom: String::from("borrowedcoinnative"),
+            on_behalf_of: None,
         };
-        let res = execute(deps.as_mut(), env, info, msg).unwrap();
+        let error_res = execute(deps.as_mut(), env, info, msg).unwrap_err();
+        assert_eq!(error_res, ContractError::CannotRepayZeroDebt {});
 
-        let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
-        assert!(get_bit(user.borrowed_assets, 0).unwrap());
-        assert!(get_bit(user.borrowed_assets, 1).unwrap());
+        // *
+        // Repay all cw20 debt (and then some)
+        // *
+        let block_time = market_2_after_repay_all_2.indexes_last_updated + 5000u64;
+        let repay_amount = Uint128::from(4800u128);
 
-        let expected_params_native = th_get_expected_indices_and_rates(
-            &market_2_initial,
+        let expected_params_cw20 = th_get_expected_indices_and_rates(
+            &market_1_after_borrow_again,
             block_time,
-            available_liquidity_native,
+            available_liquidity_cw20,
             TestUtilizationDeltaInfo {
-                less_liquidity: borrow_amount,
-                more_debt: borrow_amount,
+                less_debt: repay_amount,
+                user_current_debt_scaled: expected_debt_scaled_1_after_borrow_again,
                 ..Default::default()
             },
         );
 
-        // check correct messages and logging
+        let env = mock_env_at_block_time(block_time);
+        let info = mock_info("borrowedcoincw20");
+
+        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            msg: to_binary(&ReceiveMsg::RepayCw20 { on_behalf_of: None }).unwrap(),
+            sender: borrower_addr.to_string(),
+            amount: repay_amount,
+        });
+
+        let res = execute(deps.as_mut(), env, info, msg).unwrap();
+
+        let expected_refund_amount = repay_amount
+            - compute_underlying_amount(
+                expected_debt_scaled_1_after_borrow_again,
+                expected_params_cw20.borrow_index,
+                ScalingOperation::Ceil,
+            )
+            .unwrap();
+
         assert_eq!(
             res.messages,
-            vec![SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
-                to_address: "borrower".to_string(),
-                amount: vec![deduct_tax(
-                    deps.as_ref(),
-                    Coin {
-                        denom: String::from("borrowedcoinnative"),
-                        amount: borrow_amount.into(),
-                    }
-                )
-                .unwrap()],
+            vec![SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: cw20_contract_addr.to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: borrower_addr.to_string(),
+                    amount: expected_refund_amount,
+                })
+                .unwrap(),
+                funds: vec![]
             }))]
         );
         assert_eq!(
             res.attributes,
             vec![
-                attr("action", "borrow"),
-                attr("asset", "borrowedcoinnative"),
+                attr("action", "repay"),
+                attr("asset", "borrowedcoincw20"),
+                attr("sender", "borrower"),
                 attr("user", "borrower"),
-                attr("recipient", "borrower"),
-                attr("amount", borrow_amount.to_string()),
+                attr(
+                    "amount",
+                    (repay_amount - expected_refund_amount).to_string()
+                ),
             ]
         );
         assert_eq!(
             res.events,
             vec![
+                th_build_interests_updated_event("borrowedcoincw20", &expected_params_cw20),
                 build_debt_position_changed_event(
-                    "borrowedcoinnative",
-                    true,
+                    "borrowedcoincw20",
+                    false,
                     "borrower".to_string()
                 ),
-                th_build_interests_updated_event("borrowedcoinnative", &expected_params_native)
             ]
         );
+        let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
+        assert!(!get_bit(user.borrowed_assets, 0).unwrap());
+        assert!(!get_bit(user.borrowed_assets, 1).unwrap());
 
-        let debt2 = DEBTS
-            .load(&deps.storage, (b"borrowedcoinnative", &borrower_addr))
+        let debt1 = DEBTS
+            .load(
+                &deps.storage,
+                (cw20_contract_addr.as_bytes(), &borrower_addr),
+            )
             .unwrap();
-        let market_2_after_borrow_2 = MARKETS.load(&deps.storage, b"borrowedcoinnative").unwrap();
+        let market_1_after_repay_1 = MARKETS
+            .load(&deps.storage, cw20_contract_addr.as_bytes())
+            .unwrap();
+        assert_eq!(Uint128::zero(), debt1.amount_scaled);
+        assert_eq!(Uint128::zero(), market_1_after_repay_1.debt_total_scaled);
+    }
 
-        let expected_debt_scaled_2_after_borrow_2 = compute_scaled_amount(
-            Uint128::from(borrow_amount),
-            expected_params_native.borrow_index,
-            ScalingOperation::Ceil,
-        )
-        .unwrap();
-        assert_eq!(expected_debt_scaled_2_after_borrow_2, debt2.amount_scaled);
+    #[test]
+    fn test_cannot_repay_if_market_inactive() {
+        let mut deps = th_setup(&[]);
+
+        let mock_market = Market {
+            ma_token_address: Addr::unchecked("ma_somecoin"),
+            asset_type: AssetType::Native,
+            active: false,
+            deposit_enabled: true,
+            borrow_enabled: true,
+            ..Default::default()
+        };
+        let _market = th_init_market(deps.as_mut(), b"somecoin", &mock_market);
+
+        let env = mock_env(MockEnvParams::default());
+        let info = cosmwasm_std::testing::mock_info("borrower", &[coin(110000, "somecoin")]);
+        let msg = ExecuteMsg::RepayNative {
+            denom: "somecoin".to_string(),
+            on_behalf_of: None,
+        };
+        let error_res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).unwrap_err();
         assert_eq!(
-            expected_debt_scaled_2_after_borrow_2,
-            market_2_after_borrow_2.debt_total_scaled
+            error_res,
+            ContractError::MarketNotActive {
+                asset: "somecoin".to_string()
+            }
         );
-        assert_eq!(
-            expected_params_native.borrow_rate,
-            market_2_after_borrow_2.borrow_rate
+    }
+
+    #[test]
+    fn test_repay_on_behalf_of() {
+        let available_liquidity_native = Uint128::from(1000000000u128);
+        let mut deps = th_setup(&[coin(
+            available_liquidity_native.into(),
+            "borrowedcoinnative",
+        )]);
+
+        deps.querier
+            .set_oracle_price(b"depositedcoinnative".to_vec(), Decimal::one());
+        deps.querier
+            .set_oracle_price(b"borrowedcoinnative".to_vec(), Decimal::one());
+
+        deps.querier.set_native_tax(
+            Decimal::from_ratio(1u128, 100u128),
+            &[(String::from("borrowedcoinnative"), Uint128::new(100u128))],
         );
-        assert_eq!(
-            expected_params_native.liquidity_rate,
-            market_2_after_borrow_2.liquidity_rate
+
+        let mock_market_1 = Market {
+            ma_token_address: Addr::unchecked("matoken1"),
+            liquidity_index: Decimal::one(),
+            borrow_index: Decimal::one(),
+            max_loan_to_value: Decimal::from_ratio(50u128, 100u128),
+            asset_type: AssetType::Native,
+            ..Default::default()
+        };
+        let mock_market_2 = Market {
+            ma_token_address: Addr::unchecked("matoken2"),
+            liquidity_index: Decimal::one(),
+            borrow_index: Decimal::one(),
+            max_loan_to_value: Decimal::from_ratio(50u128, 100u128),
+            asset_type: AssetType::Native,
+            ..Default::default()
+        };
+
+        let market_1_initial =
+            th_init_market(deps.as_mut(), b"depositedcoinnative", &mock_market_1); // collateral
+        let market_2_initial = th_init_market(deps.as_mut(), b"borrowedcoinnative", &mock_market_2);
+
+        let borrower_addr = Addr::unchecked("borrower");
+        let user_addr = Addr::unchecked("user");
+
+        // Set user as having the market_1_initial (collateral) deposited
+        let mut user = User::default();
+
+        set_bit(&mut user.collateral_assets, market_1_initial.index).unwrap();
+        USERS
+            .save(deps.as_mut().storage, &borrower_addr, &user)
+            .unwrap();
+
+        // Set the querier to return a certain collateral balance
+        let deposit_coin_address = Addr::unchecked("matoken1");
+        deps.querier.set_cw20_balances(
+            deposit_coin_address,
+            &[(borrower_addr.clone(), Uint128::new(10000) * SCALING_FACTOR)],
         );
 
         // *
-        // Borrow native coin again (should fail due to insufficient collateral)
+        // 'borrower' borrows native coin
         // *
-
+        let borrow_amount = 4000u128;
         let env = mock_env(MockEnvParams::default());
-        let info = mock_info("borrower");
+        let info = mock_info(borrower_addr.as_str());
         let msg = ExecuteMsg::Borrow {
             asset: Asset::Native {
                 denom: String::from("borrowedcoinnative"),
             },
-            amount: Uint128::from(83968_u128),
+            amount: Uint128::from(borrow_amount),
             recipient: None,
         };
-        let error_res = execute(deps.as_mut(), env, info, msg).unwrap_err();
-        assert_eq!(
-            error_res,
-            ContractError::BorrowAmountExceedsGivenCollateral {}
-        );
+        let _res = execute(deps.as_mut(), env, info, msg).unwrap();
 
-        // *
-        // Repay zero native debt (should fail)
-        // *
-        let env = mock_env_at_block_time(block_time);
-        let info = mock_info("borrower");
-        let msg = ExecuteMsg::RepayNative {
-            denom: String::from("borrowedcoinnative"),
-            on_behalf_of: None,
-        };
-        let error_res = execute(deps.as_mut(), env, info, msg).unwrap_err();
-        assert_eq!(
-            error_res,
-            ContractError::InvalidNativeCoinsSent {
-                denom: "borrowedcoinnative".to_string()
-            }
-        );
+        let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
+        assert!(get_bit(user.borrowed_assets, market_2_initial.index).unwrap());
 
         // *
-        // Repay some native debt
+        // 'user' repays debt on behalf of 'borrower'
         // *
-        let repay_amount = Uint128::from(2000u128);
-        let block_time = market_2_after_borrow_2.indexes_last_updated + 8000u64;
-        let env = mock_env_at_block_time(block_time);
+        let repay_amount = borrow_amount;
+        let env = mock_env(MockEnvParams::default());
         let info = cosmwasm_std::testing::mock_info(
-            "borrower",
-            &[coin(repay_amount.into(), "borrowedcoinnative")],
+            user_addr.as_str(),
+            &[coin(repay_amount, "borrowedcoinnative")],
         );
         let msg = ExecuteMsg::RepayNative {
             denom: String::from("borrowedcoinnative"),
-            on_behalf_of: None,
+            on_behalf_of: Some(borrower_addr.to_string()),
         };
-        let res = execute(deps.as_mut(), env, info, msg).unwrap();
+        let res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
 
-        let expected_params_native = th_get_expected_indices_and_rates(
-            &market_2_after_borrow_2,
-            block_time,
-            available_liquidity_native,
-            TestUtilizationDeltaInfo {
-                less_debt: repay_amount,
-                user_current_debt_scaled: expected_debt_scaled_2_after_borrow_2,
-                ..Default::default()
-            },
-        );
+        // 'user' should not be saved
+        let _user = USERS.load(&deps.storage, &user_addr).unwrap_err();
+
+        // Debt for 'user' should not exist
+        let debt = DEBTS
+            .may_load(&deps.storage, (b"borrowedcoinnative", &user_addr))
+            .unwrap();
+        assert!(debt.is_none());
+
+        // Debt for 'borrower' should be repayed
+        let debt = DEBTS
+            .load(&deps.storage, (b"borrowedcoinnative", &borrower_addr))
+            .unwrap();
+        assert_eq!(debt.amount_scaled, Uint128::zero());
+
+        // 'borrower' should have unset bit for debt after full repay
+        let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
+        assert!(!get_bit(user.borrowed_assets, market_2_initial.index).unwrap());
 
+        // Check msgs and attributes
         assert_eq!(res.messages, vec![]);
         assert_eq!(
             res.attributes,
             vec![
                 attr("action", "repay"),
                 attr("asset", "borrowedcoinnative"),
-                attr("sender", "borrower"),
+                attr("sender", "user"),
                 attr("user", "borrower"),
                 attr("amount", repay_amount.to_string()),
             ]
         );
-        assert_eq!(
-            res.events,
-            vec![th_build_interests_updated_event(
-                "borrowedcoinnative",
-                &expected_params_native
-            )]
-        );
+    }
 
-        let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
-        assert!(get_bit(user.borrowed_assets, 0).unwrap());
-        assert!(get_bit(user.borrowed_assets, 1).unwrap());
+    #[test]
+    fn test_repay_uncollateralized_loan_on_behalf_of() {
+        let mut deps = th_setup(&[]);
 
-        let debt2 = DEBTS
-            .load(&deps.storage, (b"borrowedcoinnative", &borrower_addr))
-            .unwrap();
-        let market_2_after_repay_some_2 =
-            MARKETS.load(&deps.storage, b"borrowedcoinnative").unwrap();
+        let repayer_addr = Addr::unchecked("repayer");
+        let another_user_addr = Addr::unchecked("another_user");
 
-        let expected_debt_scaled_2_after_repay_some_2 = expected_debt_scaled_2_after_borrow_2
-            - compute_scaled_amount(
-                Uint128::from(repay_amount),
-                expected_params_native.borrow_index,
-                ScalingOperation::Ceil,
+        UNCOLLATERALIZED_LOAN_LIMITS
+            .save(
+                deps.as_mut().storage,
+                (b"somecoin", &another_user_addr),
+                &Uint128::new(1000u128),
             )
             .unwrap();
+
+        let env = mock_env(MockEnvParams::default());
+        let info =
+            cosmwasm_std::testing::mock_info(repayer_addr.as_str(), &[coin(110000, "somecoin")]);
+        let msg = ExecuteMsg::RepayNative {
+            denom: "somecoin".to_string(),
+            on_behalf_of: Some(another_user_addr.to_string()),
+        };
+        let error_res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).unwrap_err();
         assert_eq!(
-            expected_debt_scaled_2_after_repay_some_2,
-            debt2.amount_scaled
-        );
-        assert_eq!(
-            expected_debt_scaled_2_after_repay_some_2,
-            market_2_after_repay_some_2.debt_total_scaled
-        );
-        assert_eq!(
-            expected_params_native.borrow_rate,
-            market_2_after_repay_some_2.borrow_rate
-        );
-        assert_eq!(
-            expected_params_native.liquidity_rate,
-            market_2_after_repay_some_2.liquidity_rate
+            error_res,
+            ContractError::CannotRepayUncollateralizedLoanOnBehalfOf {}
         );
+    }
 
-        // *
-        // Repay all native debt
-        // *
-        let block_time = market_2_after_repay_some_2.indexes_last_updated + 10000u64;
-        // need this to compute the repay amount
-        let expected_params_native = th_get_expected_indices_and_rates(
-            &market_2_after_repay_some_2,
-            block_time,
-            available_liquidity_native,
-            TestUtilizationDeltaInfo {
-                less_debt: Uint128::from(9999999999999_u128), // hack: Just do a big number to repay all debt,
-                user_current_debt_scaled: expected_debt_scaled_2_after_repay_some_2,
-                ..Default::default()
-            },
-        );
+    #[test]
+    fn test_borrow_uusd() {
+        let initial_liquidity = 10000000;
+        let mut deps = th_setup(&[coin(initial_liquidity, "uusd")]);
+        let block_time = 1;
 
-        let repay_amount: u128 = compute_underlying_amount(
-            expected_debt_scaled_2_after_repay_some_2,
-            expected_params_native.borrow_index,
-            ScalingOperation::Ceil,
-        )
-        .unwrap()
-        .into();
+        let borrower_addr = Addr::unchecked("borrower");
+        let ltv = Decimal::from_ratio(7u128, 10u128);
 
-        let env = mock_env_at_block_time(block_time);
-        let info = cosmwasm_std::testing::mock_info(
-            "borrower",
-            &[coin(repay_amount, "borrowedcoinnative")],
-        );
-        let msg = ExecuteMsg::RepayNative {
-            denom: String::from("borrowedcoinnative"),
-            on_behalf_of: None,
+        let mock_market = Market {
+            ma_token_address: Addr::unchecked("matoken"),
+            liquidity_index: Decimal::one(),
+            max_loan_to_value: ltv,
+            borrow_index: Decimal::from_ratio(20u128, 10u128),
+            borrow_rate: Decimal::one(),
+            liquidity_rate: Decimal::one(),
+            debt_total_scaled: Uint128::zero(),
+            indexes_last_updated: block_time,
+            asset_type: AssetType::Native,
+            ..Default::default()
         };
-        let res = execute(deps.as_mut(), env, info, msg).unwrap();
+        let market = th_init_market(deps.as_mut(), b"uusd", &mock_market);
 
-        assert_eq!(res.messages, vec![]);
-        assert_eq!(
-            res.attributes,
-            vec![
-                attr("action", "repay"),
-                attr("asset", "borrowedcoinnative"),
-                attr("sender", "borrower"),
-                attr("user", "borrower"),
-                attr("amount", repay_amount.to_string()),
-            ]
+        // Set tax data for uusd
+        deps.querier.set_native_tax(
+            Decimal::from_ratio(1u128, 100u128),
+            &[(String::from("uusd"), Uint128::new(100u128))],
         );
-        assert_eq!(
-            res.events,
-            vec![
-                th_build_interests_updated_event("borrowedcoinnative", &expected_params_native),
-                build_debt_position_changed_event(
-                    "borrowedcoinnative",
-                    false,
-                    "borrower".to_string()
-                ),
-            ]
+
+        // Set user as having the market_collateral deposited
+        let deposit_amount_scaled = Uint128::new(110_000) * SCALING_FACTOR;
+        let mut user = User::default();
+        set_bit(&mut user.collateral_assets, market.index).unwrap();
+        USERS
+            .save(deps.as_mut().storage, &borrower_addr, &user)
+            .unwrap();
+
+        // Set the querier to return collateral balance
+        let deposit_coin_address = Addr::unchecked("matoken");
+        deps.querier.set_cw20_balances(
+            deposit_coin_address,
+            &[(borrower_addr.clone(), deposit_amount_scaled.into())],
+        );
+
+        // borrow with insufficient collateral, should fail
+        let new_block_time = 120u64;
+        let time_elapsed = new_block_time - market.indexes_last_updated;
+        let liquidity_index = calculate_applied_linear_interest_rate(
+            market.liquidity_index,
+            market.liquidity_rate,
+            time_elapsed,
+        )
+        .unwrap();
+        let collateral = compute_underlying_amount(
+            deposit_amount_scaled,
+            liquidity_index,
+            ScalingOperation::Truncate,
+        )
+        .unwrap();
+        let max_to_borrow = collateral * ltv;
+        let msg = ExecuteMsg::Borrow {
+            asset: Asset::Native {
+                denom: "uusd".to_string(),
+            },
+            amount: max_to_borrow + Uint128::from(1u128),
+            recipient: None,
+        };
+        let env = mock_env_at_block_time(new_block_time);
+        let info = mock_info("borrower");
+        let error_res = execute(deps.as_mut(), env, info, msg).unwrap_err();
+        assert_eq!(
+            error_res,
+            ContractError::BorrowAmountExceedsGivenCollateral {}
         );
 
+        let valid_amount = max_to_borrow - Uint128::from(1000u128);
+        let msg = ExecuteMsg::Borrow {
+            asset: Asset::Native {
+                denom: "uusd".to_string(),
+            },
+            amount: valid_amount,
+            recipient: None,
+        };
+        let env = mock_env_at_block_time(block_time);
+        let info = mock_info("borrower");
+        execute(deps.as_mut(), env, info, msg).unwrap();
+
+        let market_after_borrow = MARKETS.load(&deps.storage, b"uusd").unwrap();
+
         let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
         assert!(get_bit(user.borrowed_assets, 0).unwrap());
-        assert!(!get_bit(user.borrowed_assets, 1).unwrap());
 
-        let debt2 = DEBTS
-            .load(&deps.storage, (b"borrowedcoinnative", &borrower_addr))
+        let debt = DEBTS
+            .load(&deps.storage, (b"uusd", &borrower_addr))
             .unwrap();
-        let market_2_after_repay_all_2 =
-            MARKETS.load(&deps.storage, b"borrowedcoinnative").unwrap();
 
-        assert_eq!(Uint128::zero(), debt2.amount_scaled);
         assert_eq!(
-            Uint128::zero(),
-            market_2_after_repay_all_2.debt_total_scaled
+            valid_amount,
+            compute_underlying_amount(
+                debt.amount_scaled,
+                market_after_borrow.borrow_index,
+                ScalingOperation::Ceil
+            )
+            .unwrap()
         );
+    }
 
-        // *
-        // Repay more native debt (should fail)
-        // *
-        let env = mock_env(MockEnvParams::default());
-        let info =
-            cosmwasm_std::testing::mock_info("borrower", &[coin(2000, "borrowedcoinnative")]);
-        let msg = ExecuteMsg::RepayNative {
-            denom: String::from("borrowedcoinnative"),
-            on_behalf_of: None,
-        };
-        let error_res = execute(deps.as_mut(), env, info, msg).unwrap_err();
-        assert_eq!(error_res, ContractError::CannotRepayZeroDebt {});
+    #[test]
+    fn test_borrow_full_liquidity_and_then_repay() {
+        let initial_liquidity = 50000;
+        let mut deps = th_setup(&[coin(initial_liquidity, "uusd")]);
+        let info = mock_info("borrower");
+        let borrower_addr = Addr::unchecked("borrower");
+        let block_time = 1;
+        let ltv = Decimal::one();
 
-        // *
-        // Repay all cw20 debt (and then some)
-        // *
-        let block_time = market_2_after_repay_all_2.indexes_last_updated + 5000u64;
-        let repay_amount = Uint128::from(4800u128);
+        let mock_market = Market {
+            ma_token_address: Addr::unchecked("matoken"),
+            liquidity_index: Decimal::one(),
+            max_loan_to_value: ltv,
+            borrow_index: Decimal::one(),
+            borrow_rate: Decimal::one(),
+            liquidity_rate: Decimal::one(),
+            debt_total_scaled: Uint128::zero(),
+            reserve_factor: Decimal::from_ratio(12u128, 100u128),
+            indexes_last_updated: block_time,
+            asset_type: AssetType::Native,
+            ..Default::default()
+        };
+        let market = th_init_market(deps.as_mut(), b"uusd", &mock_market);
 
-        let expected_params_cw20 = th_get_expected_indices_and_rates(
-            &market_1_after_borrow_again,
-            block_time,
-            available_liquidity_cw20,
-            TestUtilizationDeltaInfo {
-                less_debt: repay_amount,
-                user_current_debt_scaled: expected_debt_scaled_1_after_borrow_again,
-                ..Default::default()
-            },
+        // Set tax data for uusd
+        deps.querier.set_native_tax(
+            Decimal::from_ratio(1u128, 100u128),
+            &[(String::from("uusd"), Uint128::new(100u128))],
         );
 
-        let env = mock_env_at_block_time(block_time);
-        let info = mock_info("borrowedcoincw20");
+        // User should have amount of collateral more than initial liquidity in order to borrow full liquidity
+        let deposit_amount = initial_liquidity + 1000u128;
+        let mut user = User::default();
+        set_bit(&mut user.collateral_assets, market.index).unwrap();
+        USERS
+            .save(deps.as_mut().storage, &borrower_addr, &user)
+            .unwrap();
 
-        let msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
-            msg: to_binary(&ReceiveMsg::RepayCw20 { on_behalf_of: None }).unwrap(),
-            sender: borrower_addr.to_string(),
-            amount: repay_amount,
-        });
+        // Set the querier to return collateral balance
+        let deposit_coin_address = Addr::unchecked("matoken");
+        deps.querier.set_cw20_balances(
+            deposit_coin_address,
+            &[(
+                borrower_addr.clone(),
+                Uint128::new(deposit_amount) * SCALING_FACTOR,
+            )],
+        );
 
-        let res = execute(deps.as_mut(), env, info, msg).unwrap();
+        // Borrow full liquidity
+        {
+            let env = mock_env_at_block_time(block_time);
+            let msg = ExecuteMsg::Borrow {
+                asset: Asset::Native {
+                    denom: "uusd".to_string(),
+                },
+                amount: initial_liquidity.into(),
+                recipient: None,
+            };
+            let _res = execute(deps.as_mut(), env, info.clone(), msg).unwrap();
 
-        let expected_refund_amount = repay_amount
-            - compute_underlying_amount(
-                expected_debt_scaled_1_after_borrow_again,
-                expected_params_cw20.borrow_index,
+            let market_after_borrow = MARKETS.load(&deps.storage, b"uusd").unwrap();
+            let debt_total = compute_underlying_amount(
+                market_after_borrow.debt_total_scaled,
+                market_after_borrow.borrow_index,
                 ScalingOperation::Ceil,
             )
             .unwrap();
+            assert_eq!(debt_total, initial_liquidity.into());
+        }
 
-        assert_eq!(
-            res.messages,
-            vec![SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
-                contract_addr: cw20_contract_addr.to_string(),
-                msg: to_binary(&Cw20ExecuteMsg::Transfer {
-                    recipient: borrower_addr.to_string(),
-                    amount: expected_refund_amount,
-                })
-                .unwrap(),
-                funds: vec![]
-            }))]
-        );
-        assert_eq!(
-            res.attributes,
-            vec![
-                attr("action", "repay"),
-                attr("asset", "borrowedcoincw20"),
-                attr("sender", "borrower"),
-                attr("user", "borrower"),
-                attr(
-                    "amount",
-                    (repay_amount - expected_refund_amount).to_string()
-                ),
-            ]
-        );
-        assert_eq!(
-            res.events,
-            vec![
-                th_build_interests_updated_event("borrowedcoincw20", &expected_params_cw20),
-                build_debt_position_changed_event(
-                    "borrowedcoincw20",
-                    false,
-                    "borrower".to_string()
-                ),
-            ]
-        );
-        let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
-        assert!(!get_bit(user.borrowed_assets, 0).unwrap());
-        assert!(!get_bit(user.borrowed_assets, 1).unwrap());
+        let new_block_time = 12000u64;
+        // We need to update balance after borrowing
+        deps.querier.set_contract_balances(&[coin(0, "uusd")]);
 
-        let debt1 = DEBTS
-            .load(
-                &deps.storage,
-                (cw20_contract_addr.as_bytes(), &borrower_addr),
-            )
-            .unwrap();
-        let market_1_after_repay_1 = MARKETS
-            .load(&deps.storage, cw20_contract_addr.as_bytes())
-            .unwrap();
-        assert_eq!(Uint128::zero(), debt1.amount_scaled);
-        assert_eq!(Uint128::zero(), market_1_after_repay_1.debt_total_scaled);
+        // Try to borrow more than available liquidity
+        {
+            let env = mock_env_at_block_time(new_block_time);
+            let msg = ExecuteMsg::Borrow {
+                asset: Asset::Native {
+                    denom: "uusd".to_string(),
+                },
+                amount: 100u128.into(),
+                recipient: None,
+            };
+            let error_res = execute(deps.as_mut(), env, info.clone(), msg).unwrap_err();
+            assert_eq!(
+                error_res,
+                ContractError::OperationExceedsAvailableLiquidity {}
+            );
+        }
+
+        // Repay part of the debt
+        {
+            let env = mock_env_at_block_time(new_block_time);
+            let info = cosmwasm_std::testing::mock_info("borrower", &[coin(2000, "uusd")]);
+            let msg = ExecuteMsg::RepayNative {
+                denom: String::from("uusd"),
+                on_behalf_of: None,
+            };
+            // check that repay succeeds
+            execute(deps.as_mut(), env, info, msg).unwrap();
+        }
     }
 
     #[test]
-    fn test_cannot_repay_if_market_inactive() {
-        let mut deps = th_setup(&[]);
-
-        let mock_market = Market {
-            ma_token_address: Addr::unchecked("ma_somecoin"),
-            asset_type: AssetType::Native,
-            active: false,
-            deposit_enabled: true,
-            borrow_enabled: true,
-            ..Default::default()
-        };
-        let _market = th_init_market(deps.as_mut(), b"somecoin", &mock_market);
+    fn test_borrow_collateral_check() {
+        // NOTE: available liquidity stays fixed as the test environment does not get changes in
+        // contract balances on subsequent calls. They would change from call to call in practice
+        let available_liquidity_1 = Uint128::from(1000000000u128);
+        let available_liquidity_2 = Uint128::from(2000000000u128);
+        let available_liquidity_3 = Uint128::from(3000000000u128);
+        let mut deps = th_setup(&[
+            coin(available_liquidity_2.into(), "depositedcoin2"),
+            coin(available_liquidity_3.into(), "uusd"),
+        ]);
 
-        let env = mock_env(MockEnvParams::default());
-        let info = cosmwasm_std::testing::mock_info("borrower", &[coin(110000, "somecoin")]);
-        let msg = ExecuteMsg::RepayNative {
-            denom: "somecoin".to_string(),
-            on_behalf_of: None,
-        };
-        let error_res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).unwrap_err();
-        assert_eq!(
-            error_res,
-            ContractError::MarketNotActive {
-                asset: "somecoin".to_string()
-            }
+        // Set tax data
+        deps.querier.set_native_tax(
+            Decimal::from_ratio(1u128, 100u128),
+            &[(String::from("depositedcoin2"), Uint128::new(100u128))],
         );
-    }
 
-    #[test]
-    fn test_repay_on_behalf_of() {
-        let available_liquidity_native = Uint128::from(1000000000u128);
-        let mut deps = th_setup(&[coin(
-            available_liquidity_native.into(),
-            "borrowedcoinnative",
-        )]);
+        let cw20_contract_addr = Addr::unchecked("depositedcoin1");
+        deps.querier.set_cw20_balances(
+            cw20_contract_addr.clone(),
+            &[(Addr::unchecked(MOCK_CONTRACT_ADDR), available_liquidity_1)],
+        );
+
+        let exchange_rate_1 = Decimal::one();
+        let exchange_rate_2 = Decimal::from_ratio(15u128, 4u128);
+        let exchange_rate_3 = Decimal::one();
 
         deps.querier
-            .set_oracle_price(b"depositedcoinnative".to_vec(), Decimal::one());
+            .set_oracle_price(cw20_contract_addr.as_bytes().to_vec(), exchange_rate_1);
         deps.querier
-            .set_oracle_price(b"borrowedcoinnative".to_vec(), Decimal::one());
+            .set_oracle_price(b"depositedcoin2".to_vec(), exchange_rate_2);
+        // NOTE: uusd price (asset3) should be set to 1 by the oracle helper
 
-        deps.querier.set_native_tax(
-            Decimal::from_ratio(1u128, 100u128),
-            &[(String::from("borrowedcoinnative"), Uint128::new(100u128))],
-        );
+        let exchange_rates = &[(String::from("depositedcoin2"), exchange_rate_2)];
+        deps.querier
+            .set_native_exchange_rates(String::from("uusd"), &exchange_rates[..]);
 
         let mock_market_1 = Market {
             ma_token_address: Addr::unchecked("matoken1"),
+            max_loan_to_value: Decimal::from_ratio(8u128, 10u128),
+            debt_total_scaled: Uint128::zero(),
             liquidity_index: Decimal::one(),
-            borrow_index: Decimal::one(),
-            max_loan_to_value: Decimal::from_ratio(50u128, 100u128),
-            asset_type: AssetType::Native,
+            borrow_index: Decimal::from_ratio(1u128, 2u128),
+            asset_type: AssetType::Cw20,
             ..Default::default()
         };
         let mock_market_2 = Market {
             ma_token_address: Addr::unchecked("matoken2"),
+            max_loan_to_value: Decimal::from_ratio(6u128, 10u128),
+            debt_total_scaled: Uint128::zero(),
             liquidity_index: Decimal::one(),
-            borrow_index: Decimal::one(),
-            max_loan_to_value: Decimal::from_ratio(50u128, 100u128),
+            borrow_index: Decimal::from_ratio(1u128, 2u128),
+            asset_type: AssetType::Native,
+            ..Default::default()
+        };
+        let mock_market_3 = Market {
+            ma_token_address: Addr::unchecked("matoken3"),
+            max_loan_to_value: Decimal::from_ratio(4u128, 10u128),
+            debt_total_scaled: Uint128::zero(),
+            liquidity_index: Decimal::one(),
+            borrow_index: Decimal::from_ratio(1u128, 2u128),
             asset_type: AssetType::Native,
             ..Default::default()
         };
 
+        // should get index 0
         let market_1_initial =
-            th_init_market(deps.as_mut(), b"depositedcoinnative", &mock_market_1); // collateral
-        let market_2_initial = th_init_market(deps.as_mut(), b"borrowedcoinnative", &mock_market_2);
+            th_init_market(deps.as_mut(), cw20_contract_addr.as_bytes(), &mock_market_1);
+        // should get index 1
+        let market_2_initial = th_init_market(deps.as_mut(), b"depositedcoin2", &mock_market_2);
+        // should get index 2
+        let market_3_initial = th_init_market(deps.as_mut(), b"uusd", &mock_market_3);
 
         let borrower_addr = Addr::unchecked("borrower");
-        let user_addr = Addr::unchecked("user");
 
-        // Set user as having the market_1_initial (collateral) deposited
+        // Set user as having all the markets as collateral
         let mut user = User::default();
 
         set_bit(&mut user.collateral_assets, market_1_initial.index).unwrap();
+        set_bit(&mut user.collateral_assets, market_2_initial.index).unwrap();
+        set_bit(&mut user.collateral_assets, market_3_initial.index).unwrap();
+
         USERS
             .save(deps.as_mut().storage, &borrower_addr, &user)
             .unwrap();
 
+        let ma_token_address_1 = Addr::unchecked("matoken1");
+        let ma_token_address_2 = Addr::unchecked("matoken2");
+        let ma_token_address_3 = Addr::unchecked("matoken3");
+
+        let balance_1 = Uint128::new(4_000_000) * SCALING_FACTOR;
+        let balance_2 = Uint128::new(7_000_000) * SCALING_FACTOR;
+        let balance_3 = Uint128::new(3_000_000) * SCALING_FACTOR;
+
         // Set the querier to return a certain collateral balance
-        let deposit_coin_address = Addr::unchecked("matoken1");
         deps.querier.set_cw20_balances(
-            deposit_coin_address,
-            &[(borrower_addr.clone(), Uint128::new(10000) * SCALING_FACTOR)],
+            ma_token_address_1,
+            &[(borrower_addr.clone(), balance_1.into())],
+        );
+        deps.querier.set_cw20_balances(
+            ma_token_address_2,
+            &[(borrower_addr.clone(), balance_2.into())],
         );
+        deps.querier
+            .set_cw20_balances(ma_token_address_3, &[(borrower_addr, balance_3.into())]);
 
-        // *
-        // 'borrower' borrows native coin
-        // *
-        let borrow_amount = 4000u128;
-        let env = mock_env(MockEnvParams::default());
-        let info = mock_info(borrower_addr.as_str());
-        let msg = ExecuteMsg::Borrow {
+        let max_borrow_allowed_in_base_asset = (market_1_initial.max_loan_to_value
+            * compute_underlying_amount(
+                balance_1,
+                market_1_initial.liquidity_index,
+                ScalingOperation::Truncate,
+            )
+            .unwrap()
+            * exchange_rate_1)
+            + (market_2_initial.max_loan_to_value
+                * compute_underlying_amount(
+                    balance_2,
+                    market_2_initial.liquidity_index,
+                    ScalingOperation::Truncate,
+                )
+                .unwrap()
+                * exchange_rate_2)
+            + (market_3_initial.max_loan_to_value
+                * compute_underlying_amount(
+                    balance_3,
+                    market_3_initial.liquidity_index,
+                    ScalingOperation::Truncate,
+                )
+                .unwrap()
+                * exchange_rate_3);
+        let exceeding_borrow_amount =
+            Decimal::divide_uint128_by_decimal(max_borrow_allowed_in_base_asset, exchange_rate_2)
+                .unwrap()
+                + Uint128::from(100_u64);
+        let permissible_borrow_amount =
+            Decimal::divide_uint128_by_decimal(max_borrow_allowed_in_base_asset, exchange_rate_2)
+                .unwrap()
+                - Uint128::from(100_u64);
+
+        // borrow above the allowed amount given current collateral, should fail
+        let borrow_msg = ExecuteMsg::Borrow {
             asset: Asset::Native {
-                denom: String::from("borrowedcoinnative"),
+                denom: "depositedcoin2".to_string(),
             },
-            amount: Uint128::from(borrow_amount),
+            amount: exceeding_borrow_amount,
             recipient: None,
         };
-        let _res = execute(deps.as_mut(), env, info, msg).unwrap();
-
-        let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
-        assert!(get_bit(user.borrowed_assets, market_2_initial.index).unwrap());
-
-        // *
-        // 'user' repays debt on behalf of 'borrower'
-        // *
-        let repay_amount = borrow_amount;
         let env = mock_env(MockEnvParams::default());
-        let info = cosmwasm_std::testing::mock_info(
-            user_addr.as_str(),
-            &[coin(repay_amount, "borrowedcoinnative")],
+        let info = mock_info("borrower");
+        let error_res = execute(deps.as_mut(), env.clone(), info.clone(), borrow_msg).unwrap_err();
+        assert_eq!(
+            error_res,
+            ContractError::BorrowAmountExceedsGivenCollateral {}
         );
-        let msg = ExecuteMsg::RepayNative {
-            denom: String::from("borrowedcoinnative"),
-            on_behalf_of: Some(borrower_addr.to_string()),
-        };
-        let res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
-
-        // 'user' should not be saved
-        let _user = USERS.load(&deps.storage, &user_addr).unwrap_err();
 
-        // Debt for 'user' should not exist
-        let debt = DEBTS
-            .may_load(&deps.storage, (b"borrowedcoinnative", &user_addr))
-            .unwrap();
-        assert!(debt.is_none());
+        // borrow permissible amount given current collateral, should succeed
+        let borrow_msg = ExecuteMsg::Borrow {
+            asset: Asset::Native {
+                denom: "depositedcoin2".to_string(),
+            },
+            amount: permissible_borrow_amount,
+            recipient: None,
+        };
+        execute(deps.as_mut(), env, info, borrow_msg).unwrap();
+    }
 
-        // Debt for 'borrower' should be repayed
-        let debt = DEBTS
-            .load(&deps.storage, (b"borrowedcoinnative", &borrower_addr))
-            .unwrap();
-        assert_eq!(debt.amount_scaled, Uint128::zero());
+    #[test]
+    fn test_cannot_borrow_if_market_not_active() {
+        let mut deps = th_setup(&[]);
 
-        // 'borrower' should have unset bit for debt after full repay
-        let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
-        assert!(!get_bit(user.borrowed_assets, market_2_initial.index).unwrap());
+        let mock_market = Market {
+            ma_token_address: Addr::unchecked("ma_somecoin"),
+            asset_type: AssetType::Native,
+            active: false,
+            borrow_enabled: true,
+            ..Default::default()
+        };
+        th_init_market(deps.as_mut(), b"somecoin", &mock_market);
 
-        // Check msgs and attributes
-        assert_eq!(res.messages, vec![]);
+        // Check error when borrowing not allowed on market
+        let env = mock_env(MockEnvParams::default());
+        let info = cosmwasm_std::testing::mock_info("borrower", &[coin(110000, "somecoin")]);
+        let msg = ExecuteMsg::Borrow {
+            asset: Asset::Native {
+                denom: "somecoin".to_string(),
+            },
+            amount: Uint128::new(1000),
+            recipient: None,
+        };
+        let error_res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).unwrap_err();
         assert_eq!(
-            res.attributes,
-            vec![
-                attr("action", "repay"),
-                attr("asset", "borrowedcoinnative"),
-                attr("sender", "user"),
-                attr("user", "borrower"),
-                attr("amount", repay_amount.to_string()),
-            ]
+            error_res,
+            ContractError::MarketNotActive {
+                asset: "somecoin".to_string()
+            }
         );
     }
 
     #[test]
-    fn test_repay_uncollateralized_loan_on_behalf_of() {
+    fn test_cannot_borrow_if_market_not_enabled() {
         let mut deps = th_setup(&[]);
 
-        let repayer_addr = Addr::unchecked("repayer");
-        let another_user_addr = Addr::unchecked("another_user");
-
-        UNCOLLATERALIZED_LOAN_LIMITS
-            .save(
-                deps.as_mut().storage,
-                (b"somecoin", &another_user_addr),
-                &Uint128::new(1000u128),
-            )
-            .unwrap();
+        let mock_market = Market {
+            ma_token_address: Addr::unchecked("ma_somecoin"),
+            asset_type: AssetType::Native,
+            active: true,
+            borrow_enabled: false,
+            ..Default::default()
+        };
+        th_init_market(deps.as_mut(), b"somecoin", &mock_market);
 
+        // Check error when borrowing not allowed on market
         let env = mock_env(MockEnvParams::default());
-        let info =
-            cosmwasm_std::testing::mock_info(repayer_addr.as_str(), &[coin(110000, "somecoin")]);
-        let msg = ExecuteMsg::RepayNative {
-            denom: "somecoin".to_string(),
-            on_behalf_of: Some(another_user_addr.to_string()),
+        let info = cosmwasm_std::testing::mock_info("borrower", &[coin(110000, "somecoin")]);
+        let msg = ExecuteMsg::Borrow {
+            asset: Asset::Native {
+                denom: "somecoin".to_string(),
+            },
+            amount: Uint128::new(1000),
+            recipient: None,
         };
         let error_res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).unwrap_err();
         assert_eq!(
             error_res,
-            ContractError::CannotRepayUncollateralizedLoanOnBehalfOf {}
-        );
-    }
-
-    #[test]
-    fn test_borrow_uusd() {
-        let initial_liquidity = 10000000;
-        let mut deps = th_setup(&[coin(initial_liquidity, "uusd")]);
-        let block_time = 1;
+            ContractError::BorrowNotEnabled {
+                asset: "somecoin".to_string()
+            }
+        );
+    }
 
-        let borrower_addr = Addr::unchecked("borrower");
-        let ltv = Decimal::from_ratio(7u128, 10u128);
+    #[test]
+    fn test_frozen_market() {
+        let mut deps = th_setup(&[]);
 
+        let user_addr = Addr::unchecked("user");
         let mock_market = Market {
-            ma_token_address: Addr::unchecked("matoken"),
-            liquidity_index: Decimal::one(),
-            max_loan_to_value: ltv,
-            borrow_index: Decimal::from_ratio(20u128, 10u128),
-            borrow_rate: Decimal::one(),
-            liquidity_rate: Decimal::one(),
-            debt_total_scaled: Uint128::zero(),
-            indexes_last_updated: block_time,
+            ma_token_address: Addr::unchecked("ma_somecoin"),
             asset_type: AssetType::Native,
+            max_loan_to_value: Decimal::from_ratio(5u128, 10u128),
+            liquidation_threshold: Decimal::from_ratio(6u128, 10u128),
+            frozen: true,
             ..Default::default()
         };
-        let market = th_init_market(deps.as_mut(), b"uusd", &mock_market);
+        let market = th_init_market(deps.as_mut(), b"somecoin", &mock_market);
+        let env = mock_env(MockEnvParams::default());
+        let asset = Asset::Native {
+            denom: "somecoin".to_string(),
+        };
 
-        // Set tax data for uusd
-        deps.querier.set_native_tax(
-            Decimal::from_ratio(1u128, 100u128),
-            &[(String::from("uusd"), Uint128::new(100u128))],
+        // cannot deposit
+        let info = cosmwasm_std::testing::mock_info(user_addr.as_str(), &[coin(1000, "somecoin")]);
+        let msg = ExecuteMsg::DepositNative {
+            denom: String::from("somecoin"),
+            on_behalf_of: None,
+        };
+        let error_res = execute(deps.as_mut(), env.clone(), info, msg).unwrap_err();
+        assert_eq!(
+            error_res,
+            ContractError::MarketFrozen {
+                asset: "somecoin".to_string()
+            }
         );
 
-        // Set user as having the market_collateral deposited
-        let deposit_amount_scaled = Uint128::new(110_000) * SCALING_FACTOR;
-        let mut user = User::default();
-        set_bit(&mut user.collateral_assets, market.index).unwrap();
-        USERS
-            .save(deps.as_mut().storage, &borrower_addr, &user)
-            .unwrap();
+        // cannot borrow
+        let msg = ExecuteMsg::Borrow {
+            asset: asset.clone(),
+            amount: Uint128::new(1000),
+            recipient: None,
+        };
+        let error_res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(user_addr.as_str()),
+            msg,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error_res,
+            ContractError::MarketFrozen {
+                asset: "somecoin".to_string()
+            }
+        );
 
-        // Set the querier to return collateral balance
-        let deposit_coin_address = Addr::unchecked("matoken");
+        // cannot enable as collateral
         deps.querier.set_cw20_balances(
-            deposit_coin_address,
-            &[(borrower_addr.clone(), deposit_amount_scaled.into())],
+            Addr::unchecked("ma_somecoin"),
+            &[(user_addr.clone(), Uint128::new(1000))],
         );
-
-        // borrow with insufficient collateral, should fail
-        let new_block_time = 120u64;
-        let time_elapsed = new_block_time - market.indexes_last_updated;
-        let liquidity_index = calculate_applied_linear_interest_rate(
-            market.liquidity_index,
-            market.liquidity_rate,
-            time_elapsed,
-        )
-        .unwrap();
-        let collateral = compute_underlying_amount(
-            deposit_amount_scaled,
-            liquidity_index,
-            ScalingOperation::Truncate,
+        let msg = ExecuteMsg::UpdateAssetCollateralStatus {
+            asset: asset.clone(),
+            enable: true,
+        };
+        let error_res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(user_addr.as_str()),
+            msg,
         )
-        .unwrap();
-        let max_to_borrow = collateral * ltv;
-        let msg = ExecuteMsg::Borrow {
-            asset: Asset::Native {
-                denom: "uusd".to_string(),
+        .unwrap_err();
+        assert_eq!(
+            error_res,
+            ContractError::MarketFrozen {
+                asset: "somecoin".to_string()
+            }
+        );
+
+        // receiving ma tokens does not enable the asset as collateral
+        let sender_addr = Addr::unchecked("sender");
+        USERS
+            .save(deps.as_mut().storage, &sender_addr, &User::default())
+            .unwrap();
+        let msg = ExecuteMsg::FinalizeLiquidityTokenTransfer {
+            sender_address: sender_addr,
+            recipient_address: user_addr.clone(),
+            sender_previous_balance: Uint128::new(2000),
+            recipient_previous_balance: Uint128::zero(),
+            amount: Uint128::new(1000),
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("ma_somecoin"), msg).unwrap();
+        let user = USERS
+            .may_load(&deps.storage, &user_addr)
+            .unwrap()
+            .unwrap_or_default();
+        assert!(!get_bit(user.collateral_assets, market.index).unwrap());
+
+        // cannot lower liquidation threshold while frozen
+        let empty_asset_params = InitOrUpdateAssetParams {
+            initial_borrow_rate: None,
+            max_loan_to_value: None,
+            reserve_factor: None,
+            liquidation_threshold: None,
+            liquidation_bonus: None,
+            interest_rate_model_params: None,
+            active: None,
+            deposit_enabled: None,
+            borrow_enabled: None,
+            frozen: None,
+            risk_params_ramp_end_time: None,
+        };
+        let msg = ExecuteMsg::UpdateAsset {
+            asset: asset.clone(),
+            asset_params: InitOrUpdateAssetParams {
+                liquidation_threshold: Some(Decimal::from_ratio(55u128, 100u128)),
+                ..empty_asset_params.clone()
             },
-            amount: max_to_borrow + Uint128::from(1u128),
-            recipient: None,
         };
-        let env = mock_env_at_block_time(new_block_time);
-        let info = mock_info("borrower");
-        let error_res = execute(deps.as_mut(), env, info, msg).unwrap_err();
+        let error_res = execute(deps.as_mut(), env.clone(), mock_info("owner"), msg).unwrap_err();
         assert_eq!(
             error_res,
-            ContractError::BorrowAmountExceedsGivenCollateral {}
+            ContractError::CannotLowerLiquidationThresholdWhenFrozen {
+                asset: "somecoin".to_string()
+            }
         );
 
-        let valid_amount = max_to_borrow - Uint128::from(1000u128);
-        let msg = ExecuteMsg::Borrow {
-            asset: Asset::Native {
-                denom: "uusd".to_string(),
+        // raising it is allowed
+        let msg = ExecuteMsg::UpdateAsset {
+            asset: asset.clone(),
+            asset_params: InitOrUpdateAssetParams {
+                liquidation_threshold: Some(Decimal::from_ratio(7u128, 10u128)),
+                ..empty_asset_params.clone()
             },
-            amount: valid_amount,
-            recipient: None,
         };
-        let env = mock_env_at_block_time(block_time);
-        let info = mock_info("borrower");
-        execute(deps.as_mut(), env, info, msg).unwrap();
-
-        let market_after_borrow = MARKETS.load(&deps.storage, b"uusd").unwrap();
-
-        let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
-        assert!(get_bit(user.borrowed_assets, 0).unwrap());
-
-        let debt = DEBTS
-            .load(&deps.storage, (b"uusd", &borrower_addr))
-            .unwrap();
+        execute(deps.as_mut(), env.clone(), mock_info("owner"), msg).unwrap();
+        let market = MARKETS.load(&deps.storage, b"somecoin").unwrap();
+        assert_eq!(
+            market.liquidation_threshold_at(u64::MAX).unwrap(),
+            Decimal::from_ratio(7u128, 10u128)
+        );
 
+        // lowering it is allowed again once the market is unfrozen
+        let msg = ExecuteMsg::UpdateAsset {
+            asset: asset.clone(),
+            asset_params: InitOrUpdateAssetParams {
+                liquidation_threshold: Some(Decimal::from_ratio(6u128, 10u128)),
+                frozen: Some(false),
+                risk_params_ramp_end_time: None,
+                ..empty_asset_params.clone()
+            },
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("owner"), msg).unwrap();
+        let market = MARKETS.load(&deps.storage, b"somecoin").unwrap();
+        assert!(!market.frozen);
         assert_eq!(
-            valid_amount,
-            compute_underlying_amount(
-                debt.amount_scaled,
-                market_after_borrow.borrow_index,
-                ScalingOperation::Ceil
-            )
-            .unwrap()
+            market.liquidation_threshold_at(u64::MAX).unwrap(),
+            Decimal::from_ratio(6u128, 10u128)
         );
+
+        // the market can be frozen while the liquidation threshold is ramping down
+        let msg = ExecuteMsg::UpdateAsset {
+            asset,
+            asset_params: InitOrUpdateAssetParams {
+                frozen: Some(true),
+                ..empty_asset_params
+            },
+        };
+        execute(deps.as_mut(), env, mock_info("owner"), msg).unwrap();
+        let market = MARKETS.load(&deps.storage, b"somecoin").unwrap();
+        assert!(market.frozen);
     }
 
     #[test]
-    fn test_borrow_full_liquidity_and_then_repay() {
-        let initial_liquidity = 50000;
-        let mut deps = th_setup(&[coin(initial_liquidity, "uusd")]);
-        let info = mock_info("borrower");
-        let borrower_addr = Addr::unchecked("borrower");
-        let block_time = 1;
-        let ltv = Decimal::one();
+    fn test_pause() {
+        let mut deps = th_setup(&[]);
 
         let mock_market = Market {
-            ma_token_address: Addr::unchecked("matoken"),
-            liquidity_index: Decimal::one(),
-            max_loan_to_value: ltv,
-            borrow_index: Decimal::one(),
-            borrow_rate: Decimal::one(),
-            liquidity_rate: Decimal::one(),
-            debt_total_scaled: Uint128::zero(),
-            reserve_factor: Decimal::from_ratio(12u128, 100u128),
-            indexes_last_updated: block_time,
+            ma_token_address: Addr::unchecked("ma_somecoin"),
             asset_type: AssetType::Native,
             ..Default::default()
         };
-        let market = th_init_market(deps.as_mut(), b"uusd", &mock_market);
+        th_init_market(deps.as_mut(), b"somecoin", &mock_market);
+        let env = mock_env(MockEnvParams::default());
+        let asset = Asset::Native {
+            denom: "somecoin".to_string(),
+        };
+        let deposit_msg = ExecuteMsg::DepositNative {
+            denom: String::from("somecoin"),
+            on_behalf_of: None,
+        };
+        let deposit_info = cosmwasm_std::testing::mock_info("depositor", &[coin(1000, "somecoin")]);
 
-        // Set tax data for uusd
-        deps.querier.set_native_tax(
-            Decimal::from_ratio(1u128, 100u128),
-            &[(String::from("uusd"), Uint128::new(100u128))],
-        );
+        // only owner or guardian can pause
+        let msg = ExecuteMsg::Pause {
+            asset: Some(asset.clone()),
+        };
+        let error_res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("somebody"),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(error_res, MarsError::Unauthorized {}.into());
 
-        // User should have amount of collateral more than initial liquidity in order to borrow full liquidity
-        let deposit_amount = initial_liquidity + 1000u128;
-        let mut user = User::default();
-        set_bit(&mut user.collateral_assets, market.index).unwrap();
-        USERS
-            .save(deps.as_mut().storage, &borrower_addr, &user)
-            .unwrap();
+        execute(deps.as_mut(), env.clone(), mock_info("guardian"), msg).unwrap();
+        let market = MARKETS.load(&deps.storage, b"somecoin").unwrap();
+        assert!(market.paused);
 
-        // Set the querier to return collateral balance
-        let deposit_coin_address = Addr::unchecked("matoken");
-        deps.querier.set_cw20_balances(
-            deposit_coin_address,
-            &[(
-                borrower_addr.clone(),
-                Uint128::new(deposit_amount) * SCALING_FACTOR,
-            )],
+        let error_res = execute(
+            deps.as_mut(),
+            env.clone(),
+            deposit_info.clone(),
+            deposit_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error_res,
+            ContractError::MarketPaused {
+                asset: "somecoin".to_string()
+            }
         );
 
-        // Borrow full liquidity
-        {
-            let env = mock_env_at_block_time(block_time);
-            let msg = ExecuteMsg::Borrow {
-                asset: Asset::Native {
-                    denom: "uusd".to_string(),
-                },
-                amount: initial_liquidity.into(),
-                recipient: None,
-            };
-            let _res = execute(deps.as_mut(), env, info.clone(), msg).unwrap();
-
-            let market_after_borrow = MARKETS.load(&deps.storage, b"uusd").unwrap();
-            let debt_total = compute_underlying_amount(
-                market_after_borrow.debt_total_scaled,
-                market_after_borrow.borrow_index,
-                ScalingOperation::Ceil,
-            )
-            .unwrap();
-            assert_eq!(debt_total, initial_liquidity.into());
-        }
-
-        let new_block_time = 12000u64;
-        // We need to update balance after borrowing
-        deps.querier.set_contract_balances(&[coin(0, "uusd")]);
-
-        // Try to borrow more than available liquidity
-        {
-            let env = mock_env_at_block_time(new_block_time);
-            let msg = ExecuteMsg::Borrow {
-                asset: Asset::Native {
-                    denom: "uusd".to_string(),
-                },
-                amount: 100u128.into(),
-                recipient: None,
-            };
-            let error_res = execute(deps.as_mut(), env, info.clone(), msg).unwrap_err();
-            assert_eq!(
-                error_res,
-                ContractError::OperationExceedsAvailableLiquidity {}
-            );
-        }
+        // only owner can unpause
+        let msg = ExecuteMsg::Unpause {
+            asset: Some(asset.clone()),
+        };
+        let error_res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("guardian"),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(error_res, MarsError::Unauthorized {}.into());
 
-        // Repay part of the debt
-        {
-            let env = mock_env_at_block_time(new_block_time);
-            let info = cosmwasm_std::testing::mock_info("borrower", &[coin(2000, "uusd")]);
-            let msg = ExecuteMsg::RepayNative {
-                denom: String::from("uusd"),
-                on_behalf_of: None,
-            };
-            // check that repay succeeds
-            execute(deps.as_mut(), env, info, msg).unwrap();
-        }
+        execute(deps.as_mut(), env.clone(), mock_info("owner"), msg).unwrap();
+        let market = MARKETS.load(&deps.storage, b"somecoin").unwrap();
+        assert!(!market.paused);
+
+        // global pause blocks every market
+        let msg = ExecuteMsg::Pause { asset: None };
+        execute(deps.as_mut(), env.clone(), mock_info("guardian"), msg).unwrap();
+        let config = query_config(deps.as_ref()).unwrap();
+        assert!(config.paused);
+
+        let error_res = execute(
+            deps.as_mut(),
+            env.clone(),
+            deposit_info.clone(),
+            deposit_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(error_res, MarsError::Paused {}.into());
+
+        let msg = ExecuteMsg::Unpause { asset: None };
+        execute(deps.as_mut(), env, mock_info("owner"), msg).unwrap();
+        let config = query_config(deps.as_ref()).unwrap();
+        assert!(!config.paused);
     }
 
     #[test]
-    fn test_borrow_collateral_check() {
-        // NOTE: available liquidity stays fixed as the test environment does not get changes in
-        // contract balances on subsequent calls. They would change from call to call in practice
-        let available_liquidity_1 = Uint128::from(1000000000u128);
-        let available_liquidity_2 = Uint128::from(2000000000u128);
-        let available_liquidity_3 = Uint128::from(3000000000u128);
-        let mut deps = th_setup(&[
-            coin(available_liquidity_2.into(), "depositedcoin2"),
-            coin(available_liquidity_3.into(), "uusd"),
-        ]);
+    fn test_outflow_limit() {
+        let mut deps = th_setup(&[coin(1_000_000, "somecoin")]);
 
-        // Set tax data
-        deps.querier.set_native_tax(
-            Decimal::from_ratio(1u128, 100u128),
-            &[(String::from("depositedcoin2"), Uint128::new(100u128))],
+        let mock_market = Market {
+            ma_token_address: Addr::unchecked("ma_somecoin"),
+            asset_type: AssetType::Native,
+            ..Default::default()
+        };
+        th_init_market(deps.as_mut(), b"somecoin", &mock_market);
+        let asset = Asset::Native {
+            denom: "somecoin".to_string(),
+        };
+        let start_time = 1_000_000;
+        let env = mock_env_at_block_time(start_time);
+
+        // only owner can set the limit
+        let msg = ExecuteMsg::UpdateOutflowLimit {
+            asset: asset.clone(),
+            limit: Some(OutflowLimitParams {
+                max_amount: Uint128::new(1000),
+                window: 100,
+            }),
+        };
+        let error_res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("somebody"),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(error_res, MarsError::Unauthorized {}.into());
+
+        // window cannot be zero
+        let invalid_msg = ExecuteMsg::UpdateOutflowLimit {
+            asset: asset.clone(),
+            limit: Some(OutflowLimitParams {
+                max_amount: Uint128::new(1000),
+                window: 0,
+            }),
+        };
+        let error_res =
+            execute(deps.as_mut(), env.clone(), mock_info("owner"), invalid_msg).unwrap_err();
+        assert_eq!(
+            error_res,
+            MarsError::InvalidParam {
+                param_name: "window".to_string(),
+                invalid_value: "0".to_string(),
+                predicate: "> 0".to_string(),
+            }
+            .into()
         );
 
-        let cw20_contract_addr = Addr::unchecked("depositedcoin1");
+        execute(deps.as_mut(), env.clone(), mock_info("owner"), msg).unwrap();
+        let res = query_outflow_capacity(deps.as_ref(), env.clone(), asset.clone()).unwrap();
+        assert_eq!(res.remaining_capacity, Some(Uint128::new(1000)));
+
+        // outflows are accounted up to the limit
+        deps.querier.set_native_tax(
+            Decimal::zero(),
+            &[(String::from("somecoin"), Uint128::zero())],
+        );
         deps.querier.set_cw20_balances(
-            cw20_contract_addr.clone(),
-            &[(Addr::unchecked(MOCK_CONTRACT_ADDR), available_liquidity_1)],
+            Addr::unchecked("ma_somecoin"),
+            &[(
+                Addr::unchecked("withdrawer"),
+                Uint128::new(1_000) * SCALING_FACTOR,
+            )],
         );
+        USERS
+            .save(
+                deps.as_mut().storage,
+                &Addr::unchecked("withdrawer"),
+                &User::default(),
+            )
+            .unwrap();
+        let msg = ExecuteMsg::Withdraw {
+            asset: asset.clone(),
+            amount: Some(Uint128::new(600)),
+            recipient: None,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("withdrawer"), msg).unwrap();
+        let res = query_outflow_capacity(deps.as_ref(), env.clone(), asset.clone()).unwrap();
+        assert_eq!(res.remaining_capacity, Some(Uint128::new(400)));
 
-        let exchange_rate_1 = Decimal::one();
-        let exchange_rate_2 = Decimal::from_ratio(15u128, 4u128);
-        let exchange_rate_3 = Decimal::one();
+        // borrowing past the limit is rejected
+        let msg = ExecuteMsg::Borrow {
+            asset: asset.clone(),
+            amount: Uint128::new(500),
+            recipient: None,
+        };
+        let error_res = execute(deps.as_mut(), env, mock_info("borrower"), msg).unwrap_err();
+        assert_eq!(
+            error_res,
+            ContractError::OutflowLimitExceeded {
+                asset: "somecoin".to_string(),
+                amount: Uint128::new(500),
+                remaining_capacity: Uint128::new(400),
+            }
+        );
 
-        deps.querier
-            .set_oracle_price(cw20_contract_addr.as_bytes().to_vec(), exchange_rate_1);
-        deps.querier
-            .set_oracle_price(b"depositedcoin2".to_vec(), exchange_rate_2);
-        // NOTE: uusd price (asset3) should be set to 1 by the oracle helper
+        // capacity is recovered as the window passes
+        let env = mock_env_at_block_time(start_time + 50);
+        let res = query_outflow_capacity(deps.as_ref(), env.clone(), asset.clone()).unwrap();
+        assert_eq!(res.remaining_capacity, Some(Uint128::new(900)));
 
-        let exchange_rates = &[(String::from("depositedcoin2"), exchange_rate_2)];
-        deps.querier
-            .set_native_exchange_rates(String::from("uusd"), &exchange_rates[..]);
+        // deposits free up capacity
+        let info = cosmwasm_std::testing::mock_info("depositor", &[coin(300, "somecoin")]);
+        let msg = ExecuteMsg::DepositNative {
+            denom: String::from("somecoin"),
+            on_behalf_of: None,
+        };
+        execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        let res = query_outflow_capacity(deps.as_ref(), env.clone(), asset.clone()).unwrap();
+        assert_eq!(res.remaining_capacity, Some(Uint128::new(1000)));
 
-        let mock_market_1 = Market {
-            ma_token_address: Addr::unchecked("matoken1"),
-            max_loan_to_value: Decimal::from_ratio(8u128, 10u128),
-            debt_total_scaled: Uint128::zero(),
-            liquidity_index: Decimal::one(),
-            borrow_index: Decimal::from_ratio(1u128, 2u128),
-            asset_type: AssetType::Cw20,
-            ..Default::default()
+        // removing the limit
+        let msg = ExecuteMsg::UpdateOutflowLimit {
+            asset: asset.clone(),
+            limit: None,
         };
-        let mock_market_2 = Market {
-            ma_token_address: Addr::unchecked("matoken2"),
-            max_loan_to_value: Decimal::from_ratio(6u128, 10u128),
-            debt_total_scaled: Uint128::zero(),
+        execute(deps.as_mut(), env.clone(), mock_info("owner"), msg).unwrap();
+        let res = query_outflow_capacity(deps.as_ref(), env, asset).unwrap();
+        assert_eq!(res.limit, None);
+        assert_eq!(res.remaining_capacity, None);
+    }
+
+    #[test]
+    fn test_market_history() {
+        let mut deps = th_setup(&[coin(1_000_000, "somecoin")]);
+        let start_time = 1_000_000;
+
+        let mock_market = Market {
+            ma_token_address: Addr::unchecked("ma_somecoin"),
             liquidity_index: Decimal::one(),
-            borrow_index: Decimal::from_ratio(1u128, 2u128),
+            borrow_index: Decimal::one(),
+            indexes_last_updated: start_time,
             asset_type: AssetType::Native,
             ..Default::default()
         };
-        let mock_market_3 = Market {
-            ma_token_address: Addr::unchecked("matoken3"),
-            max_loan_to_value: Decimal::from_ratio(4u128, 10u128),
-            debt_total_scaled: Uint128::zero(),
-            liquidity_index: Decimal::one(),
-            borrow_index: Decimal::from_ratio(1u128, 2u128),
-            asset_type: AssetType::Native,
-            ..Default::default()
+        th_init_market(deps.as_mut(), b"somecoin", &mock_market);
+        let asset = Asset::Native {
+            denom: "somecoin".to_string(),
         };
 
-        // should get index 0
-        let market_1_initial =
-            th_init_market(deps.as_mut(), cw20_contract_addr.as_bytes(), &mock_market_1);
-        // should get index 1
-        let market_2_initial = th_init_market(deps.as_mut(), b"depositedcoin2", &mock_market_2);
-        // should get index 2
-        let market_3_initial = th_init_market(deps.as_mut(), b"uusd", &mock_market_3);
+        // snapshots are enabled by config
+        let msg = ExecuteMsg::UpdateConfig {
+            config: CreateOrUpdateConfig {
+                owner: None,
+                address_provider_address: None,
+                ma_token_code_id: None,
+                close_factor: None,
+                market_snapshot_interval: Some(100),
+                market_snapshot_max_count: Some(3),
+            },
+        };
+        execute(
+            deps.as_mut(),
+            mock_env_at_block_time(start_time),
+            mock_info("owner"),
+            msg,
+        )
+        .unwrap();
 
-        let borrower_addr = Addr::unchecked("borrower");
+        // a snapshot is taken at most once per interval and the ones older than
+        // interval * max count are pruned
+        for seconds_elapsed in [0, 50, 100, 200, 400] {
+            let info = cosmwasm_std::testing::mock_info("depositor", &[coin(1000, "somecoin")]);
+            let msg = ExecuteMsg::DepositNative {
+                denom: String::from("somecoin"),
+                on_behalf_of: None,
+            };
+            execute(
+                deps.as_mut(),
+                mock_env_at_block_time(start_time + seconds_elapsed),
+                info,
+                msg,
+            )
+            .unwrap();
+        }
 
-        // Set user as having all the markets as collateral
-        let mut user = User::default();
+        let res = query_market_history(deps.as_ref(), asset.clone(), None, None).unwrap();
+        let timestamps: Vec<u64> = res
+            .snapshots
+            .iter()
+            .map(|snapshot| snapshot.timestamp)
+            .collect();
+        assert_eq!(
+            timestamps,
+            vec![start_time + 100, start_time + 200, start_time + 400]
+        );
+        assert_eq!(res.snapshots[0].liquidity_index, Decimal::one());
+        assert_eq!(
+            res.snapshots[0].available_liquidity,
+            Uint128::new(1_000_000)
+        );
 
-        set_bit(&mut user.collateral_assets, market_1_initial.index).unwrap();
-        set_bit(&mut user.collateral_assets, market_2_initial.index).unwrap();
-        set_bit(&mut user.collateral_assets, market_3_initial.index).unwrap();
+        let res = query_market_history(
+            deps.as_ref(),
+            asset.clone(),
+            Some(start_time + 100),
+            Some(1),
+        )
+        .unwrap();
+        assert_eq!(res.snapshots.len(), 1);
+        assert_eq!(res.snapshots[0].timestamp, start_time + 200);
 
-        USERS
-            .save(deps.as_mut().storage, &borrower_addr, &user)
-            .unwrap();
+        // large snapshot settings don't make interest updates fail
+        let msg = ExecuteMsg::UpdateConfig {
+            config: CreateOrUpdateConfig {
+                owner: None,
+                address_provider_address: None,
+                ma_token_code_id: None,
+                close_factor: None,
+                market_snapshot_interval: Some(u64::MAX),
+                market_snapshot_max_count: Some(u32::MAX),
+            },
+        };
+        execute(
+            deps.as_mut(),
+            mock_env_at_block_time(start_time + 500),
+            mock_info("owner"),
+            msg,
+        )
+        .unwrap();
+        let info = cosmwasm_std::testing::mock_info("depositor", &[coin(1000, "somecoin")]);
+        let msg = ExecuteMsg::DepositNative {
+            denom: String::from("somecoin"),
+            on_behalf_of: None,
+        };
+        execute(
+            deps.as_mut(),
+            mock_env_at_block_time(start_time + 600),
+            info,
+            msg,
+        )
+        .unwrap();
+        let res = query_market_history(deps.as_ref(), asset, None, None).unwrap();
+        assert_eq!(res.snapshots.len(), 3);
+    }
 
-        let ma_token_address_1 = Addr::unchecked("matoken1");
-        let ma_token_address_2 = Addr::unchecked("matoken2");
-        let ma_token_address_3 = Addr::unchecked("matoken3");
+    #[test]
+    fn test_user_pnl() {
+        let mut deps = th_setup(&[coin(1_000_000, "somecoin")]);
+        deps.querier
+            .set_oracle_price(b"somecoin".to_vec(), Decimal::one());
 
-        let balance_1 = Uint128::new(4_000_000) * SCALING_FACTOR;
-        let balance_2 = Uint128::new(7_000_000) * SCALING_FACTOR;
-        let balance_3 = Uint128::new(3_000_000) * SCALING_FACTOR;
+        let start_time = 1_000_000;
+        let mock_market = Market {
+            ma_token_address: Addr::unchecked("ma_somecoin"),
+            liquidity_index: Decimal::one(),
+            borrow_index: Decimal::one(),
+            indexes_last_updated: start_time,
+            asset_type: AssetType::Native,
+            ..Default::default()
+        };
+        th_init_market(deps.as_mut(), b"somecoin", &mock_market);
+        MARKET_REFERENCES_BY_MA_TOKEN
+            .save(
+                deps.as_mut().storage,
+                &Addr::unchecked("ma_somecoin"),
+                &(b"somecoin".to_vec()),
+            )
+            .unwrap();
 
-        // Set the querier to return a certain collateral balance
-        deps.querier.set_cw20_balances(
-            ma_token_address_1,
-            &[(borrower_addr.clone(), balance_1.into())],
-        );
+        let depositor_addr = Addr::unchecked("depositor");
+        let info = cosmwasm_std::testing::mock_info("depositor", &[coin(1000, "somecoin")]);
+        let msg = ExecuteMsg::DepositNative {
+            denom: String::from("somecoin"),
+            on_behalf_of: None,
+        };
+        execute(deps.as_mut(), mock_env_at_block_time(start_time), info, msg).unwrap();
+
+        // set rates so half a year later the liquidity index is 1.05 and the borrow index is 1.1
+        let mut market = MARKETS.load(&deps.storage, b"somecoin").unwrap();
+        market.liquidity_rate = Decimal::percent(10);
+        market.borrow_rate = Decimal::percent(20);
+        MARKETS
+            .save(deps.as_mut().storage, b"somecoin", &market)
+            .unwrap();
         deps.querier.set_cw20_balances(
-            ma_token_address_2,
-            &[(borrower_addr.clone(), balance_2.into())],
+            Addr::unchecked("ma_somecoin"),
+            &[
+                (depositor_addr.clone(), Uint128::new(1000) * SCALING_FACTOR),
+                (Addr::unchecked("borrower"), Uint128::zero()),
+            ],
         );
-        deps.querier
-            .set_cw20_balances(ma_token_address_3, &[(borrower_addr, balance_3.into())]);
 
-        let max_borrow_allowed_in_uusd = (market_1_initial.max_loan_to_value
-            * compute_underlying_amount(
-                balance_1,
-                market_1_initial.liquidity_index,
-                ScalingOperation::Truncate,
+        // debt drawn at start
+        let borrower_addr = Addr::unchecked("borrower");
+        DEBTS
+            .save(
+                deps.as_mut().storage,
+                (b"somecoin", &borrower_addr),
+                &Debt {
+                    amount_scaled: Uint128::new(2000) * SCALING_FACTOR,
+                    uncollateralized: false,
+                },
             )
-            .unwrap()
-            * exchange_rate_1)
-            + (market_2_initial.max_loan_to_value
-                * compute_underlying_amount(
-                    balance_2,
-                    market_2_initial.liquidity_index,
-                    ScalingOperation::Truncate,
-                )
-                .unwrap()
-                * exchange_rate_2)
-            + (market_3_initial.max_loan_to_value
-                * compute_underlying_amount(
-                    balance_3,
-                    market_3_initial.liquidity_index,
-                    ScalingOperation::Truncate,
-                )
-                .unwrap()
-                * exchange_rate_3);
-        let exceeding_borrow_amount =
-            Decimal::divide_uint128_by_decimal(max_borrow_allowed_in_uusd, exchange_rate_2)
-                .unwrap()
-                + Uint128::from(100_u64);
-        let permissible_borrow_amount =
-            Decimal::divide_uint128_by_decimal(max_borrow_allowed_in_uusd, exchange_rate_2)
-                .unwrap()
-                - Uint128::from(100_u64);
+            .unwrap();
+        record_principal_change(
+            deps.as_mut().storage,
+            b"somecoin",
+            &borrower_addr,
+            PrincipalChange::Borrow,
+            Uint128::new(2000),
+        )
+        .unwrap();
 
-        // borrow above the allowed amount given current collateral, should fail
-        let borrow_msg = ExecuteMsg::Borrow {
-            asset: Asset::Native {
-                denom: "depositedcoin2".to_string(),
-            },
-            amount: exceeding_borrow_amount,
-            recipient: None,
-        };
-        let env = mock_env(MockEnvParams::default());
-        let info = mock_info("borrower");
-        let error_res = execute(deps.as_mut(), env.clone(), info.clone(), borrow_msg).unwrap_err();
+        let env = mock_env_at_block_time(start_time + 15768000);
+        let res = query_user_pnl(deps.as_ref(), env.clone(), depositor_addr.clone()).unwrap();
+        assert_eq!(res.markets.len(), 1);
         assert_eq!(
-            error_res,
-            ContractError::BorrowAmountExceedsGivenCollateral {}
+            res.markets[0].principal,
+            UserPrincipal {
+                deposited: Uint128::new(1000),
+                ..Default::default()
+            }
         );
+        assert_eq!(res.markets[0].collateral, Uint128::new(1050));
+        assert_eq!(res.markets[0].interest_earned, Uint128::new(50));
 
-        // borrow permissible amount given current collateral, should succeed
-        let borrow_msg = ExecuteMsg::Borrow {
-            asset: Asset::Native {
-                denom: "depositedcoin2".to_string(),
-            },
-            amount: permissible_borrow_amount,
-            recipient: None,
+        let res = query_user_pnl(deps.as_ref(), env.clone(), borrower_addr).unwrap();
+        assert_eq!(res.markets[0].debt, Uint128::new(2200));
+        assert_eq!(res.markets[0].interest_paid, Uint128::new(200));
+
+        // transferring maTokens moves principal at the current underlying value
+        let msg = ExecuteMsg::FinalizeLiquidityTokenTransfer {
+            sender_address: depositor_addr.clone(),
+            recipient_address: Addr::unchecked("recipient"),
+            sender_previous_balance: Uint128::new(1000) * SCALING_FACTOR,
+            recipient_previous_balance: Uint128::zero(),
+            amount: Uint128::new(400) * SCALING_FACTOR,
         };
-        execute(deps.as_mut(), env, info, borrow_msg).unwrap();
+        execute(deps.as_mut(), env.clone(), mock_info("ma_somecoin"), msg).unwrap();
+        deps.querier.set_cw20_balances(
+            Addr::unchecked("ma_somecoin"),
+            &[
+                (depositor_addr.clone(), Uint128::new(600) * SCALING_FACTOR),
+                (
+                    Addr::unchecked("recipient"),
+                    Uint128::new(400) * SCALING_FACTOR,
+                ),
+            ],
+        );
+
+        let res = query_user_pnl(deps.as_ref(), env.clone(), depositor_addr.clone()).unwrap();
+        assert_eq!(res.markets[0].principal.withdrawn, Uint128::new(420));
+        assert_eq!(res.markets[0].collateral, Uint128::new(630));
+        assert_eq!(res.markets[0].interest_earned, Uint128::new(50));
+
+        let res = query_user_pnl(deps.as_ref(), env.clone(), Addr::unchecked("recipient")).unwrap();
+        assert_eq!(res.markets[0].principal.deposited, Uint128::new(420));
+        assert_eq!(res.markets[0].interest_earned, Uint128::zero());
+
+        // maTokens locked in the withdraw queue still count towards interest earned
+        let msg = ExecuteMsg::Receive(cw20::Cw20ReceiveMsg {
+            sender: depositor_addr.to_string(),
+            amount: Uint128::new(300) * SCALING_FACTOR,
+            msg: to_binary(&ReceiveMsg::QueueWithdraw { recipient: None }).unwrap(),
+        });
+        execute(deps.as_mut(), env.clone(), mock_info("ma_somecoin"), msg).unwrap();
+        deps.querier.set_cw20_balances(
+            Addr::unchecked("ma_somecoin"),
+            &[
+                (depositor_addr.clone(), Uint128::new(300) * SCALING_FACTOR),
+                (
+                    Addr::unchecked("recipient"),
+                    Uint128::new(400) * SCALING_FACTOR,
+                ),
+            ],
+        );
+
+        let res = query_user_pnl(deps.as_ref(), env, depositor_addr).unwrap();
+        assert_eq!(res.markets[0].collateral, Uint128::new(315));
+        assert_eq!(res.markets[0].queued, Uint128::new(315));
+        assert_eq!(res.markets[0].interest_earned, Uint128::new(50));
     }
 
     #[test]
-    fn test_cannot_borrow_if_market_not_active() {
-        let mut deps = th_setup(&[]);
+    fn test_withdraw_queue() {
+        let mut deps = th_setup(&[coin(1000, "somecoin")]);
+        deps.querier.set_native_tax(
+            Decimal::zero(),
+            &[(String::from("somecoin"), Uint128::new(100u128))],
+        );
 
+        let start_time = 1_000_000;
         let mock_market = Market {
             ma_token_address: Addr::unchecked("ma_somecoin"),
+            liquidity_index: Decimal::one(),
+            borrow_index: Decimal::one(),
+            indexes_last_updated: start_time,
             asset_type: AssetType::Native,
-            active: false,
-            borrow_enabled: true,
             ..Default::default()
         };
         th_init_market(deps.as_mut(), b"somecoin", &mock_market);
+        MARKET_REFERENCES_BY_MA_TOKEN
+            .save(
+                deps.as_mut().storage,
+                &Addr::unchecked("ma_somecoin"),
+                &(b"somecoin".to_vec()),
+            )
+            .unwrap();
+        let asset = Asset::Native {
+            denom: "somecoin".to_string(),
+        };
+        let env = mock_env_at_block_time(start_time);
 
-        // Check error when borrowing not allowed on market
-        let env = mock_env(MockEnvParams::default());
-        let info = cosmwasm_std::testing::mock_info("borrower", &[coin(110000, "somecoin")]);
-        let msg = ExecuteMsg::Borrow {
-            asset: Asset::Native {
-                denom: "somecoin".to_string(),
-            },
-            amount: Uint128::new(1000),
-            recipient: None,
+        // only maTokens can be queued
+        let msg = ExecuteMsg::Receive(cw20::Cw20ReceiveMsg {
+            sender: "alice".to_string(),
+            amount: Uint128::new(600) * SCALING_FACTOR,
+            msg: to_binary(&ReceiveMsg::QueueWithdraw { recipient: None }).unwrap(),
+        });
+        let error_res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("other_token"),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(error_res, ContractError::InvalidMaToken {});
+
+        execute(deps.as_mut(), env.clone(), mock_info("ma_somecoin"), msg).unwrap();
+        let msg = ExecuteMsg::Receive(cw20::Cw20ReceiveMsg {
+            sender: "bob".to_string(),
+            amount: Uint128::new(800) * SCALING_FACTOR,
+            msg: to_binary(&ReceiveMsg::QueueWithdraw {
+                recipient: Some("bob_recipient".to_string()),
+            })
+            .unwrap(),
+        });
+        execute(deps.as_mut(), env.clone(), mock_info("ma_somecoin"), msg).unwrap();
+
+        // queue positions and expected fills
+        let res =
+            query_withdraw_queue(deps.as_ref(), env.clone(), asset.clone(), None, None).unwrap();
+        assert_eq!(res.requests.len(), 2);
+        assert_eq!(res.requests[0].id, 0);
+        assert_eq!(res.requests[0].amount, Uint128::new(600));
+        assert_eq!(res.requests[0].amount_ahead, Uint128::zero());
+        assert_eq!(res.requests[0].expected_fill, Uint128::new(600));
+        assert_eq!(res.requests[1].id, 1);
+        assert_eq!(res.requests[1].amount_ahead, Uint128::new(600));
+        assert_eq!(res.requests[1].expected_fill, Uint128::new(400));
+
+        let res = query_user_withdraw_requests(
+            deps.as_ref(),
+            env.clone(),
+            Addr::unchecked("bob"),
+            asset.clone(),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(res.requests.len(), 1);
+        assert_eq!(
+            res.requests[0].recipient_address,
+            Addr::unchecked("bob_recipient")
+        );
+
+        // processing pays out in order with a partial fill for the last request
+        let msg = ExecuteMsg::ProcessWithdrawQueue {
+            asset: asset.clone(),
+            limit: None,
         };
-        let error_res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).unwrap_err();
+        let res = execute(deps.as_mut(), env.clone(), mock_info("anyone"), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![
+                SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "alice".to_string(),
+                    amount: vec![coin(600, "somecoin")],
+                })),
+                SubMsg::new(CosmosMsg::Bank(BankMsg::Send {
+                    to_address: "bob_recipient".to_string(),
+                    amount: vec![coin(400, "somecoin")],
+                })),
+                SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: "ma_somecoin".to_string(),
+                    msg: to_binary(&ma_token::msg::ExecuteMsg::Burn {
+                        user: MOCK_CONTRACT_ADDR.to_string(),
+                        amount: Uint128::new(1000) * SCALING_FACTOR,
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                })),
+            ]
+        );
+
+        let request = WITHDRAW_REQUESTS
+            .load(&deps.storage, (b"somecoin", U64Key::new(1)))
+            .unwrap();
+        assert_eq!(request.amount_scaled, Uint128::new(400) * SCALING_FACTOR);
+        assert!(WITHDRAW_REQUESTS
+            .may_load(&deps.storage, (b"somecoin", U64Key::new(0)))
+            .unwrap()
+            .is_none());
+
+        // partially filled requests keep their position at the front of the queue
+        let msg = ExecuteMsg::Receive(cw20::Cw20ReceiveMsg {
+            sender: "alice".to_string(),
+            amount: Uint128::new(200) * SCALING_FACTOR,
+            msg: to_binary(&ReceiveMsg::QueueWithdraw { recipient: None }).unwrap(),
+        });
+        execute(deps.as_mut(), env.clone(), mock_info("ma_somecoin"), msg).unwrap();
+        let res =
+            query_withdraw_queue(deps.as_ref(), env.clone(), asset.clone(), None, None).unwrap();
+        assert_eq!(res.requests.len(), 2);
+        assert_eq!(res.requests[0].id, 1);
+        assert_eq!(res.requests[0].amount, Uint128::new(400));
+        assert_eq!(res.requests[0].amount_ahead, Uint128::zero());
+        assert_eq!(res.requests[1].id, 2);
+        assert_eq!(res.requests[1].amount_ahead, Uint128::new(400));
+
+        // queries are paginated
+        let res =
+            query_withdraw_queue(deps.as_ref(), env.clone(), asset.clone(), Some(1), None).unwrap();
+        assert_eq!(res.requests.len(), 1);
+        assert_eq!(res.requests[0].id, 2);
+        let res = query_user_withdraw_requests(
+            deps.as_ref(),
+            env.clone(),
+            Addr::unchecked("alice"),
+            asset.clone(),
+            None,
+            Some(1),
+        )
+        .unwrap();
+        assert_eq!(res.requests.len(), 1);
+        assert_eq!(res.requests[0].id, 2);
+
+        // only the user that queued the request can cancel it
+        let msg = ExecuteMsg::CancelWithdrawRequest {
+            asset: asset.clone(),
+            request_id: 1,
+        };
+        let error_res =
+            execute(deps.as_mut(), env.clone(), mock_info("alice"), msg.clone()).unwrap_err();
+        assert_eq!(error_res, MarsError::Unauthorized {}.into());
+
+        let res = execute(deps.as_mut(), env.clone(), mock_info("bob"), msg.clone()).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "ma_somecoin".to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "bob".to_string(),
+                    amount: Uint128::new(400) * SCALING_FACTOR,
+                })
+                .unwrap(),
+                funds: vec![],
+            }))]
+        );
+        let error_res = execute(deps.as_mut(), env.clone(), mock_info("bob"), msg).unwrap_err();
         assert_eq!(
             error_res,
-            ContractError::MarketNotActive {
-                asset: "somecoin".to_string()
-            }
+            ContractError::WithdrawRequestNotFound { request_id: 1 }
         );
-    }
 
-    #[test]
-    fn test_cannot_borrow_if_market_not_enabled() {
-        let mut deps = th_setup(&[]);
+        // maTokens sent back from the queue go back to the user's collateral, their principal
+        // is left untouched
+        let principal_before = USER_PRINCIPALS
+            .load(&deps.storage, (b"somecoin", &Addr::unchecked("bob")))
+            .unwrap();
+        let msg = ExecuteMsg::FinalizeLiquidityTokenTransfer {
+            sender_address: Addr::unchecked(MOCK_CONTRACT_ADDR),
+            recipient_address: Addr::unchecked("bob"),
+            sender_previous_balance: Uint128::new(600) * SCALING_FACTOR,
+            recipient_previous_balance: Uint128::zero(),
+            amount: Uint128::new(400) * SCALING_FACTOR,
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("ma_somecoin"), msg).unwrap();
+        let market = MARKETS.load(&deps.storage, b"somecoin").unwrap();
+        let user = USERS.load(&deps.storage, &Addr::unchecked("bob")).unwrap();
+        assert!(get_bit(user.collateral_assets, market.index).unwrap());
+        assert!(USERS
+            .may_load(&deps.storage, &Addr::unchecked(MOCK_CONTRACT_ADDR))
+            .unwrap()
+            .is_none());
+        let principal_after = USER_PRINCIPALS
+            .load(&deps.storage, (b"somecoin", &Addr::unchecked("bob")))
+            .unwrap();
+        assert_eq!(principal_after, principal_before);
+
+        let res =
+            query_withdraw_queue(deps.as_ref(), env.clone(), asset.clone(), None, None).unwrap();
+        assert_eq!(res.requests.len(), 1);
+        assert_eq!(res.requests[0].id, 2);
+        assert_eq!(res.requests[0].amount_ahead, Uint128::zero());
+        let res = query_user_withdraw_requests(
+            deps.as_ref(),
+            env.clone(),
+            Addr::unchecked("bob"),
+            asset.clone(),
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(res.requests.is_empty());
 
-        let mock_market = Market {
-            ma_token_address: Addr::unchecked("ma_somecoin"),
-            asset_type: AssetType::Native,
-            active: true,
-            borrow_enabled: false,
-            ..Default::default()
+        // deposits trigger processing of the first requests in the pending queue
+        let info = cosmwasm_std::testing::mock_info("depositor", &[coin(500, "somecoin")]);
+        let msg = ExecuteMsg::DepositNative {
+            denom: String::from("somecoin"),
+            on_behalf_of: None,
         };
-        th_init_market(deps.as_mut(), b"somecoin", &mock_market);
+        let res = execute(deps.as_mut(), env.clone(), info, msg).unwrap();
+        assert_eq!(
+            res.messages.last().unwrap(),
+            &SubMsg::reply_on_error(
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: MOCK_CONTRACT_ADDR.to_string(),
+                    msg: to_binary(&ExecuteMsg::ProcessWithdrawQueue {
+                        asset,
+                        limit: Some(WITHDRAW_QUEUE_AUTO_PROCESS_LIMIT),
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                }),
+                PROCESS_WITHDRAW_QUEUE_REPLY_ID,
+            )
+        );
 
-        // Check error when borrowing not allowed on market
-        let env = mock_env(MockEnvParams::default());
-        let info = cosmwasm_std::testing::mock_info("borrower", &[coin(110000, "somecoin")]);
-        let msg = ExecuteMsg::Borrow {
-            asset: Asset::Native {
-                denom: "somecoin".to_string(),
+        // a processing error is swallowed, so the deposit is not reverted
+        let res = reply(
+            deps.as_mut(),
+            env,
+            Reply {
+                id: PROCESS_WITHDRAW_QUEUE_REPLY_ID,
+                result: ContractResult::Err("payout failed".to_string()),
             },
-            amount: Uint128::new(1000),
-            recipient: None,
-        };
-        let error_res = execute(deps.as_mut(), env.clone(), info.clone(), msg.clone()).unwrap_err();
+        )
+        .unwrap();
+        assert!(res.messages.is_empty());
         assert_eq!(
-            error_res,
-            ContractError::BorrowNotEnabled {
-                asset: "somecoin".to_string()
-            }
+            res.events,
+            vec![
+                Event::new("process_withdraw_queue_failed").add_attribute("error", "payout failed")
+            ]
         );
     }
 
@@ -6924,6 +9881,22 @@ mod tests {
                 .unwrap();
 
             let block_time = second_block_time;
+
+            // Collateral market has no outflow capacity left, liquidation goes through anyway
+            let exhausted_outflow_limit = OutflowLimit {
+                max_amount: Uint128::new(1_000),
+                window: 86_400,
+                current_outflow: Uint128::new(1_000),
+                last_updated: block_time,
+            };
+            OUTFLOW_LIMITS
+                .save(
+                    deps.as_mut().storage,
+                    b"collateral",
+                    &exhausted_outflow_limit,
+                )
+                .unwrap();
+
             let env = mock_env_at_block_time(block_time);
             let info = mock_info(cw20_debt_contract_addr.as_str());
             let res = execute(deps.as_mut(), env, info, liquidate_msg).unwrap();
@@ -6961,6 +9934,13 @@ mod tests {
             )
             .unwrap();
 
+            // Liquidated collateral is still accounted as an outflow
+            let outflow_limit = OUTFLOW_LIMITS.load(&deps.storage, b"collateral").unwrap();
+            assert_eq!(
+                outflow_limit.current_outflow,
+                exhausted_outflow_limit.current_outflow + expected_liquidated_collateral_amount
+            );
+
             let expected_collateral_rates = th_get_expected_indices_and_rates(
                 &collateral_market_before,
                 block_time,
@@ -8525,32 +11505,238 @@ mod tests {
         DEBTS
             .save(
                 deps.as_mut().storage,
-                (b"uncollateralized_debt", &healthy_user_address),
-                &uncollateralized_debt,
+                (b"uncollateralized_debt", &healthy_user_address),
+                &uncollateralized_debt,
+            )
+            .unwrap();
+
+        // perform liquidation (should fail because health factor is > 1)
+        let liquidator_address = Addr::unchecked("liquidator");
+        let debt_to_cover = Uint128::from(1_000_000u64);
+
+        let liquidate_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
+            msg: to_binary(&ReceiveMsg::LiquidateCw20 {
+                collateral_asset: Asset::Native {
+                    denom: "collateral".to_string(),
+                },
+                user_address: healthy_user_address.to_string(),
+                receive_ma_token: true,
+            })
+            .unwrap(),
+            sender: liquidator_address.to_string(),
+            amount: debt_to_cover,
+        });
+
+        let env = mock_env(MockEnvParams::default());
+        let info = mock_info(debt_contract_addr.as_str());
+        let error_res = execute(deps.as_mut(), env, info, liquidate_msg).unwrap_err();
+        assert_eq!(error_res, ContractError::CannotLiquidateHealthyPosition {});
+    }
+
+    #[test]
+    fn test_stale_prices() {
+        let mut deps = th_setup(&[coin(1_000_000_000, "collateral")]);
+        deps.querier.set_native_tax(
+            Decimal::zero(),
+            &[(String::from("collateral"), Uint128::zero())],
+        );
+
+        let debt_contract_addr = Addr::unchecked("debt");
+        deps.querier.set_cw20_balances(
+            debt_contract_addr.clone(),
+            &[(
+                Addr::unchecked(MOCK_CONTRACT_ADDR),
+                Uint128::new(2_000_000_000),
+            )],
+        );
+        deps.querier
+            .set_oracle_price(b"collateral".to_vec(), Decimal::one());
+        deps.querier
+            .set_oracle_price(b"debt".to_vec(), Decimal::one());
+
+        let collateral_market = Market {
+            ma_token_address: Addr::unchecked("collateral"),
+            max_loan_to_value: Decimal::percent(50),
+            liquidation_threshold: Decimal::percent(70),
+            liquidity_index: Decimal::one(),
+            borrow_index: Decimal::one(),
+            asset_type: AssetType::Native,
+            ..Default::default()
+        };
+        let debt_market = Market {
+            ma_token_address: Addr::unchecked("madebt"),
+            max_loan_to_value: Decimal::percent(60),
+            debt_total_scaled: Uint128::new(1_000_000) * SCALING_FACTOR,
+            liquidity_index: Decimal::one(),
+            borrow_index: Decimal::one(),
+            asset_type: AssetType::Cw20,
+            ..Default::default()
+        };
+        let collateral_market = th_init_market(deps.as_mut(), b"collateral", &collateral_market);
+        let debt_market =
+            th_init_market(deps.as_mut(), debt_contract_addr.as_bytes(), &debt_market);
+
+        // user has collateral and debt
+        let user_address = Addr::unchecked("user");
+        let mut user = User::default();
+        set_bit(&mut user.collateral_assets, collateral_market.index).unwrap();
+        set_bit(&mut user.borrowed_assets, debt_market.index).unwrap();
+        USERS
+            .save(deps.as_mut().storage, &user_address, &user)
+            .unwrap();
+        deps.querier.set_cw20_balances(
+            Addr::unchecked("collateral"),
+            &[(
+                user_address.clone(),
+                Uint128::new(10_000_000) * SCALING_FACTOR,
+            )],
+        );
+        DEBTS
+            .save(
+                deps.as_mut().storage,
+                (debt_contract_addr.as_bytes(), &user_address),
+                &Debt {
+                    amount_scaled: Uint128::new(1_000_000) * SCALING_FACTOR,
+                    uncollateralized: false,
+                },
             )
             .unwrap();
 
-        // perform liquidation (should fail because health factor is > 1)
-        let liquidator_address = Addr::unchecked("liquidator");
-        let debt_to_cover = Uint128::from(1_000_000u64);
+        let env = mock_env(MockEnvParams::default());
+        let block_time = env.block.time.seconds();
+
+        // collateral price is older than its max age
+        deps.querier
+            .set_oracle_price_metadata(b"collateral".to_vec(), block_time - 120, Some(60));
+        let stale_price_error = ContractError::StalePrice {
+            label: "collateral".to_string(),
+            timestamp: block_time - 120,
+            max_age: 60,
+        };
+
+        let borrow_msg = ExecuteMsg::Borrow {
+            asset: Asset::Cw20 {
+                contract_addr: debt_contract_addr.to_string(),
+            },
+            amount: Uint128::new(1_000),
+            recipient: None,
+        };
+        let error_res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(user_address.as_str()),
+            borrow_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(error_res, stale_price_error);
+
+        let withdraw_msg = ExecuteMsg::Withdraw {
+            asset: Asset::Native {
+                denom: "collateral".to_string(),
+            },
+            amount: Some(Uint128::new(1_000)),
+            recipient: None,
+        };
+        let error_res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(user_address.as_str()),
+            withdraw_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(error_res, stale_price_error);
 
         let liquidate_msg = ExecuteMsg::Receive(Cw20ReceiveMsg {
             msg: to_binary(&ReceiveMsg::LiquidateCw20 {
                 collateral_asset: Asset::Native {
                     denom: "collateral".to_string(),
                 },
-                user_address: healthy_user_address.to_string(),
+                user_address: user_address.to_string(),
                 receive_ma_token: true,
             })
             .unwrap(),
-            sender: liquidator_address.to_string(),
-            amount: debt_to_cover,
+            sender: "liquidator".to_string(),
+            amount: Uint128::new(1_000),
         });
+        let error_res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(debt_contract_addr.as_str()),
+            liquidate_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(error_res, stale_price_error);
 
-        let env = mock_env(MockEnvParams::default());
-        let info = mock_info(debt_contract_addr.as_str());
-        let error_res = execute(deps.as_mut(), env, info, liquidate_msg).unwrap_err();
+        // collateral cannot leave the account through an maToken transfer or by being disabled
+        let transfer_msg = ExecuteMsg::FinalizeLiquidityTokenTransfer {
+            sender_address: user_address.clone(),
+            recipient_address: Addr::unchecked("recipient"),
+            sender_previous_balance: Uint128::new(10_000_000) * SCALING_FACTOR,
+            recipient_previous_balance: Uint128::zero(),
+            amount: Uint128::new(1_000) * SCALING_FACTOR,
+        };
+        let error_res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("collateral"),
+            transfer_msg,
+        )
+        .unwrap_err();
+        assert_eq!(error_res, stale_price_error);
+
+        // the disabled collateral no longer counts, the prices of the rest of the position must
+        // not be stale
+        deps.querier
+            .set_oracle_price_metadata(b"debt".to_vec(), block_time - 120, Some(60));
+        let disable_collateral_msg = ExecuteMsg::UpdateAssetCollateralStatus {
+            asset: Asset::Native {
+                denom: "collateral".to_string(),
+            },
+            enable: false,
+        };
+        let error_res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(user_address.as_str()),
+            disable_collateral_msg,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error_res,
+            ContractError::StalePrice {
+                label: "debt".to_string(),
+                timestamp: block_time - 120,
+                max_age: 60,
+            }
+        );
+        deps.querier
+            .set_oracle_price_metadata(b"debt".to_vec(), block_time, None);
+
+        // price within its max age is used
+        deps.querier
+            .set_oracle_price_metadata(b"collateral".to_vec(), block_time - 60, Some(60));
+        let error_res = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(debt_contract_addr.as_str()),
+            liquidate_msg,
+        )
+        .unwrap_err();
         assert_eq!(error_res, ContractError::CannotLiquidateHealthyPosition {});
+        execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info(user_address.as_str()),
+            withdraw_msg,
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            env,
+            mock_info(user_address.as_str()),
+            borrow_msg,
+        )
+        .unwrap();
     }
 
     #[test]
@@ -8864,6 +12050,9 @@ mod tests {
             },
             user_address: existing_borrower_addr.to_string(),
             new_limit: initial_uncollateralized_loan_limit,
+            expiry: None,
+            interest_premium: None,
+            grace_period: None,
         };
         let update_limit_env = mock_env_at_block_time(block_time);
         let info = mock_info("owner");
@@ -8885,6 +12074,9 @@ mod tests {
             },
             user_address: borrower_addr.to_string(),
             new_limit: initial_uncollateralized_loan_limit,
+            expiry: None,
+            interest_premium: None,
+            grace_period: None,
         };
 
         // update limit as unauthorized user, should fail
@@ -9030,6 +12222,9 @@ mod tests {
             },
             user_address: borrower_addr.to_string(),
             new_limit: Uint128::zero(),
+            expiry: None,
+            interest_premium: None,
+            grace_period: None,
         };
         let allowance_env = mock_env_at_block_time(block_time);
         let info = mock_info("owner");
@@ -9048,6 +12243,277 @@ mod tests {
         assert!(!debt.uncollateralized);
     }
 
+    #[test]
+    fn test_credit_line_lifecycle() {
+        let available_liquidity = Uint128::from(2000000000u128);
+        let mut deps = th_setup(&[coin(available_liquidity.into(), "somecoin")]);
+
+        // Set tax data
+        deps.querier.set_native_tax(
+            Decimal::from_ratio(1u128, 100u128),
+            &[(String::from("somecoin"), Uint128::new(100u128))],
+        );
+
+        let mock_market = Market {
+            ma_token_address: Addr::unchecked("matoken"),
+            borrow_index: Decimal::from_ratio(12u128, 10u128),
+            liquidity_index: Decimal::from_ratio(8u128, 10u128),
+            borrow_rate: Decimal::from_ratio(20u128, 100u128),
+            liquidity_rate: Decimal::from_ratio(10u128, 100u128),
+            reserve_factor: Decimal::from_ratio(1u128, 10u128),
+            debt_total_scaled: Uint128::zero(),
+            indexes_last_updated: 10000000,
+            asset_type: AssetType::Native,
+            ..Default::default()
+        };
+        th_init_market(deps.as_mut(), b"somecoin", &mock_market);
+        let liquidity_total_scaled = Uint128::new(2_500_000_000) * SCALING_FACTOR;
+        deps.querier
+            .set_cw20_total_supply(Addr::unchecked("matoken"), liquidity_total_scaled);
+
+        let borrower_addr = Addr::unchecked("borrower");
+        let start_time = mock_market.indexes_last_updated + 1000u64;
+        let expiry = start_time + 10000u64;
+        let grace_period = 5000u64;
+        let interest_premium = Decimal::from_ratio(10u128, 100u128);
+
+        let update_limit_msg = |expiry: u64| ExecuteMsg::UpdateUncollateralizedLoanLimit {
+            asset: Asset::Native {
+                denom: "somecoin".to_string(),
+            },
+            user_address: borrower_addr.to_string(),
+            new_limit: Uint128::from(2400u128),
+            expiry: Some(expiry),
+            interest_premium: Some(interest_premium),
+            grace_period: Some(grace_period),
+        };
+
+        // expiry cannot be in the past
+        let err = execute(
+            deps.as_mut(),
+            mock_env_at_block_time(start_time),
+            mock_info("owner"),
+            update_limit_msg(start_time),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::InvalidCreditLineExpiry { expiry: start_time }
+        );
+
+        execute(
+            deps.as_mut(),
+            mock_env_at_block_time(start_time),
+            mock_info("owner"),
+            update_limit_msg(expiry),
+        )
+        .unwrap();
+
+        // borrow while the credit line is active
+        let borrow_msg = ExecuteMsg::Borrow {
+            asset: Asset::Native {
+                denom: "somecoin".to_string(),
+            },
+            amount: Uint128::from(1000u128),
+            recipient: None,
+        };
+        execute(
+            deps.as_mut(),
+            mock_env_at_block_time(start_time),
+            mock_info("borrower"),
+            borrow_msg.clone(),
+        )
+        .unwrap();
+
+        // credit line debt includes the pending interest premium
+        {
+            let query_time = expiry - 1;
+            let res = query_credit_line(
+                deps.as_ref(),
+                mock_env_at_block_time(query_time),
+                borrower_addr.clone(),
+                Asset::Native {
+                    denom: "somecoin".to_string(),
+                },
+            )
+            .unwrap();
+            assert_eq!(res.status, CreditLineStatus::Active);
+
+            let market = MARKETS.load(&deps.storage, b"somecoin").unwrap();
+            let debt = DEBTS
+                .load(&deps.storage, (b"somecoin", &borrower_addr))
+                .unwrap();
+            let debt_amount =
+                get_underlying_debt_amount(debt.amount_scaled, &market, query_time).unwrap();
+            let expected_premium =
+                compute_pending_interest_premium(&res.terms, debt_amount, query_time).unwrap();
+            assert!(!expected_premium.is_zero());
+            assert_eq!(res.debt_amount, debt_amount + expected_premium);
+        }
+
+        // cannot borrow once the credit line has expired
+        let err = execute(
+            deps.as_mut(),
+            mock_env_at_block_time(expiry),
+            mock_info("borrower"),
+            borrow_msg,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CreditLineNotActive {
+                status: CreditLineStatus::GracePeriod
+            }
+        );
+
+        let declare_default_msg = ExecuteMsg::DeclareDefault {
+            user_address: borrower_addr.to_string(),
+            asset: Asset::Native {
+                denom: "somecoin".to_string(),
+            },
+        };
+
+        // cannot declare default during grace period
+        let err = execute(
+            deps.as_mut(),
+            mock_env_at_block_time(expiry),
+            mock_info("owner"),
+            declare_default_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::CannotDeclareDefault {
+                status: CreditLineStatus::GracePeriod
+            }
+        );
+
+        // only owner can declare default
+        let default_time = expiry + grace_period;
+        let err = execute(
+            deps.as_mut(),
+            mock_env_at_block_time(default_time),
+            mock_info("random"),
+            declare_default_msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, MarsError::Unauthorized {}.into());
+
+        // the write down cannot take all of the market's liquidity
+        deps.querier.set_cw20_total_supply(
+            Addr::unchecked("matoken"),
+            Uint128::new(1_000) * SCALING_FACTOR,
+        );
+        let err = execute(
+            deps.as_mut(),
+            mock_env_at_block_time(default_time),
+            mock_info("owner"),
+            declare_default_msg.clone(),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::WriteDownExceedsLiquidity { .. }
+        ));
+        deps.querier
+            .set_cw20_total_supply(Addr::unchecked("matoken"), liquidity_total_scaled);
+
+        let (expected_written_down_amount, expected_liquidity_index) = {
+            let market = MARKETS.load(&deps.storage, b"somecoin").unwrap();
+            let debt = DEBTS
+                .load(&deps.storage, (b"somecoin", &borrower_addr))
+                .unwrap();
+            let terms = CREDIT_LINE_TERMS
+                .load(&deps.storage, (b"somecoin", &borrower_addr))
+                .unwrap();
+            let expected_indices = th_get_expected_indices(&market, default_time);
+            let debt_amount = compute_underlying_amount(
+                debt.amount_scaled,
+                expected_indices.borrow,
+                ScalingOperation::Ceil,
+            )
+            .unwrap();
+            let premium =
+                compute_pending_interest_premium(&terms, debt_amount, default_time).unwrap();
+            let premium_scaled =
+                compute_scaled_amount(premium, expected_indices.borrow, ScalingOperation::Ceil)
+                    .unwrap();
+            let written_down_amount = compute_underlying_amount(
+                debt.amount_scaled + premium_scaled,
+                expected_indices.borrow,
+                ScalingOperation::Ceil,
+            )
+            .unwrap();
+
+            // the premium goes to depositors (minus the reserve factor) through the liquidity
+            // index, then the written down amount is taken from them the same way
+            let distributed_premium = compute_underlying_amount(
+                premium_scaled,
+                expected_indices.borrow,
+                ScalingOperation::Truncate,
+            )
+            .unwrap();
+            let depositors_premium =
+                distributed_premium - distributed_premium * market.reserve_factor;
+            let liquidity_index = expected_indices.liquidity
+                + Decimal::from_ratio(depositors_premium * SCALING_FACTOR, liquidity_total_scaled)
+                - Decimal::from_ratio(written_down_amount * SCALING_FACTOR, liquidity_total_scaled);
+            (written_down_amount, liquidity_index)
+        };
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env_at_block_time(default_time),
+            mock_info("owner"),
+            declare_default_msg,
+        )
+        .unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "declare_default"),
+                attr("asset", "somecoin"),
+                attr("user", "borrower"),
+                attr(
+                    "written_down_amount",
+                    expected_written_down_amount.to_string()
+                ),
+            ]
+        );
+
+        // debt is written down and the credit line is closed
+        let debt = DEBTS
+            .load(&deps.storage, (b"somecoin", &borrower_addr))
+            .unwrap();
+        assert_eq!(debt.amount_scaled, Uint128::zero());
+        assert!(!debt.uncollateralized);
+
+        let market = MARKETS.load(&deps.storage, b"somecoin").unwrap();
+        assert_eq!(market.debt_total_scaled, Uint128::zero());
+
+        // the loss is shared by depositors through the liquidity index
+        assert_eq!(market.liquidity_index, expected_liquidity_index);
+        assert!(
+            market.liquidity_index < th_get_expected_indices(&mock_market, default_time).liquidity
+        );
+
+        let user = USERS.load(&deps.storage, &borrower_addr).unwrap();
+        assert!(!get_bit(user.borrowed_assets, 0).unwrap());
+
+        let res = query_credit_line(
+            deps.as_ref(),
+            mock_env_at_block_time(default_time),
+            borrower_addr,
+            Asset::Native {
+                denom: "somecoin".to_string(),
+            },
+        )
+        .unwrap();
+        assert_eq!(res.limit, Uint128::zero());
+        assert_eq!(res.status, CreditLineStatus::Defaulted);
+        assert_eq!(res.debt_amount, Uint128::zero());
+    }
+
     #[test]
     fn test_update_asset_collateral() {
         let mut deps = th_setup(&[]);
@@ -9199,7 +12665,7 @@ mod tests {
             );
 
             // Calculate maximum debt for the user to have valid health factor
-            let token_1_weighted_lt_in_uusd = compute_underlying_amount(
+            let token_1_weighted_lt_in_base_asset = compute_underlying_amount(
                 ma_token_1_balance_scaled,
                 get_updated_liquidity_index(&market_1_initial, env.block.time.seconds()).unwrap(),
                 ScalingOperation::Truncate,
@@ -9207,7 +12673,7 @@ mod tests {
             .unwrap()
                 * market_1_initial.liquidation_threshold
                 * token_1_exchange_rate;
-            let token_2_weighted_lt_in_uusd = compute_underlying_amount(
+            let token_2_weighted_lt_in_base_asset = compute_underlying_amount(
                 ma_token_2_balance_scaled,
                 get_updated_liquidity_index(&market_2_initial, env.block.time.seconds()).unwrap(),
                 ScalingOperation::Truncate,
@@ -9215,10 +12681,10 @@ mod tests {
             .unwrap()
                 * market_2_initial.liquidation_threshold
                 * token_2_exchange_rate;
-            let weighted_liquidation_threshold_in_uusd =
-                token_1_weighted_lt_in_uusd + token_2_weighted_lt_in_uusd;
+            let weighted_liquidation_threshold_in_base_asset =
+                token_1_weighted_lt_in_base_asset + token_2_weighted_lt_in_base_asset;
             let max_debt_for_valid_hf = Decimal::divide_uint128_by_decimal(
-                weighted_liquidation_threshold_in_uusd,
+                weighted_liquidation_threshold_in_base_asset,
                 token_3_exchange_rate,
             )
             .unwrap();
@@ -9573,6 +13039,80 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_migrate() {
+        let mut deps = th_setup(&[]);
+        let market = th_init_market(deps.as_mut(), b"uusd", &Market::default());
+
+        // state written by 1.0.0 has none of the fields added in 1.1.0
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "1.0.0").unwrap();
+        let market_key = MARKETS.key(b"uusd").to_vec();
+        let new_fields = [
+            (
+                b"config".to_vec(),
+                r#","market_snapshot_interval":0,"market_snapshot_max_count":0"#,
+            ),
+            (b"global_state".to_vec(), r#","paused":false"#),
+            (
+                market_key.clone(),
+                r#","frozen":false,"paused":false,"risk_params_ramp":null"#,
+            ),
+        ];
+        for (key, fields) in &new_fields {
+            let value = String::from_utf8(deps.storage.get(key).unwrap()).unwrap();
+            assert!(value.contains(fields));
+            deps.storage.set(key, value.replace(fields, "").as_bytes());
+        }
+
+        let res = migrate(
+            deps.as_mut(),
+            mock_env(MockEnvParams::default()),
+            MigrateMsg {},
+        )
+        .unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "migrate"),
+                attr("previous_version", "1.0.0"),
+                attr("new_version", CONTRACT_VERSION),
+                attr("migration_steps", "1.1.0"),
+            ]
+        );
+
+        // new fields are stored with their default values
+        for (key, fields) in &new_fields {
+            let value = String::from_utf8(deps.storage.get(key).unwrap()).unwrap();
+            assert!(value.contains(fields));
+        }
+        assert_eq!(MARKETS.load(&deps.storage, b"uusd").unwrap(), market);
+
+        // steps are not run again when migrating from the current version
+        let res = migrate(
+            deps.as_mut(),
+            mock_env(MockEnvParams::default()),
+            MigrateMsg {},
+        )
+        .unwrap();
+        assert_eq!(res.attributes[3], attr("migration_steps", ""));
+
+        // cannot migrate to an older version
+        set_contract_version(&mut deps.storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let error_res = migrate(
+            deps.as_mut(),
+            mock_env(MockEnvParams::default()),
+            MigrateMsg {},
+        )
+        .unwrap_err();
+        assert_eq!(
+            error_res,
+            ContractError::Mars(MarsError::MigrateDowngrade {
+                stored_version: "99.0.0".to_string(),
+                version: CONTRACT_VERSION.to_string(),
+            })
+        );
+    }
+
     // TEST HELPERS
 
     fn th_setup(contract_balances: &[Coin]) -> OwnedDeps<MockStorage, MockApi, MarsMockQuerier> {
@@ -9584,6 +13124,8 @@ mod tests {
             address_provider_address: Some("address_provider".to_string()),
             ma_token_code_id: Some(1u64),
             close_factor: Some(Decimal::from_ratio(1u128, 2u128)),
+            market_snapshot_interval: None,
+            market_snapshot_max_count: None,
         };
         let msg = InstantiateMsg { config };
         instantiate(deps.as_mut(), env, info, msg).unwrap();
@@ -1,11 +1,11 @@
 use thiserror::Error;
 
-use cosmwasm_std::{OverflowError, StdError};
+use cosmwasm_std::{OverflowError, StdError, Uint128};
 
 use mars_core::error::MarsError;
 
 use crate::interest_rate_models::InterestRateModelError;
-use crate::MarketError;
+use crate::{CreditLineStatus, MarketError};
 
 #[derive(Error, Debug, PartialEq)]
 pub enum ContractError {
@@ -27,6 +27,13 @@ pub enum ContractError {
     #[error("Price not found for asset: {label:?}")]
     PriceNotFound { label: String },
 
+    #[error("Price for asset {label:?} is stale (timestamp: {timestamp}, max age: {max_age})")]
+    StalePrice {
+        label: String,
+        timestamp: u64,
+        max_age: u64,
+    },
+
     #[error("User has no balance (asset: {asset:?})")]
     UserNoBalance { asset: String },
 
@@ -122,6 +129,28 @@ pub enum ContractError {
     #[error("Cannot perform operation. Market for {asset:?} is not active")]
     MarketNotActive { asset: String },
 
+    #[error("Only maTokens can be sent to the withdraw queue")]
+    InvalidMaToken {},
+
+    #[error("Withdraw request {request_id} not found")]
+    WithdrawRequestNotFound { request_id: u64 },
+
+    #[error("Outflow limit for {asset:?} exceeded. Amount: {amount}, remaining capacity: {remaining_capacity}")]
+    OutflowLimitExceeded {
+        asset: String,
+        amount: Uint128,
+        remaining_capacity: Uint128,
+    },
+
+    #[error("Cannot perform operation. Market for {asset:?} is paused")]
+    MarketPaused { asset: String },
+
+    #[error("Cannot perform operation. Market for {asset:?} is frozen")]
+    MarketFrozen { asset: String },
+
+    #[error("Cannot lower liquidation threshold for {asset:?} while market is frozen")]
+    CannotLowerLiquidationThresholdWhenFrozen { asset: String },
+
     #[error("Cannot liquidate. Debt asset {asset:?}")]
     LiquidationNotAllowedWhenDebtMarketInactive { asset: String },
 
@@ -136,6 +165,23 @@ pub enum ContractError {
 
     #[error("Cannot repay uncollateralized loan on behalf of another user")]
     CannotRepayUncollateralizedLoanOnBehalfOf {},
+
+    #[error("Credit line expiry should be later than the current block time, got {expiry:?}")]
+    InvalidCreditLineExpiry { expiry: u64 },
+
+    #[error("Cannot borrow from credit line with status {status:?}")]
+    CreditLineNotActive { status: CreditLineStatus },
+
+    #[error("No credit line found for user and asset")]
+    CreditLineNotFound {},
+
+    #[error("Cannot declare default on credit line with status {status:?}")]
+    CannotDeclareDefault { status: CreditLineStatus },
+
+    #[error(
+        "Cannot write down {written_down_amount} as it would take all of the market's liquidity"
+    )]
+    WriteDownExceedsLiquidity { written_down_amount: Uint128 },
 }
 
 impl ContractError {
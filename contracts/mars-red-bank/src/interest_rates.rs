@@ -1,18 +1,21 @@
 use std::str;
 
 use cosmwasm_std::{
-    to_binary, Addr, CosmosMsg, DepsMut, Env, Event, Response, StdError, StdResult, Uint128,
-    WasmMsg,
+    to_binary, Addr, CosmosMsg, DepsMut, Env, Event, Order, QuerierWrapper, Response, StdError,
+    StdResult, Storage, Uint128, WasmMsg,
 };
 use cw20::Cw20ExecuteMsg;
+use cw_storage_plus::{Bound, U64Key};
 
 use mars_core::asset::get_asset_balance;
+use mars_core::helpers::cw20_get_total_supply;
 use mars_core::math::decimal::Decimal;
 use mars_core::math::uint128_checked_div_with_ceil;
 
 use crate::error::ContractError;
 use crate::interest_rate_models::update_market_interest_rates_with_model;
-use crate::Market;
+use crate::state::{CONFIG, MARKET_SNAPSHOTS};
+use crate::{CreditLineTerms, Debt, Market, MarketSnapshot};
 
 /// Scaling factor used to keep more precision during division / multiplication by index.
 pub const SCALING_FACTOR: Uint128 = Uint128::new(1_000_000);
@@ -33,7 +36,7 @@ pub fn apply_accumulated_interests(
     env: &Env,
     protocol_rewards_collector_address: Addr,
     market: &mut Market,
-    mut response: Response,
+    response: Response,
 ) -> StdResult<Response> {
     let current_timestamp = env.block.time.seconds();
     let previous_borrow_index = market.borrow_index;
@@ -82,12 +85,24 @@ pub fn apply_accumulated_interests(
 
     let accrued_protocol_rewards = borrow_interest_accrued * market.reserve_factor;
 
-    if accrued_protocol_rewards > Uint128::zero() {
-        let mint_amount = compute_scaled_amount(
-            accrued_protocol_rewards,
-            market.liquidity_index,
-            ScalingOperation::Truncate,
-        )?;
+    mint_protocol_rewards(
+        protocol_rewards_collector_address,
+        market,
+        accrued_protocol_rewards,
+        response,
+    )
+}
+
+/// Mints maTokens worth the given underlying amount to the protocol rewards collector
+fn mint_protocol_rewards(
+    protocol_rewards_collector_address: Addr,
+    market: &Market,
+    amount: Uint128,
+    mut response: Response,
+) -> StdResult<Response> {
+    if amount > Uint128::zero() {
+        let mint_amount =
+            compute_scaled_amount(amount, market.liquidity_index, ScalingOperation::Truncate)?;
         response = response.add_message(CosmosMsg::Wasm(WasmMsg::Execute {
             contract_addr: market.ma_token_address.clone().into(),
             msg: to_binary(&Cw20ExecuteMsg::Mint {
@@ -112,6 +127,95 @@ pub fn calculate_applied_linear_interest_rate(
     index.checked_mul(Decimal::one() + rate_factor)
 }
 
+/// Computes the interest premium a credit line's debt accrued on top of the market's borrow
+/// rate between the last time it was added to the debt and the given timestamp.
+/// Premium amounts are always ceiled to make sure rounding errors accumulate in favor of
+/// the protocol
+pub fn compute_pending_interest_premium(
+    terms: &CreditLineTerms,
+    debt_amount: Uint128,
+    timestamp: u64,
+) -> StdResult<Uint128> {
+    if terms.defaulted
+        || terms.interest_premium.is_zero()
+        || terms.premium_last_accrued >= timestamp
+    {
+        return Ok(Uint128::zero());
+    }
+
+    let time_elapsed = timestamp - terms.premium_last_accrued;
+    let premium_factor = terms.interest_premium.checked_mul(Decimal::from_ratio(
+        Uint128::from(time_elapsed),
+        Uint128::from(SECONDS_PER_YEAR),
+    ))?;
+    Decimal::multiply_uint128_by_decimal_and_ceil(debt_amount, premium_factor)
+}
+
+/// Adds the pending interest premium to the user's debt and sets the credit line's premium
+/// accrual timestamp to the given one.
+/// Returns the scaled amount added to the debt, which the caller should also add to the
+/// market's total debt and distribute (see `distribute_interest_premium`) once accumulated
+/// interests have been applied to the market.
+/// NOTE: it does not save the terms nor the debt to store
+pub fn apply_accrued_interest_premium(
+    terms: &mut CreditLineTerms,
+    market: &Market,
+    debt: &mut Debt,
+    timestamp: u64,
+) -> StdResult<Uint128> {
+    let debt_amount = get_underlying_debt_amount(debt.amount_scaled, market, timestamp)?;
+    let premium = compute_pending_interest_premium(terms, debt_amount, timestamp)?;
+    let premium_scaled = get_scaled_debt_amount(premium, market, timestamp)?;
+
+    debt.amount_scaled = debt.amount_scaled.checked_add(premium_scaled)?;
+    terms.premium_last_accrued = timestamp;
+
+    Ok(premium_scaled)
+}
+
+/// Distributes an interest premium added to the market's debt like regular interest: the reserve
+/// factor share is minted as maTokens to the protocol rewards collector and the rest increases
+/// the liquidity index, so depositors own the premium once it gets repaid.
+/// NOTE: accumulated interests must have been applied to the market for the current block
+/// NOTE: it does not save the market to store
+pub fn distribute_interest_premium(
+    querier: &QuerierWrapper,
+    protocol_rewards_collector_address: Addr,
+    market: &mut Market,
+    interest_premium_scaled: Uint128,
+    response: Response,
+) -> StdResult<Response> {
+    if interest_premium_scaled.is_zero() {
+        return Ok(response);
+    }
+
+    let interest_premium = compute_underlying_amount(
+        interest_premium_scaled,
+        market.borrow_index,
+        ScalingOperation::Truncate,
+    )?;
+    let mut protocol_rewards = interest_premium * market.reserve_factor;
+    let depositors_share = interest_premium.checked_sub(protocol_rewards)?;
+
+    let liquidity_total_scaled = cw20_get_total_supply(querier, market.ma_token_address.clone())?;
+    if liquidity_total_scaled.is_zero() {
+        protocol_rewards = interest_premium;
+    } else {
+        market.liquidity_index = market.liquidity_index
+            + Decimal::from_ratio(
+                depositors_share.checked_mul(SCALING_FACTOR)?,
+                liquidity_total_scaled,
+            );
+    }
+
+    mint_protocol_rewards(
+        protocol_rewards_collector_address,
+        market,
+        protocol_rewards,
+        response,
+    )
+}
+
 /// Get scaled liquidity amount from an underlying amount, a Market and timestamp in seconds
 /// Liquidity amounts are always truncated to make sure rounding errors accumulate in favor of
 /// the protocol
@@ -276,12 +380,13 @@ pub fn get_updated_liquidity_index(market: &Market, timestamp: u64) -> StdResult
 }
 
 /// Update interest rates for current liquidity and debt levels
-/// Note it does not save the market to the store (that is left to the caller)
+/// Note it does not save the market to the store (that is left to the caller) but it does
+/// store a market snapshot if the configured interval has passed since the last one
 /// Returns response with appended interest rates updated event
 /// NOTE: For a given block, interest rates should not be updated before updating indexes first
 /// as it should result in wrong indexes
 pub fn update_interest_rates(
-    deps: &DepsMut,
+    deps: &mut DepsMut,
     env: &Env,
     market: &mut Market,
     liquidity_taken: Uint128,
@@ -310,10 +415,89 @@ pub fn update_interest_rates(
 
     update_market_interest_rates_with_model(env, market, current_utilization_rate)?;
 
+    take_market_snapshot(
+        deps.storage,
+        env.block.time.seconds(),
+        asset_label.as_bytes(),
+        market,
+        total_debt,
+        available_liquidity,
+    )?;
+
     response = response.add_event(build_interests_updated_event(asset_label, market));
     Ok(response)
 }
 
+/// Max number of expired snapshots removed on each new snapshot. As at most one snapshot
+/// is added per interval, this keeps storage bounded even after lowering the max count
+const MARKET_SNAPSHOT_MAX_PRUNED: usize = 2;
+
+/// Stores the market's indexes, rates and totals if at least the configured interval has
+/// passed since the last snapshot, pruning snapshots older than the retention period
+/// (interval * max count)
+fn take_market_snapshot(
+    storage: &mut dyn Storage,
+    block_time: u64,
+    asset_reference: &[u8],
+    market: &Market,
+    debt_total: Uint128,
+    available_liquidity: Uint128,
+) -> StdResult<()> {
+    let config = CONFIG.load(storage)?;
+    if config.market_snapshot_interval == 0 {
+        return Ok(());
+    }
+
+    let last_snapshot_time = MARKET_SNAPSHOTS
+        .prefix(asset_reference)
+        .range(storage, None, None, Order::Descending)
+        .next()
+        .transpose()?
+        .map(|(_, snapshot)| snapshot.timestamp);
+    if let Some(last_snapshot_time) = last_snapshot_time {
+        if block_time < last_snapshot_time.saturating_add(config.market_snapshot_interval) {
+            return Ok(());
+        }
+    }
+
+    MARKET_SNAPSHOTS.save(
+        storage,
+        (asset_reference, U64Key::new(block_time)),
+        &MarketSnapshot {
+            timestamp: block_time,
+            borrow_index: market.borrow_index,
+            liquidity_index: market.liquidity_index,
+            borrow_rate: market.borrow_rate,
+            liquidity_rate: market.liquidity_rate,
+            debt_total,
+            available_liquidity,
+        },
+    )?;
+
+    // large configured values must not make interest updates fail
+    let retention_period = config
+        .market_snapshot_interval
+        .saturating_mul(config.market_snapshot_max_count as u64);
+    let expired_timestamps = MARKET_SNAPSHOTS
+        .prefix(asset_reference)
+        .range(
+            storage,
+            None,
+            Some(Bound::exclusive(U64Key::new(
+                block_time.saturating_sub(retention_period),
+            ))),
+            Order::Ascending,
+        )
+        .take(MARKET_SNAPSHOT_MAX_PRUNED)
+        .map(|item| item.map(|(_, snapshot)| snapshot.timestamp))
+        .collect::<StdResult<Vec<_>>>()?;
+    for timestamp in expired_timestamps {
+        MARKET_SNAPSHOTS.remove(storage, (asset_reference, U64Key::new(timestamp)));
+    }
+
+    Ok(())
+}
+
 pub fn build_interests_updated_event(label: &str, market: &Market) -> Event {
     Event::new("interests_updated")
         .add_attribute("asset", label)
@@ -329,9 +513,12 @@ mod tests {
     use mars_core::math::decimal::Decimal;
     use mars_core::red_bank::Market;
 
+    use mars_core::red_bank::CreditLineTerms;
+
     use crate::interest_rates::{
-        calculate_applied_linear_interest_rate, get_scaled_debt_amount,
-        get_scaled_liquidity_amount, get_underlying_debt_amount, get_underlying_liquidity_amount,
+        calculate_applied_linear_interest_rate, compute_pending_interest_premium,
+        get_scaled_debt_amount, get_scaled_liquidity_amount, get_underlying_debt_amount,
+        get_underlying_liquidity_amount,
     };
 
     #[test]
@@ -345,6 +532,30 @@ mod tests {
         assert_eq!(accumulated, Decimal::from_ratio(11u128, 100u128));
     }
 
+    #[test]
+    fn test_pending_interest_premium_calculation() {
+        let terms = CreditLineTerms {
+            interest_premium: Decimal::from_ratio(5u128, 100u128),
+            ..CreditLineTerms::perpetual(1_000)
+        };
+        let debt_amount = Uint128::from(1_000_000u128);
+
+        // no time elapsed
+        let premium = compute_pending_interest_premium(&terms, debt_amount, 1_000).unwrap();
+        assert_eq!(premium, Uint128::zero());
+
+        // half a year
+        let premium =
+            compute_pending_interest_premium(&terms, debt_amount, 1_000 + 15768000).unwrap();
+        assert_eq!(premium, Uint128::from(25_000u128));
+
+        // no premium
+        let terms = CreditLineTerms::perpetual(1_000);
+        let premium =
+            compute_pending_interest_premium(&terms, debt_amount, 1_000 + 15768000).unwrap();
+        assert_eq!(premium, Uint128::zero());
+    }
+
     #[test]
     fn test_liquidity_and_debt_rounding() {
         let start = Uint128::from(100_000_000_000_u128);
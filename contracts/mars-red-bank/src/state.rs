@@ -1,7 +1,10 @@
-use cosmwasm_std::{Addr, Uint128};
-use cw_storage_plus::{Item, Map, U32Key};
+use cosmwasm_std::{Addr, Empty, Uint128};
+use cw_storage_plus::{Item, Map, U32Key, U64Key};
 
-use crate::{Config, Debt, GlobalState, Market, User};
+use crate::{
+    Config, CreditLineTerms, Debt, GlobalState, Market, MarketSnapshot, OutflowLimit, User,
+    UserPrincipal, WithdrawRequest,
+};
 
 pub const CONFIG: Item<Config> = Item::new("config");
 pub const GLOBAL_STATE: Item<GlobalState> = Item::new("global_state");
@@ -11,7 +14,19 @@ pub const USERS: Map<&Addr, User> = Map::new("users");
 pub const MARKETS: Map<&[u8], Market> = Map::new("markets");
 pub const MARKET_REFERENCES_BY_INDEX: Map<U32Key, Vec<u8>> = Map::new("market_refs_by_index");
 pub const MARKET_REFERENCES_BY_MA_TOKEN: Map<&Addr, Vec<u8>> = Map::new("market_refs_by_ma_token");
+pub const MARKET_SNAPSHOTS: Map<(&[u8], U64Key), MarketSnapshot> = Map::new("market_snapshots");
 
 pub const DEBTS: Map<(&[u8], &Addr), Debt> = Map::new("debts");
+pub const USER_PRINCIPALS: Map<(&[u8], &Addr), UserPrincipal> = Map::new("user_principals");
 pub const UNCOLLATERALIZED_LOAN_LIMITS: Map<(&[u8], &Addr), Uint128> =
     Map::new("uncollateralized_loan_limits");
+pub const CREDIT_LINE_TERMS: Map<(&[u8], &Addr), CreditLineTerms> = Map::new("credit_line_terms");
+
+pub const OUTFLOW_LIMITS: Map<&[u8], OutflowLimit> = Map::new("outflow_limits");
+
+pub const WITHDRAW_REQUEST_COUNT: Item<u64> = Item::new("withdraw_request_count");
+pub const WITHDRAW_REQUESTS: Map<(&[u8], U64Key), WithdrawRequest> = Map::new("withdraw_requests");
+pub const USER_WITHDRAW_REQUESTS: Map<((&[u8], &Addr), U64Key), Empty> =
+    Map::new("user_withdraw_requests");
+/// Total maToken amount (scaled) ever queued per market, used as the next request's offset
+pub const WITHDRAW_QUEUE_OFFSETS: Map<&[u8], Uint128> = Map::new("withdraw_queue_offsets");
@@ -3,13 +3,19 @@ use cosmwasm_std::entry_point;
 use cosmwasm_std::{
     to_binary, Binary, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Response, StdResult,
 };
+use cw2::set_contract_version;
 
-use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
+use crate::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
 use crate::state::CONFIG;
 use crate::Config;
 
 use mars_core::error::MarsError;
 use mars_core::helpers::option_string_to_addr;
+use mars_core::versioning::{migrate_contract, MigrationStep};
+
+// version info for migration info
+const CONTRACT_NAME: &str = "crates.io:safety-fund";
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 // INIT
 
@@ -20,6 +26,8 @@ pub fn instantiate(
     _info: MessageInfo,
     msg: InstantiateMsg,
 ) -> StdResult<Response> {
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
     // initialize Config
     let config = Config {
         owner: deps.api.addr_validate(&msg.owner)?,
@@ -102,6 +110,25 @@ fn query_config(deps: Deps) -> StdResult<Config> {
     Ok(config)
 }
 
+// MIGRATION
+
+/// State migrations, run when migrating from a version older than theirs
+const MIGRATION_STEPS: &[MigrationStep] = &[];
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> StdResult<Response> {
+    let attributes = migrate_contract(
+        deps.storage,
+        CONTRACT_NAME,
+        CONTRACT_VERSION,
+        MIGRATION_STEPS,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attributes(attributes))
+}
+
 // TESTS
 
 #[cfg(test)]
@@ -3,7 +3,9 @@ use std::fs::create_dir_all;
 
 use cosmwasm_schema::{export_schema, remove_schemas, schema_for};
 
-use mars_staking::msg::{ExecuteMsg, InstantiateMsg, QueryMsg, ReceiveMsg};
+use mars_core::swapping::SwapRoute;
+
+use mars_staking::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, ReceiveMsg};
 use mars_staking::{ClaimResponse, Config, GlobalState};
 
 fn main() {
@@ -16,8 +18,10 @@ fn main() {
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(ReceiveMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
 
     export_schema(&schema_for!(Config), &out_dir);
     export_schema(&schema_for!(GlobalState), &out_dir);
     export_schema(&schema_for!(ClaimResponse), &out_dir);
+    export_schema(&schema_for!(SwapRoute), &out_dir);
 }
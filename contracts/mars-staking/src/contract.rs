@@ -4,6 +4,7 @@ use cosmwasm_std::{
     from_binary, to_binary, Addr, Binary, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Order,
     Response, StdError, StdResult, Storage, Uint128, WasmMsg,
 };
+use cw2::set_contract_version;
 use cw20::{Cw20ExecuteMsg, Cw20ReceiveMsg};
 use cw_storage_plus::{Bound, U64Key};
 
@@ -14,15 +15,22 @@ use mars_core::helpers::{
     cw20_get_balance, cw20_get_total_supply, option_string_to_addr, zero_address,
 };
 use mars_core::math::decimal::Decimal;
-use mars_core::swapping::execute_swap;
+use mars_core::swapping::{execute_swap, swap_route_key, OracleDeviation, SwapRoute, SwapSlippage};
+use mars_core::versioning::{migrate_contract, MigrationStep, Version};
 
 use mars_core::address_provider::{self, MarsContract};
 
 use crate::error::ContractError;
-use crate::msg::{CreateOrUpdateConfig, ExecuteMsg, InstantiateMsg, QueryMsg, ReceiveMsg};
-use crate::state::{CLAIMS, CONFIG, GLOBAL_STATE, SLASH_EVENTS};
+use crate::msg::{
+    CreateOrUpdateConfig, ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, ReceiveMsg,
+};
+use crate::state::{CLAIMS, CONFIG, GLOBAL_STATE, SLASH_EVENTS, SWAP_ROUTES};
 use crate::{Claim, ClaimResponse, Config, GlobalState, SlashEvent};
 
+// version info for migration info
+const CONTRACT_NAME: &str = "crates.io:staking";
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 // INSTANTIATE
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -32,6 +40,8 @@ pub fn instantiate(
     _info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
     // Destructuring a struct’s fields into separate variables in order to force
     // compile error if we add more params
     let CreateOrUpdateConfig {
@@ -40,6 +50,8 @@ pub fn instantiate(
         address_provider_address,
         astroport_factory_address,
         astroport_max_spread,
+        swap_max_oracle_deviation,
+        swap_venue,
     } = msg.config;
 
     // All fields should be available
@@ -68,8 +80,16 @@ pub fn instantiate(
             zero_address(),
         )?,
         astroport_max_spread: astroport_max_spread.unwrap(),
+        swap_max_oracle_deviation,
+        swap_venue: swap_venue
+            .map(|swap_venue| swap_venue.to_checked(deps.api))
+            .transpose()?
+            .unwrap_or_default(),
+        paused: false,
     };
 
+    config.validate()?;
+
     CONFIG.save(deps.storage, &config)?;
 
     // Initialize global state
@@ -103,7 +123,28 @@ pub fn execute(
             Ok(execute_transfer_mars(deps, env, info, recipient, amount)?)
         }
 
-        ExecuteMsg::SwapUusdToMars { amount } => Ok(execute_swap_uusd_to_mars(deps, env, amount)?),
+        ExecuteMsg::SwapUusdToMars {
+            amount,
+            minimum_receive,
+        } => Ok(execute_swap_uusd_to_mars(
+            deps,
+            env,
+            amount,
+            minimum_receive,
+        )?),
+
+        ExecuteMsg::SetSwapRoute {
+            offer_asset_info,
+            hops,
+        } => Ok(execute_set_swap_route(deps, info, offer_asset_info, hops)?),
+
+        ExecuteMsg::SetSwapMaxOracleDeviation { max_deviation } => Ok(
+            execute_set_swap_max_oracle_deviation(deps, info, max_deviation)?,
+        ),
+
+        ExecuteMsg::Pause {} => Ok(execute_pause(deps, info)?),
+
+        ExecuteMsg::Unpause {} => Ok(execute_unpause(deps, info)?),
     }
 }
 
@@ -143,6 +184,8 @@ pub fn execute_update_config(
         address_provider_address,
         astroport_factory_address,
         astroport_max_spread,
+        swap_max_oracle_deviation,
+        swap_venue,
     } = new_config;
 
     // Update config
@@ -158,8 +201,16 @@ pub fn execute_update_config(
         config.astroport_factory_address,
     )?;
     config.astroport_max_spread = astroport_max_spread.unwrap_or(config.astroport_max_spread);
+    if swap_max_oracle_deviation.is_some() {
+        config.swap_max_oracle_deviation = swap_max_oracle_deviation;
+    }
+    if let Some(swap_venue) = swap_venue {
+        config.swap_venue = swap_venue.to_checked(deps.api)?;
+    }
     config.cooldown_duration = cooldown_duration.unwrap_or(config.cooldown_duration);
 
+    config.validate()?;
+
     CONFIG.save(deps.storage, &config)?;
 
     let res = Response::new().add_attribute("action", "update_config");
@@ -412,36 +463,136 @@ pub fn execute_swap_uusd_to_mars(
     deps: DepsMut,
     env: Env,
     amount: Option<Uint128>,
+    minimum_receive: Option<Uint128>,
 ) -> Result<Response, MarsError> {
     let config = CONFIG.load(deps.storage)?;
 
+    if config.paused {
+        return Err(MarsError::Paused {});
+    }
+
     let offer_asset_info = AssetInfo::NativeToken {
         denom: "uusd".to_string(),
     };
 
-    let mars_token_address = address_provider::helpers::query_address(
-        &deps.querier,
-        config.address_provider_address,
-        MarsContract::MarsToken,
-    )?;
-
-    let ask_asset_info = AssetInfo::Token {
-        contract_addr: mars_token_address,
+    let ask_asset_info = mars_asset_info(deps.as_ref(), &config)?;
+
+    let route = SWAP_ROUTES
+        .may_load(deps.storage, &swap_route_key(&offer_asset_info))?
+        .unwrap_or_else(|| SwapRoute::direct(offer_asset_info));
+    let slippage = SwapSlippage {
+        max_spread: Some(config.astroport_max_spread),
+        minimum_receive,
+        max_oracle_deviation: config.swap_max_oracle_deviation.map(|max_deviation| {
+            OracleDeviation {
+                address_provider_address: config.address_provider_address.clone(),
+                max_deviation,
+            }
+        }),
     };
-
-    let astroport_max_spread = Some(config.astroport_max_spread);
+    let swap_adapter = config.swap_venue.adapter(config.astroport_factory_address);
 
     Ok(execute_swap(
         deps,
         env,
-        offer_asset_info,
+        route,
         ask_asset_info,
         amount,
-        config.astroport_factory_address,
-        astroport_max_spread,
+        swap_adapter.as_ref(),
+        slippage,
     )?)
 }
 
+/// Set the route used to swap an asset to Mars
+pub fn execute_set_swap_route(
+    deps: DepsMut,
+    info: MessageInfo,
+    offer_asset_info: AssetInfo,
+    hops: Vec<AssetInfo>,
+) -> Result<Response, MarsError> {
+    let config = CONFIG.load(deps.storage)?;
+
+    if info.sender != config.owner {
+        return Err(MarsError::Unauthorized {});
+    }
+
+    let key = swap_route_key(&offer_asset_info);
+    let route = SwapRoute {
+        offer_asset_info,
+        hops,
+    };
+    route.validate(&mars_asset_info(deps.as_ref(), &config)?)?;
+
+    if route.hops.is_empty() {
+        SWAP_ROUTES.remove(deps.storage, &key);
+    } else {
+        SWAP_ROUTES.save(deps.storage, &key, &route)?;
+    }
+
+    let hops: Vec<String> = route.hops.iter().map(|hop| hop.to_string()).collect();
+    let res = Response::new()
+        .add_attribute("action", "set_swap_route")
+        .add_attribute("offer_asset", route.offer_asset_info.to_string())
+        .add_attribute("route", hops.join(","));
+    Ok(res)
+}
+
+pub fn execute_set_swap_max_oracle_deviation(
+    deps: DepsMut,
+    info: MessageInfo,
+    max_deviation: Option<Decimal>,
+) -> Result<Response, MarsError> {
+    let mut config = CONFIG.load(deps.storage)?;
+
+    if info.sender != config.owner {
+        return Err(MarsError::Unauthorized {});
+    }
+
+    config.swap_max_oracle_deviation = max_deviation;
+    config.validate()?;
+
+    CONFIG.save(deps.storage, &config)?;
+
+    let res = Response::new()
+        .add_attribute("action", "set_swap_max_oracle_deviation")
+        .add_attribute(
+            "max_deviation",
+            max_deviation.map_or("none".to_string(), |max_deviation| {
+                max_deviation.to_string()
+            }),
+        );
+    Ok(res)
+}
+
+pub fn execute_pause(deps: DepsMut, info: MessageInfo) -> Result<Response, MarsError> {
+    let mut config = CONFIG.load(deps.storage)?;
+
+    address_provider::helpers::assert_owner_or_guardian(
+        &deps.querier,
+        config.address_provider_address.clone(),
+        &config.owner,
+        &info.sender,
+    )?;
+
+    config.paused = true;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new().add_attribute("action", "pause"))
+}
+
+pub fn execute_unpause(deps: DepsMut, info: MessageInfo) -> Result<Response, MarsError> {
+    let mut config = CONFIG.load(deps.storage)?;
+
+    if info.sender != config.owner {
+        return Err(MarsError::Unauthorized {});
+    }
+
+    config.paused = false;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new().add_attribute("action", "unpause"))
+}
+
 // QUERY
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -452,6 +603,12 @@ pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
         QueryMsg::XMarsPerMars {} => to_binary(&query_xmars_per_mars(deps, env)?),
         QueryMsg::MarsPerXMars {} => to_binary(&query_mars_per_xmars(deps, env)?),
         QueryMsg::Claim { user_address } => to_binary(&query_claim(deps, env, user_address)?),
+        QueryMsg::SwapRoute { offer_asset_info } => {
+            to_binary(&query_swap_route(deps, offer_asset_info)?)
+        }
+        QueryMsg::SwapRoutes { start_after, limit } => {
+            to_binary(&query_swap_routes(deps, start_after, limit)?)
+        }
     }
 }
 
@@ -499,6 +656,65 @@ fn query_claim(deps: Deps, _env: Env, user_address_unchecked: String) -> StdResu
     }
 }
 
+fn query_swap_route(deps: Deps, offer_asset_info: AssetInfo) -> StdResult<SwapRoute> {
+    let route = SWAP_ROUTES
+        .may_load(deps.storage, &swap_route_key(&offer_asset_info))?
+        .unwrap_or_else(|| SwapRoute::direct(offer_asset_info));
+
+    Ok(route)
+}
+
+const PAGINATION_DEFAULT_LIMIT: u32 = 10;
+const PAGINATION_MAX_LIMIT: u32 = 30;
+
+fn query_swap_routes(
+    deps: Deps,
+    start_after: Option<AssetInfo>,
+    limit: Option<u32>,
+) -> StdResult<Vec<SwapRoute>> {
+    let start = start_after.map(|asset_info| Bound::exclusive(swap_route_key(&asset_info)));
+    let limit = limit
+        .unwrap_or(PAGINATION_DEFAULT_LIMIT)
+        .min(PAGINATION_MAX_LIMIT) as usize;
+
+    SWAP_ROUTES
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|item| {
+            let (_, route) = item?;
+            Ok(route)
+        })
+        .collect()
+}
+
+// MIGRATION
+
+/// State migrations, run when migrating from a version older than theirs
+const MIGRATION_STEPS: &[MigrationStep] = &[MigrationStep {
+    version: Version::new(1, 1, 0),
+    migrate: migrate_v1_1_0,
+}];
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let attributes = migrate_contract(
+        deps.storage,
+        CONTRACT_NAME,
+        CONTRACT_VERSION,
+        MIGRATION_STEPS,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attributes(attributes))
+}
+
+/// Stores the swap settings and `paused` flag added to the config in 1.1.0
+fn migrate_v1_1_0(storage: &mut dyn Storage) -> StdResult<()> {
+    let config = CONFIG.load(storage)?;
+    CONFIG.save(storage, &config)
+}
+
 // HELPERS
 
 /// Gets mars and xmars token addresses from address provider and returns them in a tuple.
@@ -516,6 +732,18 @@ fn get_token_addresses(deps: Deps, config: &Config) -> StdResult<(Addr, Addr)> {
     Ok((mars_token_address, xmars_token_address))
 }
 
+fn mars_asset_info(deps: Deps, config: &Config) -> StdResult<AssetInfo> {
+    let mars_token_address = address_provider::helpers::query_address(
+        &deps.querier,
+        config.address_provider_address.clone(),
+        MarsContract::MarsToken,
+    )?;
+
+    Ok(AssetInfo::Token {
+        contract_addr: mars_token_address,
+    })
+}
+
 fn apply_slash_events_to_claim(storage: &dyn Storage, claim: &mut Claim) -> StdResult<()> {
     let start = Some(Bound::inclusive(U64Key::new(claim.created_at_block)));
 
@@ -611,10 +839,12 @@ fn compute_mars_per_xmars(staking_tokens_info: &StakingTokensInfo) -> StdResult<
 #[cfg(test)]
 mod tests {
     use super::*;
+    use astroport::router::{ExecuteMsg as AstroportRouterExecuteMsg, SwapOperation};
     use cosmwasm_std::testing::{mock_info, MockApi, MockStorage, MOCK_CONTRACT_ADDR};
     use cosmwasm_std::{
         attr, Addr, Coin, CosmosMsg, Decimal as StdDecimal, OwnedDeps, StdError, SubMsg, Timestamp,
     };
+    use mars_core::swapping::{SwapVenueChecked, SwapVenueUnchecked};
     use mars_core::testing::{
         mock_dependencies, mock_env, mock_env_at_block_height, mock_env_at_block_time,
         MarsMockQuerier, MockEnvParams,
@@ -634,6 +864,8 @@ mod tests {
             address_provider_address: None,
             astroport_factory_address: None,
             astroport_max_spread: None,
+            swap_max_oracle_deviation: None,
+            swap_venue: None,
             cooldown_duration: None,
         };
         let msg = InstantiateMsg {
@@ -657,6 +889,8 @@ mod tests {
             address_provider_address: Some(String::from("address_provider")),
             astroport_factory_address: Some(String::from("astroport_factory")),
             astroport_max_spread: Some(StdDecimal::from_ratio(1u128, 100u128)),
+            swap_max_oracle_deviation: None,
+            swap_venue: None,
             cooldown_duration: Some(20),
         };
         let msg = InstantiateMsg { config };
@@ -685,6 +919,8 @@ mod tests {
             address_provider_address: Some(String::from("address_provider")),
             astroport_factory_address: Some(String::from("astroport_factory")),
             astroport_max_spread: Some(StdDecimal::from_ratio(1u128, 100u128)),
+            swap_max_oracle_deviation: None,
+            swap_venue: None,
             cooldown_duration: Some(20),
         };
         let msg = InstantiateMsg {
@@ -713,6 +949,10 @@ mod tests {
             address_provider_address: Some(String::from("new_address_provider")),
             astroport_factory_address: Some(String::from("new_factory")),
             astroport_max_spread: Some(StdDecimal::from_ratio(2u128, 100u128)),
+            swap_max_oracle_deviation: None,
+            swap_venue: Some(SwapVenueUnchecked::PoolManager {
+                address: String::from("pool_manager"),
+            }),
             cooldown_duration: Some(200),
         };
         let msg = ExecuteMsg::UpdateConfig {
@@ -729,6 +969,12 @@ mod tests {
         assert_eq!(new_config.owner, "new_owner");
         assert_eq!(new_config.address_provider_address, "new_address_provider");
         assert_eq!(new_config.astroport_factory_address, "new_factory");
+        assert_eq!(
+            new_config.swap_venue,
+            SwapVenueChecked::PoolManager {
+                address: Addr::unchecked("pool_manager")
+            }
+        );
         assert_eq!(
             new_config.cooldown_duration,
             config.cooldown_duration.unwrap()
@@ -1559,6 +1805,306 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_pause_swaps() {
+        let mut deps = th_setup(&[]);
+        let env = mock_env(MockEnvParams::default());
+
+        // only owner or guardian can pause
+        let info = mock_info("somebody", &[]);
+        let err = execute(deps.as_mut(), env.clone(), info, ExecuteMsg::Pause {}).unwrap_err();
+        assert_eq!(err, ContractError::Mars(MarsError::Unauthorized {}));
+
+        let info = mock_info("guardian", &[]);
+        execute(deps.as_mut(), env.clone(), info, ExecuteMsg::Pause {}).unwrap();
+        assert!(CONFIG.load(deps.as_ref().storage).unwrap().paused);
+
+        // cannot swap while paused
+        let info = mock_info("somebody", &[]);
+        let msg = ExecuteMsg::SwapUusdToMars {
+            amount: None,
+            minimum_receive: None,
+        };
+        let err = execute(deps.as_mut(), env.clone(), info, msg).unwrap_err();
+        assert_eq!(err, ContractError::Mars(MarsError::Paused {}));
+
+        // only owner can unpause
+        let info = mock_info("guardian", &[]);
+        let err = execute(deps.as_mut(), env.clone(), info, ExecuteMsg::Unpause {}).unwrap_err();
+        assert_eq!(err, ContractError::Mars(MarsError::Unauthorized {}));
+
+        let info = mock_info("owner", &[]);
+        execute(deps.as_mut(), env, info, ExecuteMsg::Unpause {}).unwrap();
+        assert!(!CONFIG.load(deps.as_ref().storage).unwrap().paused);
+    }
+
+    #[test]
+    fn test_set_swap_max_oracle_deviation() {
+        let mut deps = th_setup(&[]);
+        let env = mock_env(MockEnvParams::default());
+
+        let msg = ExecuteMsg::UpdateConfig {
+            config: CreateOrUpdateConfig {
+                owner: None,
+                address_provider_address: None,
+                astroport_factory_address: None,
+                astroport_max_spread: None,
+                swap_max_oracle_deviation: Some(Decimal::percent(5)),
+                swap_venue: Some(SwapVenueUnchecked::Astroport {
+                    router_address: Some(String::from("astroport_router")),
+                }),
+                cooldown_duration: None,
+            },
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("owner", &[]), msg).unwrap();
+
+        // update config without the value keeps it
+        let msg = ExecuteMsg::UpdateConfig {
+            config: CreateOrUpdateConfig {
+                owner: None,
+                address_provider_address: None,
+                astroport_factory_address: None,
+                astroport_max_spread: None,
+                swap_max_oracle_deviation: None,
+                swap_venue: None,
+                cooldown_duration: None,
+            },
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("owner", &[]), msg).unwrap();
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        assert_eq!(config.swap_max_oracle_deviation, Some(Decimal::percent(5)));
+
+        // only owner can set it
+        let msg = ExecuteMsg::SetSwapMaxOracleDeviation {
+            max_deviation: None,
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("somebody", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Mars(MarsError::Unauthorized {}));
+
+        // invalid value
+        let invalid_msg = ExecuteMsg::SetSwapMaxOracleDeviation {
+            max_deviation: Some(Decimal::percent(101)),
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("owner", &[]),
+            invalid_msg,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::Mars(MarsError::InvalidParam {
+                param_name: "swap_max_oracle_deviation".to_string(),
+                invalid_value: "1.01".to_string(),
+                predicate: "<= 1".to_string(),
+            })
+        );
+
+        // owner can remove it
+        let res = execute(deps.as_mut(), env.clone(), mock_info("owner", &[]), msg).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "set_swap_max_oracle_deviation"),
+                attr("max_deviation", "none"),
+            ]
+        );
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        assert_eq!(config.swap_max_oracle_deviation, None);
+
+        // and set it again
+        let msg = ExecuteMsg::SetSwapMaxOracleDeviation {
+            max_deviation: Some(Decimal::percent(3)),
+        };
+        let res = execute(deps.as_mut(), env, mock_info("owner", &[]), msg).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "set_swap_max_oracle_deviation"),
+                attr("max_deviation", "0.03"),
+            ]
+        );
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        assert_eq!(config.swap_max_oracle_deviation, Some(Decimal::percent(3)));
+    }
+
+    #[test]
+    fn test_swap_routes() {
+        let mut deps = th_setup(&[Coin {
+            denom: "uusd".to_string(),
+            amount: Uint128::new(1_000_000),
+        }]);
+        let env = mock_env(MockEnvParams::default());
+
+        // oracle check needs a venue able to enforce a minimum output
+        let msg = ExecuteMsg::UpdateConfig {
+            config: CreateOrUpdateConfig {
+                owner: None,
+                address_provider_address: None,
+                astroport_factory_address: None,
+                astroport_max_spread: None,
+                swap_max_oracle_deviation: Some(Decimal::percent(5)),
+                swap_venue: None,
+                cooldown_duration: None,
+            },
+        };
+        let err = execute(deps.as_mut(), env.clone(), mock_info("owner", &[]), msg).unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::Mars(MarsError::Std(StdError::generic_err(
+                "Astroport router address is required to enforce a minimum swap output"
+            )))
+        );
+
+        let msg = ExecuteMsg::UpdateConfig {
+            config: CreateOrUpdateConfig {
+                owner: None,
+                address_provider_address: None,
+                astroport_factory_address: None,
+                astroport_max_spread: None,
+                swap_max_oracle_deviation: Some(Decimal::percent(5)),
+                swap_venue: Some(SwapVenueUnchecked::Astroport {
+                    router_address: Some(String::from("astroport_router")),
+                }),
+                cooldown_duration: None,
+            },
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("owner", &[]), msg).unwrap();
+
+        let uusd_asset_info = AssetInfo::NativeToken {
+            denom: "uusd".to_string(),
+        };
+        let uluna_asset_info = AssetInfo::NativeToken {
+            denom: "uluna".to_string(),
+        };
+        let mars_asset_info = AssetInfo::Token {
+            contract_addr: Addr::unchecked("mars_token"),
+        };
+
+        // only owner can set swap routes
+        let msg = ExecuteMsg::SetSwapRoute {
+            offer_asset_info: uusd_asset_info.clone(),
+            hops: vec![uluna_asset_info.clone()],
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("somebody", &[]),
+            msg.clone(),
+        )
+        .unwrap_err();
+        assert_eq!(err, ContractError::Mars(MarsError::Unauthorized {}));
+
+        // route cannot go through the ask asset
+        let invalid_msg = ExecuteMsg::SetSwapRoute {
+            offer_asset_info: uusd_asset_info.clone(),
+            hops: vec![mars_asset_info.clone()],
+        };
+        let err = execute(
+            deps.as_mut(),
+            env.clone(),
+            mock_info("owner", &[]),
+            invalid_msg,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ContractError::Mars(MarsError::Std(StdError::generic_err(
+                "Swap route goes through mars_token more than once"
+            )))
+        );
+
+        execute(deps.as_mut(), env.clone(), mock_info("owner", &[]), msg).unwrap();
+
+        let route: SwapRoute = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::SwapRoute {
+                    offer_asset_info: uusd_asset_info.clone(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(route.hops, vec![uluna_asset_info.clone()]);
+
+        let routes: Vec<SwapRoute> = from_binary(
+            &query(
+                deps.as_ref(),
+                env.clone(),
+                QueryMsg::SwapRoutes {
+                    start_after: None,
+                    limit: None,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(routes, vec![route]);
+
+        // swap goes through the router with a route wide minimum, raised to the value given by the
+        // oracle: 500_000 uusd are worth 250_000 Mars, 5% deviation allowed
+        deps.querier
+            .set_oracle_price(b"mars_token".to_vec(), Decimal::from_ratio(2u128, 1u128));
+        let msg = ExecuteMsg::SwapUusdToMars {
+            amount: Some(Uint128::new(500_000)),
+            minimum_receive: Some(Uint128::new(100_000)),
+        };
+        let res = execute(deps.as_mut(), env.clone(), mock_info("somebody", &[]), msg).unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: String::from("astroport_router"),
+                msg: to_binary(&AstroportRouterExecuteMsg::ExecuteSwapOperations {
+                    operations: vec![
+                        SwapOperation::AstroSwap {
+                            offer_asset_info: uusd_asset_info.clone(),
+                            ask_asset_info: uluna_asset_info.clone(),
+                        },
+                        SwapOperation::AstroSwap {
+                            offer_asset_info: uluna_asset_info,
+                            ask_asset_info: mars_asset_info,
+                        },
+                    ],
+                    minimum_receive: Some(Uint128::new(237_500)),
+                    to: None,
+                })
+                .unwrap(),
+                funds: vec![Coin {
+                    denom: "uusd".to_string(),
+                    amount: Uint128::new(500_000),
+                }],
+            }))]
+        );
+
+        // empty hops remove the route
+        let msg = ExecuteMsg::SetSwapRoute {
+            offer_asset_info: uusd_asset_info.clone(),
+            hops: vec![],
+        };
+        execute(deps.as_mut(), env.clone(), mock_info("owner", &[]), msg).unwrap();
+        let route: SwapRoute = from_binary(
+            &query(
+                deps.as_ref(),
+                env,
+                QueryMsg::SwapRoute {
+                    offer_asset_info: uusd_asset_info.clone(),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(route, SwapRoute::direct(uusd_asset_info));
+    }
+
     // TEST HELPERS
     fn th_setup(contract_balances: &[Coin]) -> OwnedDeps<MockStorage, MockApi, MarsMockQuerier> {
         let mut deps = mock_dependencies(contract_balances);
@@ -1568,6 +2114,8 @@ mod tests {
             address_provider_address: Some(String::from("address_provider")),
             astroport_factory_address: Some(String::from("astroport_factory")),
             astroport_max_spread: Some(StdDecimal::from_ratio(1u128, 100u128)),
+            swap_max_oracle_deviation: None,
+            swap_venue: None,
             cooldown_duration: Some(TEST_COOLDOWN_DURATION),
         };
         let msg = InstantiateMsg { config };
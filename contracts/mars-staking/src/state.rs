@@ -1,6 +1,8 @@
 use cosmwasm_std::Addr;
 use cw_storage_plus::{Item, Map, U64Key};
 
+use mars_core::swapping::SwapRoute;
+
 use crate::{Claim, Config, GlobalState, SlashEvent};
 
 pub const CONFIG: Item<Config> = Item::new("config");
@@ -8,3 +10,4 @@ pub const GLOBAL_STATE: Item<GlobalState> = Item::new("global_state");
 
 pub const CLAIMS: Map<&Addr, Claim> = Map::new("claims");
 pub const SLASH_EVENTS: Map<U64Key, SlashEvent> = Map::new("slash_events");
+pub const SWAP_ROUTES: Map<&[u8], SwapRoute> = Map::new("swap_routes");
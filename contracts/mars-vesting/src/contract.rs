@@ -7,13 +7,15 @@ use cosmwasm_std::{
     QuerierWrapper, QueryRequest, Response, StdResult, Uint128, WasmMsg, WasmQuery,
 };
 
+use cw2::set_contract_version;
 use cw20::{Cw20ExecuteMsg, Cw20ReceiveMsg};
 
 use mars_core::address_provider::{self, MarsContract};
 use mars_core::error::MarsError;
 use mars_core::math::decimal::Decimal;
 use mars_core::staking;
-use mars_core::vesting::msg::{ExecuteMsg, InstantiateMsg, QueryMsg, ReceiveMsg};
+use mars_core::versioning::{migrate_contract, MigrationStep};
+use mars_core::vesting::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, ReceiveMsg};
 use mars_core::vesting::{Allocation, Config, Schedule};
 
 use crate::error::ContractError;
@@ -23,6 +25,10 @@ use crate::snapshots::{
 };
 use crate::state::{ALLOCATIONS, CONFIG};
 
+// version info for migration info
+const CONTRACT_NAME: &str = "crates.io:vesting";
+const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
 // INSTANTIATE
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -32,6 +38,8 @@ pub fn instantiate(
     _info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
     validate_unlock_schedule(msg.unlock_schedule, env.block.time.seconds())?;
     CONFIG.save(deps.storage, &msg.check(deps.api)?)?;
     Ok(Response::default())
@@ -306,6 +314,25 @@ pub fn query_total_voting_power_at(deps: Deps, block: u64) -> StdResult<Uint128>
     get_total_voting_power_value_at(deps.storage, block)
 }
 
+// MIGRATION
+
+/// State migrations, run when migrating from a version older than theirs
+const MIGRATION_STEPS: &[MigrationStep] = &[];
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
+    let attributes = migrate_contract(
+        deps.storage,
+        CONTRACT_NAME,
+        CONTRACT_VERSION,
+        MIGRATION_STEPS,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attributes(attributes))
+}
+
 // TESTS
 
 #[cfg(test)]
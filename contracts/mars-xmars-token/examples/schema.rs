@@ -7,7 +7,7 @@ use cw20::{
     AllAccountsResponse, AllAllowancesResponse, AllowanceResponse, BalanceResponse,
     TokenInfoResponse,
 };
-use mars_xmars_token::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
+use mars_xmars_token::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
 use mars_xmars_token::TotalSupplyResponse;
 
 fn main() {
@@ -19,6 +19,7 @@ fn main() {
     export_schema(&schema_for!(InstantiateMsg), &out_dir);
     export_schema(&schema_for!(ExecuteMsg), &out_dir);
     export_schema(&schema_for!(QueryMsg), &out_dir);
+    export_schema(&schema_for!(MigrateMsg), &out_dir);
 
     export_schema(&schema_for!(AllowanceResponse), &out_dir);
     export_schema(&schema_for!(BalanceResponse), &out_dir);
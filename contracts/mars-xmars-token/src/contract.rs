@@ -17,10 +17,11 @@ use cw20_base::state::{BALANCES, TOKEN_INFO};
 use cw20_base::ContractError;
 
 use mars_core::cw20_core::instantiate_token_info_and_marketing;
+use mars_core::versioning::{migrate_contract, MigrationStep};
 
 use crate::allowances::{execute_burn_from, execute_send_from, execute_transfer_from};
 use crate::core;
-use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
+use crate::msg::{ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg};
 use crate::snapshots::{
     capture_balance_snapshot, capture_total_supply_snapshot, get_balance_snapshot_value_at,
     get_total_supply_snapshot_value_at,
@@ -264,6 +265,25 @@ pub fn query_total_supply_at(deps: Deps, block: u64) -> StdResult<TotalSupplyRes
     Ok(TotalSupplyResponse { total_supply })
 }
 
+// MIGRATION
+
+/// State migrations, run when migrating from a version older than theirs
+const MIGRATION_STEPS: &[MigrationStep] = &[];
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> StdResult<Response> {
+    let attributes = migrate_contract(
+        deps.storage,
+        CONTRACT_NAME,
+        CONTRACT_VERSION,
+        MIGRATION_STEPS,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attributes(attributes))
+}
+
 #[cfg(test)]
 mod tests {
     use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
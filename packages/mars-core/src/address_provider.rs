@@ -31,6 +31,9 @@ pub struct Config {
     pub vesting_address: Addr,
     /// xMars token address
     pub xmars_token_address: Addr,
+    /// Guardian address (can pause protocol contracts in an emergency but not unpause them)
+    #[serde(default = "crate::helpers::zero_address")]
+    pub guardian_address: Addr,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -50,6 +53,20 @@ pub enum MarsContract {
     XMarsToken,
 }
 
+/// Contract registered by name, used for protocol modules that are not part of `MarsContract`
+/// so they can be added without changing this crate
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RegisteredContract {
+    /// Name the contract is registered under
+    pub name: String,
+    /// Contract address
+    pub address: Addr,
+    /// Contract version (optional)
+    pub version: Option<String>,
+    /// Any additional info about the contract (optional)
+    pub metadata: Option<String>,
+}
+
 pub mod msg {
     use schemars::JsonSchema;
     use serde::{Deserialize, Serialize};
@@ -68,15 +85,38 @@ pub mod msg {
 
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
     #[serde(rename_all = "snake_case")]
+    #[allow(clippy::large_enum_variant)]
     pub enum ExecuteMsg {
         /// Update address provider config
         UpdateConfig { config: ConfigParams },
+
+        /// Register a contract under the given name, replacing the previous entry if any (only
+        /// owner can call)
+        RegisterContract {
+            name: String,
+            address: String,
+            version: Option<String>,
+            metadata: Option<String>,
+        },
+
+        /// Remove a registered contract (only owner can call)
+        RemoveContract { name: String },
+
+        /// Propose a new owner, which has to claim ownership for the transfer to be effective
+        /// (only owner can call)
+        ProposeNewOwner { owner: String },
+
+        /// Drop the current ownership transfer proposal (only owner can call)
+        DropOwnershipProposal {},
+
+        /// Accept ownership of the contract (only proposed owner can call)
+        ClaimOwnership {},
     }
 
     #[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+    /// Ownership is transferred in two steps through `ProposeNewOwner` and `ClaimOwnership`, so
+    /// the owner is not part of the updatable config
     pub struct ConfigParams {
-        /// Contract owner (has special permissions to update parameters)
-        pub owner: Option<String>,
         /// Council contract handles the submission and execution of proposals
         pub council_address: Option<String>,
         /// Incentives contract handles incentives to depositors on the red bank
@@ -105,6 +145,9 @@ pub mod msg {
         pub vesting_address: Option<String>,
         /// xMars token cw20 contract
         pub xmars_token_address: Option<String>,
+        /// Guardian can pause protocol contracts in case of an emergency. Unpausing is
+        /// restricted to each contract's owner
+        pub guardian_address: Option<String>,
     }
 
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -116,14 +159,28 @@ pub mod msg {
         Address { contract: MarsContract },
         /// Get a list of addresses
         Addresses { contracts: Vec<MarsContract> },
+        /// Get a contract registered by name. Returns `RegisteredContract`
+        RegisteredContract { name: String },
+        /// Enumerate registered contracts ordered by name. Returns `Vec<RegisteredContract>`
+        RegisteredContracts {
+            start_after: Option<String>,
+            limit: Option<u32>,
+        },
+        /// Get the owner proposed in an ownership transfer, if any. Returns `Option<Addr>`
+        PendingOwner {},
+        /// Get the guardian account (empty if not set). Returns `Addr`
+        Guardian {},
     }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct MigrateMsg {}
 }
 
 pub mod helpers {
     use super::msg::QueryMsg;
-    use super::MarsContract;
+    use super::{MarsContract, RegisteredContract};
     use crate::error::MarsError;
-    use cosmwasm_std::{to_binary, Addr, QuerierWrapper, QueryRequest, WasmQuery};
+    use cosmwasm_std::{to_binary, Addr, QuerierWrapper, QueryRequest, StdResult, WasmQuery};
 
     pub fn query_address(
         querier: &QuerierWrapper,
@@ -180,6 +237,54 @@ pub mod helpers {
             Ok(query)
         }
     }
+
+    /// Address of a contract registered by name in the address provider
+    pub fn query_registered_address(
+        querier: &QuerierWrapper,
+        address_provider_address: Addr,
+        name: &str,
+    ) -> StdResult<Addr> {
+        let registered_contract: RegisteredContract =
+            querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr: address_provider_address.to_string(),
+                msg: to_binary(&QueryMsg::RegisteredContract {
+                    name: name.to_string(),
+                })?,
+            }))?;
+
+        Ok(registered_contract.address)
+    }
+
+    /// Guardian account set in the address provider (empty if not set)
+    pub fn query_guardian(
+        querier: &QuerierWrapper,
+        address_provider_address: Addr,
+    ) -> StdResult<Addr> {
+        querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: address_provider_address.to_string(),
+            msg: to_binary(&QueryMsg::Guardian {})?,
+        }))
+    }
+
+    /// Checks sender is either the given owner or the guardian set in the address provider.
+    /// Meant to be used by emergency pause handlers (unpausing should check for the owner only)
+    pub fn assert_owner_or_guardian(
+        querier: &QuerierWrapper,
+        address_provider_address: Addr,
+        owner: &Addr,
+        sender: &Addr,
+    ) -> Result<(), MarsError> {
+        if sender == owner {
+            return Ok(());
+        }
+
+        // Any failure to resolve the guardian (e.g: address provider not set) means the sender
+        // cannot be authorized as such
+        match query_guardian(querier, address_provider_address) {
+            Ok(guardian_address) if guardian_address == *sender => Ok(()),
+            _ => Err(MarsError::Unauthorized {}),
+        }
+    }
 }
 
 // TESTS
@@ -191,10 +296,9 @@ mod tests {
     use crate::error::MarsError;
     use cosmwasm_std::testing::{MockApi, MockStorage};
     use cosmwasm_std::{
-        from_binary, from_slice, to_binary, Binary, ContractResult, OwnedDeps, Querier,
+        from_binary, from_slice, to_binary, Binary, ContractResult, Empty, OwnedDeps, Querier,
         QuerierResult, QueryRequest, StdResult, SystemError, WasmQuery,
     };
-    use terra_cosmwasm::TerraQueryWrapper;
 
     #[test]
     fn test_query_address() {
@@ -282,12 +386,84 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_query_registered_address() {
+        let deps = OwnedDeps {
+            storage: MockStorage::default(),
+            api: MockApi::default(),
+            querier: AddressProviderMockQuerier {},
+        };
+
+        let address = helpers::query_registered_address(
+            &deps.as_ref().querier,
+            Addr::unchecked("address_provider"),
+            "lockdrop",
+        )
+        .unwrap();
+        assert_eq!(address, Addr::unchecked("lockdrop"));
+    }
+
+    #[test]
+    fn test_assert_owner_or_guardian() {
+        let deps = OwnedDeps {
+            storage: MockStorage::default(),
+            api: MockApi::default(),
+            querier: AddressProviderMockQuerier {},
+        };
+        let owner = Addr::unchecked("owner");
+
+        // Owner is allowed
+        helpers::assert_owner_or_guardian(
+            &deps.as_ref().querier,
+            Addr::unchecked("address_provider"),
+            &owner,
+            &owner,
+        )
+        .unwrap();
+
+        // Guardian is allowed
+        helpers::assert_owner_or_guardian(
+            &deps.as_ref().querier,
+            Addr::unchecked("address_provider"),
+            &owner,
+            &Addr::unchecked("guardian"),
+        )
+        .unwrap();
+
+        // Anybody else is unauthorized
+        let err = helpers::assert_owner_or_guardian(
+            &deps.as_ref().querier,
+            Addr::unchecked("address_provider"),
+            &owner,
+            &Addr::unchecked("somebody"),
+        )
+        .unwrap_err();
+        assert_eq!(err, MarsError::Unauthorized {});
+
+        // Owner is still allowed and anybody else unauthorized when address provider is not set
+        helpers::assert_owner_or_guardian(
+            &deps.as_ref().querier,
+            Addr::unchecked(""),
+            &owner,
+            &owner,
+        )
+        .unwrap();
+        let err = helpers::assert_owner_or_guardian(
+            &deps.as_ref().querier,
+            Addr::unchecked(""),
+            &owner,
+            &Addr::unchecked("guardian"),
+        )
+        .unwrap_err();
+        assert_eq!(err, MarsError::Unauthorized {});
+    }
+
     #[derive(Clone, Copy)]
     pub struct AddressProviderMockQuerier {}
 
     impl Querier for AddressProviderMockQuerier {
         fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
-            let request: QueryRequest<TerraQueryWrapper> = match from_slice(bin_request) {
+            let request: QueryRequest<Empty> = match from_slice(bin_request) {
                 Ok(v) => v,
                 Err(e) => {
                     return Err(SystemError::InvalidRequest {
@@ -302,12 +478,16 @@ mod tests {
     }
 
     impl AddressProviderMockQuerier {
-        pub fn handle_query(&self, request: &QueryRequest<TerraQueryWrapper>) -> QuerierResult {
-            if let QueryRequest::Wasm(WasmQuery::Smart {
-                contract_addr: _,
-                msg,
-            }) = request
-            {
+        pub fn handle_query(&self, request: &QueryRequest<Empty>) -> QuerierResult {
+            if let QueryRequest::Wasm(WasmQuery::Smart { contract_addr, msg }) = request {
+                // unset address provider
+                if contract_addr.is_empty() {
+                    return Err(SystemError::NoSuchContract {
+                        addr: contract_addr.clone(),
+                    })
+                    .into();
+                }
+
                 let parse_address_provider_query: StdResult<QueryMsg> = from_binary(msg);
 
                 if let Ok(address_provider_query) = parse_address_provider_query {
@@ -324,6 +504,16 @@ mod tests {
                             to_binary(&addresses).into()
                         }
 
+                        QueryMsg::Guardian {} => to_binary(&Addr::unchecked("guardian")).into(),
+
+                        QueryMsg::RegisteredContract { name } => to_binary(&RegisteredContract {
+                            address: Addr::unchecked(&name),
+                            name,
+                            version: None,
+                            metadata: None,
+                        })
+                        .into(),
+
                         _ => panic!("[mock]: Unsupported address provider query"),
                     };
 
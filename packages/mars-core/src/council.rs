@@ -245,6 +245,9 @@ pub mod msg {
             limit: Option<u32>,
         },
     }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct MigrateMsg {}
 }
 
 pub mod error {
@@ -26,6 +26,21 @@ pub enum MarsError {
 
     #[error("One or more addresses are empty: {empty_addresses:?}")]
     EmptyAddresses { empty_addresses: Vec<MarsContract> },
+
+    #[error("Contract is paused")]
+    Paused {},
+
+    #[error("Cannot migrate from {stored_contract} to {contract}")]
+    MigrateWrongContract {
+        stored_contract: String,
+        contract: String,
+    },
+
+    #[error("Cannot migrate from version {stored_version} to older version {version}")]
+    MigrateDowngrade {
+        stored_version: String,
+        version: String,
+    },
 }
 
 impl From<MarsError> for StdError {
@@ -12,6 +12,9 @@ pub struct Config {
     pub owner: Addr,
     /// Address provider returns addresses for all protocol contracts
     pub address_provider_address: Addr,
+    /// If true rewards cannot be claimed (set by owner or guardian in an emergency)
+    #[serde(default)]
+    pub paused: bool,
 }
 
 /// Incentive Metadata for a given incentive
@@ -82,6 +85,12 @@ pub mod msg {
 
         /// Execute Cosmos msg (only callable by owner)
         ExecuteCosmosMsg(CosmosMsg),
+
+        /// Pause rewards claims (only callable by owner or guardian)
+        Pause {},
+
+        /// Unpause rewards claims (only callable by owner)
+        Unpause {},
     }
 
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -96,4 +105,7 @@ pub mod msg {
         /// Query user current unclaimed rewards
         UserUnclaimedRewards { user_address: String },
     }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct MigrateMsg {}
 }
@@ -22,6 +22,7 @@ pub mod error;
 pub mod helpers;
 pub mod swapping;
 pub mod tax;
+pub mod versioning;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub mod testing;
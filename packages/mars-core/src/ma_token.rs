@@ -172,6 +172,9 @@ pub mod msg {
         },
     }
 
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct MigrateMsg {}
+
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
     pub struct BalanceAndTotalSupplyResponse {
         pub balance: Uint128,
@@ -75,6 +75,12 @@ impl Decimal {
         Ok(Decimal(result))
     }
 
+    /// Subtract 'other' from 'self'.
+    /// Function can return OverflowError if 'other' is greater than 'self'.
+    pub fn checked_sub(self, other: Self) -> StdResult<Self> {
+        Ok(Decimal(self.0.checked_sub(other.0)?))
+    }
+
     /// Divide 'self' by 'other'.
     /// Function can return errors such as:
     /// - OverflowError from multiplication,
@@ -637,6 +643,17 @@ mod tests {
         let _value = Decimal::zero() - Decimal::percent(50);
     }
 
+    #[test]
+    fn checked_decimal_subtraction() {
+        let value = Decimal::one().checked_sub(Decimal::percent(50)).unwrap();
+        assert_eq!(value, Decimal::percent(50));
+
+        let error = Decimal::zero()
+            .checked_sub(Decimal::percent(50))
+            .unwrap_err();
+        assert!(matches!(error, StdError::Overflow { .. }));
+    }
+
     #[test]
     // in this test the Decimal is on the right
     fn uint128_decimal_multiply() {
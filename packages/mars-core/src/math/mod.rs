@@ -1,6 +1,6 @@
 pub mod decimal;
 
-use cosmwasm_std::{StdResult, Uint128};
+use cosmwasm_std::{StdResult, Uint128, Uint256};
 
 pub fn uint128_checked_div_with_ceil(
     numerator: Uint128,
@@ -15,6 +15,30 @@ pub fn uint128_checked_div_with_ceil(
     Ok(result)
 }
 
+/// Integer square root (rounded down) using Newton's method
+pub fn uint256_sqrt(value: Uint256) -> Uint256 {
+    if value.is_zero() {
+        return Uint256::zero();
+    }
+
+    // Start from a power of two above the root so the iterations decrease monotonically
+    let bits = 256
+        - value
+            .to_be_bytes()
+            .iter()
+            .take_while(|byte| **byte == 0)
+            .count() as u32
+            * 8;
+    let mut root = Uint256::from(1u8) << (bits / 2 + 1);
+    loop {
+        let next = (root + value / root) >> 1;
+        if next >= root {
+            return root;
+        }
+        root = next;
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -49,4 +73,32 @@ mod tests {
         let c = uint128_checked_div_with_ceil(a, b).unwrap();
         assert_eq!(c, Uint128::zero());
     }
+
+    #[test]
+    fn test_uint256_sqrt() {
+        for (value, expected) in &[
+            (0u128, 0u128),
+            (1, 1),
+            (3, 1),
+            (4, 2),
+            (15, 3),
+            (16, 4),
+            (1_000_000, 1_000),
+            (999_999, 999),
+            (u128::MAX, 18_446_744_073_709_551_615),
+        ] {
+            assert_eq!(
+                uint256_sqrt(Uint256::from(*value)),
+                Uint256::from(*expected)
+            );
+        }
+
+        // (2^128 - 1)^2 is above u128::MAX
+        let value = Uint256::from(u128::MAX) * Uint256::from(u128::MAX);
+        assert_eq!(uint256_sqrt(value), Uint256::from(u128::MAX));
+        assert_eq!(
+            uint256_sqrt(value - Uint256::from(1u8)),
+            Uint256::from(u128::MAX - 1)
+        );
+    }
 }
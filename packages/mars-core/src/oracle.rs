@@ -3,27 +3,31 @@ use std::fmt;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Api, StdResult, Uint128};
+use cosmwasm_std::{Addr, Api, Binary, StdResult, Uint128};
 
+use crate::asset::Asset;
 use crate::math::decimal::Decimal;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
+/// All prices are quoted in the base denom set in the oracle config
 pub enum PriceSource<A> {
-    /// Returns a fixed value; used for UST
+    /// Returns a fixed value; used for the base denom itself or for assets pegged to it
     Fixed { price: Decimal },
-    /// Native Terra stablecoins transaction rate quoted in UST
+    /// Native Terra stablecoins transaction rate quoted in the base denom
     Native { denom: String },
-    /// Astroport spot price quoted in UST
+    /// Astroport spot price quoted in the base denom
     ///
-    /// NOTE: `pair_address` must point to an astroport pair consists of the asset of intereset and UST
+    /// NOTE: `pair_address` must point to an astroport pair consists of the asset of intereset and
+    /// the base denom
     AstroportSpot {
         /// Address of the Astroport pair
         pair_address: A,
     },
-    /// Astroport TWAP price quoted in UST
+    /// Astroport TWAP price quoted in the base denom
     ///
-    /// NOTE: `pair_address` must point to an astroport pair consists of the asset of intereset and UST
+    /// NOTE: `pair_address` must point to an astroport pair consists of the asset of intereset and
+    /// the base denom
     AstroportTwap {
         /// Address of the Astroport pair
         pair_address: A,
@@ -41,6 +45,21 @@ pub enum PriceSource<A> {
         /// desired window size.
         tolerance: u64,
     },
+    /// Exponential moving average of the Astroport spot price quoted in the base denom, updated
+    /// through `RecordEmaPrices`. Queries blend the recorded average with the current spot price,
+    /// the average being weighted by `2^(-elapsed / half_life)`
+    ///
+    /// NOTE: `pair_address` must point to an astroport pair consists of the asset of intereset and
+    /// the base denom
+    AstroportEma {
+        /// Address of the Astroport pair
+        pair_address: A,
+        /// Time (in seconds) after which the weight of a spot price in the average is halved
+        half_life: u64,
+        /// Maximum time (in seconds) since the last recorded average. Older averages make queries
+        /// fail, and records weigh the spot price as if they were only this old
+        max_staleness: u64,
+    },
     /// Astroport liquidity token
     ///
     /// NOTE: Astroport's pair contract does not have a query command to check the address of the LP
@@ -50,10 +69,65 @@ pub enum PriceSource<A> {
         /// Address of the asset of interest
         pair_address: A,
     },
+    /// Astroport liquidity token valued from the pool invariant and the oracle prices of the pool
+    /// assets instead of the pool reserves, so it can't be skewed by swapping against the pool:
+    /// - XYK pools: `2 * sqrt(k * p0 * p1) / total_share`, with `k = reserve0 * reserve1`
+    /// - StableSwap pools: `D * min(p0, p1) / total_share`, with `D` the StableSwap invariant
+    ///
+    /// NOTE: Price sources must exist for both assets in the pool
+    AstroportFairLiquidityToken {
+        /// Address of the Astroport pair
+        pair_address: A,
+    },
     /// stLuna price calculated from stLuna/Luna exchange rate from Lido hub contract and Luna price from current price source
     Stluna { hub_address: A },
     /// Lunax price calculated from Lunax/Luna exchange rate from Stader staking contract and Luna price from current price source
     Lunax { staking_address: A },
+    /// Price of an underlying asset multiplied by an exchange rate read from any contract (e.g. the
+    /// redemption rate of a liquid staking token from its hub contract)
+    RedemptionRate {
+        /// Contract queried for the exchange rate
+        contract: A,
+        /// JSON query message sent to `contract`, as is
+        query_msg_template: Binary,
+        /// Keys leading to the exchange rate in the JSON response (e.g. `["exchange_rate"]`). The
+        /// exchange rate can be either a JSON string or number
+        response_path: Vec<String>,
+        /// Asset the exchange rate is quoted in, whose price is queried from its own price source
+        underlying_asset: Asset,
+    },
+    /// Median of the prices pushed by whitelisted feeders through `FeedPrices`. Prices older than
+    /// `max_age` seconds are ignored
+    Feed {
+        /// Addresses allowed to push prices for the asset
+        feeders: Vec<A>,
+        /// Minimum number of feeders with a recent enough price
+        min_feeders: u32,
+        /// Maximum age of a pushed price, in seconds
+        max_age: u64,
+    },
+    /// Price of the asset quoted in an intermediate asset, multiplied by the intermediate asset's
+    /// own price. Used for assets whose liquidity is not against the base denom (e.g. a token only
+    /// paired with LUNA)
+    ///
+    /// NOTE: `source` can only be an Astroport spot or TWAP source, whose pair must consist of the
+    /// asset of interest and `quote_asset`. The quote asset must have a price source, which cannot
+    /// depend on the price of the asset itself
+    Chained {
+        /// Price source quoting the asset in `quote_asset`
+        source: Box<PriceSource<A>>,
+        /// Intermediate asset, whose price is queried from its own price source
+        quote_asset: Asset,
+    },
+    /// Median of the prices returned by the given sources, which are queried for the same asset.
+    /// Sources that error are ignored as long as at least `min_sources` return a price
+    ///
+    /// NOTE: sources cannot be medians themselves
+    Median {
+        sources: Vec<PriceSource<A>>,
+        /// Minimum number of sources that must return a price
+        min_sources: u32,
+    },
 }
 
 impl<A> fmt::Display for PriceSource<A> {
@@ -63,9 +137,15 @@ impl<A> fmt::Display for PriceSource<A> {
             PriceSource::Native { .. } => "native",
             PriceSource::AstroportSpot { .. } => "astroport_spot",
             PriceSource::AstroportTwap { .. } => "astroport_twap",
+            PriceSource::AstroportEma { .. } => "astroport_ema",
             PriceSource::AstroportLiquidityToken { .. } => "astroport_liquidity_token",
+            PriceSource::AstroportFairLiquidityToken { .. } => "astroport_fair_liquidity_token",
             PriceSource::Stluna { .. } => "stluna",
             PriceSource::Lunax { .. } => "lunax",
+            PriceSource::RedemptionRate { .. } => "redemption_rate",
+            PriceSource::Feed { .. } => "feed",
+            PriceSource::Chained { .. } => "chained",
+            PriceSource::Median { .. } => "median",
         };
         write!(f, "{}", label)
     }
@@ -95,17 +175,93 @@ impl PriceSourceUnchecked {
                 window_size: *window_size,
                 tolerance: *tolerance,
             },
+            PriceSourceUnchecked::AstroportEma {
+                pair_address,
+                half_life,
+                max_staleness,
+            } => PriceSourceChecked::AstroportEma {
+                pair_address: api.addr_validate(pair_address)?,
+                half_life: *half_life,
+                max_staleness: *max_staleness,
+            },
             PriceSourceUnchecked::AstroportLiquidityToken { pair_address } => {
                 PriceSourceChecked::AstroportLiquidityToken {
                     pair_address: api.addr_validate(pair_address)?,
                 }
             }
+            PriceSourceUnchecked::AstroportFairLiquidityToken { pair_address } => {
+                PriceSourceChecked::AstroportFairLiquidityToken {
+                    pair_address: api.addr_validate(pair_address)?,
+                }
+            }
             PriceSourceUnchecked::Stluna { hub_address } => PriceSourceChecked::Stluna {
                 hub_address: api.addr_validate(hub_address)?,
             },
             PriceSourceUnchecked::Lunax { staking_address } => PriceSourceChecked::Lunax {
                 staking_address: api.addr_validate(staking_address)?,
             },
+            PriceSourceUnchecked::RedemptionRate {
+                contract,
+                query_msg_template,
+                response_path,
+                underlying_asset,
+            } => PriceSourceChecked::RedemptionRate {
+                contract: api.addr_validate(contract)?,
+                query_msg_template: query_msg_template.clone(),
+                response_path: response_path.clone(),
+                underlying_asset: underlying_asset.clone(),
+            },
+            PriceSourceUnchecked::Feed {
+                feeders,
+                min_feeders,
+                max_age,
+            } => PriceSourceChecked::Feed {
+                feeders: feeders
+                    .iter()
+                    .map(|feeder| api.addr_validate(feeder))
+                    .collect::<StdResult<Vec<_>>>()?,
+                min_feeders: *min_feeders,
+                max_age: *max_age,
+            },
+            PriceSourceUnchecked::Chained {
+                source,
+                quote_asset,
+            } => PriceSourceChecked::Chained {
+                source: Box::new(source.to_checked(api)?),
+                quote_asset: quote_asset.clone(),
+            },
+            PriceSourceUnchecked::Median {
+                sources,
+                min_sources,
+            } => PriceSourceChecked::Median {
+                sources: sources
+                    .iter()
+                    .map(|source| source.to_checked(api))
+                    .collect::<StdResult<Vec<_>>>()?,
+                min_sources: *min_sources,
+            },
+        })
+    }
+}
+
+/// Secondary price source of an asset. Used instead of the primary one when it errors, and to
+/// cross check it otherwise
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FallbackPriceSource<A> {
+    pub price_source: PriceSource<A>,
+    /// Maximum deviation between primary and fallback prices, relative to the primary price.
+    /// Price queries fail if both sources return a price and they deviate more than this
+    pub max_deviation: Decimal,
+}
+
+pub type FallbackPriceSourceUnchecked = FallbackPriceSource<String>;
+pub type FallbackPriceSourceChecked = FallbackPriceSource<Addr>;
+
+impl FallbackPriceSourceUnchecked {
+    pub fn to_checked(&self, api: &dyn Api) -> StdResult<FallbackPriceSourceChecked> {
+        Ok(FallbackPriceSourceChecked {
+            price_source: self.price_source.to_checked(api)?,
+            max_deviation: self.max_deviation,
         })
     }
 }
@@ -114,6 +270,97 @@ impl PriceSourceUnchecked {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
     pub owner: Addr,
+    /// Address provider returns addresses for all protocol contracts (used to check for
+    /// the guardian)
+    #[serde(default = "crate::helpers::zero_address")]
+    pub address_provider_address: Addr,
+    /// If true price queries return an error (set by owner or guardian in an emergency)
+    #[serde(default)]
+    pub paused: bool,
+    /// Denom all prices are quoted in. Protocol contracts value positions in this denom
+    #[serde(default = "default_base_denom")]
+    pub base_denom: String,
+}
+
+/// Base denom of deployments where it was not configurable
+pub fn default_base_denom() -> String {
+    "uusd".to_string()
+}
+
+/// Price pushed by a feeder for a `Feed` price source
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeedPrice {
+    pub price: Decimal,
+    /// Timestamp of the block the price was pushed at
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct FeederPriceResponse {
+    pub feeder: Addr,
+    pub price: Decimal,
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct AssetPriceWithMetadataResponse {
+    pub price: Decimal,
+    /// Timestamp of the oldest data the price was computed from
+    pub timestamp: u64,
+    /// Kind of the price source that returned the price (e.g. "astroport_twap")
+    pub price_source: String,
+    /// True if the price was returned by the asset's fallback price source
+    pub is_fallback: bool,
+    /// Maximum age (in seconds) of the price configured for the asset, if any
+    pub max_age: Option<u64>,
+}
+
+impl AssetPriceWithMetadataResponse {
+    /// Whether the price is older than the asset's maximum age at the given timestamp
+    pub fn is_stale(&self, current_timestamp: u64) -> bool {
+        match self.max_age {
+            Some(max_age) => current_timestamp.saturating_sub(self.timestamp) > max_age,
+            None => false,
+        }
+    }
+}
+
+/// Makes an asset's price queries fail when its price moves abnormally. Once tripped, the breaker
+/// stays tripped until reset by the owner or the guardian
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CircuitBreaker {
+    /// Prices below this bound trip the breaker
+    pub min_price: Option<Decimal>,
+    /// Prices above this bound trip the breaker
+    pub max_price: Option<Decimal>,
+    /// Maximum change of the price relative to the last recorded one (e.g. 0.2 for 20%)
+    pub max_change: Option<Decimal>,
+    /// Minimum time (in seconds) between two recorded prices, and for which a breach has to
+    /// hold for the breaker to trip
+    pub window: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct CircuitBreakerState {
+    /// If true, price queries for the asset return an error
+    pub tripped: bool,
+    /// Last recorded price the change is measured against
+    pub last_price: Option<PriceSnapshot>,
+    /// Timestamp of the check that first saw the ongoing breach, if any
+    #[serde(default)]
+    pub breached_since: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PriceSnapshot {
+    pub price: Decimal,
+    pub timestamp: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CircuitBreakerResponse {
+    pub circuit_breaker: CircuitBreaker,
+    pub state: CircuitBreakerState,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -128,26 +375,79 @@ pub mod msg {
     use schemars::JsonSchema;
     use serde::{Deserialize, Serialize};
 
-    use super::PriceSourceUnchecked;
+    use super::{CircuitBreaker, FallbackPriceSourceUnchecked, PriceSourceUnchecked};
     use crate::asset::Asset;
+    use crate::math::decimal::Decimal;
 
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
     pub struct InstantiateMsg {
         pub owner: String,
+        pub address_provider_address: Option<String>,
+        /// Denom all prices are quoted in (defaults to uusd). Cannot be updated as price
+        /// sources depend on it
+        pub base_denom: Option<String>,
     }
 
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
     #[serde(rename_all = "snake_case")]
     pub enum ExecuteMsg {
         /// Update contract config
-        UpdateConfig { owner: Option<String> },
+        UpdateConfig {
+            owner: Option<String>,
+            address_provider_address: Option<String>,
+        },
         /// Specify parameters to query asset price
         SetAsset {
             asset: Asset,
             price_source: PriceSourceUnchecked,
         },
+        /// Set or remove (if `None`) the fallback price source of an asset that already has a
+        /// price source. The primary and fallback sources can only use one Astroport TWAP and one
+        /// EMA source (same pair and parameters), as their records are stored per asset
+        SetAssetFallback {
+            asset: Asset,
+            fallback: Option<FallbackPriceSourceUnchecked>,
+        },
+        /// Set or remove (if `None`) the maximum age (in seconds) of an asset's price. Protocol
+        /// contracts refuse to use older prices for risky operations. The age is measured from
+        /// the oldest data the price is computed from: feed prices, the TWAP snapshot the
+        /// average starts at or the last recorded EMA price. Prices read live (fixed, native,
+        /// spot...) are never stale. The max age of an asset priced by a TWAP, as primary or
+        /// fallback source, must exceed the TWAP's window size plus tolerance: this is checked
+        /// when setting the max age and when setting a price source of an asset with a max age
+        SetAssetMaxAge { asset: Asset, max_age: Option<u64> },
+        /// Set or remove (if `None`) the circuit breaker of an asset that already has a price
+        /// source
+        SetCircuitBreaker {
+            asset: Asset,
+            circuit_breaker: Option<CircuitBreaker>,
+        },
+        /// Check the current prices of assets against their circuit breakers, tripping the ones
+        /// breached for at least their window and recording the price of the others if their
+        /// window has elapsed
+        CheckCircuitBreakers { assets: Vec<Asset> },
+        /// Make price queries work again for an asset whose circuit breaker tripped (only owner or
+        /// guardian can call). The last recorded price is cleared, so the next check records the
+        /// price then current
+        ResetCircuitBreaker { asset: Asset },
+        /// Push prices for assets using a `Feed` price source (only feeders whitelisted in the
+        /// asset's price source can call)
+        FeedPrices { prices: Vec<FeederPrice> },
         /// Fetch cumulative prices from Astroport pairs and record in contract storage
         RecordTwapSnapshots { assets: Vec<Asset> },
+        /// Update the moving averages of assets using an `AstroportEma` price source with the
+        /// current spot prices
+        RecordEmaPrices { assets: Vec<Asset> },
+        /// Make price queries fail (only owner or guardian can call)
+        Pause {},
+        /// Resume price queries (only owner can call)
+        Unpause {},
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct FeederPrice {
+        pub asset: Asset,
+        pub price: Decimal,
     }
 
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -157,12 +457,26 @@ pub mod msg {
         Config {},
         /// Get asset's price source. Returns `AssetConfigChecked`
         AssetPriceSource { asset: Asset },
+        /// Get asset's fallback price source. Returns `Option<FallbackPriceSourceChecked>`
+        AssetFallbackPriceSource { asset: Asset },
+        /// Get asset's circuit breaker and its state. Returns `Option<CircuitBreakerResponse>`
+        AssetCircuitBreaker { asset: Asset },
+        /// Get the prices pushed by feeders for an asset. Returns `Vec<FeederPriceResponse>`
+        AssetFeedPrices { asset: Asset },
         /// Query asset price given an asset; returns `mars_core::math::decimal::Decimal`
         AssetPrice { asset: Asset },
         /// Query asset price given it's internal reference; returns `mars_core::math::decimal::Decimal`
         ///
         /// NOTE: meant to be used by protocol contracts only
         AssetPriceByReference { asset_reference: Vec<u8> },
+        /// Query asset price along with its timestamp and source given an asset; returns
+        /// `AssetPriceWithMetadataResponse`
+        AssetPriceWithMetadata { asset: Asset },
+        /// Query asset price along with its timestamp and source given it's internal reference;
+        /// returns `AssetPriceWithMetadataResponse`
+        ///
+        /// NOTE: meant to be used by protocol contracts only
+        AssetPriceWithMetadataByReference { asset_reference: Vec<u8> },
     }
 
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -176,16 +490,27 @@ pub mod helpers {
     use crate::math::decimal::Decimal;
 
     use super::msg::QueryMsg;
+    use super::{AssetPriceWithMetadataResponse, Config};
+
+    /// Query the denom prices are quoted in
+    pub fn query_base_denom(querier: QuerierWrapper, oracle_address: Addr) -> StdResult<String> {
+        let config: Config = querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+            contract_addr: oracle_address.into(),
+            msg: to_binary(&QueryMsg::Config {})?,
+        }))?;
+        Ok(config.base_denom)
+    }
 
     pub fn query_price(
         querier: QuerierWrapper,
         oracle_address: Addr,
+        base_denom: &str,
         asset_label: &str,
         asset_reference: Vec<u8>,
         asset_type: AssetType,
     ) -> StdResult<Decimal> {
-        // For UST, we skip the query and just return 1 to save gas
-        if asset_type == AssetType::Native && asset_label == "uusd" {
+        // For the base denom, we skip the query and just return 1 to save gas
+        if asset_type == AssetType::Native && asset_label == base_denom {
             Ok(Decimal::one())
         } else {
             querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
@@ -194,4 +519,32 @@ pub mod helpers {
             }))
         }
     }
+
+    /// Same as `query_price` but also returns the price's timestamp and the asset's maximum
+    /// price age so callers can check for staleness
+    pub fn query_price_with_metadata(
+        querier: QuerierWrapper,
+        oracle_address: Addr,
+        base_denom: &str,
+        asset_label: &str,
+        asset_reference: Vec<u8>,
+        asset_type: AssetType,
+        current_timestamp: u64,
+    ) -> StdResult<AssetPriceWithMetadataResponse> {
+        // For the base denom, we skip the query and just return 1 to save gas
+        if asset_type == AssetType::Native && asset_label == base_denom {
+            Ok(AssetPriceWithMetadataResponse {
+                price: Decimal::one(),
+                timestamp: current_timestamp,
+                price_source: "base_denom".to_string(),
+                is_fallback: false,
+                max_age: None,
+            })
+        } else {
+            querier.query(&QueryRequest::Wasm(WasmQuery::Smart {
+                contract_addr: oracle_address.into(),
+                msg: to_binary(&QueryMsg::AssetPriceWithMetadataByReference { asset_reference })?,
+            }))
+        }
+    }
 }
@@ -7,6 +7,7 @@ use cosmwasm_std::{Addr, Decimal as StdDecimal};
 use crate::error::MarsError;
 use crate::helpers::decimal_param_le_one;
 use crate::math::decimal::Decimal;
+use crate::swapping::{validate_swap_max_oracle_deviation, SwapVenueChecked};
 
 /// Global configuration
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -23,6 +24,13 @@ pub struct Config {
     pub astroport_factory_address: Addr,
     /// Astroport max spread
     pub astroport_max_spread: StdDecimal,
+    /// Maximum deviation of a swap output from the value given by oracle prices (no oracle check
+    /// if not set)
+    #[serde(default)]
+    pub swap_max_oracle_deviation: Option<Decimal>,
+    /// Venue swaps are executed on (`astroport_max_spread` applies to all venues)
+    #[serde(default)]
+    pub swap_venue: SwapVenueChecked,
 }
 
 impl Config {
@@ -36,6 +44,10 @@ impl Config {
             return Err(ConfigError::InvalidFeeShareAmounts {});
         }
 
+        if let Some(max_deviation) = &self.swap_max_oracle_deviation {
+            validate_swap_max_oracle_deviation(&self.swap_venue, max_deviation)?;
+        }
+
         Ok(())
     }
 }
@@ -73,6 +85,7 @@ pub mod msg {
 
     use crate::asset::Asset;
     use crate::math::decimal::Decimal;
+    use crate::swapping::SwapVenueUnchecked;
 
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
     pub struct InstantiateMsg {
@@ -87,6 +100,12 @@ pub mod msg {
         pub treasury_fee_share: Option<Decimal>,
         pub astroport_factory_address: Option<String>,
         pub astroport_max_spread: Option<StdDecimal>,
+        /// Defaults to `None` on instantiation, which leaves the permissionless `SwapAssetToUusd`
+        /// protected by `astroport_max_spread` only. Cannot be removed through `UpdateConfig`,
+        /// use `SetSwapMaxOracleDeviation` instead
+        pub swap_max_oracle_deviation: Option<Decimal>,
+        /// Defaults to Astroport on instantiation
+        pub swap_venue: Option<SwapVenueUnchecked>,
     }
 
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -118,8 +137,22 @@ pub mod msg {
         SwapAssetToUusd {
             offer_asset_info: AssetInfo,
             amount: Option<Uint128>,
+            /// Minimum amount of uusd to receive, over the whole swap route.
+            /// Requires `swap_max_oracle_deviation` to be set, as do non direct routes
+            minimum_receive: Option<Uint128>,
+        },
+
+        /// Set the route used to swap an asset to uusd, through the given intermediate assets
+        /// (only callable by owner). Empty `hops` removes the route so the asset is swapped directly
+        SetSwapRoute {
+            offer_asset_info: AssetInfo,
+            hops: Vec<AssetInfo>,
         },
 
+        /// Set or remove (if `None`) the maximum deviation of swap outputs from oracle prices
+        /// (only callable by owner)
+        SetSwapMaxOracleDeviation { max_deviation: Option<Decimal> },
+
         /// Execute Cosmos msg (only callable by owner)
         ExecuteCosmosMsg(CosmosMsg),
     }
@@ -131,5 +164,15 @@ pub mod msg {
         Config {},
         /// Get asset config parameters
         AssetConfig { asset: Asset },
+        /// Get the route used to swap an asset (with no hops if the asset is swapped directly)
+        SwapRoute { offer_asset_info: AssetInfo },
+        /// Enumerate swap routes with pagination
+        SwapRoutes {
+            start_after: Option<AssetInfo>,
+            limit: Option<u32>,
+        },
     }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct MigrateMsg {}
 }
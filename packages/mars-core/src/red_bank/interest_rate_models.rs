@@ -20,6 +20,29 @@ pub enum InterestRateModel {
     },
 }
 
+impl InterestRateModel {
+    /// Params of the model, without its internal state
+    pub fn params(&self) -> InterestRateModelParams {
+        match self {
+            InterestRateModel::Dynamic { params, .. } => {
+                InterestRateModelParams::Dynamic(params.clone())
+            }
+            InterestRateModel::Linear { params } => InterestRateModelParams::Linear(params.clone()),
+        }
+    }
+
+    /// Replaces the params of the model. The internal state is kept if the model stays the same
+    pub fn update_params(&mut self, new_params: InterestRateModelParams, current_block_time: u64) {
+        match (self, new_params) {
+            (
+                InterestRateModel::Dynamic { params, .. },
+                InterestRateModelParams::Dynamic(new_params),
+            ) => *params = new_params,
+            (model, new_params) => *model = new_interest_rate_model(new_params, current_block_time),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum InterestRateModelParams {
@@ -53,7 +76,13 @@ pub fn init_interest_rate_model(
     current_block_time: u64,
 ) -> Result<InterestRateModel, InterestRateModelError> {
     params.validate()?;
+    Ok(new_interest_rate_model(params, current_block_time))
+}
 
+fn new_interest_rate_model(
+    params: InterestRateModelParams,
+    current_block_time: u64,
+) -> InterestRateModel {
     match params {
         InterestRateModelParams::Dynamic(dynamic_params) => {
             let state = DynamicInterestRateModelState {
@@ -61,14 +90,14 @@ pub fn init_interest_rate_model(
                 borrow_rate_last_updated: current_block_time,
             };
 
-            Ok(InterestRateModel::Dynamic {
+            InterestRateModel::Dynamic {
                 params: dynamic_params,
                 state,
-            })
+            }
         }
-        InterestRateModelParams::Linear(linear_params) => Ok(InterestRateModel::Linear {
+        InterestRateModelParams::Linear(linear_params) => InterestRateModel::Linear {
             params: linear_params,
-        }),
+        },
     }
 }
 
@@ -78,13 +107,25 @@ pub fn update_market_interest_rates_with_model(
     market: &mut Market,
     current_utilization_rate: Decimal,
 ) -> StdResult<()> {
+    let current_block_time = env.block.time.seconds();
+
+    // A ramp that has ended is settled so its target model, with its own state, is used from now
+    // on. During a ramp, the model stays the same and its params are interpolated
+    if matches!(&market.risk_params_ramp, Some(ramp) if ramp.end_time <= current_block_time) {
+        market.settle_risk_params_ramp(current_block_time)?;
+    }
+    let effective_params = market.interest_rate_model_params_at(current_block_time)?;
+
     // update borrow rate
     match market.interest_rate_model {
         InterestRateModel::Dynamic {
             ref params,
             ref mut state,
         } => {
-            let current_block_time = env.block.time.seconds();
+            let params = match &effective_params {
+                InterestRateModelParams::Dynamic(effective_params) => effective_params,
+                _ => params,
+            };
 
             // update tx count and determine if borrow rate should be updated
             state.txs_since_last_borrow_rate_update += 1;
@@ -110,6 +151,10 @@ pub fn update_market_interest_rates_with_model(
         }
 
         InterestRateModel::Linear { ref params } => {
+            let params = match &effective_params {
+                InterestRateModelParams::Linear(effective_params) => effective_params,
+                _ => params,
+            };
             market.borrow_rate = linear_get_borrow_rate(params, current_utilization_rate)?;
         }
     }
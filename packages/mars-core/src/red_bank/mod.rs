@@ -5,14 +5,17 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use cosmwasm_std::{Addr, Uint128};
+use cosmwasm_std::{Addr, StdResult, Uint128};
 
 use crate::asset::AssetType;
 use crate::error::MarsError;
 use crate::helpers::decimal_param_le_one;
 use crate::math::decimal::Decimal;
 
-use self::interest_rate_models::InterestRateModel;
+use self::interest_rate_models::{
+    DynamicInterestRateModelParams, InterestRateModel, InterestRateModelParams,
+    LinearInterestRateModelParams,
+};
 
 /// Global configuration
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -25,12 +28,26 @@ pub struct Config {
     pub ma_token_code_id: u64,
     /// Maximum percentage of outstanding debt that can be covered by a liquidator
     pub close_factor: Decimal,
+    /// Min seconds between market snapshots. Snapshots are disabled if zero
+    #[serde(default)]
+    pub market_snapshot_interval: u64,
+    /// Max number of snapshots kept per market, older ones are pruned
+    #[serde(default)]
+    pub market_snapshot_max_count: u32,
 }
 
 impl Config {
     pub fn validate(&self) -> Result<(), MarsError> {
         decimal_param_le_one(&self.close_factor, "close_factor")?;
 
+        if self.market_snapshot_interval > 0 && self.market_snapshot_max_count == 0 {
+            return Err(MarsError::InvalidParam {
+                param_name: "market_snapshot_max_count".to_string(),
+                invalid_value: self.market_snapshot_max_count.to_string(),
+                predicate: "> 0 when snapshots are enabled".to_string(),
+            });
+        }
+
         Ok(())
     }
 }
@@ -40,6 +57,9 @@ impl Config {
 pub struct GlobalState {
     /// Market count
     pub market_count: u32,
+    /// If true no user action can be performed on any market
+    #[serde(default)]
+    pub paused: bool,
 }
 
 /// Asset markets
@@ -52,9 +72,11 @@ pub struct Market {
     /// Indicated whether the asset is native or a cw20 token
     pub asset_type: AssetType,
 
-    /// Max uusd that can be borrowed per uusd collateral when using the asset as collateral
+    /// Max base asset value that can be borrowed per base asset value of collateral when using
+    /// the asset as collateral
     pub max_loan_to_value: Decimal,
-    /// uusd amount in debt position per uusd of asset collateral that if surpassed makes the user's position liquidatable.
+    /// Base asset value in debt position per base asset value of collateral that if surpassed
+    /// makes the user's position liquidatable.
     pub liquidation_threshold: Decimal,
     /// Bonus amount of collateral liquidator get when repaying user's debt (Will get collateral
     /// from user in an amount equal to debt repayed + bonus)
@@ -85,8 +107,29 @@ pub struct Market {
     pub deposit_enabled: bool,
     /// If false cannot borrow
     pub borrow_enabled: bool,
+    /// If true cannot deposit, borrow or enable the asset as collateral, while repay, withdraw
+    /// and liquidation are still allowed so users can exit their positions (e.g: when delisting
+    /// an asset). Liquidation threshold cannot be lowered while the market is frozen
+    #[serde(default)]
+    pub frozen: bool,
+    /// If true no user action can be performed on the market (set by owner or guardian
+    /// in an emergency)
+    #[serde(default)]
+    pub paused: bool,
+    /// Ongoing linear update of max_loan_to_value, liquidation_threshold and interest rate model
+    /// params. While set, max_loan_to_value, liquidation_threshold and interest_rate_model hold
+    /// the values at the start of the ramp
+    #[serde(default)]
+    pub risk_params_ramp: Option<RiskParamsRamp>,
 }
 
+/// Max change of max_loan_to_value allowed on a single ramp
+pub const MAX_LOAN_TO_VALUE_MAX_DELTA_PERCENT: u64 = 10;
+/// Max change of liquidation_threshold allowed on a single ramp
+pub const LIQUIDATION_THRESHOLD_MAX_DELTA_PERCENT: u64 = 10;
+/// Min duration (seconds) of a risk params ramp
+pub const MIN_RISK_PARAMS_RAMP_DURATION: u64 = 86_400;
+
 impl Market {
     pub fn validate(&self) -> Result<(), MarketError> {
         decimal_param_le_one(&self.max_loan_to_value, "max_loan_to_value")?;
@@ -94,17 +137,275 @@ impl Market {
         decimal_param_le_one(&self.liquidation_bonus, "liquidation_bonus")?;
 
         // liquidation_threshold should be greater than max_loan_to_value
-        if self.liquidation_threshold <= self.max_loan_to_value {
-            return Err(MarketError::InvalidLiquidationThreshold {
-                liquidation_threshold: self.liquidation_threshold,
-                max_loan_to_value: self.max_loan_to_value,
+        validate_liquidation_threshold(self.liquidation_threshold, self.max_loan_to_value)?;
+
+        if let Some(ramp) = &self.risk_params_ramp {
+            decimal_param_le_one(&ramp.target_max_loan_to_value, "max_loan_to_value")?;
+            decimal_param_le_one(&ramp.target_liquidation_threshold, "liquidation_threshold")?;
+
+            // As both ends of the ramp are valid so is any interpolated value in between
+            validate_liquidation_threshold(
+                ramp.target_liquidation_threshold,
+                ramp.target_max_loan_to_value,
+            )?;
+
+            validate_max_delta(
+                self.max_loan_to_value,
+                ramp.target_max_loan_to_value,
+                MAX_LOAN_TO_VALUE_MAX_DELTA_PERCENT,
+                "max_loan_to_value",
+            )?;
+            validate_max_delta(
+                self.liquidation_threshold,
+                ramp.target_liquidation_threshold,
+                LIQUIDATION_THRESHOLD_MAX_DELTA_PERCENT,
+                "liquidation_threshold",
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Effective max loan to value at the given timestamp
+    pub fn max_loan_to_value_at(&self, block_time: u64) -> StdResult<Decimal> {
+        match &self.risk_params_ramp {
+            Some(ramp) => ramp.interpolate(
+                self.max_loan_to_value,
+                ramp.target_max_loan_to_value,
+                block_time,
+            ),
+            None => Ok(self.max_loan_to_value),
+        }
+    }
+
+    /// Effective liquidation threshold at the given timestamp
+    pub fn liquidation_threshold_at(&self, block_time: u64) -> StdResult<Decimal> {
+        match &self.risk_params_ramp {
+            Some(ramp) => ramp.interpolate(
+                self.liquidation_threshold,
+                ramp.target_liquidation_threshold,
+                block_time,
+            ),
+            None => Ok(self.liquidation_threshold),
+        }
+    }
+
+    /// Effective interest rate model params at the given timestamp. Params of the same model are
+    /// interpolated, while a change of model takes effect at the end of the ramp
+    pub fn interest_rate_model_params_at(
+        &self,
+        block_time: u64,
+    ) -> StdResult<InterestRateModelParams> {
+        let initial = self.interest_rate_model.params();
+        match &self.risk_params_ramp {
+            Some(ramp) => match &ramp.target_interest_rate_model_params {
+                Some(target) => {
+                    ramp.interpolate_interest_rate_model_params(initial, target.clone(), block_time)
+                }
+                None => Ok(initial),
+            },
+            None => Ok(initial),
+        }
+    }
+
+    /// Stores the effective risk params at the given timestamp, removing the ramp
+    pub fn settle_risk_params_ramp(&mut self, block_time: u64) -> StdResult<()> {
+        self.max_loan_to_value = self.max_loan_to_value_at(block_time)?;
+        self.liquidation_threshold = self.liquidation_threshold_at(block_time)?;
+        let interest_rate_model_params = self.interest_rate_model_params_at(block_time)?;
+        self.interest_rate_model
+            .update_params(interest_rate_model_params, block_time);
+        self.risk_params_ramp = None;
+        Ok(())
+    }
+
+    /// Replaces any ongoing ramp with a new one going from the risk params effective at the
+    /// given timestamp to the target values. While a ramp is ongoing, max deltas are also
+    /// checked against the values it started from so a series of updates cannot move the
+    /// params further than a single one
+    pub fn start_risk_params_ramp(
+        &mut self,
+        target_max_loan_to_value: Decimal,
+        target_liquidation_threshold: Decimal,
+        target_interest_rate_model_params: Option<InterestRateModelParams>,
+        block_time: u64,
+        end_time: u64,
+    ) -> Result<(), MarketError> {
+        let min_end_time = block_time + MIN_RISK_PARAMS_RAMP_DURATION;
+        if end_time < min_end_time {
+            return Err(MarketError::RiskParamsRampTooShort {
+                end_time,
+                min_end_time,
             });
         }
 
+        if let Some(ramp) = &self.risk_params_ramp {
+            if block_time < ramp.end_time {
+                validate_max_delta(
+                    self.max_loan_to_value,
+                    target_max_loan_to_value,
+                    MAX_LOAN_TO_VALUE_MAX_DELTA_PERCENT,
+                    "max_loan_to_value",
+                )?;
+                validate_max_delta(
+                    self.liquidation_threshold,
+                    target_liquidation_threshold,
+                    LIQUIDATION_THRESHOLD_MAX_DELTA_PERCENT,
+                    "liquidation_threshold",
+                )?;
+            }
+        }
+
+        self.settle_risk_params_ramp(block_time)
+            .map_err(MarsError::from)?;
+        self.risk_params_ramp = Some(RiskParamsRamp {
+            target_max_loan_to_value,
+            target_liquidation_threshold,
+            target_interest_rate_model_params,
+            start_time: block_time,
+            end_time,
+        });
         Ok(())
     }
 }
 
+fn validate_liquidation_threshold(
+    liquidation_threshold: Decimal,
+    max_loan_to_value: Decimal,
+) -> Result<(), MarketError> {
+    if liquidation_threshold <= max_loan_to_value {
+        return Err(MarketError::InvalidLiquidationThreshold {
+            liquidation_threshold,
+            max_loan_to_value,
+        });
+    }
+    Ok(())
+}
+
+fn validate_max_delta(
+    current: Decimal,
+    target: Decimal,
+    max_delta_percent: u64,
+    param_name: &str,
+) -> Result<(), MarketError> {
+    let delta = if target > current {
+        target - current
+    } else {
+        current - target
+    };
+    let max_delta = Decimal::percent(max_delta_percent);
+    if delta > max_delta {
+        return Err(MarketError::RiskParamDeltaTooLarge {
+            param_name: param_name.to_string(),
+            current,
+            target,
+            max_delta,
+        });
+    }
+    Ok(())
+}
+
+/// Linear update of a market's risk params from their values at start_time to the
+/// target values at end_time
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct RiskParamsRamp {
+    /// max_loan_to_value at the end of the ramp
+    pub target_max_loan_to_value: Decimal,
+    /// liquidation_threshold at the end of the ramp
+    pub target_liquidation_threshold: Decimal,
+    /// Interest rate model params at the end of the ramp, if they are updated
+    #[serde(default)]
+    pub target_interest_rate_model_params: Option<InterestRateModelParams>,
+    /// Timestamp (seconds) where the ramp started
+    pub start_time: u64,
+    /// Timestamp (seconds) where target values are reached
+    pub end_time: u64,
+}
+
+impl RiskParamsRamp {
+    fn interpolate(
+        &self,
+        initial: Decimal,
+        target: Decimal,
+        block_time: u64,
+    ) -> StdResult<Decimal> {
+        if block_time >= self.end_time {
+            return Ok(target);
+        }
+        if block_time <= self.start_time {
+            return Ok(initial);
+        }
+
+        let progress = Decimal::from_ratio(
+            block_time - self.start_time,
+            self.end_time - self.start_time,
+        );
+        if target > initial {
+            Ok(initial + (target - initial).checked_mul(progress)?)
+        } else {
+            Ok(initial - (initial - target).checked_mul(progress)?)
+        }
+    }
+
+    /// Interpolates each rate of a model's params. Update thresholds and changes of model are
+    /// applied at end_time
+    fn interpolate_interest_rate_model_params(
+        &self,
+        initial: InterestRateModelParams,
+        target: InterestRateModelParams,
+        block_time: u64,
+    ) -> StdResult<InterestRateModelParams> {
+        if block_time >= self.end_time {
+            return Ok(target);
+        }
+
+        let params = match (initial, target) {
+            (
+                InterestRateModelParams::Dynamic(initial),
+                InterestRateModelParams::Dynamic(target),
+            ) => InterestRateModelParams::Dynamic(DynamicInterestRateModelParams {
+                min_borrow_rate: self.interpolate(
+                    initial.min_borrow_rate,
+                    target.min_borrow_rate,
+                    block_time,
+                )?,
+                max_borrow_rate: self.interpolate(
+                    initial.max_borrow_rate,
+                    target.max_borrow_rate,
+                    block_time,
+                )?,
+                optimal_utilization_rate: self.interpolate(
+                    initial.optimal_utilization_rate,
+                    target.optimal_utilization_rate,
+                    block_time,
+                )?,
+                kp_1: self.interpolate(initial.kp_1, target.kp_1, block_time)?,
+                kp_2: self.interpolate(initial.kp_2, target.kp_2, block_time)?,
+                kp_augmentation_threshold: self.interpolate(
+                    initial.kp_augmentation_threshold,
+                    target.kp_augmentation_threshold,
+                    block_time,
+                )?,
+                ..initial
+            }),
+            (InterestRateModelParams::Linear(initial), InterestRateModelParams::Linear(target)) => {
+                InterestRateModelParams::Linear(LinearInterestRateModelParams {
+                    optimal_utilization_rate: self.interpolate(
+                        initial.optimal_utilization_rate,
+                        target.optimal_utilization_rate,
+                        block_time,
+                    )?,
+                    base: self.interpolate(initial.base, target.base, block_time)?,
+                    slope_1: self.interpolate(initial.slope_1, target.slope_1, block_time)?,
+                    slope_2: self.interpolate(initial.slope_2, target.slope_2, block_time)?,
+                })
+            }
+            (initial, _) => initial,
+        };
+        Ok(params)
+    }
+}
+
 impl Default for Market {
     fn default() -> Self {
         let dynamic_ir_model = interest_rate_models::InterestRateModel::Dynamic {
@@ -143,6 +444,9 @@ impl Default for Market {
             active: true,
             deposit_enabled: true,
             borrow_enabled: true,
+            frozen: false,
+            paused: false,
+            risk_params_ramp: None,
         }
     }
 }
@@ -157,6 +461,17 @@ pub enum MarketError {
         liquidation_threshold: Decimal,
         max_loan_to_value: Decimal,
     },
+
+    #[error("{param_name} can change by at most {max_delta} per ramp. current: {current}, target: {target}")]
+    RiskParamDeltaTooLarge {
+        param_name: String,
+        current: Decimal,
+        target: Decimal,
+        max_delta: Decimal,
+    },
+
+    #[error("Risk params ramp should end at least at {min_end_time}. end_time: {end_time}")]
+    RiskParamsRampTooShort { end_time: u64, min_end_time: u64 },
 }
 
 /// Data for individual users
@@ -189,6 +504,143 @@ pub struct Debt {
     pub uncollateralized: bool,
 }
 
+/// Cumulative underlying amounts moved by a user in a market, used to derive the interest
+/// earned and paid. maToken transfers count as a withdrawal for the sender and a deposit
+/// for the recipient
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct UserPrincipal {
+    pub deposited: Uint128,
+    pub withdrawn: Uint128,
+    pub borrowed: Uint128,
+    pub repaid: Uint128,
+}
+
+/// Terms attached to an uncollateralized loan limit (credit line) for a given user and asset
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CreditLineTerms {
+    /// Timestamp (seconds) after which no more debt can be drawn from the credit line.
+    /// None means the credit line does not expire
+    pub expiry: Option<u64>,
+    /// Rate charged on top of the market's borrow rate for the debt drawn from the credit line
+    pub interest_premium: Decimal,
+    /// Seconds after expiry the borrower has to repay before the debt can be liquidated or
+    /// declared in default
+    pub grace_period: u64,
+    /// Timestamp (seconds) where the interest premium was last added to the debt
+    pub premium_last_accrued: u64,
+    /// Set when the owner declares the credit line in default
+    pub defaulted: bool,
+}
+
+impl CreditLineTerms {
+    /// Terms for a credit line that never expires and charges no premium
+    pub fn perpetual(block_time: u64) -> Self {
+        CreditLineTerms {
+            expiry: None,
+            interest_premium: Decimal::zero(),
+            grace_period: 0,
+            premium_last_accrued: block_time,
+            defaulted: false,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), MarsError> {
+        decimal_param_le_one(&self.interest_premium, "interest_premium")
+    }
+
+    /// Returns the lifecycle status of the credit line at the given block time
+    pub fn status(&self, block_time: u64) -> CreditLineStatus {
+        if self.defaulted {
+            return CreditLineStatus::Defaulted;
+        }
+        match self.expiry {
+            Some(expiry) if block_time >= expiry.saturating_add(self.grace_period) => {
+                CreditLineStatus::Delinquent
+            }
+            Some(expiry) if block_time >= expiry => CreditLineStatus::GracePeriod,
+            _ => CreditLineStatus::Active,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CreditLineStatus {
+    /// Debt can be drawn up to the limit and the position cannot be liquidated
+    Active,
+    /// Credit line has expired: no more debt can be drawn but the position cannot be
+    /// liquidated yet
+    GracePeriod,
+    /// Grace period is over: the debt is treated as collateralized (so the position can be
+    /// liquidated) and the owner can declare the credit line in default
+    Delinquent,
+    /// Outstanding debt was written down by the owner
+    Defaulted,
+}
+
+/// Limits the net amount of an asset (withdrawals + borrows + liquidated collateral sent,
+/// minus deposits and repayments) that can leave a market over a rolling time window
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OutflowLimit {
+    /// Max net outflow allowed over a window
+    pub max_amount: Uint128,
+    /// Window duration (seconds)
+    pub window: u64,
+    /// Net outflow accounted so far. Decays linearly at a rate of `max_amount` per `window`
+    /// so the capacity is fully recovered after a window without outflows
+    pub current_outflow: Uint128,
+    /// Timestamp (seconds) where current outflow was last updated
+    pub last_updated: u64,
+}
+
+impl OutflowLimit {
+    pub fn validate(&self) -> Result<(), MarsError> {
+        if self.window == 0 {
+            return Err(MarsError::InvalidParam {
+                param_name: "window".to_string(),
+                invalid_value: self.window.to_string(),
+                predicate: "> 0".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Net outflow at the given block time after applying the decay since last update
+    pub fn current_outflow_at(&self, block_time: u64) -> Uint128 {
+        let elapsed = block_time
+            .saturating_sub(self.last_updated)
+            .min(self.window);
+        let decay = self
+            .max_amount
+            .multiply_ratio(Uint128::from(elapsed), Uint128::from(self.window));
+        self.current_outflow.saturating_sub(decay)
+    }
+
+    /// Amount that can still leave the market at the given block time
+    pub fn remaining_capacity(&self, block_time: u64) -> Uint128 {
+        self.max_amount
+            .saturating_sub(self.current_outflow_at(block_time))
+    }
+}
+
+/// maTokens locked in a market's withdraw queue, to be paid out in the underlying asset
+/// (in FIFO order) when the market has available liquidity
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WithdrawRequest {
+    /// Address that locked the maTokens
+    pub user_address: Addr,
+    /// Address that receives the underlying asset
+    pub recipient_address: Addr,
+    /// maToken amount still to be paid out. Keeps accruing interest until it is
+    pub amount_scaled: Uint128,
+    /// Total maToken amount queued in the market before this request plus the part of it
+    /// already paid out. The difference with the offset of the first request in the queue is
+    /// the amount ahead of this one
+    pub queue_offset_scaled: Uint128,
+    /// Timestamp (seconds) when the request was queued
+    pub created_at: u64,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum UserHealthStatus {
@@ -203,6 +655,9 @@ pub struct ConfigResponse {
     pub ma_token_code_id: u64,
     pub market_count: u32,
     pub close_factor: Decimal,
+    pub paused: bool,
+    pub market_snapshot_interval: u64,
+    pub market_snapshot_max_count: u32,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -265,12 +720,135 @@ pub struct UserAssetCollateralResponse {
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+/// Amounts are denominated in the oracle's base asset. Aliases keep responses from
+/// deployments where it was hard-coded to uusd readable
 pub struct UserPositionResponse {
-    pub total_collateral_in_uusd: Uint128,
-    pub total_debt_in_uusd: Uint128,
+    #[serde(alias = "total_collateral_in_uusd")]
+    pub total_collateral_in_base_asset: Uint128,
+    #[serde(alias = "total_debt_in_uusd")]
+    pub total_debt_in_base_asset: Uint128,
     /// Total debt minus the uncollateralized debt
-    pub total_collateralized_debt_in_uusd: Uint128,
-    pub max_debt_in_uusd: Uint128,
-    pub weighted_liquidation_threshold_in_uusd: Uint128,
+    #[serde(alias = "total_collateralized_debt_in_uusd")]
+    pub total_collateralized_debt_in_base_asset: Uint128,
+    #[serde(alias = "max_debt_in_uusd")]
+    pub max_debt_in_base_asset: Uint128,
+    #[serde(alias = "weighted_liquidation_threshold_in_uusd")]
+    pub weighted_liquidation_threshold_in_base_asset: Uint128,
     pub health_status: UserHealthStatus,
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct CreditLineResponse {
+    /// Uncollateralized loan limit
+    pub limit: Uint128,
+    /// Terms of the credit line
+    pub terms: CreditLineTerms,
+    /// Lifecycle status of the credit line at the current block
+    pub status: CreditLineStatus,
+    /// Underlying debt amount owed at the current block, including the interest premium
+    /// accrued since it was last added to the debt
+    pub debt_amount: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OutflowCapacityResponse {
+    /// Outflow limit set for the market (None if outflows are not limited)
+    pub limit: Option<OutflowLimit>,
+    /// Amount that can still leave the market at the current block (None if unlimited)
+    pub remaining_capacity: Option<Uint128>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WithdrawRequestResponse {
+    /// Request id (requests are paid out in increasing id order)
+    pub id: u64,
+    /// Address that locked the maTokens
+    pub user_address: Addr,
+    /// Address that receives the underlying asset
+    pub recipient_address: Addr,
+    /// maToken amount still to be paid out
+    pub amount_scaled: Uint128,
+    /// Underlying amount still to be paid out at the current block
+    pub amount: Uint128,
+    /// Underlying amount queued ahead of this request at the current block. Requests cancelled
+    /// ahead of this one are still included until the queue is processed up to them
+    pub amount_ahead: Uint128,
+    /// Underlying amount that would (at least) be paid out if the queue was processed at the
+    /// current block
+    pub expected_fill: Uint128,
+    /// Timestamp (seconds) when the request was queued
+    pub created_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct WithdrawQueueResponse {
+    pub requests: Vec<WithdrawRequestResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarketRiskParamsResponse {
+    /// Effective max loan to value at the current block
+    pub max_loan_to_value: Decimal,
+    /// Effective liquidation threshold at the current block
+    pub liquidation_threshold: Decimal,
+    /// Effective interest rate model params at the current block
+    pub interest_rate_model_params: InterestRateModelParams,
+    /// Max loan to value once the ongoing ramp ends (same as current if there is no ramp)
+    pub target_max_loan_to_value: Decimal,
+    /// Liquidation threshold once the ongoing ramp ends (same as current if there is no ramp)
+    pub target_liquidation_threshold: Decimal,
+    /// Interest rate model params once the ongoing ramp ends (same as current if there is no
+    /// ramp)
+    pub target_interest_rate_model_params: InterestRateModelParams,
+    /// Timestamp (seconds) where the ongoing ramp ends, if any
+    pub ramp_end_time: Option<u64>,
+}
+
+/// Market indexes, rates and totals at a point in time
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarketSnapshot {
+    /// Timestamp (seconds) of the snapshot
+    pub timestamp: u64,
+    pub borrow_index: Decimal,
+    pub liquidity_index: Decimal,
+    pub borrow_rate: Decimal,
+    pub liquidity_rate: Decimal,
+    /// Total debt (underlying amount) of the market
+    pub debt_total: Uint128,
+    /// Asset balance of the red bank available to be withdrawn or borrowed
+    pub available_liquidity: Uint128,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MarketHistoryResponse {
+    pub snapshots: Vec<MarketSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UserPnlResponse {
+    pub markets: Vec<UserAssetPnlResponse>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct UserAssetPnlResponse {
+    /// Asset denom
+    pub denom: String,
+    /// Either denom if native asset or contract address if cw20
+    pub asset_label: String,
+    /// Bytes used as key on the kv store for data related to the asset
+    pub asset_reference: Vec<u8>,
+    /// Indicated whether the asset is native or a cw20 token
+    pub asset_type: AssetType,
+    /// Cumulative principal amounts
+    pub principal: UserPrincipal,
+    /// Underlying amount deposited at the current block
+    pub collateral: Uint128,
+    /// Underlying amount locked in the withdraw queue at the current block
+    pub queued: Uint128,
+    /// Underlying amount owed at the current block
+    pub debt: Uint128,
+    /// collateral + queued + withdrawn - deposited
+    pub interest_earned: Uint128,
+    /// debt + repaid - borrowed
+    pub interest_paid: Uint128,
+}
@@ -56,7 +56,7 @@ pub enum ExecuteMsg {
     },
 
     /// Update uncollateralized loan limit for a given user and asset.
-    /// Overrides previous value if any. A limit of zero means no
+    /// Overrides previous value and credit line terms if any. A limit of zero means no
     /// uncollateralized limit and the debt in that asset needs to be
     /// collateralized (only owner can call)
     UpdateUncollateralizedLoanLimit {
@@ -66,8 +66,62 @@ pub enum ExecuteMsg {
         asset: Asset,
         /// Limit for the uncolateralize loan.
         new_limit: Uint128,
+        /// Timestamp (seconds) after which no more debt can be drawn. If None is
+        /// specified, the credit line does not expire
+        expiry: Option<u64>,
+        /// Rate charged on top of the market's borrow rate. Defaults to zero
+        interest_premium: Option<Decimal>,
+        /// Seconds after expiry before the debt can be liquidated or declared in default.
+        /// Defaults to zero
+        grace_period: Option<u64>,
     },
 
+    /// Declare a credit line whose grace period is over in default, writing down the
+    /// outstanding debt (only owner can call)
+    DeclareDefault {
+        /// Address that received the credit
+        user_address: String,
+        /// Asset the credit was given in
+        asset: Asset,
+    },
+
+    /// Pay out queued withdraw requests for the given market in FIFO order using the
+    /// available liquidity. Deposits and repayments process the first few requests, anyone
+    /// can call it to go through the rest of the queue
+    ProcessWithdrawQueue {
+        /// Asset whose withdraw queue gets processed
+        asset: Asset,
+        /// Max number of requests to process
+        limit: Option<u32>,
+    },
+
+    /// Cancel a queued withdraw request, sending back the maTokens still locked in it
+    /// (only the user that queued the request can call)
+    CancelWithdrawRequest {
+        /// Asset the request was queued for
+        asset: Asset,
+        /// Id of the request
+        request_id: u64,
+    },
+
+    /// Set the max net outflow allowed for a market over a rolling window. Outflows past
+    /// the limit are rejected, except for liquidations which are accounted but never blocked.
+    /// If None, the limit is removed (only owner can call)
+    UpdateOutflowLimit {
+        /// Asset the limit applies to
+        asset: Asset,
+        /// Max net outflow allowed over a window and window duration (seconds)
+        limit: Option<OutflowLimitParams>,
+    },
+
+    /// Pause the given market or, if no asset is specified, the whole contract. While paused
+    /// no user action can be performed on it (only owner or guardian can call)
+    Pause { asset: Option<Asset> },
+
+    /// Unpause the given market or, if no asset is specified, the whole contract
+    /// (only owner can call)
+    Unpause { asset: Option<Asset> },
+
     /// Deposit Terra native coins. Deposited coins must be sent in the transaction
     /// this call is made
     DepositNative {
@@ -176,6 +230,12 @@ pub enum ReceiveMsg {
         /// the underlying collateral asset (false)
         receive_ma_token: bool,
     },
+    /// Lock sent maTokens in the market's withdraw queue. The underlying asset gets paid out
+    /// when the market has enough available liquidity
+    QueueWithdraw {
+        /// The address where the withdrawn amount is sent
+        recipient: Option<String>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -184,6 +244,8 @@ pub struct CreateOrUpdateConfig {
     pub address_provider_address: Option<String>,
     pub ma_token_code_id: Option<u64>,
     pub close_factor: Option<Decimal>,
+    pub market_snapshot_interval: Option<u64>,
+    pub market_snapshot_max_count: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -193,15 +255,18 @@ pub struct InitOrUpdateAssetParams {
 
     /// Portion of the borrow rate that is kept as protocol rewards
     pub reserve_factor: Option<Decimal>,
-    /// Max uusd that can be borrowed per uusd of collateral when using the asset as collateral
+    /// Max base asset value that can be borrowed per base asset value of collateral when using
+    /// the asset as collateral
     pub max_loan_to_value: Option<Decimal>,
-    /// uusd amount in debt position per uusd of asset collateral that if surpassed makes the user's position liquidatable.
+    /// Base asset value in debt position per base asset value of collateral that if surpassed
+    /// makes the user's position liquidatable.
     pub liquidation_threshold: Option<Decimal>,
     /// Bonus amount of collateral liquidator get when repaying user's debt (Will get collateral
     /// from user in an amount equal to debt repayed + bonus)
     pub liquidation_bonus: Option<Decimal>,
 
-    /// Interest rate strategy to calculate borrow_rate and liquidity_rate
+    /// Interest rate strategy to calculate borrow_rate and liquidity_rate. Updates are
+    /// ramped like max_loan_to_value and liquidation_threshold
     pub interest_rate_model_params: Option<InterestRateModelParams>,
 
     /// If false cannot do any action (deposit/withdraw/borrow/repay/liquidate)
@@ -210,6 +275,24 @@ pub struct InitOrUpdateAssetParams {
     pub deposit_enabled: Option<bool>,
     /// If false cannot borrow
     pub borrow_enabled: Option<bool>,
+    /// If true cannot deposit, borrow or enable the asset as collateral but can still repay,
+    /// withdraw and liquidate. Defaults to false on init
+    pub frozen: Option<bool>,
+    /// Timestamp (seconds) at which updated max_loan_to_value, liquidation_threshold and
+    /// interest_rate_model_params are reached, interpolating linearly from the current values
+    /// until then (a change of interest rate model type, and its update thresholds, only apply
+    /// then). Has to be at least MIN_RISK_PARAMS_RAMP_DURATION in the future, which is the
+    /// default if None. Updates that don't set any of these params leave an ongoing ramp
+    /// untouched. Not allowed on init
+    pub risk_params_ramp_end_time: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct OutflowLimitParams {
+    /// Max net outflow allowed over a window
+    pub max_amount: Uint128,
+    /// Window duration (seconds)
+    pub window: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -221,13 +304,50 @@ pub enum QueryMsg {
     /// Get asset market
     Market { asset: Asset },
 
+    /// Get current and target risk params of an asset market.
+    /// Returns MarketRiskParamsResponse
+    MarketRiskParams { asset: Asset },
+
     /// Get a list of all markets. Returns MarketsListResponse
     MarketsList {},
 
+    /// Get periodic snapshots of a market's indexes, rates and totals, oldest first.
+    /// Returns MarketHistoryResponse
+    MarketHistory {
+        asset: Asset,
+        /// Timestamp (seconds) to start after
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
     /// Get uncollateralized limit for given asset and user.
     /// Returns UncollateralizedLoanLimitResponse
     UncollateralizedLoanLimit { user_address: String, asset: Asset },
 
+    /// Get credit line (uncollateralized limit and its terms) for given asset and user.
+    /// Returns CreditLineResponse
+    CreditLine { user_address: String, asset: Asset },
+
+    /// Get withdraw requests queued for given asset in the order they are paid out.
+    /// Returns WithdrawQueueResponse
+    WithdrawQueue {
+        asset: Asset,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    /// Get withdraw requests queued by a user for given asset. Returns WithdrawQueueResponse
+    UserWithdrawRequests {
+        user_address: String,
+        asset: Asset,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    /// Get outflow limit for given asset and how much can still be withdrawn or borrowed
+    /// at the current block. Returns OutflowCapacityResponse
+    OutflowCapacity { asset: Asset },
+
     /// Get all debt positions for a user. Returns UsetDebtResponse
     UserDebt { user_address: String },
 
@@ -241,6 +361,10 @@ pub enum QueryMsg {
     /// Get user position. Returns UserPositionResponse
     UserPosition { user_address: String },
 
+    /// Get the cumulative principal and the interest earned and paid by a user on each
+    /// market they have interacted with. Returns UserPnlResponse
+    UserPnl { user_address: String },
+
     /// Get liquidity scaled amount for a given underlying asset amount
     /// (i.e: how much maTokens will get minted if the given amount is deposited)
     ScaledLiquidityAmount { asset: Asset, amount: Uint128 },
@@ -263,3 +387,6 @@ pub enum QueryMsg {
         amount_scaled: Uint128,
     },
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct MigrateMsg {}
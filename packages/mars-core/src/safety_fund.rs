@@ -36,4 +36,7 @@ pub mod msg {
         /// Query contract config
         Config {},
     }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct MigrateMsg {}
 }
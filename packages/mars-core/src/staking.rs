@@ -1,7 +1,9 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::error::MarsError;
 use crate::math::decimal::Decimal;
+use crate::swapping::{validate_swap_max_oracle_deviation, SwapVenueChecked};
 use cosmwasm_std::{Addr, Decimal as StdDecimal, Uint128};
 
 /// Protocol configuration
@@ -17,9 +19,30 @@ pub struct Config {
     pub astroport_factory_address: Addr,
     /// Astroport max spread
     pub astroport_max_spread: StdDecimal,
+    /// Maximum deviation of a swap output from the value given by oracle prices (no oracle check
+    /// if not set)
+    #[serde(default)]
+    pub swap_max_oracle_deviation: Option<Decimal>,
+    /// Venue swaps are executed on (`astroport_max_spread` applies to all venues)
+    #[serde(default)]
+    pub swap_venue: SwapVenueChecked,
 
     /// Cooldown duration in seconds
     pub cooldown_duration: u64,
+
+    /// If true swaps cannot be executed (set by owner or guardian in an emergency)
+    #[serde(default)]
+    pub paused: bool,
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), MarsError> {
+        if let Some(max_deviation) = &self.swap_max_oracle_deviation {
+            validate_swap_max_oracle_deviation(&self.swap_venue, max_deviation)?;
+        }
+
+        Ok(())
+    }
 }
 
 /// Global State
@@ -62,6 +85,11 @@ pub mod msg {
     use schemars::JsonSchema;
     use serde::{Deserialize, Serialize};
 
+    use astroport::asset::AssetInfo;
+
+    use crate::math::decimal::Decimal;
+    use crate::swapping::SwapVenueUnchecked;
+
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
     pub struct InstantiateMsg {
         pub config: CreateOrUpdateConfig,
@@ -73,6 +101,12 @@ pub mod msg {
         pub address_provider_address: Option<String>,
         pub astroport_factory_address: Option<String>,
         pub astroport_max_spread: Option<StdDecimal>,
+        /// Defaults to `None` on instantiation, which leaves the permissionless `SwapUusdToMars`
+        /// protected by `astroport_max_spread` only. Cannot be removed through `UpdateConfig`,
+        /// use `SetSwapMaxOracleDeviation` instead
+        pub swap_max_oracle_deviation: Option<Decimal>,
+        /// Defaults to Astroport on instantiation
+        pub swap_venue: Option<SwapVenueUnchecked>,
         pub cooldown_duration: Option<u64>,
     }
 
@@ -94,7 +128,29 @@ pub mod msg {
 
         /// Swap uusd on the contract to Mars. Meant for received protocol rewards in order
         /// for them to belong to xMars holders as underlying Mars.
-        SwapUusdToMars { amount: Option<Uint128> },
+        SwapUusdToMars {
+            amount: Option<Uint128>,
+            /// Minimum amount of Mars to receive, over the whole swap route.
+            /// Requires `swap_max_oracle_deviation` to be set, as do non direct routes
+            minimum_receive: Option<Uint128>,
+        },
+
+        /// Set the route used to swap an asset to Mars, through the given intermediate assets
+        /// (only callable by owner). Empty `hops` removes the route so the asset is swapped directly
+        SetSwapRoute {
+            offer_asset_info: AssetInfo,
+            hops: Vec<AssetInfo>,
+        },
+
+        /// Set or remove (if `None`) the maximum deviation of swap outputs from oracle prices
+        /// (only callable by owner)
+        SetSwapMaxOracleDeviation { max_deviation: Option<Decimal> },
+
+        /// Pause swaps (only callable by owner or guardian)
+        Pause {},
+
+        /// Unpause swaps (only callable by owner)
+        Unpause {},
     }
 
     #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -130,5 +186,15 @@ pub mod msg {
         /// Get open claim for given user. If claim exists, slash events are applied to the amount
         /// so actual amount of Mars received is given.
         Claim { user_address: String },
+        /// Get the route used to swap an asset (with no hops if the asset is swapped directly)
+        SwapRoute { offer_asset_info: AssetInfo },
+        /// Enumerate swap routes with pagination
+        SwapRoutes {
+            start_after: Option<AssetInfo>,
+            limit: Option<u32>,
+        },
     }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct MigrateMsg {}
 }
@@ -1,25 +1,385 @@
-use crate::helpers::cw20_get_balance;
+use crate::address_provider::{self, MarsContract};
+use crate::asset::Asset;
+use crate::error::MarsError;
+use crate::helpers::{cw20_get_balance, decimal_param_le_one};
+use crate::math::decimal::Decimal;
+use crate::oracle;
 use astroport::{
     asset::{Asset as AstroportAsset, AssetInfo, PairInfo},
     pair::ExecuteMsg as AstroportPairExecuteMsg,
     querier::query_pair_info,
+    router::{
+        Cw20HookMsg as AstroportRouterCw20HookMsg, ExecuteMsg as AstroportRouterExecuteMsg,
+        SwapOperation,
+    },
 };
 use cosmwasm_std::{
-    attr, to_binary, Addr, Coin, CosmosMsg, Decimal as StdDecimal, DepsMut, Empty, Env, Response,
-    StdError, StdResult, Uint128, WasmMsg,
+    attr, to_binary, Addr, Api, Binary, Coin, CosmosMsg, Decimal as StdDecimal, DepsMut, Empty,
+    Env, QuerierWrapper, Response, StdError, StdResult, Uint128, WasmMsg,
 };
 use cw20::Cw20ExecuteMsg;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
-/// Swap assets via Astroport
+/// Venue used by a contract to swap assets
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SwapVenue<A> {
+    /// Astroport pairs, looked up in the Astroport factory set in the contract config
+    Astroport {
+        /// Astroport router, needed to swap through routes
+        router_address: Option<A>,
+    },
+    /// Single contract holding every pool (Osmosis style pool manager), see
+    /// [`PoolManagerExecuteMsg`] for the expected interface
+    PoolManager { address: A },
+}
+
+pub type SwapVenueUnchecked = SwapVenue<String>;
+pub type SwapVenueChecked = SwapVenue<Addr>;
+
+impl<A> Default for SwapVenue<A> {
+    fn default() -> Self {
+        SwapVenue::Astroport {
+            router_address: None,
+        }
+    }
+}
+
+impl SwapVenueUnchecked {
+    pub fn to_checked(&self, api: &dyn Api) -> StdResult<SwapVenueChecked> {
+        Ok(match self {
+            SwapVenueUnchecked::Astroport { router_address } => SwapVenueChecked::Astroport {
+                router_address: router_address
+                    .as_ref()
+                    .map(|router_address| api.addr_validate(router_address))
+                    .transpose()?,
+            },
+            SwapVenueUnchecked::PoolManager { address } => SwapVenueChecked::PoolManager {
+                address: api.addr_validate(address)?,
+            },
+        })
+    }
+}
+
+impl SwapVenueChecked {
+    /// Adapter building the swap messages for the venue
+    pub fn adapter(&self, astroport_factory_address: Addr) -> Box<dyn SwapAdapter> {
+        match self {
+            SwapVenueChecked::Astroport { router_address } => Box::new(AstroportSwapAdapter {
+                factory_address: astroport_factory_address,
+                router_address: router_address.clone(),
+            }),
+            SwapVenueChecked::PoolManager { address } => Box::new(PoolManagerSwapAdapter {
+                pool_manager_address: address.clone(),
+            }),
+        }
+    }
+}
+
+/// Builds the messages needed to swap assets on a given venue
+pub trait SwapAdapter {
+    /// Message swapping `offer_asset` (held by the contract) into the `ask_asset_info` asset
+    fn swap_msg(
+        &self,
+        querier: &QuerierWrapper,
+        offer_asset: AstroportAsset,
+        ask_asset_info: AssetInfo,
+        max_spread: Option<StdDecimal>,
+    ) -> StdResult<CosmosMsg<Empty>>;
+
+    /// Message swapping `offer_asset` (held by the contract) through every asset of `path` in
+    /// order, the last one being the ask asset. Fails if less than `minimum_receive` of the ask
+    /// asset is received at the end of the route
+    fn swap_route_msg(
+        &self,
+        querier: &QuerierWrapper,
+        offer_asset: AstroportAsset,
+        path: Vec<AssetInfo>,
+        minimum_receive: Option<Uint128>,
+    ) -> StdResult<CosmosMsg<Empty>>;
+}
+
+/// Swaps on the Astroport pair of the two assets, or through the Astroport router for routes
+pub struct AstroportSwapAdapter {
+    pub factory_address: Addr,
+    pub router_address: Option<Addr>,
+}
+
+impl SwapAdapter for AstroportSwapAdapter {
+    fn swap_msg(
+        &self,
+        querier: &QuerierWrapper,
+        offer_asset: AstroportAsset,
+        ask_asset_info: AssetInfo,
+        max_spread: Option<StdDecimal>,
+    ) -> StdResult<CosmosMsg<Empty>> {
+        let pair_info: PairInfo = query_pair_info(
+            querier,
+            self.factory_address.clone(),
+            &[offer_asset.info.clone(), ask_asset_info],
+        )?;
+
+        let swap_msg = AstroportPairExecuteMsg::Swap {
+            offer_asset: offer_asset.clone(),
+            belief_price: None,
+            max_spread,
+            to: None,
+        };
+        asset_into_swap_msg(pair_info.contract_addr, offer_asset, to_binary(&swap_msg)?)
+    }
+
+    fn swap_route_msg(
+        &self,
+        _querier: &QuerierWrapper,
+        offer_asset: AstroportAsset,
+        path: Vec<AssetInfo>,
+        minimum_receive: Option<Uint128>,
+    ) -> StdResult<CosmosMsg<Empty>> {
+        let router_address = self.router_address.clone().ok_or_else(|| {
+            StdError::generic_err("Astroport router address is required to swap through a route")
+        })?;
+
+        let mut operations = vec![];
+        let mut offer_asset_info = offer_asset.info.clone();
+        for ask_asset_info in path {
+            operations.push(SwapOperation::AstroSwap {
+                offer_asset_info,
+                ask_asset_info: ask_asset_info.clone(),
+            });
+            offer_asset_info = ask_asset_info;
+        }
+
+        let swap_msg = match &offer_asset.info {
+            AssetInfo::NativeToken { .. } => {
+                to_binary(&AstroportRouterExecuteMsg::ExecuteSwapOperations {
+                    operations,
+                    minimum_receive,
+                    to: None,
+                })?
+            }
+            AssetInfo::Token { .. } => {
+                to_binary(&AstroportRouterCw20HookMsg::ExecuteSwapOperations {
+                    operations,
+                    minimum_receive,
+                    to: None,
+                })?
+            }
+        };
+        asset_into_swap_msg(router_address, offer_asset, swap_msg)
+    }
+}
+
+/// Execute message of a pool manager contract. Native assets are sent as funds along with the
+/// message, cw20 tokens are sent with `Cw20ExecuteMsg::Send` with the message as hook
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolManagerExecuteMsg {
+    /// Swap the offer asset into the ask asset through the pool holding both
+    Swap {
+        offer_asset: AstroportAsset,
+        ask_asset_info: AssetInfo,
+        max_spread: Option<StdDecimal>,
+    },
+    /// Swap the offer asset through the pools of each consecutive pair of assets in `path`
+    SwapRoute {
+        offer_asset: AstroportAsset,
+        path: Vec<AssetInfo>,
+        minimum_receive: Option<Uint128>,
+    },
+}
+
+/// Swaps on a single contract holding every pool
+pub struct PoolManagerSwapAdapter {
+    pub pool_manager_address: Addr,
+}
+
+impl SwapAdapter for PoolManagerSwapAdapter {
+    fn swap_msg(
+        &self,
+        _querier: &QuerierWrapper,
+        offer_asset: AstroportAsset,
+        ask_asset_info: AssetInfo,
+        max_spread: Option<StdDecimal>,
+    ) -> StdResult<CosmosMsg<Empty>> {
+        let swap_msg = PoolManagerExecuteMsg::Swap {
+            offer_asset: offer_asset.clone(),
+            ask_asset_info,
+            max_spread,
+        };
+        asset_into_swap_msg(
+            self.pool_manager_address.clone(),
+            offer_asset,
+            to_binary(&swap_msg)?,
+        )
+    }
+
+    fn swap_route_msg(
+        &self,
+        _querier: &QuerierWrapper,
+        offer_asset: AstroportAsset,
+        path: Vec<AssetInfo>,
+        minimum_receive: Option<Uint128>,
+    ) -> StdResult<CosmosMsg<Empty>> {
+        let swap_msg = PoolManagerExecuteMsg::SwapRoute {
+            offer_asset: offer_asset.clone(),
+            path,
+            minimum_receive,
+        };
+        asset_into_swap_msg(
+            self.pool_manager_address.clone(),
+            offer_asset,
+            to_binary(&swap_msg)?,
+        )
+    }
+}
+
+/// Maximum number of intermediate assets in a swap route
+pub const MAX_SWAP_ROUTE_HOPS: usize = 4;
+
+/// Route used to swap an asset that has no direct pool with the ask asset
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct SwapRoute {
+    /// Asset swapped through the route
+    pub offer_asset_info: AssetInfo,
+    /// Intermediate assets the offer asset is swapped through, in order, before being swapped
+    /// into the ask asset. Empty for a direct swap
+    pub hops: Vec<AssetInfo>,
+}
+
+impl SwapRoute {
+    /// Direct swap, with no intermediate asset
+    pub fn direct(offer_asset_info: AssetInfo) -> Self {
+        SwapRoute {
+            offer_asset_info,
+            hops: vec![],
+        }
+    }
+
+    /// Every asset the route swaps into, the ask asset being the last one
+    pub fn path(&self, ask_asset_info: &AssetInfo) -> Vec<AssetInfo> {
+        let mut path = self.hops.clone();
+        path.push(ask_asset_info.clone());
+        path
+    }
+
+    pub fn validate(&self, ask_asset_info: &AssetInfo) -> StdResult<()> {
+        if self.hops.len() > MAX_SWAP_ROUTE_HOPS {
+            return Err(StdError::generic_err(format!(
+                "Swap route cannot have more than {} hops",
+                MAX_SWAP_ROUTE_HOPS
+            )));
+        }
+
+        let mut assets = vec![self.offer_asset_info.clone()];
+        assets.extend(self.path(ask_asset_info));
+        for (i, asset_info) in assets.iter().enumerate() {
+            if assets[i + 1..].contains(asset_info) {
+                return Err(StdError::generic_err(format!(
+                    "Swap route goes through {} more than once",
+                    asset_info
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Storage key of the swap route of an offer asset
+pub fn swap_route_key(offer_asset_info: &AssetInfo) -> Vec<u8> {
+    Asset::from(offer_asset_info).get_reference()
+}
+
+/// Protection against unfavourable prices when swapping
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SwapSlippage {
+    /// Maximum spread of a direct swap
+    pub max_spread: Option<StdDecimal>,
+    /// Minimum amount of the ask asset to receive at the end of the swap. When set, direct swaps
+    /// are also executed as a route. Only allowed along with a max oracle deviation
+    pub minimum_receive: Option<Uint128>,
+    /// If set, the minimum amount to receive is raised to the value of the offer asset given by
+    /// the oracle, minus the maximum deviation. Required to swap through a route
+    pub max_oracle_deviation: Option<OracleDeviation>,
+}
+
+/// Maximum deviation of a swap output from the value given by oracle prices
+#[derive(Clone, Debug, PartialEq)]
+pub struct OracleDeviation {
+    /// Address provider, used to get the oracle address
+    pub address_provider_address: Addr,
+    pub max_deviation: Decimal,
+}
+
+/// Validates the maximum deviation from oracle prices allowed when swapping on the venue
+pub fn validate_swap_max_oracle_deviation(
+    swap_venue: &SwapVenueChecked,
+    max_deviation: &Decimal,
+) -> Result<(), MarsError> {
+    decimal_param_le_one(max_deviation, "swap_max_oracle_deviation")?;
+
+    // NOTE: Astroport pairs don't take a minimum output, swaps with a minimum go through the router
+    if let SwapVenueChecked::Astroport {
+        router_address: None,
+    } = swap_venue
+    {
+        return Err(StdError::generic_err(
+            "Astroport router address is required to enforce a minimum swap output",
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+/// Minimum amount of the ask asset that swapping the offer asset should return, according to
+/// oracle prices and the maximum deviation allowed from them
+pub fn query_oracle_minimum_receive(
+    querier: &QuerierWrapper,
+    oracle_deviation: &OracleDeviation,
+    offer_asset: &AstroportAsset,
+    ask_asset_info: &AssetInfo,
+) -> StdResult<Uint128> {
+    let oracle_address = address_provider::helpers::query_address(
+        querier,
+        oracle_deviation.address_provider_address.clone(),
+        MarsContract::Oracle,
+    )?;
+    let base_denom = oracle::helpers::query_base_denom(*querier, oracle_address.clone())?;
+
+    let query_asset_price = |asset_info: &AssetInfo| -> StdResult<Decimal> {
+        let (asset_label, asset_reference, asset_type) = Asset::from(asset_info).get_attributes();
+        oracle::helpers::query_price(
+            *querier,
+            oracle_address.clone(),
+            &base_denom,
+            &asset_label,
+            asset_reference,
+            asset_type,
+        )
+    };
+    let offer_asset_price = query_asset_price(&offer_asset.info)?;
+    let ask_asset_price = query_asset_price(ask_asset_info)?;
+
+    let offer_asset_value = offer_asset.amount * offer_asset_price;
+    let expected_return = Decimal::divide_uint128_by_decimal(offer_asset_value, ask_asset_price)?;
+
+    Ok(expected_return * (Decimal::one() - oracle_deviation.max_deviation))
+}
+
+/// Swap assets via the given swap adapter, through the intermediate assets of the route if any
 pub fn execute_swap(
     deps: DepsMut,
     env: Env,
-    offer_asset_info: AssetInfo,
+    route: SwapRoute,
     ask_asset_info: AssetInfo,
     amount: Option<Uint128>,
-    astroport_factory_addr: Addr,
-    astroport_max_spread: Option<StdDecimal>,
+    swap_adapter: &dyn SwapAdapter,
+    slippage: SwapSlippage,
 ) -> StdResult<Response> {
+    let offer_asset_info = route.offer_asset_info.clone();
+
     // Having the same asset as offer and ask asset doesn't make any sense
     if offer_asset_info == ask_asset_info {
         return Err(StdError::generic_err(format!(
@@ -48,8 +408,8 @@ pub fn execute_swap(
         }
     };
 
-    let ask_asset_label = match ask_asset_info.clone() {
-        AssetInfo::NativeToken { denom } => denom,
+    let ask_asset_label = match &ask_asset_info {
+        AssetInfo::NativeToken { denom } => denom.clone(),
         AssetInfo::Token { contract_addr } => contract_addr.to_string(),
     };
 
@@ -71,48 +431,79 @@ pub fn execute_swap(
         None => contract_offer_asset_balance,
     };
 
-    let pair_info: PairInfo = query_pair_info(
-        &deps.querier,
-        astroport_factory_addr,
-        &[offer_asset_info.clone(), ask_asset_info],
-    )?;
-
     let offer_asset = AstroportAsset {
         info: offer_asset_info,
         amount: amount_to_swap,
     };
-    let send_msg = asset_into_swap_msg(
-        deps.api
-            .addr_validate(&pair_info.contract_addr.to_string())?,
-        offer_asset,
-        astroport_max_spread,
-    )?;
 
-    let response = Response::new().add_message(send_msg).add_attributes(vec![
+    // Routes don't take a max spread on each hop, and a minimum set by the caller cannot be
+    // trusted on permissionless swaps, so the output has to be bounded by oracle prices
+    if (!route.hops.is_empty() || slippage.minimum_receive.is_some())
+        && slippage.max_oracle_deviation.is_none()
+    {
+        return Err(StdError::generic_err(
+            "A max oracle deviation is required to swap through a route or with a minimum output",
+        ));
+    }
+
+    let mut minimum_receive = slippage.minimum_receive;
+    if let Some(oracle_deviation) = &slippage.max_oracle_deviation {
+        let oracle_minimum_receive = query_oracle_minimum_receive(
+            &deps.querier,
+            oracle_deviation,
+            &offer_asset,
+            &ask_asset_info,
+        )?;
+        minimum_receive = Some(match minimum_receive {
+            Some(minimum_receive) => minimum_receive.max(oracle_minimum_receive),
+            None => oracle_minimum_receive,
+        });
+    }
+
+    let send_msg = if route.hops.is_empty() && minimum_receive.is_none() {
+        swap_adapter.swap_msg(
+            &deps.querier,
+            offer_asset,
+            ask_asset_info,
+            slippage.max_spread,
+        )?
+    } else {
+        route.validate(&ask_asset_info)?;
+        swap_adapter.swap_route_msg(
+            &deps.querier,
+            offer_asset,
+            route.path(&ask_asset_info),
+            minimum_receive,
+        )?
+    };
+
+    let mut response = Response::new().add_message(send_msg).add_attributes(vec![
         attr("action", "swap"),
         attr("offer_asset", offer_asset_label),
         attr("ask_asset", ask_asset_label),
         attr("offer_asset_amount", amount_to_swap),
     ]);
+    if !route.hops.is_empty() {
+        let hops: Vec<String> = route.hops.iter().map(|hop| hop.to_string()).collect();
+        response = response.add_attribute("route", hops.join(","));
+    }
+    if let Some(minimum_receive) = minimum_receive {
+        response = response.add_attribute("minimum_receive", minimum_receive);
+    }
 
     Ok(response)
 }
 
-/// Construct Astroport message in order to swap assets
+/// Construct message sending the offer asset along with the swap message to the swap contract
 fn asset_into_swap_msg(
-    pair_contract: Addr,
+    swap_contract: Addr,
     offer_asset: AstroportAsset,
-    max_spread: Option<StdDecimal>,
+    swap_msg: Binary,
 ) -> StdResult<CosmosMsg<Empty>> {
-    let message = match offer_asset.info.clone() {
+    let message = match offer_asset.info {
         AssetInfo::NativeToken { denom } => CosmosMsg::Wasm(WasmMsg::Execute {
-            contract_addr: pair_contract.to_string(),
-            msg: to_binary(&AstroportPairExecuteMsg::Swap {
-                offer_asset: offer_asset.clone(),
-                belief_price: None,
-                max_spread,
-                to: None,
-            })?,
+            contract_addr: swap_contract.to_string(),
+            msg: swap_msg,
             funds: vec![Coin {
                 denom,
                 amount: offer_asset.amount,
@@ -121,14 +512,9 @@ fn asset_into_swap_msg(
         AssetInfo::Token { contract_addr } => CosmosMsg::Wasm(WasmMsg::Execute {
             contract_addr: contract_addr.to_string(),
             msg: to_binary(&Cw20ExecuteMsg::Send {
-                contract: pair_contract.to_string(),
+                contract: swap_contract.to_string(),
                 amount: offer_asset.amount,
-                msg: to_binary(&AstroportPairExecuteMsg::Swap {
-                    offer_asset,
-                    belief_price: None,
-                    max_spread,
-                    to: None,
-                })?,
+                msg: swap_msg,
             })?,
             funds: vec![],
         }),
@@ -146,6 +532,13 @@ mod tests {
     use cosmwasm_std::testing::MOCK_CONTRACT_ADDR;
     use cosmwasm_std::SubMsg;
 
+    fn astroport_adapter() -> AstroportSwapAdapter {
+        AstroportSwapAdapter {
+            factory_address: Addr::unchecked("astroport_factory"),
+            router_address: Some(Addr::unchecked("astroport_router")),
+        }
+    }
+
     #[test]
     fn test_cannot_swap_same_assets() {
         let mut deps = mock_dependencies(&[]);
@@ -169,11 +562,11 @@ mod tests {
             let response = execute_swap(
                 deps.as_mut(),
                 env.clone(),
-                asset_info.clone(),
+                SwapRoute::direct(asset_info.clone()),
                 asset_info,
                 None,
-                Addr::unchecked("astroport_factory"),
-                None,
+                &astroport_adapter(),
+                SwapSlippage::default(),
             );
             assert_generic_error_message(
                 response,
@@ -203,11 +596,11 @@ mod tests {
         let response = execute_swap(
             deps.as_mut(),
             env,
-            offer_asset_info,
+            SwapRoute::direct(offer_asset_info),
             ask_asset_info,
             None,
-            Addr::unchecked("astroport_factory"),
-            None,
+            &astroport_adapter(),
+            SwapSlippage::default(),
         );
         assert_generic_error_message(response, "Contract has no balance for the asset cw20_zero")
     }
@@ -230,11 +623,11 @@ mod tests {
         let response = execute_swap(
             deps.as_mut(),
             env,
-            offer_asset_info,
+            SwapRoute::direct(offer_asset_info),
             ask_asset_info,
             Some(Uint128::new(1_000_001)),
-            Addr::unchecked("astroport_factory"),
-            None,
+            &astroport_adapter(),
+            SwapSlippage::default(),
         );
         assert_generic_error_message(
             response,
@@ -271,11 +664,11 @@ mod tests {
         let res = execute_swap(
             deps.as_mut(),
             env,
-            offer_asset_info,
+            SwapRoute::direct(offer_asset_info),
             ask_asset_info,
             Some(Uint128::new(999)),
-            Addr::unchecked("astroport_factory"),
-            None,
+            &astroport_adapter(),
+            SwapSlippage::default(),
         )
         .unwrap();
 
@@ -341,11 +734,14 @@ mod tests {
         let res = execute_swap(
             deps.as_mut(),
             env,
-            offer_asset_info,
+            SwapRoute::direct(offer_asset_info),
             ask_asset_info,
             None,
-            Addr::unchecked("astroport_factory"),
-            Some(StdDecimal::from_ratio(1u128, 100u128)),
+            &astroport_adapter(),
+            SwapSlippage {
+                max_spread: Some(StdDecimal::from_ratio(1u128, 100u128)),
+                ..Default::default()
+            },
         )
         .unwrap();
 
@@ -382,4 +778,367 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_swap_via_pool_manager() {
+        let mut deps = mock_dependencies(&[Coin {
+            denom: "uusd".to_string(),
+            amount: Uint128::new(1_000_000),
+        }]);
+        let env = mock_env(MockEnvParams::default());
+
+        let cw20_contract_address = Addr::unchecked("cw20");
+        deps.querier.set_cw20_balances(
+            cw20_contract_address.clone(),
+            &[(Addr::unchecked(MOCK_CONTRACT_ADDR), Uint128::new(500))],
+        );
+
+        let swap_venue = SwapVenueUnchecked::PoolManager {
+            address: "pool_manager".to_string(),
+        }
+        .to_checked(&deps.api)
+        .unwrap();
+        let swap_adapter = swap_venue.adapter(Addr::unchecked("astroport_factory"));
+
+        let uusd_asset_info = AssetInfo::NativeToken {
+            denom: "uusd".to_string(),
+        };
+        let cw20_asset_info = AssetInfo::Token {
+            contract_addr: cw20_contract_address.clone(),
+        };
+
+        // native offer asset is sent as funds
+        {
+            let res = execute_swap(
+                deps.as_mut(),
+                env.clone(),
+                SwapRoute::direct(uusd_asset_info.clone()),
+                cw20_asset_info.clone(),
+                Some(Uint128::new(1_000)),
+                swap_adapter.as_ref(),
+                SwapSlippage {
+                    max_spread: Some(StdDecimal::percent(1)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+
+            assert_eq!(
+                res.messages,
+                vec![SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: String::from("pool_manager"),
+                    msg: to_binary(&PoolManagerExecuteMsg::Swap {
+                        offer_asset: AstroportAsset {
+                            info: uusd_asset_info.clone(),
+                            amount: Uint128::new(1_000),
+                        },
+                        ask_asset_info: cw20_asset_info.clone(),
+                        max_spread: Some(StdDecimal::percent(1)),
+                    })
+                    .unwrap(),
+                    funds: vec![Coin {
+                        denom: "uusd".to_string(),
+                        amount: Uint128::new(1_000),
+                    }],
+                }))]
+            );
+        }
+
+        // cw20 offer asset is sent with the swap message as hook
+        {
+            let res = execute_swap(
+                deps.as_mut(),
+                env,
+                SwapRoute::direct(cw20_asset_info.clone()),
+                uusd_asset_info.clone(),
+                None,
+                swap_adapter.as_ref(),
+                SwapSlippage::default(),
+            )
+            .unwrap();
+
+            assert_eq!(
+                res.messages,
+                vec![SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr: cw20_contract_address.to_string(),
+                    msg: to_binary(&Cw20ExecuteMsg::Send {
+                        contract: String::from("pool_manager"),
+                        amount: Uint128::new(500),
+                        msg: to_binary(&PoolManagerExecuteMsg::Swap {
+                            offer_asset: AstroportAsset {
+                                info: cw20_asset_info,
+                                amount: Uint128::new(500),
+                            },
+                            ask_asset_info: uusd_asset_info,
+                            max_spread: None,
+                        })
+                        .unwrap(),
+                    })
+                    .unwrap(),
+                    funds: vec![],
+                }))]
+            );
+        }
+    }
+
+    #[test]
+    fn test_swap_through_route() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env(MockEnvParams::default());
+
+        let cw20_contract_address = Addr::unchecked("cw20");
+        deps.querier.set_cw20_balances(
+            cw20_contract_address.clone(),
+            &[(Addr::unchecked(MOCK_CONTRACT_ADDR), Uint128::new(2_000))],
+        );
+        deps.querier
+            .set_oracle_price(b"cw20".to_vec(), Decimal::from_ratio(1u128, 2u128));
+
+        let cw20_asset_info = AssetInfo::Token {
+            contract_addr: cw20_contract_address.clone(),
+        };
+        let uluna_asset_info = AssetInfo::NativeToken {
+            denom: "uluna".to_string(),
+        };
+        let uusd_asset_info = AssetInfo::NativeToken {
+            denom: "uusd".to_string(),
+        };
+        let route = SwapRoute {
+            offer_asset_info: cw20_asset_info.clone(),
+            hops: vec![uluna_asset_info.clone()],
+        };
+        let slippage = SwapSlippage {
+            minimum_receive: Some(Uint128::new(1_500)),
+            max_oracle_deviation: Some(OracleDeviation {
+                address_provider_address: Addr::unchecked("address_provider"),
+                max_deviation: Decimal::percent(5),
+            }),
+            ..Default::default()
+        };
+
+        // routes need an oracle based minimum since hops have no max spread
+        {
+            let response = execute_swap(
+                deps.as_mut(),
+                env.clone(),
+                route.clone(),
+                uusd_asset_info.clone(),
+                None,
+                &astroport_adapter(),
+                SwapSlippage {
+                    max_spread: Some(StdDecimal::percent(1)),
+                    minimum_receive: Some(Uint128::new(1_500)),
+                    max_oracle_deviation: None,
+                },
+            );
+            assert_generic_error_message(
+                response,
+                "A max oracle deviation is required to swap through a route or with a minimum output",
+            );
+        }
+
+        // a minimum set by the caller is not enough on a direct swap either
+        {
+            let response = execute_swap(
+                deps.as_mut(),
+                env.clone(),
+                SwapRoute::direct(cw20_asset_info.clone()),
+                uusd_asset_info.clone(),
+                None,
+                &astroport_adapter(),
+                SwapSlippage {
+                    minimum_receive: Some(Uint128::new(1)),
+                    ..Default::default()
+                },
+            );
+            assert_generic_error_message(
+                response,
+                "A max oracle deviation is required to swap through a route or with a minimum output",
+            );
+        }
+
+        // route cannot go through the same asset twice
+        {
+            let invalid_route = SwapRoute {
+                offer_asset_info: cw20_asset_info.clone(),
+                hops: vec![uluna_asset_info.clone(), uusd_asset_info.clone()],
+            };
+            let response = execute_swap(
+                deps.as_mut(),
+                env.clone(),
+                invalid_route,
+                uusd_asset_info.clone(),
+                None,
+                &astroport_adapter(),
+                slippage.clone(),
+            );
+            assert_generic_error_message(response, "Swap route goes through uusd more than once");
+        }
+
+        // astroport needs a router to swap through a route
+        {
+            let response = execute_swap(
+                deps.as_mut(),
+                env.clone(),
+                route.clone(),
+                uusd_asset_info.clone(),
+                None,
+                &AstroportSwapAdapter {
+                    factory_address: Addr::unchecked("astroport_factory"),
+                    router_address: None,
+                },
+                slippage.clone(),
+            );
+            assert_generic_error_message(
+                response,
+                "Astroport router address is required to swap through a route",
+            );
+        }
+
+        let res = execute_swap(
+            deps.as_mut(),
+            env,
+            route,
+            uusd_asset_info.clone(),
+            None,
+            &astroport_adapter(),
+            slippage,
+        )
+        .unwrap();
+
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: cw20_contract_address.to_string(),
+                msg: to_binary(&Cw20ExecuteMsg::Send {
+                    contract: String::from("astroport_router"),
+                    amount: Uint128::new(2_000),
+                    msg: to_binary(&AstroportRouterCw20HookMsg::ExecuteSwapOperations {
+                        operations: vec![
+                            SwapOperation::AstroSwap {
+                                offer_asset_info: cw20_asset_info,
+                                ask_asset_info: uluna_asset_info.clone(),
+                            },
+                            SwapOperation::AstroSwap {
+                                offer_asset_info: uluna_asset_info,
+                                ask_asset_info: uusd_asset_info,
+                            },
+                        ],
+                        minimum_receive: Some(Uint128::new(1_500)),
+                        to: None,
+                    })
+                    .unwrap(),
+                })
+                .unwrap(),
+                funds: vec![],
+            }))]
+        );
+
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "swap"),
+                attr("offer_asset", "cw20"),
+                attr("ask_asset", "uusd"),
+                attr("offer_asset_amount", "2000"),
+                attr("route", "uluna"),
+                attr("minimum_receive", "1500"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_swap_with_oracle_minimum_receive() {
+        let mut deps = mock_dependencies(&[]);
+        let env = mock_env(MockEnvParams::default());
+
+        let cw20_contract_address = Addr::unchecked("cw20");
+        deps.querier.set_cw20_balances(
+            cw20_contract_address.clone(),
+            &[(Addr::unchecked(MOCK_CONTRACT_ADDR), Uint128::new(1_000))],
+        );
+        deps.querier
+            .set_oracle_price(b"cw20".to_vec(), Decimal::from_ratio(2u128, 1u128));
+
+        let cw20_asset_info = AssetInfo::Token {
+            contract_addr: cw20_contract_address,
+        };
+        let uusd_asset_info = AssetInfo::NativeToken {
+            denom: "uusd".to_string(),
+        };
+        let swap_adapter = PoolManagerSwapAdapter {
+            pool_manager_address: Addr::unchecked("pool_manager"),
+        };
+        let oracle_deviation = OracleDeviation {
+            address_provider_address: Addr::unchecked("address_provider"),
+            max_deviation: Decimal::percent(5),
+        };
+
+        // 1000 cw20 are worth 2000 uusd, 5% deviation allowed
+        let res = execute_swap(
+            deps.as_mut(),
+            env.clone(),
+            SwapRoute::direct(cw20_asset_info.clone()),
+            uusd_asset_info.clone(),
+            None,
+            &swap_adapter,
+            SwapSlippage {
+                max_oracle_deviation: Some(oracle_deviation.clone()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(res.attributes[4], attr("minimum_receive", "1900"));
+
+        // a higher minimum set by the caller is kept
+        let res = execute_swap(
+            deps.as_mut(),
+            env,
+            SwapRoute::direct(cw20_asset_info),
+            uusd_asset_info,
+            None,
+            &swap_adapter,
+            SwapSlippage {
+                minimum_receive: Some(Uint128::new(1_950)),
+                max_oracle_deviation: Some(oracle_deviation),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(res.attributes[4], attr("minimum_receive", "1950"));
+    }
+
+    #[test]
+    fn test_validate_swap_max_oracle_deviation() {
+        let astroport_without_router = SwapVenueChecked::Astroport {
+            router_address: None,
+        };
+        let astroport_with_router = SwapVenueChecked::Astroport {
+            router_address: Some(Addr::unchecked("astroport_router")),
+        };
+
+        let err =
+            validate_swap_max_oracle_deviation(&astroport_with_router, &Decimal::percent(101))
+                .unwrap_err();
+        assert_eq!(
+            err,
+            MarsError::InvalidParam {
+                param_name: "swap_max_oracle_deviation".to_string(),
+                invalid_value: "1.01".to_string(),
+                predicate: "<= 1".to_string(),
+            }
+        );
+
+        let err =
+            validate_swap_max_oracle_deviation(&astroport_without_router, &Decimal::percent(5))
+                .unwrap_err();
+        assert_eq!(
+            err,
+            MarsError::Std(StdError::generic_err(
+                "Astroport router address is required to enforce a minimum swap output"
+            ))
+        );
+
+        validate_swap_max_oracle_deviation(&astroport_with_router, &Decimal::percent(5)).unwrap();
+    }
 }
@@ -1,7 +1,10 @@
-use crate::math::decimal::Decimal;
 use cosmwasm_std::{Coin, Deps, StdResult, Uint128};
+#[cfg(feature = "terra")]
 use terra_cosmwasm::TerraQuerier;
 
+#[cfg(feature = "terra")]
+use crate::math::decimal::Decimal;
+
 pub fn deduct_tax(deps: Deps, coin: Coin) -> StdResult<Coin> {
     let tax_amount = compute_tax(deps, &coin)?;
     Ok(Coin {
@@ -10,6 +13,8 @@ pub fn deduct_tax(deps: Deps, coin: Coin) -> StdResult<Coin> {
     })
 }
 
+/// Stability tax charged by Terra on native coin transfers
+#[cfg(feature = "terra")]
 pub fn compute_tax(deps: Deps, coin: &Coin) -> StdResult<Uint128> {
     let terra_querier = TerraQuerier::new(&deps.querier);
     let tax_rate: Decimal = (terra_querier.query_tax_rate()?).rate.into();
@@ -20,3 +25,9 @@ pub fn compute_tax(deps: Deps, coin: &Coin) -> StdResult<Uint128> {
         tax_cap,
     ))
 }
+
+/// Chains other than Terra charge no tax on native coin transfers
+#[cfg(not(feature = "terra"))]
+pub fn compute_tax(_deps: Deps, _coin: &Coin) -> StdResult<Uint128> {
+    Ok(Uint128::zero())
+}
@@ -2,10 +2,15 @@ use std::collections::HashMap;
 
 use cosmwasm_std::{to_binary, Addr, Binary, ContractResult, QuerierResult, SystemError};
 
-use astroport::pair::{CumulativePricesResponse, PoolResponse, QueryMsg, SimulationResponse};
+use astroport::asset::PairInfo;
+use astroport::pair::{
+    ConfigResponse, CumulativePricesResponse, PoolResponse, QueryMsg, SimulationResponse,
+};
 
 #[derive(Clone, Default)]
 pub struct AstroportPairQuerier {
+    pub pair_infos: HashMap<String, PairInfo>,
+    pub configs: HashMap<String, ConfigResponse>,
     pub pairs: HashMap<String, PoolResponse>,
     pub simulations: HashMap<String, SimulationResponse>,
     pub cumulative_prices: HashMap<String, CumulativePricesResponse>,
@@ -15,6 +20,22 @@ impl AstroportPairQuerier {
     pub fn handle_query(&self, contract_addr: &Addr, request: &QueryMsg) -> QuerierResult {
         let key = contract_addr.to_string();
         let ret: ContractResult<Binary> = match &request {
+            QueryMsg::Pair {} => match self.pair_infos.get(&key) {
+                Some(pair_info) => to_binary(&pair_info).into(),
+                None => Err(SystemError::InvalidRequest {
+                    error: format!("PairInfo is not found for {}", key),
+                    request: Default::default(),
+                })
+                .into(),
+            },
+            QueryMsg::Config {} => match self.configs.get(&key) {
+                Some(config_response) => to_binary(&config_response).into(),
+                None => Err(SystemError::InvalidRequest {
+                    error: format!("ConfigResponse is not found for {}", key),
+                    request: Default::default(),
+                })
+                .into(),
+            },
             QueryMsg::Pool {} => match self.pairs.get(&key) {
                 Some(pool_response) => to_binary(&pool_response).into(),
                 None => Err(SystemError::InvalidRequest {
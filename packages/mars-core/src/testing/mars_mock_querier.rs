@@ -1,10 +1,12 @@
 use cosmwasm_std::{
     from_binary, from_slice,
     testing::{MockQuerier, MOCK_CONTRACT_ADDR},
-    Addr, Coin, Fraction, Querier, QuerierResult, QueryRequest, StdResult, SystemError, Uint128,
-    WasmQuery,
+    Addr, Binary, Coin, ContractResult, Fraction, Querier, QuerierResult, QueryRequest, StdResult,
+    SystemError, Uint128, WasmQuery,
 };
 use cw20::Cw20QueryMsg;
+use std::collections::HashMap;
+#[cfg(feature = "terra")]
 use terra_cosmwasm::TerraQueryWrapper;
 
 use crate::{
@@ -13,7 +15,7 @@ use crate::{
 };
 use astroport::{
     asset::{Asset, PairInfo},
-    pair::{CumulativePricesResponse, PoolResponse, SimulationResponse},
+    pair::{ConfigResponse, CumulativePricesResponse, PoolResponse, SimulationResponse},
 };
 
 use super::{
@@ -33,8 +35,14 @@ use crate::testing::stader_querier::StaderQuerier;
 use basset::hub::StateResponse;
 use stader::msg::QueryStateResponse as LunaxStateResponse;
 
+/// Custom query type of the chain the contracts are built for
+#[cfg(feature = "terra")]
+pub type MockCustomQuery = TerraQueryWrapper;
+#[cfg(not(feature = "terra"))]
+pub type MockCustomQuery = cosmwasm_std::Empty;
+
 pub struct MarsMockQuerier {
-    base: MockQuerier<TerraQueryWrapper>,
+    base: MockQuerier<MockCustomQuery>,
     native_querier: NativeQuerier,
     cw20_querier: Cw20Querier,
     xmars_querier: XMarsQuerier,
@@ -46,12 +54,14 @@ pub struct MarsMockQuerier {
     incentives_querier: IncentivesQuerier,
     basset_querier: BAssetQuerier,
     stader_querier: StaderQuerier,
+    /// Raw responses to smart queries, by contract address and query message
+    smart_query_responses: HashMap<(String, Binary), Binary>,
 }
 
 impl Querier for MarsMockQuerier {
     fn raw_query(&self, bin_request: &[u8]) -> QuerierResult {
         // MockQuerier doesn't support Custom, so we ignore it completely here
-        let request: QueryRequest<TerraQueryWrapper> = match from_slice(bin_request) {
+        let request: QueryRequest<MockCustomQuery> = match from_slice(bin_request) {
             Ok(v) => v,
             Err(e) => {
                 return Err(SystemError::InvalidRequest {
@@ -66,7 +76,7 @@ impl Querier for MarsMockQuerier {
 }
 
 impl MarsMockQuerier {
-    pub fn new(base: MockQuerier<TerraQueryWrapper>) -> Self {
+    pub fn new(base: MockQuerier<MockCustomQuery>) -> Self {
         MarsMockQuerier {
             base,
             native_querier: NativeQuerier::default(),
@@ -80,6 +90,7 @@ impl MarsMockQuerier {
             incentives_querier: IncentivesQuerier::default(),
             basset_querier: BAssetQuerier::default(),
             stader_querier: StaderQuerier::default(),
+            smart_query_responses: HashMap::new(),
         }
     }
 
@@ -139,10 +150,25 @@ impl MarsMockQuerier {
         token_info.symbol = symbol;
     }
 
+    pub fn set_oracle_base_denom(&mut self, base_denom: &str) {
+        self.oracle_querier.base_denom = Some(base_denom.to_string());
+    }
+
     pub fn set_oracle_price(&mut self, asset_reference: Vec<u8>, price: Decimal) {
         self.oracle_querier.prices.insert(asset_reference, price);
     }
 
+    pub fn set_oracle_price_metadata(
+        &mut self,
+        asset_reference: Vec<u8>,
+        timestamp: u64,
+        max_age: Option<u64>,
+    ) {
+        self.oracle_querier
+            .price_metadata
+            .insert(asset_reference, (timestamp, max_age));
+    }
+
     pub fn set_staking_xmars_per_mars(&mut self, xmars_per_mars: Decimal) {
         self.staking_querier.xmars_per_mars = xmars_per_mars;
         self.staking_querier.mars_per_xmars = xmars_per_mars.inv().unwrap();
@@ -212,9 +238,24 @@ impl MarsMockQuerier {
             total_share: Uint128::zero(),
         };
         let key = pair_info.contract_addr.to_string();
+        self.astroport_pair_querier
+            .pair_infos
+            .insert(key.clone(), pair_info);
         self.astroport_pair_querier.pairs.insert(key, pool_response);
     }
 
+    pub fn set_astroport_pair_pool(&mut self, contract_addr: String, pool: PoolResponse) {
+        self.astroport_pair_querier
+            .pairs
+            .insert(contract_addr, pool);
+    }
+
+    pub fn set_astroport_pair_config(&mut self, contract_addr: String, config: ConfigResponse) {
+        self.astroport_pair_querier
+            .configs
+            .insert(contract_addr, config);
+    }
+
     pub fn set_astroport_pair_cumulative_prices(
         &mut self,
         contract_addr: String,
@@ -235,6 +276,12 @@ impl MarsMockQuerier {
             .insert(contract_addr, simulation);
     }
 
+    /// Set the raw response of any contract to a given query message
+    pub fn set_smart_query_response(&mut self, contract_addr: &str, msg: Binary, response: Binary) {
+        self.smart_query_responses
+            .insert((contract_addr.to_string(), msg), response);
+    }
+
     pub fn set_incentives_address(&mut self, address: Addr) {
         self.incentives_querier.incentives_address = address;
     }
@@ -253,15 +300,39 @@ impl MarsMockQuerier {
         self.stader_querier.state_response = Some(state_response);
     }
 
-    pub fn handle_query(&self, request: &QueryRequest<TerraQueryWrapper>) -> QuerierResult {
+    pub fn handle_query(&self, request: &QueryRequest<MockCustomQuery>) -> QuerierResult {
         match &request {
+            #[cfg(feature = "terra")]
             QueryRequest::Custom(TerraQueryWrapper { route, query_data }) => {
                 self.native_querier.handle_query(route, query_data)
             }
 
             QueryRequest::Wasm(WasmQuery::Smart { contract_addr, msg }) => {
+                if let Some(response) = self
+                    .smart_query_responses
+                    .get(&(contract_addr.clone(), msg.clone()))
+                {
+                    return Ok(ContractResult::Ok(response.clone())).into();
+                }
+
                 let contract_addr = Addr::unchecked(contract_addr);
 
+                // Astroport pair Config queries share their format with other contracts' ones, so
+                // route them based on the address
+                if self
+                    .astroport_pair_querier
+                    .configs
+                    .contains_key(contract_addr.as_str())
+                {
+                    let astroport_pair_query: StdResult<astroport::pair::QueryMsg> =
+                        from_binary(msg);
+                    if let Ok(pair_query) = astroport_pair_query {
+                        return self
+                            .astroport_pair_querier
+                            .handle_query(&contract_addr, &pair_query);
+                    }
+                }
+
                 // Cw20 Queries
                 let parse_cw20_query: StdResult<Cw20QueryMsg> = from_binary(msg);
                 if let Ok(cw20_query) = parse_cw20_query {
@@ -285,13 +356,21 @@ impl MarsMockQuerier {
                 }
 
                 // Address Provider Queries
+                // NOTE: Config queries are shared with other contracts (e.g. the oracle), so
+                // only route them to the address provider mock when targeting it
                 let parse_address_provider_query: StdResult<address_provider::msg::QueryMsg> =
                     from_binary(msg);
                 if let Ok(address_provider_query) = parse_address_provider_query {
-                    return mock_address_provider::handle_query(
-                        &contract_addr,
+                    let is_config_query = matches!(
                         address_provider_query,
+                        address_provider::msg::QueryMsg::Config {}
                     );
+                    if !is_config_query || contract_addr == Addr::unchecked("address_provider") {
+                        return mock_address_provider::handle_query(
+                            &contract_addr,
+                            address_provider_query,
+                        );
+                    }
                 }
 
                 // Oracle Queries
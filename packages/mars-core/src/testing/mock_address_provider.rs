@@ -26,6 +26,8 @@ pub fn handle_query(contract_addr: &Addr, query: QueryMsg) -> QuerierResult {
             to_binary(&addresses).into()
         }
 
+        QueryMsg::Guardian {} => to_binary(&Addr::unchecked("guardian")).into(),
+
         _ => panic!("[mock]: Unsupported address provider query"),
     };
 
@@ -1,6 +1,9 @@
 use crate::math::decimal::Decimal;
-use cosmwasm_std::{to_binary, Binary, ContractResult, QuerierResult, Uint128};
+use cosmwasm_std::Uint128;
+#[cfg(feature = "terra")]
+use cosmwasm_std::{to_binary, Binary, ContractResult, QuerierResult};
 use std::collections::HashMap;
+#[cfg(feature = "terra")]
 use terra_cosmwasm::{
     ExchangeRateItem, ExchangeRatesResponse, TaxCapResponse, TaxRateResponse, TerraQuery,
     TerraRoute,
@@ -24,6 +27,9 @@ impl Default for NativeQuerier {
     }
 }
 
+// NOTE: Exchange rates and tax data are only served to contracts through Terra custom queries,
+// when built without the `terra` feature they can still be set but are never queried
+#[cfg(feature = "terra")]
 impl NativeQuerier {
     pub fn handle_query(&self, route: &TerraRoute, query_data: &TerraQuery) -> QuerierResult {
         match route {
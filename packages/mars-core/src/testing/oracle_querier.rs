@@ -3,10 +3,16 @@ use std::collections::HashMap;
 
 use crate::math::decimal::Decimal;
 use crate::oracle::msg::QueryMsg;
+use crate::oracle::{default_base_denom, AssetPriceWithMetadataResponse, Config};
 
 #[derive(Default)]
 pub struct OracleQuerier {
     pub prices: HashMap<Vec<u8>, Decimal>,
+    /// Price timestamp and max age by asset reference. Prices without metadata have timestamp
+    /// zero and no max age (i.e. they are never stale)
+    pub price_metadata: HashMap<Vec<u8>, (u64, Option<u64>)>,
+    /// Defaults to uusd if not set
+    pub base_denom: Option<String>,
 }
 
 impl OracleQuerier {
@@ -20,6 +26,14 @@ impl OracleQuerier {
         }
 
         let ret: ContractResult<Binary> = match query {
+            QueryMsg::Config {} => to_binary(&Config {
+                owner: Addr::unchecked("owner"),
+                address_provider_address: Addr::unchecked("address_provider"),
+                paused: false,
+                base_denom: self.base_denom.clone().unwrap_or_else(default_base_denom),
+            })
+            .into(),
+
             QueryMsg::AssetPriceByReference { asset_reference } => {
                 let option_price = self.prices.get(&asset_reference);
 
@@ -34,6 +48,32 @@ impl OracleQuerier {
                 }
             }
 
+            QueryMsg::AssetPriceWithMetadataByReference { asset_reference } => {
+                let option_price = self.prices.get(&asset_reference);
+
+                if let Some(price) = option_price {
+                    let (timestamp, max_age) = self
+                        .price_metadata
+                        .get(&asset_reference)
+                        .cloned()
+                        .unwrap_or((0, None));
+                    to_binary(&AssetPriceWithMetadataResponse {
+                        price: *price,
+                        timestamp,
+                        price_source: "fixed".to_string(),
+                        is_fallback: false,
+                        max_age,
+                    })
+                    .into()
+                } else {
+                    Err(format!(
+                        "[mock]: could not find oracle price for {}",
+                        String::from_utf8(asset_reference).unwrap()
+                    ))
+                    .into()
+                }
+            }
+
             _ => Err("[mock]: Unsupported address provider query").into(),
         };
 
@@ -35,4 +35,7 @@ pub mod msg {
     pub enum QueryMsg {
         Config {},
     }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct MigrateMsg {}
 }
@@ -0,0 +1,266 @@
+use std::fmt;
+use std::str::FromStr;
+
+use cosmwasm_std::{attr, Attribute, StdError, StdResult, Storage};
+use cw2::{ContractVersion, CONTRACT};
+
+use crate::error::MarsError;
+
+/// Version assumed for contracts instantiated before their version was stored
+pub const UNVERSIONED_CONTRACT_VERSION: &str = "1.0.0";
+
+/// Contract semantic version (`major.minor.patch`), ordered so migrations can check which state
+/// changes need to be applied
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl Version {
+    pub const fn new(major: u64, minor: u64, patch: u64) -> Self {
+        Version {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl FromStr for Version {
+    type Err = StdError;
+
+    fn from_str(s: &str) -> StdResult<Self> {
+        let invalid_version = || StdError::generic_err(format!("Invalid contract version: {}", s));
+
+        let parts = s
+            .split('.')
+            .map(|part| part.parse::<u64>().map_err(|_| invalid_version()))
+            .collect::<StdResult<Vec<u64>>>()?;
+
+        match parts[..] {
+            [major, minor, patch] => Ok(Version::new(major, minor, patch)),
+            _ => Err(invalid_version()),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// To be called at the start of a contract's `migrate` entry point: checks the stored contract
+/// name matches and that the stored version is not newer than the one being migrated to, then
+/// stores the new version. Returns the previous version so the caller can run the state
+/// migrations needed to go from it to the current one.
+pub fn migrate_contract_version(
+    storage: &mut dyn Storage,
+    contract_name: &str,
+    contract_version: &str,
+) -> Result<Version, MarsError> {
+    let stored = CONTRACT
+        .may_load(storage)?
+        .unwrap_or_else(|| ContractVersion {
+            contract: contract_name.to_string(),
+            version: UNVERSIONED_CONTRACT_VERSION.to_string(),
+        });
+
+    if stored.contract != contract_name {
+        return Err(MarsError::MigrateWrongContract {
+            stored_contract: stored.contract,
+            contract: contract_name.to_string(),
+        });
+    }
+
+    let stored_version: Version = stored.version.parse()?;
+    let version: Version = contract_version.parse()?;
+    if stored_version > version {
+        return Err(MarsError::MigrateDowngrade {
+            stored_version: stored.version,
+            version: contract_version.to_string(),
+        });
+    }
+
+    cw2::set_contract_version(storage, contract_name, contract_version)?;
+
+    Ok(stored_version)
+}
+
+/// State migration introduced by a contract version
+pub struct MigrationStep {
+    /// Version that introduced the state change, the step runs when migrating from an older one
+    pub version: Version,
+    /// Moves state written by older versions to what `version` expects
+    pub migrate: fn(&mut dyn Storage) -> StdResult<()>,
+}
+
+/// Migrates a contract: checks and stores the new version (see `migrate_contract_version`), then
+/// runs the steps introduced after the stored version, in the order given (which has to be by
+/// ascending version). Returns the attributes to add to the migrate response
+pub fn migrate_contract(
+    storage: &mut dyn Storage,
+    contract_name: &str,
+    contract_version: &str,
+    steps: &[MigrationStep],
+) -> Result<Vec<Attribute>, MarsError> {
+    let stored_version = migrate_contract_version(storage, contract_name, contract_version)?;
+
+    let mut applied_steps = vec![];
+    for step in steps.iter().filter(|step| step.version > stored_version) {
+        (step.migrate)(storage)?;
+        applied_steps.push(step.version.to_string());
+    }
+
+    Ok(vec![
+        attr("previous_version", stored_version.to_string()),
+        attr("new_version", contract_version),
+        attr("migration_steps", applied_steps.join(",")),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cosmwasm_std::testing::MockStorage;
+    use cw2::{get_contract_version, set_contract_version};
+
+    const STEPS_RUN_KEY: &[u8] = b"steps_run";
+
+    fn step_run(storage: &mut dyn Storage, step: &str) -> StdResult<()> {
+        let mut steps_run = storage.get(STEPS_RUN_KEY).unwrap_or_default();
+        steps_run.extend_from_slice(step.as_bytes());
+        steps_run.push(b';');
+        storage.set(STEPS_RUN_KEY, &steps_run);
+        Ok(())
+    }
+
+    const STEPS: &[MigrationStep] = &[
+        MigrationStep {
+            version: Version::new(1, 0, 2),
+            migrate: |storage| step_run(storage, "1.0.2"),
+        },
+        MigrationStep {
+            version: Version::new(1, 1, 0),
+            migrate: |storage| step_run(storage, "1.1.0"),
+        },
+    ];
+
+    #[test]
+    fn test_version_parse_and_order() {
+        let version: Version = "1.2.3".parse().unwrap();
+        assert_eq!(version, Version::new(1, 2, 3));
+        assert_eq!(version.to_string(), "1.2.3");
+
+        assert!(Version::new(1, 0, 10) > Version::new(1, 0, 9));
+        assert!(Version::new(1, 1, 0) > Version::new(1, 0, 10));
+        assert!(Version::new(2, 0, 0) > Version::new(1, 10, 10));
+
+        for invalid in &["", "1.0", "1.0.0.0", "v1.0.0", "1.a.0"] {
+            assert_eq!(
+                invalid.parse::<Version>().unwrap_err(),
+                StdError::generic_err(format!("Invalid contract version: {}", invalid))
+            );
+        }
+    }
+
+    #[test]
+    fn test_migrate_contract_version() {
+        let mut storage = MockStorage::new();
+
+        // contracts instantiated before versioning are assumed to be at the first release
+        let stored_version =
+            migrate_contract_version(&mut storage, "crates.io:contract", "1.1.0").unwrap();
+        assert_eq!(stored_version, Version::new(1, 0, 0));
+        let contract_version = get_contract_version(&storage).unwrap();
+        assert_eq!(contract_version.contract, "crates.io:contract");
+        assert_eq!(contract_version.version, "1.1.0");
+
+        // migrating to the same version is allowed
+        let stored_version =
+            migrate_contract_version(&mut storage, "crates.io:contract", "1.1.0").unwrap();
+        assert_eq!(stored_version, Version::new(1, 1, 0));
+
+        // cannot downgrade
+        let error =
+            migrate_contract_version(&mut storage, "crates.io:contract", "1.0.5").unwrap_err();
+        assert_eq!(
+            error,
+            MarsError::MigrateDowngrade {
+                stored_version: "1.1.0".to_string(),
+                version: "1.0.5".to_string(),
+            }
+        );
+
+        // cannot migrate a different contract
+        let error = migrate_contract_version(&mut storage, "crates.io:other-contract", "1.2.0")
+            .unwrap_err();
+        assert_eq!(
+            error,
+            MarsError::MigrateWrongContract {
+                stored_contract: "crates.io:contract".to_string(),
+                contract: "crates.io:other-contract".to_string(),
+            }
+        );
+
+        // version is left untouched on errors
+        let contract_version = get_contract_version(&storage).unwrap();
+        assert_eq!(contract_version.version, "1.1.0");
+
+        // unparseable stored version
+        set_contract_version(&mut storage, "crates.io:contract", "latest").unwrap();
+        let error =
+            migrate_contract_version(&mut storage, "crates.io:contract", "1.2.0").unwrap_err();
+        assert_eq!(
+            error,
+            MarsError::Std(StdError::generic_err("Invalid contract version: latest"))
+        );
+    }
+
+    #[test]
+    fn test_migrate_contract() {
+        let mut storage = MockStorage::new();
+
+        // steps introduced after the stored version are run in order
+        set_contract_version(&mut storage, "crates.io:contract", "1.0.1").unwrap();
+        let attributes =
+            migrate_contract(&mut storage, "crates.io:contract", "1.1.0", STEPS).unwrap();
+        assert_eq!(
+            attributes,
+            vec![
+                attr("previous_version", "1.0.1"),
+                attr("new_version", "1.1.0"),
+                attr("migration_steps", "1.0.2,1.1.0"),
+            ]
+        );
+        assert_eq!(storage.get(STEPS_RUN_KEY).unwrap(), b"1.0.2;1.1.0;");
+
+        // steps of the stored version or older are not run again
+        storage.remove(STEPS_RUN_KEY);
+        set_contract_version(&mut storage, "crates.io:contract", "1.0.2").unwrap();
+        let attributes =
+            migrate_contract(&mut storage, "crates.io:contract", "1.1.0", STEPS).unwrap();
+        assert_eq!(attributes[2], attr("migration_steps", "1.1.0"));
+        assert_eq!(storage.get(STEPS_RUN_KEY).unwrap(), b"1.1.0;");
+
+        storage.remove(STEPS_RUN_KEY);
+        let attributes =
+            migrate_contract(&mut storage, "crates.io:contract", "1.1.0", STEPS).unwrap();
+        assert_eq!(attributes[2], attr("migration_steps", ""));
+        assert_eq!(storage.get(STEPS_RUN_KEY), None);
+
+        // no step is run on a downgrade
+        let error =
+            migrate_contract(&mut storage, "crates.io:contract", "1.0.0", STEPS).unwrap_err();
+        assert_eq!(
+            error,
+            MarsError::MigrateDowngrade {
+                stored_version: "1.1.0".to_string(),
+                version: "1.0.0".to_string(),
+            }
+        );
+        assert_eq!(storage.get(STEPS_RUN_KEY), None);
+    }
+}
@@ -91,4 +91,7 @@ pub mod msg {
         /// Martian Council to calculate a governance proposal's quorum. Returns `Uint128`
         TotalVotingPowerAt { block: u64 },
     }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct MigrateMsg {}
 }
@@ -70,4 +70,7 @@ pub mod msg {
         /// Return type: DownloadLogoResponse.
         DownloadLogo {},
     }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+    pub struct MigrateMsg {}
 }
@@ -255,6 +255,7 @@ async function main() {
     join(MARS_ARTIFACTS_PATH, 'mars_oracle.wasm'),
     {
       "owner": wallet.key.accAddress,
+      "address_provider_address": addressProviderContractAddress,
     },
   )
   console.log("Oracle Contract Address: " + oracleContractAddress)
@@ -268,7 +269,6 @@ async function main() {
     {
       "update_config": {
         "config": {
-          "owner": councilContractAddress,
           "council_address": councilContractAddress,
           "incentives_address": incentivesContractAddress,
           "safety_fund_address": safetyFundContractAddress,
@@ -287,6 +287,19 @@ async function main() {
   )
   console.log("Address Provider config successfully setup: ", await queryContract(terra, addressProviderContractAddress, { "config": {} }))
 
+  // The council becomes the owner once it claims ownership through a proposal
+  await executeContract(
+    terra,
+    wallet,
+    addressProviderContractAddress,
+    {
+      "propose_new_owner": {
+        "owner": councilContractAddress,
+      }
+    }
+  )
+  console.log("Address Provider ownership proposed to the council")
+
   /*************************************** Setup Oracle Assets *****************************************/
   await setupOracle(
     terra,
@@ -150,7 +150,6 @@ async function assertXmarsBalanceAt(
     {
       update_config: {
         config: {
-          owner: deployer.key.accAddress,
           council_address: council,
           incentives_address: incentives,
           mars_token_address: mars,
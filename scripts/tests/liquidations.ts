@@ -549,8 +549,8 @@ async function testUncollateralizedNativeLoan(
   const userPositionT2 = await queryContract(terra, redBank,
     { user_position: { user_address: borrower.key.accAddress } }
   )
-  strictEqual(userPositionT1.total_collateralized_debt_in_uusd, userPositionT2.total_collateralized_debt_in_uusd)
-  strictEqual(userPositionT1.max_debt_in_uusd, userPositionT2.max_debt_in_uusd)
+  strictEqual(userPositionT1.total_collateralized_debt_in_base_asset, userPositionT2.total_collateralized_debt_in_base_asset)
+  strictEqual(userPositionT1.max_debt_in_base_asset, userPositionT2.max_debt_in_base_asset)
 }
 
 // MAIN
@@ -133,7 +133,6 @@ async function castVote(
     {
       update_config: {
         config: {
-          owner: deployer.key.accAddress,
           council_address: council,
           vesting_address: vesting,
           mars_token_address: mars,
@@ -215,7 +215,7 @@ async function waitUntilTerraOracleAvailable(terra: LCDClient) {
     )
 
     // 1 luna should be worth $25
-    strictEqual(parseInt(userPosition.total_collateral_in_uusd), 25_000000)
+    strictEqual(parseInt(userPosition.total_collateral_in_base_asset), 25_000000)
   }
 
   {
@@ -258,7 +258,7 @@ async function waitUntilTerraOracleAvailable(terra: LCDClient) {
     )
 
     // 1 luna should be worth $30
-    approximateEqual(parseInt(userPosition.total_collateral_in_uusd), 30_000000, 100)
+    approximateEqual(parseInt(userPosition.total_collateral_in_base_asset), 30_000000, 100)
   }
 
   {
@@ -320,7 +320,7 @@ async function waitUntilTerraOracleAvailable(terra: LCDClient) {
     )
 
     // 1 luna should be worth $30
-    strictEqual(parseInt(userPosition.total_collateral_in_uusd), 30_000000)
+    strictEqual(parseInt(userPosition.total_collateral_in_base_asset), 30_000000)
   }
 
   {
@@ -346,7 +346,7 @@ async function waitUntilTerraOracleAvailable(terra: LCDClient) {
 
     const lunaUsdPrice = await terra.oracle.exchangeRate("uusd")
     const lunaUusdPrice = lunaUsdPrice?.amount.mul(1_000000).floor().toNumber()
-    strictEqual(parseInt(userPosition.total_collateral_in_uusd), lunaUusdPrice)
+    strictEqual(parseInt(userPosition.total_collateral_in_base_asset), lunaUusdPrice)
   }
 
   console.log("OK")
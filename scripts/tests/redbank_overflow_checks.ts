@@ -134,7 +134,7 @@ const UUSD_MAX_BALANCE = 10_000_000_000_000000;
   const userPositionT1 = await queryContract(terra, redBank,
     { user_position: { user_address: user.key.accAddress } }
   )
-  strictEqual(Number(userPositionT1.total_collateral_in_uusd), depositAmount)
+  strictEqual(Number(userPositionT1.total_collateral_in_base_asset), depositAmount)
 
   const borrowAmount = maxLTV * depositAmount
   console.log("Borrow max uusd:", borrowAmount)
@@ -142,11 +142,11 @@ const UUSD_MAX_BALANCE = 10_000_000_000_000000;
   const userPositionT2 = await queryContract(terra, redBank,
     { user_position: { user_address: user.key.accAddress } }
   )
-  const totalDebtInUusd = Number(userPositionT2.total_debt_in_uusd)
-  strictEqual(totalDebtInUusd, borrowAmount)
+  const totalDebtInBaseAsset = Number(userPositionT2.total_debt_in_base_asset)
+  strictEqual(totalDebtInBaseAsset, borrowAmount)
 
   // hack: Just do a big number to repay all debt
-  const repayAmount = totalDebtInUusd + 10_000000
+  const repayAmount = totalDebtInBaseAsset + 10_000000
   console.log("Repay max borrowed uusd:", repayAmount)
   await executeContract(terra, user, redBank,
     { repay_native: { denom: "uusd" } },
@@ -155,7 +155,7 @@ const UUSD_MAX_BALANCE = 10_000_000_000_000000;
   const userPositionT3 = await queryContract(terra, redBank,
     { user_position: { user_address: user.key.accAddress } }
   )
-  strictEqual(Number(userPositionT3.total_debt_in_uusd), 0)
+  strictEqual(Number(userPositionT3.total_debt_in_base_asset), 0)
 
   console.log("Withdraw max uusd")
   await executeContract(terra, user, redBank,
@@ -165,7 +165,7 @@ const UUSD_MAX_BALANCE = 10_000_000_000_000000;
   const userPositionT4 = await queryContract(terra, redBank,
     { user_position: { user_address: user.key.accAddress } }
   )
-  strictEqual(Number(userPositionT4.total_collateral_in_uusd), 0)
+  strictEqual(Number(userPositionT4.total_collateral_in_base_asset), 0)
 
   console.log("OK")
 
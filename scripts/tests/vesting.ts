@@ -210,7 +210,6 @@ const JOHN_WALLET_MARS_BALANCE = 600_000000; // Mars tokens in john's wallet
   await executeContract(terra, deployer, addressProvider, {
     update_config: {
       config: {
-        owner: deployer.key.accAddress,
         council_address: council,
         mars_token_address: mars,
         staking_address: staking,